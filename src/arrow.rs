@@ -0,0 +1,623 @@
+//! `--format arrow`: records written as an Arrow IPC file (Feather V2), the
+//! columnar format pandas, polars and DuckDB can map into memory as is.
+//!
+//! The metadata is FlatBuffers, written here by a small encoder that lays
+//! out each table before the tables and vectors it refers to.
+
+use crate::error::Error;
+use crate::Record;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The bytes that start and end an Arrow IPC file.
+const MAGIC: &[u8] = b"ARROW1";
+
+/// Rows per record batch.
+pub const BATCH_ROWS: usize = 64 * 1024;
+
+/// `MetadataVersion.V5`
+const METADATA_VERSION: i16 = 4;
+
+/// `MessageHeader` values
+const HEADER_SCHEMA: u8 = 1;
+const HEADER_RECORD_BATCH: u8 = 3;
+
+/// The type of a column, inferred from its values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    /// Every value is null or blank
+    Null,
+    Boolean,
+    Int64,
+    Float64,
+    /// Strings, and the JSON text of other values in a column of mixed types
+    Utf8,
+}
+
+impl ColumnType {
+    /// The `Type` union member and its table.
+    fn flatbuffer(self) -> (u8, Item) {
+        match self {
+            ColumnType::Null => (1, Item::Table(vec![])),
+            // Int { bitWidth: 64, is_signed: true }
+            ColumnType::Int64 => (
+                2,
+                Item::Table(vec![Some(Slot::int(64)), Some(Slot::bool(true))]),
+            ),
+            // FloatingPoint { precision: DOUBLE }
+            ColumnType::Float64 => (3, Item::Table(vec![Some(Slot::short(2))])),
+            ColumnType::Utf8 => (5, Item::Table(vec![])),
+            ColumnType::Boolean => (6, Item::Table(vec![])),
+        }
+    }
+
+    fn of(value: &Value) -> ColumnType {
+        match value {
+            Value::Null => ColumnType::Null,
+            Value::String(text) if text.is_empty() => ColumnType::Null,
+            Value::Bool(_) => ColumnType::Boolean,
+            Value::Number(n) if n.is_i64() => ColumnType::Int64,
+            Value::Number(_) => ColumnType::Float64,
+            Value::String(_) | Value::Array(_) | Value::Object(_) => ColumnType::Utf8,
+        }
+    }
+
+    /// Combines the type seen so far with that of another value: integers
+    /// and other numbers together are doubles, and other mixes are strings.
+    fn merge(self, other: ColumnType) -> ColumnType {
+        match (self, other) {
+            (ColumnType::Null, other) | (other, ColumnType::Null) => other,
+            (a, b) if a == b => a,
+            (ColumnType::Int64, ColumnType::Float64) | (ColumnType::Float64, ColumnType::Int64) => {
+                ColumnType::Float64
+            }
+            _ => ColumnType::Utf8,
+        }
+    }
+}
+
+/// The columns of a file: every key of the records in the order first seen.
+#[derive(Debug, PartialEq)]
+pub struct Schema {
+    columns: Vec<(Arc<str>, ColumnType)>,
+}
+
+/// Infers the column types from all records. Blank values count as nulls
+/// except in string columns.
+pub fn infer_schema(records: &[Record]) -> Schema {
+    let mut columns: Vec<(Arc<str>, ColumnType)> = Vec::new();
+    let mut positions: HashMap<Arc<str>, usize> = HashMap::new();
+    for record in records {
+        for (key, value) in record {
+            let kind = ColumnType::of(value);
+            match positions.get(key) {
+                Some(&position) => columns[position].1 = columns[position].1.merge(kind),
+                None => {
+                    positions.insert(Arc::clone(key), columns.len());
+                    columns.push((Arc::clone(key), kind));
+                }
+            }
+        }
+    }
+    Schema { columns }
+}
+
+impl Schema {
+    fn flatbuffer(&self) -> Item {
+        let fields = self
+            .columns
+            .iter()
+            .map(|(name, kind)| {
+                let (type_type, type_table) = kind.flatbuffer();
+                // Field { name, nullable, type_type, type, dictionary, children }
+                Item::Table(vec![
+                    Some(Slot::Child(Item::String(name.to_string()))),
+                    Some(Slot::bool(true)),
+                    Some(Slot::byte(type_type)),
+                    Some(Slot::Child(type_table)),
+                    None,
+                    Some(Slot::Child(Item::Tables(vec![]))),
+                ])
+            })
+            .collect();
+        // Schema { endianness: Little, fields }
+        Item::Table(vec![
+            Some(Slot::short(0)),
+            Some(Slot::Child(Item::Tables(fields))),
+        ])
+    }
+}
+
+/// Writes an Arrow IPC file: the magic bytes, a schema message, a record
+/// batch message per `BATCH_ROWS` records, the end-of-stream marker, and a
+/// footer locating the messages.
+pub fn write_file(records: &[Record], out: &mut Vec<u8>) -> Result<(), Error> {
+    let schema = infer_schema(records);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&[0, 0]);
+    write_message(HEADER_SCHEMA, schema.flatbuffer(), &[], out);
+
+    let mut blocks = Vec::new();
+    for batch in records.chunks(BATCH_ROWS) {
+        let (header, body) = record_batch(&schema, batch)?;
+        let offset = out.len();
+        let metadata_length = write_message(HEADER_RECORD_BATCH, header, &body, out);
+        // Block { offset, metaDataLength, bodyLength }, with 4 bytes of padding
+        blocks.extend_from_slice(&(offset as i64).to_le_bytes());
+        blocks.extend_from_slice(&(metadata_length as i32).to_le_bytes());
+        blocks.extend_from_slice(&[0; 4]);
+        blocks.extend_from_slice(&(body.len() as i64).to_le_bytes());
+    }
+    out.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+
+    // Footer { version, schema, dictionaries, recordBatches }
+    let footer = finish(Item::Table(vec![
+        Some(Slot::short(METADATA_VERSION)),
+        Some(Slot::Child(schema.flatbuffer())),
+        Some(Slot::Child(Item::Structs(8, 0, vec![]))),
+        Some(Slot::Child(Item::Structs(
+            8,
+            records.chunks(BATCH_ROWS).len(),
+            blocks,
+        ))),
+    ]));
+    out.extend_from_slice(&footer);
+    out.extend_from_slice(&(footer.len() as i32).to_le_bytes());
+    out.extend_from_slice(MAGIC);
+    Ok(())
+}
+
+/// Writes an encapsulated message: the continuation marker, the metadata
+/// length, the `Message` padded to 8 bytes, then the body. Returns the
+/// length of all but the body.
+fn write_message(header_type: u8, header: Item, body: &[u8], out: &mut Vec<u8>) -> usize {
+    // Message { version, header_type, header, bodyLength }
+    let mut metadata = finish(Item::Table(vec![
+        Some(Slot::short(METADATA_VERSION)),
+        Some(Slot::byte(header_type)),
+        Some(Slot::Child(header)),
+        Some(Slot::long(body.len() as i64)),
+    ]));
+    metadata.resize(metadata.len().next_multiple_of(8), 0);
+    out.extend_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+    out.extend_from_slice(&(metadata.len() as i32).to_le_bytes());
+    out.extend_from_slice(&metadata);
+    out.extend_from_slice(body);
+    8 + metadata.len()
+}
+
+/// Builds the `RecordBatch` of some records and its body, which holds each
+/// column's buffers padded to 8 bytes.
+fn record_batch(schema: &Schema, records: &[Record]) -> Result<(Item, Vec<u8>), Error> {
+    let mut nodes = Vec::new();
+    let mut buffers = Vec::new();
+    let mut body = Vec::new();
+    let mut add_buffer = |body: &mut Vec<u8>, data: &[u8]| {
+        buffers.extend_from_slice(&(body.len() as i64).to_le_bytes());
+        buffers.extend_from_slice(&(data.len() as i64).to_le_bytes());
+        body.extend_from_slice(data);
+        body.resize(body.len().next_multiple_of(8), 0);
+    };
+
+    for (name, kind) in &schema.columns {
+        let values: Vec<Option<&Value>> = records
+            .iter()
+            .map(|record| {
+                record.get(name).filter(|value| match value {
+                    Value::Null => false,
+                    Value::String(text) => !text.is_empty() || *kind == ColumnType::Utf8,
+                    _ => true,
+                })
+            })
+            .collect();
+        let null_count = values.iter().filter(|value| value.is_none()).count();
+        // FieldNode { length, null_count }
+        nodes.extend_from_slice(&(records.len() as i64).to_le_bytes());
+        nodes.extend_from_slice(&(null_count as i64).to_le_bytes());
+        if *kind == ColumnType::Null {
+            continue;
+        }
+
+        let validity = if null_count == 0 {
+            Vec::new()
+        } else {
+            bitmap(values.iter().map(Option::is_some))
+        };
+        add_buffer(&mut body, &validity);
+        match kind {
+            ColumnType::Boolean => {
+                let bits = values
+                    .iter()
+                    .map(|value| value.and_then(Value::as_bool) == Some(true));
+                add_buffer(&mut body, &bitmap(bits));
+            }
+            ColumnType::Int64 => {
+                let data: Vec<u8> = values
+                    .iter()
+                    .flat_map(|value| value.and_then(Value::as_i64).unwrap_or(0).to_le_bytes())
+                    .collect();
+                add_buffer(&mut body, &data);
+            }
+            ColumnType::Float64 => {
+                let data: Vec<u8> = values
+                    .iter()
+                    .flat_map(|value| value.and_then(Value::as_f64).unwrap_or(0.0).to_le_bytes())
+                    .collect();
+                add_buffer(&mut body, &data);
+            }
+            ColumnType::Utf8 => {
+                let mut offsets = Vec::with_capacity((values.len() + 1) * 4);
+                let mut data = Vec::new();
+                offsets.extend_from_slice(&0i32.to_le_bytes());
+                for value in &values {
+                    match value {
+                        Some(Value::String(text)) => data.extend_from_slice(text.as_bytes()),
+                        Some(other) => serde_json::to_writer(&mut data, other)?,
+                        None => {}
+                    }
+                    let offset = i32::try_from(data.len()).map_err(|_| {
+                        Error::Config(format!(
+                            "Column {} holds more than 2 GiB of text in {} records, too much for an Arrow string column",
+                            name,
+                            records.len()
+                        ))
+                    })?;
+                    offsets.extend_from_slice(&offset.to_le_bytes());
+                }
+                add_buffer(&mut body, &offsets);
+                add_buffer(&mut body, &data);
+            }
+            ColumnType::Null => unreachable!("null columns have no buffers"),
+        }
+    }
+
+    // RecordBatch { length, nodes, buffers }
+    let header = Item::Table(vec![
+        Some(Slot::long(records.len() as i64)),
+        Some(Slot::Child(Item::Structs(8, schema.columns.len(), nodes))),
+        Some(Slot::Child(Item::Structs(8, buffers.len() / 16, buffers))),
+    ]);
+    Ok((header, body))
+}
+
+/// Packs bits least significant first, padded to whole bytes.
+fn bitmap(bits: impl Iterator<Item = bool>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (i, bit) in bits.enumerate() {
+        if i % 8 == 0 {
+            bytes.push(0);
+        }
+        if bit {
+            *bytes.last_mut().expect("pushed above") |= 1 << (i % 8);
+        }
+    }
+    bytes
+}
+
+/// A FlatBuffers object.
+enum Item {
+    /// A table, by field slot; `None` leaves a field out
+    Table(Vec<Option<Slot>>),
+    /// A vector of tables
+    Tables(Vec<Item>),
+    /// A vector of structs or scalars: their alignment, count and bytes
+    Structs(usize, usize, Vec<u8>),
+    String(String),
+}
+
+/// The value of a table field: a little-endian scalar, aligned to its size,
+/// or an object it refers to.
+enum Slot {
+    Scalar(Vec<u8>),
+    Child(Item),
+}
+
+impl Slot {
+    fn bool(value: bool) -> Slot {
+        Slot::Scalar(vec![u8::from(value)])
+    }
+
+    fn byte(value: u8) -> Slot {
+        Slot::Scalar(vec![value])
+    }
+
+    fn short(value: i16) -> Slot {
+        Slot::Scalar(value.to_le_bytes().to_vec())
+    }
+
+    fn int(value: i32) -> Slot {
+        Slot::Scalar(value.to_le_bytes().to_vec())
+    }
+
+    fn long(value: i64) -> Slot {
+        Slot::Scalar(value.to_le_bytes().to_vec())
+    }
+}
+
+/// Encodes a FlatBuffer with `root` as its root table.
+fn finish(root: Item) -> Vec<u8> {
+    let mut buf = vec![0; 4];
+    let position = write_item(root, &mut buf);
+    patch_offset(&mut buf, 0, position);
+    buf
+}
+
+/// Appends an object, then the objects it refers to, and returns the
+/// position that refers to it.
+fn write_item(item: Item, buf: &mut Vec<u8>) -> usize {
+    match item {
+        Item::Table(slots) => {
+            // The table's layout: a 4-byte offset to the vtable, then the
+            // fields in slot order, each aligned to its size
+            let mut field_offsets = Vec::with_capacity(slots.len());
+            let mut size: usize = 4;
+            for slot in &slots {
+                let width = match slot {
+                    None => {
+                        field_offsets.push(0);
+                        continue;
+                    }
+                    Some(Slot::Scalar(bytes)) => bytes.len(),
+                    Some(Slot::Child(_)) => 4,
+                };
+                size = size.next_multiple_of(width);
+                field_offsets.push(size);
+                size += width;
+            }
+
+            pad(buf, 2);
+            let vtable = buf.len();
+            buf.extend_from_slice(&(4 + 2 * slots.len() as u16).to_le_bytes());
+            buf.extend_from_slice(&(size as u16).to_le_bytes());
+            for offset in &field_offsets {
+                buf.extend_from_slice(&(*offset as u16).to_le_bytes());
+            }
+            pad(buf, 8);
+            let table = buf.len();
+            buf.extend_from_slice(&((table - vtable) as i32).to_le_bytes());
+            buf.resize(table + size, 0);
+
+            let mut children = Vec::new();
+            for (slot, offset) in slots.into_iter().zip(field_offsets) {
+                match slot {
+                    Some(Slot::Scalar(bytes)) => {
+                        buf[table + offset..table + offset + bytes.len()].copy_from_slice(&bytes)
+                    }
+                    Some(Slot::Child(child)) => children.push((table + offset, child)),
+                    None => {}
+                }
+            }
+            for (at, child) in children {
+                let position = write_item(child, buf);
+                patch_offset(buf, at, position);
+            }
+            table
+        }
+        Item::Tables(items) => {
+            pad(buf, 4);
+            let vector = buf.len();
+            buf.extend_from_slice(&(items.len() as u32).to_le_bytes());
+            buf.resize(vector + 4 + 4 * items.len(), 0);
+            for (i, item) in items.into_iter().enumerate() {
+                let position = write_item(item, buf);
+                patch_offset(buf, vector + 4 + 4 * i, position);
+            }
+            vector
+        }
+        Item::Structs(align, count, bytes) => {
+            // The elements, not the length before them, are aligned
+            pad(buf, 4);
+            while !(buf.len() + 4).is_multiple_of(align) {
+                buf.extend_from_slice(&[0; 4]);
+            }
+            let vector = buf.len();
+            buf.extend_from_slice(&(count as u32).to_le_bytes());
+            buf.extend_from_slice(&bytes);
+            vector
+        }
+        Item::String(text) => {
+            pad(buf, 4);
+            let string = buf.len();
+            buf.extend_from_slice(&(text.len() as u32).to_le_bytes());
+            buf.extend_from_slice(text.as_bytes());
+            buf.push(0);
+            string
+        }
+    }
+}
+
+fn pad(buf: &mut Vec<u8>, align: usize) {
+    buf.resize(buf.len().next_multiple_of(align), 0);
+}
+
+/// Stores at `at` the offset to a later position, as a FlatBuffers `uoffset`.
+fn patch_offset(buf: &mut [u8], at: usize, position: usize) {
+    buf[at..at + 4].copy_from_slice(&((position - at) as u32).to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads FlatBuffers tables the way a reader following the schema does.
+    struct Table<'a> {
+        buf: &'a [u8],
+        position: usize,
+    }
+
+    fn u32_at(buf: &[u8], at: usize) -> usize {
+        u32::from_le_bytes(buf[at..at + 4].try_into().unwrap()) as usize
+    }
+
+    impl<'a> Table<'a> {
+        fn root(buf: &'a [u8]) -> Self {
+            Table {
+                buf,
+                position: u32_at(buf, 0),
+            }
+        }
+
+        fn field(&self, slot: usize) -> Option<usize> {
+            let soffset = i32::from_le_bytes(
+                self.buf[self.position..self.position + 4]
+                    .try_into()
+                    .unwrap(),
+            );
+            let vtable = (self.position as i64 - soffset as i64) as usize;
+            let vtable_size = u16::from_le_bytes([self.buf[vtable], self.buf[vtable + 1]]) as usize;
+            if 4 + 2 * slot >= vtable_size {
+                return None;
+            }
+            let at = vtable + 4 + 2 * slot;
+            let offset = u16::from_le_bytes([self.buf[at], self.buf[at + 1]]) as usize;
+            (offset != 0).then_some(self.position + offset)
+        }
+
+        fn scalar(&self, slot: usize, width: usize) -> i64 {
+            let at = self.field(slot).unwrap();
+            let mut bytes = [0; 8];
+            bytes[..width].copy_from_slice(&self.buf[at..at + width]);
+            i64::from_le_bytes(bytes)
+        }
+
+        fn child(&self, slot: usize) -> usize {
+            let at = self.field(slot).unwrap();
+            at + u32_at(self.buf, at)
+        }
+
+        fn table(&self, slot: usize) -> Table<'a> {
+            Table {
+                buf: self.buf,
+                position: self.child(slot),
+            }
+        }
+
+        fn tables(&self, slot: usize) -> Vec<Table<'a>> {
+            let vector = self.child(slot);
+            (0..u32_at(self.buf, vector))
+                .map(|i| {
+                    let at = vector + 4 + 4 * i;
+                    Table {
+                        buf: self.buf,
+                        position: at + u32_at(self.buf, at),
+                    }
+                })
+                .collect()
+        }
+
+        fn string(&self, slot: usize) -> &'a str {
+            let string = self.child(slot);
+            let length = u32_at(self.buf, string);
+            std::str::from_utf8(&self.buf[string + 4..string + 4 + length]).unwrap()
+        }
+
+        /// The `i64`s of a vector of structs that each hold `per_element`,
+        /// checking that the elements are aligned.
+        fn longs(&self, slot: usize, per_element: usize) -> Vec<i64> {
+            let vector = self.child(slot);
+            assert_eq!((vector + 4) % 8, 0);
+            (0..u32_at(self.buf, vector) * per_element)
+                .map(|i| {
+                    let at = vector + 4 + 8 * i;
+                    i64::from_le_bytes(self.buf[at..at + 8].try_into().unwrap())
+                })
+                .collect()
+        }
+    }
+
+    fn record(value: Value) -> Record {
+        let Value::Object(fields) = value else {
+            panic!("records are objects");
+        };
+        fields
+            .into_iter()
+            .map(|(key, value)| (Arc::from(key), value))
+            .collect()
+    }
+
+    #[test]
+    fn test_infer_schema() {
+        let records = [
+            record(serde_json::json!({"id": 1, "score": 2, "name": "Ann", "tags": ["a"]})),
+            record(serde_json::json!({"id": "", "score": 2.5, "name": 3, "ok": true})),
+        ];
+        let schema = infer_schema(&records);
+        let columns: Vec<_> = schema
+            .columns
+            .iter()
+            .map(|(name, kind)| (&**name, *kind))
+            .collect();
+        assert_eq!(
+            columns,
+            [
+                ("id", ColumnType::Int64),
+                ("score", ColumnType::Float64),
+                ("name", ColumnType::Utf8),
+                ("tags", ColumnType::Utf8),
+                ("ok", ColumnType::Boolean),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_file() {
+        let records = [
+            record(serde_json::json!({"id": 1, "name": "Ann", "ok": true, "none": null})),
+            record(serde_json::json!({"id": null, "name": "Bo", "ok": false})),
+            record(serde_json::json!({"id": 3, "name": "", "ok": null})),
+        ];
+        let mut file = Vec::new();
+        write_file(&records, &mut file).unwrap();
+        assert!(file.starts_with(b"ARROW1\0\0"));
+        assert!(file.ends_with(b"ARROW1"));
+
+        let footer_length =
+            i32::from_le_bytes(file[file.len() - 10..file.len() - 6].try_into().unwrap());
+        let footer_start = file.len() - 10 - footer_length as usize;
+        assert_eq!(footer_start % 8, 0);
+        assert_eq!(
+            file[footer_start - 8..footer_start],
+            [0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]
+        );
+        let footer = Table::root(&file[footer_start..file.len() - 10]);
+        assert_eq!(footer.scalar(0, 2), 4);
+
+        let fields = footer.table(1).tables(1);
+        let names: Vec<_> = fields.iter().map(|field| field.string(0)).collect();
+        assert_eq!(names, ["id", "name", "ok", "none"]);
+        let types: Vec<_> = fields.iter().map(|field| field.scalar(2, 1)).collect();
+        assert_eq!(types, [2, 5, 6, 1]);
+        assert_eq!(fields[0].table(3).scalar(0, 4), 64);
+        assert!(fields.iter().all(|field| field.tables(5).is_empty()));
+
+        // Block { offset, metaDataLength, bodyLength }
+        let blocks = footer.longs(3, 3);
+        assert_eq!(blocks.len(), 3);
+        let (offset, metadata_length) = (blocks[0] as usize, blocks[1] as usize & 0xffff_ffff);
+        assert_eq!(file[offset..offset + 4], [0xff; 4]);
+        let message = Table::root(&file[offset + 8..offset + metadata_length]);
+        assert_eq!(message.scalar(1, 1), 3);
+        assert_eq!(message.scalar(3, 8), blocks[2]);
+
+        let batch = message.table(2);
+        assert_eq!(batch.scalar(0, 8), 3);
+        assert_eq!(batch.longs(1, 2), [3, 1, 3, 0, 3, 1, 3, 3]);
+        let buffers = batch.longs(2, 2);
+        let body = &file[offset + metadata_length..];
+        let buffer = |i: usize| {
+            let (start, length) = (buffers[2 * i] as usize, buffers[2 * i + 1] as usize);
+            &body[start..start + length]
+        };
+        // id: validity and values; name: validity, offsets and text; ok:
+        // validity and values; none has no buffers
+        assert_eq!(buffers.len(), 2 * 7);
+        assert_eq!(buffer(0), [0b101]);
+        assert_eq!(buffer(1)[16..24], 3i64.to_le_bytes());
+        assert!(buffer(2).is_empty());
+        assert_eq!(buffer(3), [0, 0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 5, 0, 0, 0]);
+        assert_eq!(buffer(4), b"AnnBo");
+        assert_eq!(buffer(5), [0b011]);
+        assert_eq!(buffer(6), [0b001]);
+    }
+}
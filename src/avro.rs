@@ -0,0 +1,668 @@
+//! `--format avro`: records written as an Avro object container file, with a
+//! schema read from `--avro-schema` or inferred from the records.
+
+use crate::sha256;
+use crate::Record;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// The bytes that start every object container file.
+const MAGIC: &[u8] = b"Obj\x01";
+
+/// Encoded records to collect before they are written out as a block.
+pub const BLOCK_SIZE: usize = 64 * 1024;
+
+/// A record schema, with the JSON text embedded in the files written with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    fields: Vec<Field>,
+    json: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Field {
+    /// The Avro field name
+    name: String,
+    /// The record key the field holds, which differs from `name` when an
+    /// inferred name had to be made valid
+    key: String,
+    kind: Type,
+    default: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Type {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Array(Box<Type>),
+    Map(Box<Type>),
+    Union(Vec<Type>),
+    Record(Vec<Field>),
+    Enum(Vec<String>),
+    Fixed(usize),
+}
+
+impl Type {
+    fn name(&self) -> &'static str {
+        match self {
+            Type::Null => "null",
+            Type::Boolean => "boolean",
+            Type::Int => "int",
+            Type::Long => "long",
+            Type::Float => "float",
+            Type::Double => "double",
+            Type::Bytes => "bytes",
+            Type::String => "string",
+            Type::Array(_) => "array",
+            Type::Map(_) => "map",
+            Type::Union(_) => "union",
+            Type::Record(_) => "record",
+            Type::Enum(_) => "enum",
+            Type::Fixed(_) => "fixed",
+        }
+    }
+}
+
+/// Parses the JSON text of a schema, which must describe a record.
+pub fn parse_schema(text: &str) -> Result<Schema, String> {
+    let value: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    match parse_type(&value, None, &mut HashMap::new())? {
+        Type::Record(fields) => Ok(Schema {
+            fields,
+            json: value.to_string(),
+        }),
+        other => Err(format!("expected a record schema, found {}", other.name())),
+    }
+}
+
+fn parse_type(
+    value: &Value,
+    namespace: Option<&str>,
+    names: &mut HashMap<String, Type>,
+) -> Result<Type, String> {
+    match value {
+        Value::String(name) => match name.as_str() {
+            "null" => Ok(Type::Null),
+            "boolean" => Ok(Type::Boolean),
+            "int" => Ok(Type::Int),
+            "long" => Ok(Type::Long),
+            "float" => Ok(Type::Float),
+            "double" => Ok(Type::Double),
+            "bytes" => Ok(Type::Bytes),
+            "string" => Ok(Type::String),
+            _ => names
+                .get(&full_name(name, namespace))
+                .or_else(|| names.get(name))
+                .cloned()
+                .ok_or_else(|| format!("unknown type '{}'", name)),
+        },
+        Value::Array(branches) => {
+            let branches = branches
+                .iter()
+                .map(|branch| parse_type(branch, namespace, names))
+                .collect::<Result<Vec<_>, _>>()?;
+            if branches
+                .iter()
+                .any(|branch| matches!(branch, Type::Union(_)))
+            {
+                return Err("a union cannot directly contain another union".to_string());
+            }
+            Ok(Type::Union(branches))
+        }
+        Value::Object(object) => {
+            let kind = object
+                .get("type")
+                .ok_or("a schema object needs a \"type\"")?;
+            let named = |object: &Map<String, Value>| -> Result<String, String> {
+                let name = object
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| format!("a {} needs a \"name\"", kind))?;
+                let namespace = object
+                    .get("namespace")
+                    .and_then(Value::as_str)
+                    .or(namespace);
+                Ok(full_name(name, namespace))
+            };
+            let parsed = match kind.as_str() {
+                Some("record") => {
+                    let name = named(object)?;
+                    let namespace = name.rsplit_once('.').map(|(namespace, _)| namespace);
+                    let fields = object
+                        .get("fields")
+                        .and_then(Value::as_array)
+                        .ok_or_else(|| format!("record {} needs a \"fields\" array", name))?;
+                    let fields = fields
+                        .iter()
+                        .map(|field| {
+                            let field_name = field
+                                .get("name")
+                                .and_then(Value::as_str)
+                                .ok_or_else(|| format!("a field of {} needs a \"name\"", name))?;
+                            let kind = field.get("type").ok_or_else(|| {
+                                format!("field {} of {} needs a \"type\"", field_name, name)
+                            })?;
+                            Ok(Field {
+                                name: field_name.to_string(),
+                                key: field_name.to_string(),
+                                kind: parse_type(kind, namespace, names)?,
+                                default: field.get("default").cloned(),
+                            })
+                        })
+                        .collect::<Result<Vec<_>, String>>()?;
+                    names.insert(name, Type::Record(fields.clone()));
+                    Type::Record(fields)
+                }
+                Some("enum") => {
+                    let name = named(object)?;
+                    let symbols = object
+                        .get("symbols")
+                        .and_then(Value::as_array)
+                        .and_then(|symbols| {
+                            symbols
+                                .iter()
+                                .map(|symbol| symbol.as_str().map(str::to_string))
+                                .collect::<Option<Vec<_>>>()
+                        })
+                        .ok_or_else(|| format!("enum {} needs a \"symbols\" array", name))?;
+                    names.insert(name, Type::Enum(symbols.clone()));
+                    Type::Enum(symbols)
+                }
+                Some("fixed") => {
+                    let name = named(object)?;
+                    let size = object
+                        .get("size")
+                        .and_then(Value::as_u64)
+                        .ok_or_else(|| format!("fixed {} needs a \"size\"", name))?;
+                    names.insert(name, Type::Fixed(size as usize));
+                    Type::Fixed(size as usize)
+                }
+                Some("array") => {
+                    let items = object.get("items").ok_or("an array needs \"items\"")?;
+                    Type::Array(Box::new(parse_type(items, namespace, names)?))
+                }
+                Some("map") => {
+                    let values = object.get("values").ok_or("a map needs \"values\"")?;
+                    Type::Map(Box::new(parse_type(values, namespace, names)?))
+                }
+                // A primitive with attributes such as a logical type, which
+                // is written as the underlying type
+                _ => parse_type(kind, namespace, names)?,
+            };
+            Ok(parsed)
+        }
+        other => Err(format!("invalid schema {}", other)),
+    }
+}
+
+fn full_name(name: &str, namespace: Option<&str>) -> String {
+    match namespace {
+        Some(namespace) if !name.contains('.') && !namespace.is_empty() => {
+            format!("{}.{}", namespace, name)
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// Infers a record schema from the records, with a field for every key in
+/// the order first seen. A field missing from some records, or with null or
+/// blank values, is a union with null; integers and other numbers together are
+/// doubles, and objects are maps.
+pub fn infer_schema(records: &[Record]) -> Schema {
+    let mut kinds: Vec<(&str, Type)> = Vec::new();
+    let mut positions: HashMap<&str, usize> = HashMap::new();
+    for (i, record) in records.iter().enumerate() {
+        for (key, value) in record {
+            let kind = infer_type(value);
+            match positions.get(&**key) {
+                Some(&position) => {
+                    let current = std::mem::replace(&mut kinds[position].1, Type::Null);
+                    kinds[position].1 = merge(current, kind);
+                }
+                None => {
+                    positions.insert(key, kinds.len());
+                    // Earlier records lack the field
+                    let kind = if i > 0 { merge(Type::Null, kind) } else { kind };
+                    kinds.push((key, kind));
+                }
+            }
+        }
+        if record.len() < kinds.len() {
+            for (key, kind) in &mut kinds {
+                if !record.contains_key(*key) {
+                    *kind = merge(std::mem::replace(kind, Type::Null), Type::Null);
+                }
+            }
+        }
+    }
+
+    let mut taken = HashMap::new();
+    let fields: Vec<Field> = kinds
+        .into_iter()
+        .map(|(key, kind)| {
+            let mut name = field_name(key);
+            let uses = taken.entry(name.clone()).or_insert(0);
+            *uses += 1;
+            if *uses > 1 {
+                name = format!("{}_{}", name, uses);
+            }
+            let default = matches!(&kind, Type::Union(branches) if branches[0] == Type::Null)
+                .then_some(Value::Null);
+            Field {
+                name,
+                key: key.to_string(),
+                kind,
+                default,
+            }
+        })
+        .collect();
+    let json = json!({
+        "type": "record",
+        "name": "Record",
+        "fields": fields.iter().map(field_json).collect::<Vec<_>>(),
+    })
+    .to_string();
+    Schema { fields, json }
+}
+
+fn infer_type(value: &Value) -> Type {
+    match value {
+        Value::Null => Type::Null,
+        Value::String(text) if text.is_empty() => Type::Null,
+        Value::Bool(_) => Type::Boolean,
+        Value::Number(n) if n.is_i64() => Type::Long,
+        Value::Number(_) => Type::Double,
+        Value::String(_) => Type::String,
+        Value::Array(elements) => Type::Array(Box::new(
+            elements
+                .iter()
+                .map(infer_type)
+                .reduce(merge)
+                .unwrap_or(Type::Null),
+        )),
+        Value::Object(fields) => Type::Map(Box::new(
+            fields
+                .values()
+                .map(infer_type)
+                .reduce(merge)
+                .unwrap_or(Type::Null),
+        )),
+    }
+}
+
+/// Combines two inferred types. Types of different kinds make a union, with
+/// null first so that it can be the default.
+fn merge(a: Type, b: Type) -> Type {
+    let mut branches = match a {
+        Type::Union(branches) => branches,
+        other => vec![other],
+    };
+    let others = match b {
+        Type::Union(branches) => branches,
+        other => vec![other],
+    };
+    for other in others {
+        let same = branches.iter().position(|branch| {
+            branch.name() == other.name()
+                || matches!(
+                    (branch, &other),
+                    (Type::Long, Type::Double) | (Type::Double, Type::Long)
+                )
+        });
+        match (same, other) {
+            (None, other) => branches.push(other),
+            (Some(i), Type::Array(items)) => {
+                if let Type::Array(current) = &mut branches[i] {
+                    let merged = merge(std::mem::replace(current, Type::Null), *items);
+                    **current = merged;
+                }
+            }
+            (Some(i), Type::Map(values)) => {
+                if let Type::Map(current) = &mut branches[i] {
+                    let merged = merge(std::mem::replace(current, Type::Null), *values);
+                    **current = merged;
+                }
+            }
+            (Some(i), Type::Double) => branches[i] = Type::Double,
+            (Some(_), _) => {}
+        }
+    }
+    if branches.len() == 1 {
+        return branches.pop().expect("one branch");
+    }
+    if let Some(null) = branches.iter().position(|branch| *branch == Type::Null) {
+        let null = branches.remove(null);
+        branches.insert(0, null);
+    }
+    Type::Union(branches)
+}
+
+/// Makes a valid Avro name of a record key: letters, digits and
+/// underscores, not starting with a digit.
+fn field_name(key: &str) -> String {
+    let mut name: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
+
+fn field_json(field: &Field) -> Value {
+    let mut object = Map::new();
+    object.insert("name".to_string(), Value::from(field.name.as_str()));
+    object.insert("type".to_string(), type_json(&field.kind));
+    if let Some(default) = &field.default {
+        object.insert("default".to_string(), default.clone());
+    }
+    Value::Object(object)
+}
+
+/// The schema of an inferred type, which is never a record, enum or fixed.
+fn type_json(kind: &Type) -> Value {
+    match kind {
+        Type::Array(items) => json!({"type": "array", "items": type_json(items)}),
+        Type::Map(values) => json!({"type": "map", "values": type_json(values)}),
+        Type::Union(branches) => Value::Array(branches.iter().map(type_json).collect()),
+        other => Value::from(other.name()),
+    }
+}
+
+impl Schema {
+    /// The marker that ends each block: the start of the schema's SHA-256,
+    /// so the same schema always gives the same file.
+    pub fn sync_marker(&self) -> [u8; 16] {
+        let digest = sha256::hex_digest(self.json.as_bytes());
+        let mut marker = [0; 16];
+        for (i, byte) in marker.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&digest[i * 2..i * 2 + 2], 16).expect("hex digest");
+        }
+        marker
+    }
+
+    /// Appends the file header: the magic bytes, the schema and codec
+    /// metadata, and the sync marker.
+    pub fn write_header(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(MAGIC);
+        write_long(2, out);
+        write_bytes(b"avro.codec", out);
+        write_bytes(b"null", out);
+        write_bytes(b"avro.schema", out);
+        write_bytes(self.json.as_bytes(), out);
+        write_long(0, out);
+        out.extend_from_slice(&self.sync_marker());
+    }
+
+    /// Appends a record. Fields the schema does not list are left out, and
+    /// a field the record lacks takes its default, or null if it may be
+    /// null.
+    pub fn encode(&self, record: &Record, out: &mut Vec<u8>) -> Result<(), String> {
+        encode_fields(&self.fields, |key| record.get(key), out)
+    }
+}
+
+fn encode_fields<'a>(
+    fields: &[Field],
+    get: impl Fn(&str) -> Option<&'a Value>,
+    out: &mut Vec<u8>,
+) -> Result<(), String> {
+    for field in fields {
+        let result = match (get(&field.key), &field.default, &field.kind) {
+            (Some(value), _, kind) => encode(kind, value, out),
+            // A union's default is of its first branch
+            (None, Some(default), Type::Union(branches)) => {
+                write_long(0, out);
+                encode(&branches[0], default, out)
+            }
+            (None, Some(default), kind) => encode(kind, default, out),
+            (None, None, kind) => encode(kind, &Value::Null, out)
+                .map_err(|_| "missing and has no default".to_string()),
+        };
+        result.map_err(|e| format!("field {}: {}", field.name, e))?;
+    }
+    Ok(())
+}
+
+fn encode(kind: &Type, value: &Value, out: &mut Vec<u8>) -> Result<(), String> {
+    let mismatch = || format!("expected {}, found {}", kind.name(), value);
+    match (kind, value) {
+        (Type::Null, Value::Null) => {}
+        // Blank fields are null, unless a string field takes them
+        (Type::Null, Value::String(text)) if text.is_empty() => {}
+        (Type::Boolean, Value::Bool(b)) => out.push(u8::from(*b)),
+        (Type::Int, Value::Number(n)) => {
+            let n = n
+                .as_i64()
+                .and_then(|n| i32::try_from(n).ok())
+                .ok_or_else(mismatch)?;
+            write_long(n.into(), out);
+        }
+        (Type::Long, Value::Number(n)) => write_long(n.as_i64().ok_or_else(mismatch)?, out),
+        (Type::Float, Value::Number(n)) => {
+            let n = n.as_f64().ok_or_else(mismatch)? as f32;
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        (Type::Double, Value::Number(n)) => {
+            out.extend_from_slice(&n.as_f64().ok_or_else(mismatch)?.to_le_bytes());
+        }
+        (Type::Bytes | Type::String, Value::String(text)) => write_bytes(text.as_bytes(), out),
+        // Inferred numbers and booleans fit a string field as their text
+        (Type::String, Value::Number(_) | Value::Bool(_)) => {
+            write_bytes(value.to_string().as_bytes(), out)
+        }
+        (Type::Enum(symbols), Value::String(text)) => {
+            let index = symbols.iter().position(|symbol| symbol == text);
+            write_long(index.ok_or_else(mismatch)? as i64, out);
+        }
+        (Type::Fixed(size), Value::String(text)) if text.len() == *size => {
+            out.extend_from_slice(text.as_bytes())
+        }
+        (Type::Array(items), Value::Array(elements)) => {
+            if !elements.is_empty() {
+                write_long(elements.len() as i64, out);
+                for element in elements {
+                    encode(items, element, out)?;
+                }
+            }
+            write_long(0, out);
+        }
+        (Type::Map(values), Value::Object(fields)) => {
+            if !fields.is_empty() {
+                write_long(fields.len() as i64, out);
+                for (key, value) in fields {
+                    write_bytes(key.as_bytes(), out);
+                    encode(values, value, out).map_err(|e| format!("{}: {}", key, e))?;
+                }
+            }
+            write_long(0, out);
+        }
+        (Type::Record(fields), Value::Object(object)) => {
+            encode_fields(fields, |key| object.get(key), out)?
+        }
+        (Type::Union(branches), value) => {
+            // The first branch of the value's own type, then any that takes it
+            let index = branches
+                .iter()
+                .position(|branch| same_kind(branch, value))
+                .or_else(|| {
+                    branches
+                        .iter()
+                        .position(|branch| encode(branch, value, &mut Vec::new()).is_ok())
+                })
+                .ok_or_else(mismatch)?;
+            write_long(index as i64, out);
+            encode(&branches[index], value, out)?;
+        }
+        _ => return Err(mismatch()),
+    }
+    Ok(())
+}
+
+/// Whether a value is of the JSON type a branch stands for, which picks
+/// `long` over `string` for `1` in `["string", "long"]`.
+fn same_kind(kind: &Type, value: &Value) -> bool {
+    match (kind, value) {
+        (Type::Null, Value::Null) | (Type::Boolean, Value::Bool(_)) => true,
+        (Type::Int | Type::Long, Value::Number(n)) => n.is_i64(),
+        (Type::Float | Type::Double, Value::Number(n)) => !n.is_i64(),
+        (Type::String, Value::String(_)) => true,
+        (Type::Array(_), Value::Array(_)) => true,
+        (Type::Map(_) | Type::Record(_), Value::Object(_)) => true,
+        _ => false,
+    }
+}
+
+/// Appends a block: its record count and size, the records, and the sync
+/// marker.
+pub fn write_block(count: usize, data: &[u8], sync: &[u8; 16], out: &mut Vec<u8>) {
+    write_long(count as i64, out);
+    write_long(data.len() as i64, out);
+    out.extend_from_slice(data);
+    out.extend_from_slice(sync);
+}
+
+/// A zig-zag variable-length integer, as Avro writes `int` and `long`.
+fn write_long(n: i64, out: &mut Vec<u8>) {
+    let mut n = ((n << 1) ^ (n >> 63)) as u64;
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn write_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    write_long(bytes.len() as i64, out);
+    out.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn record(value: Value) -> Record {
+        let Value::Object(fields) = value else {
+            panic!("records are objects");
+        };
+        fields
+            .into_iter()
+            .map(|(key, value)| (Arc::from(key), value))
+            .collect()
+    }
+
+    #[test]
+    fn test_write_long() {
+        for (n, expected) in [
+            (0, vec![0x00]),
+            (-1, vec![0x01]),
+            (1, vec![0x02]),
+            (-64, vec![0x7f]),
+            (64, vec![0x80, 0x01]),
+            (i64::MIN, vec![0xff; 9].into_iter().chain([0x01]).collect()),
+        ] {
+            let mut out = Vec::new();
+            write_long(n, &mut out);
+            assert_eq!(out, expected, "{}", n);
+        }
+    }
+
+    #[test]
+    fn test_infer_schema() {
+        let records = [
+            record(json!({"id": 1, "first name": "Ann", "score": 2, "tags": ["a"]})),
+            record(json!({"id": 2, "first name": null, "score": 2.5, "extra": {"k": true}})),
+        ];
+        let schema = infer_schema(&records);
+        assert_eq!(
+            serde_json::from_str::<Value>(&schema.json).unwrap(),
+            json!({
+                "type": "record",
+                "name": "Record",
+                "fields": [
+                    {"name": "id", "type": "long"},
+                    {"name": "first_name", "type": ["null", "string"], "default": null},
+                    {"name": "score", "type": "double"},
+                    {"name": "tags", "type": ["null", {"type": "array", "items": "string"}], "default": null},
+                    {"name": "extra", "type": ["null", {"type": "map", "values": "boolean"}], "default": null},
+                ]
+            })
+        );
+
+        let mut out = Vec::new();
+        schema.encode(&records[1], &mut out).unwrap();
+        let mut expected = vec![0x04, 0x00];
+        expected.extend_from_slice(&2.5f64.to_le_bytes());
+        expected.extend_from_slice(&[0x00, 0x02, 0x02, 0x02, b'k', 0x01, 0x00]);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_encode_with_schema() {
+        let schema = parse_schema(
+            r#"{"type": "record", "name": "User", "namespace": "example", "fields": [
+                {"name": "name", "type": "string"},
+                {"name": "age", "type": ["null", "int"]},
+                {"name": "zip", "type": "string"},
+                {"name": "plan", "type": {"type": "enum", "name": "Plan", "symbols": ["free", "pro"]}, "default": "free"},
+                {"name": "next", "type": ["null", "Plan"]}
+            ]}"#,
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        schema
+            .encode(
+                &record(json!({"name": "Bo", "age": 30, "zip": 1234, "next": "pro"})),
+                &mut out,
+            )
+            .unwrap();
+        assert_eq!(
+            out,
+            [0x04, b'B', b'o', 0x02, 0x3c, 0x08, b'1', b'2', b'3', b'4', 0x00, 0x02, 0x02]
+        );
+
+        let error = schema
+            .encode(
+                &record(json!({"name": "Bo", "age": "old", "zip": "1"})),
+                &mut Vec::new(),
+            )
+            .unwrap_err();
+        assert_eq!(error, "field age: expected union, found \"old\"");
+        let error = schema
+            .encode(&record(json!({"age": 1, "zip": "1"})), &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(error, "field name: missing and has no default");
+    }
+
+    #[test]
+    fn test_parse_schema_errors() {
+        assert_eq!(
+            parse_schema(r#""string""#).unwrap_err(),
+            "expected a record schema, found string"
+        );
+        assert_eq!(
+            parse_schema(
+                r#"{"type": "record", "name": "R", "fields": [{"name": "a", "type": "Thing"}]}"#
+            )
+            .unwrap_err(),
+            "unknown type 'Thing'"
+        );
+    }
+
+    #[test]
+    fn test_write_header() {
+        let schema = infer_schema(&[]);
+        let mut out = Vec::new();
+        schema.write_header(&mut out);
+        assert!(out.starts_with(b"Obj\x01\x04\x14avro.codec\x08null\x16avro.schema"));
+        assert!(out.ends_with(&schema.sync_marker()));
+    }
+}
@@ -0,0 +1,166 @@
+//! `--format bson`: records encoded as BSON documents, one after another as
+//! in the `.bson` files `mongodump` writes and `mongorestore` reads.
+
+use crate::error::Error;
+use crate::Record;
+use serde::ser::Error as _;
+use serde_json::Value;
+
+const DOUBLE: u8 = 0x01;
+const STRING: u8 = 0x02;
+const DOCUMENT: u8 = 0x03;
+const ARRAY: u8 = 0x04;
+const BOOLEAN: u8 = 0x08;
+const NULL: u8 = 0x0a;
+const INT32: u8 = 0x10;
+const INT64: u8 = 0x12;
+
+/// Appends a record as a document of its fields in order. Fails if a field
+/// name contains a NUL byte, which BSON names cannot hold.
+pub fn encode_record(record: &Record, out: &mut Vec<u8>) -> Result<(), Error> {
+    encode_fields(record.iter().map(|(key, value)| (&**key, value)), out)
+}
+
+fn encode_fields<'a>(
+    fields: impl Iterator<Item = (&'a str, &'a Value)>,
+    out: &mut Vec<u8>,
+) -> Result<(), Error> {
+    document(out, |out| {
+        for (key, value) in fields {
+            element(key, value, out)?;
+        }
+        Ok(())
+    })
+}
+
+/// Writes a document: its length, the elements `body` appends, and a NUL.
+fn document(
+    out: &mut Vec<u8>,
+    body: impl FnOnce(&mut Vec<u8>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    body(out)?;
+    out.push(0);
+    let length = i32::try_from(out.len() - start)
+        .map_err(|_| Error::Json(serde_json::Error::custom("record too large for BSON")))?;
+    out[start..start + 4].copy_from_slice(&length.to_le_bytes());
+    Ok(())
+}
+
+/// Appends one named value. Integers are 32-bit when they fit and 64-bit
+/// otherwise; larger integers and other numbers are doubles.
+fn element(name: &str, value: &Value, out: &mut Vec<u8>) -> Result<(), Error> {
+    if name.contains('\0') {
+        return Err(Error::Json(serde_json::Error::custom(format!(
+            "field name {:?} contains a NUL byte, which BSON cannot hold",
+            name
+        ))));
+    }
+    // The type comes first but is only known once the value is written
+    let kind = out.len();
+    out.push(0);
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    out[kind] = match value {
+        Value::Null => NULL,
+        Value::Bool(b) => {
+            out.push(u8::from(*b));
+            BOOLEAN
+        }
+        Value::Number(n) => match n.as_i64() {
+            Some(n) => match i32::try_from(n) {
+                Ok(n) => {
+                    out.extend_from_slice(&n.to_le_bytes());
+                    INT32
+                }
+                Err(_) => {
+                    out.extend_from_slice(&n.to_le_bytes());
+                    INT64
+                }
+            },
+            None => {
+                let n = n.as_f64().unwrap_or(f64::NAN);
+                out.extend_from_slice(&n.to_le_bytes());
+                DOUBLE
+            }
+        },
+        Value::String(text) => {
+            // The length counts the trailing NUL
+            out.extend_from_slice(&(text.len() as i32 + 1).to_le_bytes());
+            out.extend_from_slice(text.as_bytes());
+            out.push(0);
+            STRING
+        }
+        Value::Array(elements) => {
+            // An array is a document keyed by the indexes "0", "1", ...
+            document(out, |out| {
+                for (i, element_value) in elements.iter().enumerate() {
+                    element(&i.to_string(), element_value, out)?;
+                }
+                Ok(())
+            })?;
+            ARRAY
+        }
+        Value::Object(fields) => {
+            encode_fields(fields.iter().map(|(key, value)| (key.as_str(), value)), out)?;
+            DOCUMENT
+        }
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn encoded(record: Value) -> Vec<u8> {
+        let Value::Object(fields) = record else {
+            panic!("records are objects");
+        };
+        let record: Record = fields
+            .into_iter()
+            .map(|(key, value)| (Arc::from(key), value))
+            .collect();
+        let mut out = Vec::new();
+        encode_record(&record, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_encode_record() {
+        // The example from the BSON specification
+        assert_eq!(
+            encoded(json!({"hello": "world"})),
+            b"\x16\0\0\0\x02hello\0\x06\0\0\0world\0\0"
+        );
+        assert_eq!(
+            encoded(json!({"a": 1, "b": 5000000000i64, "c": 1.5, "d": true, "e": null})),
+            b"\x29\0\0\0\
+              \x10a\0\x01\0\0\0\
+              \x12b\0\x00\xf2\x05\x2a\x01\0\0\0\
+              \x01c\0\0\0\0\0\0\0\xf8\x3f\
+              \x08d\0\x01\
+              \x0ae\0\
+              \0"
+        );
+        assert_eq!(
+            encoded(json!({"t": ["x", {"y": 2}]})),
+            b"\x25\0\0\0\
+              \x04t\0\x1d\0\0\0\
+              \x020\0\x02\0\0\0x\0\
+              \x031\0\x0c\0\0\0\x10y\0\x02\0\0\0\0\
+              \0\
+              \0"
+        );
+    }
+
+    #[test]
+    fn test_nul_in_name() {
+        let record = Record::from([(Arc::from("a\0b"), json!(1))]);
+        let error = encode_record(&record, &mut Vec::new()).unwrap_err();
+        assert_eq!(error.exit_code(), 5);
+    }
+}
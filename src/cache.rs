@@ -0,0 +1,106 @@
+//! `--cache-dir`: skips reconversion when the same input has already been
+//! converted with the same options, copying the earlier output instead.
+
+use crate::error::Error;
+use crate::log;
+use crate::sha256::Sha256;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A cache entry, named after the SHA-256 of everything that decides the
+/// output: the ctj version, the resolved options and the contents of the
+/// input and of every file the options refer to.
+pub struct Entry {
+    path: PathBuf,
+}
+
+impl Entry {
+    pub fn new(dir: &str, options: &[u8], files: &[&str]) -> Result<Entry, Error> {
+        let mut hasher = Sha256::new();
+        for part in [env!("CARGO_PKG_VERSION").as_bytes(), options] {
+            hasher.update(&(part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        for path in files {
+            let mut file = File::open(path)?;
+            hasher.update(&file.metadata()?.len().to_le_bytes());
+            io::copy(&mut file, &mut hasher)?;
+        }
+        let key = hasher.finish();
+        log::debug!("Cache key: {}", key);
+        Ok(Entry {
+            path: Path::new(dir).join(key),
+        })
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.is_file()
+    }
+
+    /// Copies the cached output to `output`, returning false on a miss.
+    pub fn restore(&self, output: &str) -> Result<bool, Error> {
+        match fs::copy(&self.path, output) {
+            Ok(_) => {
+                log::info!("Copied cached output from {}", self.path.display());
+                Ok(true)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Saves a fresh `output` in the cache. The copy is renamed into place
+    /// so a concurrent run never sees a partial entry.
+    pub fn store(&self, output: &str) -> Result<(), Error> {
+        let dir = self
+            .path
+            .parent()
+            .expect("entries are inside the cache directory");
+        fs::create_dir_all(dir)?;
+        let temp = tempfile::NamedTempFile::new_in(dir)?;
+        fs::copy(output, temp.path())?;
+        temp.persist(&self.path).map_err(|e| e.error)?;
+        log::info!("Cached output as {}", self.path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_covers_options_and_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().to_str().unwrap();
+        let input = dir.path().join("input.csv");
+        let input = input.to_str().unwrap();
+        let output = dir.path().join("output.json");
+        let output = output.to_str().unwrap();
+
+        fs::write(input, "a\n1\n").unwrap();
+        let entry = Entry::new(cache_dir, b"{}", &[input]).unwrap();
+        assert_eq!(
+            entry.path,
+            Entry::new(cache_dir, b"{}", &[input]).unwrap().path
+        );
+        assert_ne!(
+            entry.path,
+            Entry::new(cache_dir, b"{\"pretty\":true}", &[input])
+                .unwrap()
+                .path
+        );
+        assert!(!entry.restore(output).unwrap());
+
+        fs::write(output, "[{\"a\":1}]").unwrap();
+        entry.store(output).unwrap();
+        fs::remove_file(output).unwrap();
+        assert!(entry.restore(output).unwrap());
+        assert_eq!(fs::read_to_string(output).unwrap(), "[{\"a\":1}]");
+
+        fs::write(input, "a\n2\n").unwrap();
+        let changed = Entry::new(cache_dir, b"{}", &[input]).unwrap();
+        assert!(!changed.restore(output).unwrap());
+    }
+}
@@ -0,0 +1,140 @@
+//! `--format cbor`: records encoded as CBOR (RFC 8949) maps, with the same
+//! keys and value types as the JSON output.
+
+use crate::Record;
+use serde_json::Value;
+
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+
+/// Starts an array whose length is not known up front.
+pub const ARRAY_START: u8 = 0x9f;
+/// Ends an array started with `ARRAY_START`.
+pub const BREAK: u8 = 0xff;
+
+/// Appends a record as a map of its fields in order.
+pub fn encode_record(record: &Record, out: &mut Vec<u8>) {
+    header(MAP, record.len() as u64, out);
+    for (key, value) in record {
+        encode_text(key, out);
+        encode(value, out);
+    }
+}
+
+/// Appends a JSON value. Integers take the smallest encoding that holds
+/// them, and other numbers are 64-bit floats.
+pub fn encode(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xf6),
+        Value::Bool(false) => out.push(0xf4),
+        Value::Bool(true) => out.push(0xf5),
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                header(UNSIGNED, n, out);
+            } else if let Some(n) = n.as_i64() {
+                // -1 - n, which never overflows for a negative n
+                header(NEGATIVE, !n as u64, out);
+            } else {
+                out.push(0xfb);
+                out.extend_from_slice(&n.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        }
+        Value::String(text) => encode_text(text, out),
+        Value::Array(elements) => {
+            header(ARRAY, elements.len() as u64, out);
+            for element in elements {
+                encode(element, out);
+            }
+        }
+        Value::Object(fields) => {
+            header(MAP, fields.len() as u64, out);
+            for (key, value) in fields {
+                encode_text(key, out);
+                encode(value, out);
+            }
+        }
+    }
+}
+
+fn encode_text(text: &str, out: &mut Vec<u8>) {
+    header(TEXT, text.len() as u64, out);
+    out.extend_from_slice(text.as_bytes());
+}
+
+/// Writes the initial byte of a data item and its argument (a value or a
+/// length) in the fewest bytes.
+fn header(major: u8, n: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    if n < 24 {
+        out.push(major | n as u8);
+    } else if let Ok(n) = u8::try_from(n) {
+        out.extend_from_slice(&[major | 24, n]);
+    } else if let Ok(n) = u16::try_from(n) {
+        out.push(major | 25);
+        out.extend_from_slice(&n.to_be_bytes());
+    } else if let Ok(n) = u32::try_from(n) {
+        out.push(major | 26);
+        out.extend_from_slice(&n.to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn encoded(value: Value) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode(&value, &mut out);
+        out
+    }
+
+    // Expected bytes from the examples in RFC 8949, Appendix A
+    #[test]
+    fn test_scalars() {
+        assert_eq!(encoded(json!(null)), [0xf6]);
+        assert_eq!(encoded(json!(false)), [0xf4]);
+        assert_eq!(encoded(json!(10)), [0x0a]);
+        assert_eq!(encoded(json!(25)), [0x18, 0x19]);
+        assert_eq!(encoded(json!(1000)), [0x19, 0x03, 0xe8]);
+        assert_eq!(encoded(json!(1000000)), [0x1a, 0x00, 0x0f, 0x42, 0x40]);
+        assert_eq!(
+            encoded(json!(u64::MAX)),
+            [0x1b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(encoded(json!(-1)), [0x20]);
+        assert_eq!(encoded(json!(-100)), [0x38, 0x63]);
+        assert_eq!(
+            encoded(json!(i64::MIN)),
+            [0x3b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            encoded(json!(1.1)),
+            [0xfb, 0x3f, 0xf1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a]
+        );
+        assert_eq!(encoded(json!("IETF")), [0x64, b'I', b'E', b'T', b'F']);
+        assert_eq!(encoded(json!("ü")), [0x62, 0xc3, 0xbc]);
+        assert_eq!(encoded(json!([1, [2, 3]])), [0x82, 0x01, 0x82, 0x02, 0x03]);
+    }
+
+    #[test]
+    fn test_record() {
+        let record = Record::from([
+            (Arc::from("a"), json!(1)),
+            (Arc::from("b"), json!({"c": null})),
+        ]);
+        let mut out = Vec::new();
+        encode_record(&record, &mut out);
+        assert_eq!(
+            out,
+            [0xa2, 0x61, b'a', 0x01, 0x61, b'b', 0xa1, 0x61, b'c', 0xf6]
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! Column metadata describing converted records, for `--with-columns-meta`.
+
+use crate::error::Error;
+use crate::Record;
+use indexmap::IndexMap;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The name, inferred type, null count and an example value of one column,
+/// with its description and unit from a `--dictionary` if it lists them.
+#[derive(Debug, Serialize)]
+pub struct ColumnMeta {
+    pub name: Arc<str>,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub null_count: usize,
+    pub example: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// Share of sampled values of the inferred type, with `--infer-threshold`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
+}
+
+/// Blank fields and missing keys count as nulls.
+pub fn is_null(value: &Value) -> bool {
+    value.is_null() || value == ""
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Combines the type seen so far for a column with that of another non-null
+/// value. A column whose values have several types is `mixed`, except that
+/// integers and other numbers together are `number`.
+pub fn merge_type(current: &'static str, value: &Value) -> &'static str {
+    match (current, type_name(value)) {
+        ("null", kind) => kind,
+        (current, kind) if current == kind => current,
+        ("integer" | "number", "integer" | "number") => "number",
+        _ => "mixed",
+    }
+}
+
+/// Describes every column in the order it first appears.
+pub fn describe(records: &[Record]) -> Vec<ColumnMeta> {
+    let mut columns: IndexMap<Arc<str>, ColumnMeta> = IndexMap::new();
+    for record in records {
+        for (key, value) in record {
+            let column = columns
+                .entry(Arc::clone(key))
+                .or_insert_with(|| ColumnMeta {
+                    name: Arc::clone(key),
+                    kind: "null",
+                    null_count: 0,
+                    example: Value::Null,
+                    description: None,
+                    unit: None,
+                    confidence: None,
+                });
+            if is_null(value) {
+                column.null_count += 1;
+                continue;
+            }
+            column.kind = merge_type(column.kind, value);
+            if column.example.is_null() {
+                column.example = value.clone();
+            }
+        }
+    }
+
+    // Records that lack a column hold no value for it
+    for column in columns.values_mut() {
+        let present = records
+            .iter()
+            .filter(|record| record.contains_key(&column.name))
+            .count();
+        column.null_count += records.len() - present;
+    }
+    columns.into_values().collect()
+}
+
+/// Column documentation from a `--dictionary` CSV file, which has a `column`
+/// header naming output columns and optional `description` and `unit` headers.
+#[derive(Debug, Default)]
+pub struct Dictionary {
+    entries: HashMap<String, (Option<String>, Option<String>)>,
+}
+
+impl Dictionary {
+    pub fn load(path: &str) -> Result<Dictionary, Error> {
+        let invalid = |e: csv::Error| match e.kind() {
+            csv::ErrorKind::Io(_) => Error::from(e),
+            _ => Error::Config(format!("Invalid dictionary {}: {}", path, e)),
+        };
+        let mut reader = csv::Reader::from_path(path).map_err(invalid)?;
+        let headers = reader.headers().map_err(invalid)?.clone();
+        let position = |name: &str| headers.iter().position(|header| header.trim() == name);
+        let column = position("column")
+            .ok_or_else(|| Error::Config(format!("Dictionary {} has no column header", path)))?;
+        let (description, unit) = (position("description"), position("unit"));
+
+        let mut entries = HashMap::new();
+        for row in reader.records() {
+            let row = row.map_err(invalid)?;
+            // Blank cells leave the field out of the column's metadata
+            let cell = |index: Option<usize>| {
+                index
+                    .and_then(|index| row.get(index))
+                    .filter(|value| !value.is_empty())
+                    .map(str::to_string)
+            };
+            if let Some(name) = cell(Some(column)) {
+                entries.insert(name, (cell(description), cell(unit)));
+            }
+        }
+        Ok(Dictionary { entries })
+    }
+
+    /// Adds the documented description and unit to each listed column.
+    pub fn annotate(&self, columns: &mut [ColumnMeta]) {
+        for column in columns {
+            if let Some((description, unit)) = self.entries.get(&*column.name) {
+                column.description = description.clone();
+                column.unit = unit.clone();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        let (id, score, note): (Arc<str>, Arc<str>, Arc<str>) =
+            (Arc::from("id"), Arc::from("score"), Arc::from("note"));
+        let records = vec![
+            Record::from([
+                (Arc::clone(&id), Value::from(1)),
+                (Arc::clone(&score), Value::from("")),
+                (Arc::clone(&note), Value::from("late")),
+            ]),
+            Record::from([
+                (Arc::clone(&id), Value::from(2)),
+                (Arc::clone(&score), Value::from(2.5)),
+                (Arc::clone(&note), Value::from(true)),
+            ]),
+            Record::from([(id, Value::from(3)), (score, Value::from(7))]),
+        ];
+
+        let columns = describe(&records);
+        let summary: Vec<_> = columns
+            .iter()
+            .map(|c| (&*c.name, c.kind, c.null_count, c.example.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("id", "integer", 0, Value::from(1)),
+                ("score", "number", 1, Value::from(2.5)),
+                ("note", "mixed", 1, Value::from("late")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            file.path(),
+            "column,description,unit\nscore,Exam score,points\nid,Student number,\n",
+        )
+        .unwrap();
+        let dictionary = Dictionary::load(&file.path().to_string_lossy()).unwrap();
+
+        let records = vec![Record::from([
+            (Arc::from("id"), Value::from(1)),
+            (Arc::from("score"), Value::from(2.5)),
+            (Arc::from("note"), Value::from("late")),
+        ])];
+        let mut columns = describe(&records);
+        dictionary.annotate(&mut columns);
+        assert_eq!(
+            serde_json::to_value(&columns).unwrap(),
+            serde_json::json!([
+                {"name": "id", "type": "integer", "null_count": 0, "example": 1,
+                 "description": "Student number"},
+                {"name": "score", "type": "number", "null_count": 0, "example": 2.5,
+                 "description": "Exam score", "unit": "points"},
+                {"name": "note", "type": "string", "null_count": 0, "example": "late"},
+            ])
+        );
+
+        std::fs::write(file.path(), "name,description\nid,Student number\n").unwrap();
+        assert!(matches!(
+            Dictionary::load(&file.path().to_string_lossy()),
+            Err(Error::Config(_))
+        ));
+    }
+}
@@ -0,0 +1,433 @@
+//! Compressed input and output. Gzip is handled in process; zstd, bzip2 and
+//! xz are piped through the system's `zstd`, `bzip2` or `xz` command.
+
+use crate::error::Error;
+use crate::gzip::{GzDecoder, GzEncoder};
+use crate::log;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+/// A compression format of the input or output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+pub const FORMATS: [Compression; 4] = [
+    Compression::Gzip,
+    Compression::Zstd,
+    Compression::Bzip2,
+    Compression::Xz,
+];
+
+impl Compression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Bzip2 => "bzip2",
+            Compression::Xz => "xz",
+        }
+    }
+
+    /// The command run for this format, or `None` for gzip, which is built
+    /// in.
+    pub fn command(&self) -> Option<&'static str> {
+        match self {
+            Compression::Gzip => None,
+            _ => Some(self.name()),
+        }
+    }
+
+    fn magic(&self) -> &'static [u8] {
+        match self {
+            Compression::Gzip => &[0x1f, 0x8b],
+            Compression::Zstd => &[0x28, 0xb5, 0x2f, 0xfd],
+            Compression::Bzip2 => b"BZh",
+            Compression::Xz => &[0xfd, b'7', b'z', b'X', b'Z', 0x00],
+        }
+    }
+
+    /// The file extension written for this format, e.g. `.gz`.
+    pub fn extension(&self) -> &'static str {
+        self.extensions()[0]
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            Compression::Gzip => &[".gz", ".tgz"],
+            Compression::Zstd => &[".zst", ".zstd"],
+            Compression::Bzip2 => &[".bz2"],
+            Compression::Xz => &[".xz"],
+        }
+    }
+
+    /// The format whose magic number starts `start`, if any.
+    pub fn detect(start: &[u8]) -> Option<Compression> {
+        FORMATS
+            .into_iter()
+            .find(|format| start.starts_with(format.magic()))
+    }
+
+    /// The format a file name's extension names, if any.
+    pub fn from_path(path: &str) -> Option<Compression> {
+        let path = path.to_ascii_lowercase();
+        FORMATS.into_iter().find(|format| {
+            format
+                .extensions()
+                .iter()
+                .any(|extension| path.ends_with(extension))
+        })
+    }
+}
+
+/// Whether `command` is an executable file in a `PATH` directory.
+pub fn command_exists(command: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+    std::env::split_paths(&path).any(|dir| {
+        let file = dir.join(command);
+        file.is_file() || (cfg!(windows) && file.with_extension("exe").is_file())
+    })
+}
+
+/// Decompresses `input` if it starts with a known magic number. Input from
+/// `path` whose extension names a compression format must be in it, so a
+/// truncated or mislabeled file fails instead of being read as CSV.
+pub fn decompress<R>(mut input: R, path: Option<&str>) -> Result<Box<dyn Read>, Error>
+where
+    R: BufRead + Send + 'static,
+{
+    let start = input.fill_buf()?;
+    if start.starts_with(b"PK\x03\x04") {
+        return Err(Error::Config(
+            "ZIP archives must be given as an --input file".to_string(),
+        ));
+    }
+    let format = match Compression::detect(start) {
+        Some(format) => format,
+        None => {
+            if let Some(expected) = path.and_then(Compression::from_path) {
+                if !start.is_empty() {
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{} is not in {} format",
+                            path.unwrap_or_default(),
+                            expected.name()
+                        ),
+                    )));
+                }
+            }
+            return Ok(Box::new(input));
+        }
+    };
+    log::info!("Input is {} compressed", format.name());
+    match format {
+        Compression::Gzip => Ok(Box::new(GzDecoder::new(input))),
+        _ => Ok(Box::new(ExternalDecoder::spawn(format, input)?)),
+    }
+}
+
+/// Decompresses a whole buffer in `format`, for data compressed piece by
+/// piece inside another file format.
+pub fn decompress_bytes(format: Compression, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    match format {
+        Compression::Gzip => GzDecoder::new(data).read_to_end(&mut output)?,
+        _ => ExternalDecoder::spawn(format, io::Cursor::new(data.to_vec()))?
+            .read_to_end(&mut output)?,
+    };
+    Ok(output)
+}
+
+/// An output that must be told when it is complete, so a compressor can
+/// write its trailer and a compression command can be waited for.
+pub trait Sink: Write {
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+impl<W: Write> Sink for BufWriter<W> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl Sink for GzEncoder<Box<dyn Sink>> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        GzEncoder::finish(*self)?.finish()
+    }
+}
+
+/// Opens an output that compresses what is written to it into `file`, or
+/// into stdout if there is no file.
+pub fn compress(
+    format: Compression,
+    file: Option<File>,
+    capacity: usize,
+) -> Result<Box<dyn Sink>, Error> {
+    log::info!("Compressing output with {}", format.name());
+    if format == Compression::Gzip {
+        let inner: Box<dyn Sink> = match file {
+            Some(file) => Box::new(BufWriter::with_capacity(capacity, file)),
+            None => Box::new(BufWriter::with_capacity(capacity, io::stdout().lock())),
+        };
+        return Ok(Box::new(GzEncoder::new(inner)));
+    }
+    let stdout = match file {
+        Some(file) => Stdio::from(file),
+        None => Stdio::inherit(),
+    };
+    let mut child = spawn(format, Command::new(format.name()).arg("-c").stdout(stdout))?;
+    let stdin = child.stdin.take().expect("stdin is piped");
+    Ok(Box::new(ExternalEncoder {
+        format,
+        child,
+        stdin: Some(BufWriter::with_capacity(capacity, stdin)),
+    }))
+}
+
+/// Starts a compression command with piped stdin and stderr.
+fn spawn(format: Compression, command: &mut Command) -> Result<Child, Error> {
+    let tool = format.name();
+    command
+        .arg("-q")
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::Config(format!(
+                "{} compression requires the {} command, which was not found",
+                tool, tool
+            )),
+            _ => Error::Io(e),
+        })
+}
+
+/// Waits for a compression command, turning a failure into an error with
+/// what it printed.
+fn wait(format: Compression, child: &mut Child) -> io::Result<()> {
+    let mut stderr = String::new();
+    if let Some(mut pipe) = child.stderr.take() {
+        pipe.read_to_string(&mut stderr)?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} failed ({}): {}", format.name(), status, stderr.trim()),
+        ));
+    }
+    Ok(())
+}
+
+/// Writes output through a compression command.
+struct ExternalEncoder {
+    format: Compression,
+    child: Child,
+    stdin: Option<BufWriter<ChildStdin>>,
+}
+
+impl Write for ExternalEncoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin.as_mut().expect("not finished").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.as_mut().expect("not finished").flush()
+    }
+}
+
+impl Sink for ExternalEncoder {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        // Closing stdin tells the command the output is complete
+        self.stdin.take().expect("not finished").into_inner()?;
+        wait(self.format, &mut self.child)
+    }
+}
+
+impl Drop for ExternalEncoder {
+    fn drop(&mut self) {
+        // Stops the command if the output was abandoned
+        if self.stdin.is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Reads the output of a decompression command while a thread feeds it the
+/// compressed input.
+struct ExternalDecoder {
+    format: Compression,
+    child: Child,
+    stdout: ChildStdout,
+    feeder: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl ExternalDecoder {
+    fn spawn<R: Read + Send + 'static>(format: Compression, mut input: R) -> Result<Self, Error> {
+        let mut child = spawn(
+            format,
+            Command::new(format.name())
+                .arg("-dc")
+                .stdout(Stdio::piped()),
+        )?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let feeder = thread::spawn(move || {
+            match io::copy(&mut input, &mut stdin) {
+                // The command stopped reading; its exit status tells why
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                result => result.map(|_| ()),
+            }
+        });
+        Ok(ExternalDecoder {
+            format,
+            child,
+            stdout,
+            feeder: Some(feeder),
+        })
+    }
+
+    /// Checks that the input was fed in full and the command succeeded.
+    fn finish(&mut self) -> io::Result<()> {
+        let Some(feeder) = self.feeder.take() else {
+            return Ok(());
+        };
+        feeder
+            .join()
+            .map_err(|_| io::Error::other("decompression input thread panicked"))??;
+        let mut stderr = String::new();
+        if let Some(mut pipe) = self.child.stderr.take() {
+            pipe.read_to_string(&mut stderr)?;
+        }
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} failed ({}): {}",
+                    self.format.name(),
+                    status,
+                    stderr.trim()
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Read for ExternalDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.finish()?;
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for ExternalDecoder {
+    fn drop(&mut self) {
+        // Stops a command whose output was not read to the end
+        if self.feeder.is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    /// Compresses data with the format's command, which these tests need.
+    fn compress_with_command(format: Compression, data: &[u8]) -> Vec<u8> {
+        let mut child = Command::new(format.name())
+            .arg("-c")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|e| panic!("the {} command is needed for tests: {}", format.name(), e));
+        child.stdin.take().unwrap().write_all(data).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        output.stdout
+    }
+
+    fn read(data: Vec<u8>, path: Option<&str>) -> Result<Vec<u8>, Error> {
+        let mut output = Vec::new();
+        decompress(Cursor::new(data), path)?.read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            Compression::detect(b"\x1f\x8b\x08"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::detect(b"\x28\xb5\x2f\xfd\x04"),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::detect(b"BZh91AY"), Some(Compression::Bzip2));
+        assert_eq!(
+            Compression::detect(b"\xfd7zXZ\x00\x00"),
+            Some(Compression::Xz)
+        );
+        assert_eq!(Compression::detect(b"id,name\n"), None);
+        assert_eq!(
+            Compression::from_path("data.CSV.ZST"),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::from_path("data.csv"), None);
+        assert_eq!(Compression::Gzip.command(), None);
+        assert_eq!(Compression::Xz.command(), Some("xz"));
+        assert!(command_exists("sh"));
+        assert!(!command_exists("ctj-no-such-command"));
+    }
+
+    #[test]
+    fn test_external_formats() {
+        let csv = b"id,name\n1,John\n2,Jane\n";
+        for format in [Compression::Zstd, Compression::Bzip2, Compression::Xz] {
+            let mut compressed = compress_with_command(format, csv);
+            assert_eq!(read(compressed.clone(), None).unwrap(), csv);
+
+            compressed.truncate(compressed.len() / 2);
+            assert!(matches!(read(compressed, None), Err(Error::Io(_))));
+        }
+    }
+
+    #[test]
+    fn test_compressed_output() {
+        let csv = b"id,name\n1,John\n2,Jane\n";
+        for format in FORMATS {
+            let file = tempfile::NamedTempFile::new().unwrap();
+            let mut output = compress(format, Some(file.reopen().unwrap()), 64).unwrap();
+            output.write_all(csv).unwrap();
+            output.finish().unwrap();
+            let compressed = std::fs::read(file.path()).unwrap();
+            assert_eq!(Compression::detect(&compressed), Some(format));
+            assert_eq!(read(compressed, None).unwrap(), csv);
+        }
+    }
+
+    #[test]
+    fn test_mislabeled_input() {
+        let csv = b"id,name\n1,John\n".to_vec();
+        assert_eq!(read(csv.clone(), Some("data.csv")).unwrap(), csv);
+        assert!(matches!(read(csv, Some("data.csv.bz2")), Err(Error::Io(_))));
+        assert!(read(Vec::new(), Some("empty.csv.xz")).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,187 @@
+//! Currency conversion of numeric columns using rates from a JSON file.
+
+use crate::datetime;
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A `--convert-currency` request: `COLUMN:FROM->TO:RATES_FILE[:DATE_COLUMN]`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CurrencySpec {
+    pub column: String,
+    pub from: String,
+    pub to: String,
+    pub rates_file: String,
+    pub date_column: Option<String>,
+}
+
+pub fn parse_currency_spec(s: &str) -> Result<CurrencySpec, String> {
+    let error = || {
+        format!(
+            "expected COLUMN:FROM->TO:RATES_FILE[:DATE_COLUMN], got '{}'",
+            s
+        )
+    };
+    let parts: Vec<&str> = s.split(':').collect();
+    let (column, pair, rates_file, date_column) = match parts.as_slice() {
+        [column, pair, rates_file] => (column, pair, rates_file, None),
+        [column, pair, rates_file, date_column] => {
+            (column, pair, rates_file, Some(date_column.to_string()))
+        }
+        _ => return Err(error()),
+    };
+    let (from, to) = pair.split_once("->").ok_or_else(error)?;
+    if [column, &from, &to, rates_file]
+        .iter()
+        .any(|s| s.is_empty())
+    {
+        return Err(error());
+    }
+    Ok(CurrencySpec {
+        column: column.to_string(),
+        from: from.to_uppercase(),
+        to: to.to_uppercase(),
+        rates_file: rates_file.to_string(),
+        date_column,
+    })
+}
+
+/// Conversion rates for one currency pair, either fixed or per calendar day.
+///
+/// The rates file maps pairs to rates, e.g. `{"EUR->USD": 1.08}`, or dates to
+/// such maps, e.g. `{"2024-01-02": {"EUR->USD": 1.09}}` for use with a date
+/// column. If only the inverse pair is listed, its reciprocal is used.
+#[derive(Debug)]
+pub enum Rates {
+    Fixed(f64),
+    Daily(HashMap<String, f64>),
+}
+
+impl Rates {
+    pub fn load(spec: &CurrencySpec) -> Result<Rates, Error> {
+        let content = std::fs::read_to_string(&spec.rates_file)?;
+        let table: Value = serde_json::from_str(&content)
+            .map_err(|e| Error::Config(format!("Invalid rates file {}: {}", spec.rates_file, e)))?;
+        let table = table.as_object().ok_or_else(|| {
+            Error::Config(format!(
+                "Rates file {} must contain a JSON object",
+                spec.rates_file
+            ))
+        })?;
+
+        match &spec.date_column {
+            None => pair_rate(table, spec).map(Rates::Fixed).ok_or_else(|| {
+                Error::Config(format!(
+                    "No {}->{} rate in {}",
+                    spec.from, spec.to, spec.rates_file
+                ))
+            }),
+            Some(_) => {
+                let mut daily = HashMap::new();
+                for (date, rates) in table {
+                    let rates = rates.as_object().ok_or_else(|| {
+                        Error::Config(format!(
+                            "Rates for {} in {} must be a JSON object",
+                            date, spec.rates_file
+                        ))
+                    })?;
+                    if let Some(rate) = pair_rate(rates, spec) {
+                        daily.insert(date.clone(), rate);
+                    }
+                }
+                Ok(Rates::Daily(daily))
+            }
+        }
+    }
+
+    /// The rate to apply for a row, given the raw value of its date column.
+    pub fn rate(&self, spec: &CurrencySpec, date: Option<&str>) -> Result<f64, Error> {
+        match self {
+            Rates::Fixed(rate) => Ok(*rate),
+            Rates::Daily(daily) => {
+                let day = date
+                    .and_then(datetime::parse_timestamp)
+                    .map(|millis| datetime::DatePart::Date.extract(millis, 0));
+                let day = day.as_ref().and_then(Value::as_str).ok_or_else(|| {
+                    Error::Validation(format!(
+                        "Invalid date for currency conversion: {}",
+                        date.unwrap_or("")
+                    ))
+                })?;
+                daily.get(day).copied().ok_or_else(|| {
+                    Error::Validation(format!("No {}->{} rate for {}", spec.from, spec.to, day))
+                })
+            }
+        }
+    }
+}
+
+fn pair_rate(rates: &serde_json::Map<String, Value>, spec: &CurrencySpec) -> Option<f64> {
+    let lookup = |from: &str, to: &str| {
+        rates
+            .iter()
+            .find(|(pair, _)| pair.eq_ignore_ascii_case(&format!("{}->{}", from, to)))
+            .and_then(|(_, rate)| rate.as_f64())
+    };
+    lookup(&spec.from, &spec.to)
+        .or_else(|| lookup(&spec.to, &spec.from).map(|inverse| 1.0 / inverse))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn spec_with(rates: &str, date_column: Option<&str>) -> (CurrencySpec, NamedTempFile) {
+        let file = NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), rates).unwrap();
+        let spec = CurrencySpec {
+            column: "amount".to_string(),
+            from: "EUR".to_string(),
+            to: "USD".to_string(),
+            rates_file: file.path().to_string_lossy().to_string(),
+            date_column: date_column.map(str::to_string),
+        };
+        (spec, file)
+    }
+
+    #[test]
+    fn test_parse_currency_spec() {
+        let spec = parse_currency_spec("amount:eur->usd:rates.json:booked_on").unwrap();
+        assert_eq!(spec.column, "amount");
+        assert_eq!(spec.from, "EUR");
+        assert_eq!(spec.to, "USD");
+        assert_eq!(spec.rates_file, "rates.json");
+        assert_eq!(spec.date_column.as_deref(), Some("booked_on"));
+        assert!(parse_currency_spec("amount:EUR:rates.json").is_err());
+        assert!(parse_currency_spec("amount:EUR->USD").is_err());
+    }
+
+    #[test]
+    fn test_fixed_and_inverse_rates() {
+        let (spec, _file) = spec_with(r#"{"EUR->USD": 1.25}"#, None);
+        assert_eq!(Rates::load(&spec).unwrap().rate(&spec, None).unwrap(), 1.25);
+
+        let (spec, _file) = spec_with(r#"{"USD->EUR": 0.8}"#, None);
+        assert_eq!(Rates::load(&spec).unwrap().rate(&spec, None).unwrap(), 1.25);
+
+        let (spec, _file) = spec_with(r#"{"GBP->USD": 1.3}"#, None);
+        assert!(Rates::load(&spec).is_err());
+    }
+
+    #[test]
+    fn test_daily_rates() {
+        let (spec, _file) = spec_with(
+            r#"{"2024-01-02": {"EUR->USD": 1.1}, "2024-01-03": {"EUR->USD": 1.2}}"#,
+            Some("day"),
+        );
+        let rates = Rates::load(&spec).unwrap();
+        assert_eq!(
+            rates.rate(&spec, Some("2024-01-03T10:00:00Z")).unwrap(),
+            1.2
+        );
+        assert!(rates.rate(&spec, Some("2024-01-04")).is_err());
+        assert!(rates.rate(&spec, Some("soon")).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+//! Parsing of ISO 8601 style dates and timestamps without external crates.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+/// A calendar component that can be derived from a timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DatePart {
+    Year,
+    Quarter,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    /// ISO 8601 day of the week, 1 (Monday) to 7 (Sunday)
+    Dow,
+    /// Day of the year, starting at 1
+    Doy,
+    /// The calendar date as `YYYY-MM-DD`
+    Date,
+}
+
+impl DatePart {
+    pub fn parse(s: &str) -> Option<DatePart> {
+        Some(match s {
+            "year" => DatePart::Year,
+            "quarter" => DatePart::Quarter,
+            "month" => DatePart::Month,
+            "day" => DatePart::Day,
+            "hour" => DatePart::Hour,
+            "minute" => DatePart::Minute,
+            "dow" => DatePart::Dow,
+            "doy" => DatePart::Doy,
+            "date" => DatePart::Date,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DatePart::Year => "year",
+            DatePart::Quarter => "quarter",
+            DatePart::Month => "month",
+            DatePart::Day => "day",
+            DatePart::Hour => "hour",
+            DatePart::Minute => "minute",
+            DatePart::Dow => "dow",
+            DatePart::Doy => "doy",
+            DatePart::Date => "date",
+        }
+    }
+
+    /// Extracts this part from an epoch timestamp as seen at a UTC offset.
+    pub fn extract(&self, millis: i64, offset_seconds: i64) -> Value {
+        let local = millis + offset_seconds * 1000;
+        let days = local.div_euclid(MILLIS_PER_DAY);
+        let millis_of_day = local.rem_euclid(MILLIS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        match self {
+            DatePart::Year => Value::from(year),
+            DatePart::Quarter => Value::from((month - 1) / 3 + 1),
+            DatePart::Month => Value::from(month),
+            DatePart::Day => Value::from(day),
+            DatePart::Hour => Value::from(millis_of_day / 3_600_000),
+            DatePart::Minute => Value::from(millis_of_day / 60_000 % 60),
+            // 1970-01-01 was a Thursday
+            DatePart::Dow => Value::from((days + 3).rem_euclid(7) + 1),
+            DatePart::Doy => Value::from(days - days_from_civil(year, 1, 1) + 1),
+            DatePart::Date => Value::from(format!("{:04}-{:02}-{:02}", year, month, day)),
+        }
+    }
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The `(year, month, day)` for a number of days since 1970-01-01.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn number(s: &str, len: usize) -> Option<u32> {
+    if s.len() == len && s.bytes().all(|b| b.is_ascii_digit()) {
+        s.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Parses a UTC offset like `Z`, `+09:00`, `-0500` or `+09` into seconds.
+pub fn parse_offset(s: &str) -> Option<i64> {
+    if s == "Z" || s == "z" {
+        return Some(0);
+    }
+    let sign = match s.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let digits: String = s[1..].chars().filter(|&c| c != ':').collect();
+    let (hours, minutes) = match digits.len() {
+        2 => (number(&digits, 2)?, 0),
+        4 => (number(&digits[..2], 2)?, number(&digits[2..], 2)?),
+        _ => return None,
+    };
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours as i64 * 3600 + minutes as i64 * 60))
+}
+
+/// Parses `YYYY-MM-DD` (or `YYYY/MM/DD`), optionally followed by `T` or a
+/// space and `HH:MM[:SS[.fraction]]` with an optional UTC offset. Values
+/// without an offset are treated as UTC. Returns milliseconds since the epoch.
+pub fn parse_timestamp(s: &str) -> Option<i64> {
+    parse_date_or_time(s).map(|(millis, _)| millis)
+}
+
+/// Parses like `parse_timestamp`, also telling whether the value was a date
+/// alone, with no time of day, which is the same calendar day at every UTC
+/// offset.
+pub fn parse_date_or_time(s: &str) -> Option<(i64, bool)> {
+    let s = s.trim();
+    if s.len() < 10 || !s.is_char_boundary(10) {
+        return None;
+    }
+    let (date, rest) = s.split_at(10);
+    let separator = date.as_bytes()[4];
+    if !(separator == b'-' || separator == b'/') || date.as_bytes()[7] != separator {
+        return None;
+    }
+    let year = number(&date[..4], 4)? as i64;
+    let month = number(&date[5..7], 2)?;
+    let day = number(&date[8..10], 2)?;
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    let mut millis = days_from_civil(year, month, day) * MILLIS_PER_DAY;
+
+    if rest.is_empty() {
+        return Some((millis, true));
+    }
+    let time = rest.strip_prefix('T').or_else(|| rest.strip_prefix(' '))?;
+    let offset_start = time.find(['Z', 'z', '+', '-']).unwrap_or(time.len());
+    let (clock, offset) = time.split_at(offset_start);
+    let offset = if offset.is_empty() {
+        0
+    } else {
+        parse_offset(offset)?
+    };
+
+    let (clock, fraction) = match clock.split_once('.') {
+        Some((clock, fraction)) if !fraction.is_empty() => (clock, Some(fraction)),
+        Some(_) => return None,
+        None => (clock, None),
+    };
+    let mut parts = clock.split(':');
+    let hours = number(parts.next()?, 2)?;
+    let minutes = number(parts.next()?, 2)?;
+    let seconds = match parts.next() {
+        Some(seconds) => number(seconds, 2)?,
+        None if fraction.is_none() => 0,
+        None => return None,
+    };
+    if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    let fraction_millis = match fraction {
+        Some(fraction) if fraction.bytes().all(|b| b.is_ascii_digit()) => {
+            let padded = format!("{:0<3}", &fraction[..fraction.len().min(3)]);
+            padded.parse::<i64>().ok()?
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+
+    millis += (hours as i64 * 3600 + minutes as i64 * 60 + seconds as i64 - offset) * 1000;
+    Some((millis + fraction_millis, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_round_trip() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        for days in [-719468, -1, 0, 59, 11017, 19723] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn test_extract_date_parts() {
+        // 2024-03-31T23:30:00Z is already Monday 2024-04-01 in Tokyo
+        let millis = parse_timestamp("2024-03-31T23:30:00Z").unwrap();
+        let tokyo = 9 * 3600;
+        assert_eq!(DatePart::Year.extract(millis, 0), 2024);
+        assert_eq!(DatePart::Quarter.extract(millis, 0), 1);
+        assert_eq!(DatePart::Quarter.extract(millis, tokyo), 2);
+        assert_eq!(DatePart::Month.extract(millis, tokyo), 4);
+        assert_eq!(DatePart::Day.extract(millis, tokyo), 1);
+        assert_eq!(DatePart::Hour.extract(millis, tokyo), 8);
+        assert_eq!(DatePart::Minute.extract(millis, tokyo), 30);
+        assert_eq!(DatePart::Dow.extract(millis, 0), 7);
+        assert_eq!(DatePart::Dow.extract(millis, tokyo), 1);
+        assert_eq!(DatePart::Doy.extract(millis, 0), 91);
+        assert_eq!(DatePart::Date.extract(millis, tokyo), "2024-04-01");
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01"), Some(0));
+        assert_eq!(parse_date_or_time(" 1970-01-01"), Some((0, true)));
+        assert_eq!(parse_date_or_time("1970-01-01T00:00"), Some((0, false)));
+        // Ten characters that are not a date are not taken for one
+        assert_eq!(parse_date_or_time("1700000000"), None);
+        assert_eq!(parse_date_or_time("2024/1/1 1"), None);
+        assert_eq!(parse_timestamp("1970-01-02"), Some(86_400_000));
+        assert_eq!(parse_timestamp("1970/01/01 00:01"), Some(60_000));
+        assert_eq!(parse_timestamp("1970-01-01T00:00:01.5Z"), Some(1_500));
+        assert_eq!(parse_timestamp("1970-01-01T09:00:00+09:00"), Some(0));
+        assert_eq!(parse_timestamp("1969-12-31T19:00:00-0500"), Some(0));
+        assert_eq!(parse_timestamp("2024-02-29"), Some(1_709_164_800_000));
+        assert_eq!(parse_timestamp("2023-02-29"), None);
+        assert_eq!(parse_timestamp("2024-13-01"), None);
+        assert_eq!(parse_timestamp("2024-01-01T25:00"), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+}
@@ -0,0 +1,396 @@
+//! `--encoding`: input in a legacy character set, transcoded to UTF-8 before
+//! it is parsed, such as the Shift_JIS CSV files Excel saves on Japanese
+//! Windows. Bytes that do not form a character in the encoding become
+//! U+FFFD, as a web browser would show them.
+
+use crate::jis0208;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read};
+
+/// A character encoding of the input.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum Encoding {
+    #[default]
+    #[value(name = "utf-8", alias = "utf8")]
+    Utf8,
+    /// Shift_JIS with the Windows extensions (code page 932)
+    #[value(
+        name = "shift_jis",
+        alias = "shift-jis",
+        alias = "sjis",
+        alias = "cp932",
+        alias = "windows-31j"
+    )]
+    ShiftJis,
+    #[value(name = "euc-jp", alias = "eucjp")]
+    EucJp,
+    /// ISO 8859-1
+    #[value(name = "latin1", alias = "iso-8859-1", alias = "latin-1")]
+    Latin1,
+    #[value(name = "windows-1252", alias = "cp1252")]
+    Windows1252,
+    /// UTF-16 in the byte order of its byte order mark, or little-endian
+    /// without one, as Windows writes it
+    #[value(name = "utf-16", alias = "utf16", alias = "ucs-2")]
+    Utf16,
+    #[value(name = "utf-16le")]
+    Utf16Le,
+    #[value(name = "utf-16be")]
+    Utf16Be,
+}
+
+/// The characters of windows-1252's bytes 0x80 to 0x9F, where it differs
+/// from Latin-1. The five unassigned bytes keep their Latin-1 meaning.
+const WINDOWS_1252: [u16; 32] = [
+    0x20AC, 0x0081, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021, 0x02C6, 0x2030, 0x0160, 0x2039,
+    0x0152, 0x008D, 0x017D, 0x008F, 0x0090, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014,
+    0x02DC, 0x2122, 0x0161, 0x203A, 0x0153, 0x009D, 0x017E, 0x0178,
+];
+
+const CHUNK_SIZE: usize = 8192;
+
+/// Recognizes UTF-16 text from its first bytes, by its byte order mark or,
+/// without one, by the zero bytes of the ASCII characters a header row is
+/// mostly made of, which never occur in UTF-8 text.
+pub fn detect(start: &[u8]) -> Option<Encoding> {
+    if start.starts_with(b"\xff\xfe") {
+        return Some(Encoding::Utf16Le);
+    }
+    if start.starts_with(b"\xfe\xff") {
+        return Some(Encoding::Utf16Be);
+    }
+    let units = &start[..start.len().min(1024) & !1];
+    let zeros = |offset: usize| {
+        units
+            .iter()
+            .skip(offset)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    let (even, odd) = (zeros(0), zeros(1));
+    let half = units.len() / 2;
+    if half >= 2 && odd * 4 >= half && even * 4 < odd {
+        Some(Encoding::Utf16Le)
+    } else if half >= 2 && even * 4 >= half && odd * 4 < even {
+        Some(Encoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+/// Reads text in an encoding as UTF-8.
+pub struct Decoder<R> {
+    input: R,
+    encoding: Encoding,
+    raw: Vec<u8>,
+    decoded: String,
+    at: usize,
+    done: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(input: R, encoding: Encoding) -> Self {
+        Decoder {
+            input,
+            encoding,
+            raw: Vec::with_capacity(CHUNK_SIZE),
+            decoded: String::new(),
+            at: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.at == self.decoded.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.decoded.clear();
+            self.at = 0;
+            let start = self.raw.len();
+            self.raw.resize(start + CHUNK_SIZE, 0);
+            let n = self.input.read(&mut self.raw[start..])?;
+            self.raw.truncate(start + n);
+            self.done = n == 0;
+            if self.encoding == Encoding::Utf16 && (self.raw.len() >= 2 || self.done) {
+                self.encoding = if self.raw.starts_with(b"\xfe\xff") {
+                    Encoding::Utf16Be
+                } else {
+                    Encoding::Utf16Le
+                };
+            }
+            // A character cut off at the end of the chunk waits for the next
+            let used = decode(self.encoding, &self.raw, self.done, &mut self.decoded);
+            self.raw.drain(..used);
+        }
+        let pending = &self.decoded.as_bytes()[self.at..];
+        let n = buf.len().min(pending.len());
+        buf[..n].copy_from_slice(&pending[..n]);
+        self.at += n;
+        Ok(n)
+    }
+}
+
+/// Decodes the characters of `input` onto `out`, returning how many bytes
+/// were used. Unless `last`, bytes that may start an unfinished character
+/// are left for the next call.
+fn decode(encoding: Encoding, input: &[u8], last: bool, out: &mut String) -> usize {
+    match encoding {
+        Encoding::Utf8 => {
+            out.push_str(&String::from_utf8_lossy(input));
+            input.len()
+        }
+        Encoding::Latin1 => {
+            out.extend(input.iter().map(|&byte| char::from(byte)));
+            input.len()
+        }
+        Encoding::Windows1252 => {
+            out.extend(input.iter().map(|&byte| match byte {
+                0x80..=0x9F => code_point(WINDOWS_1252[byte as usize - 0x80]),
+                _ => char::from(byte),
+            }));
+            input.len()
+        }
+        Encoding::Utf16 if !last && input.len() < 2 => 0,
+        Encoding::Utf16 | Encoding::Utf16Le => decode_utf16(input, last, out, u16::from_le_bytes),
+        Encoding::Utf16Be => decode_utf16(input, last, out, u16::from_be_bytes),
+        Encoding::ShiftJis => decode_shift_jis(input, last, out),
+        Encoding::EucJp => decode_euc_jp(input, last, out),
+    }
+}
+
+fn code_point(value: u16) -> char {
+    char::from_u32(value as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// The character a JIS X 0208 pointer stands for, if one does.
+fn jis0208(pointer: usize) -> Option<char> {
+    match jis0208::INDEX.get(pointer) {
+        Some(0) | None => None,
+        Some(&value) => Some(code_point(value)),
+    }
+}
+
+fn decode_utf16(input: &[u8], last: bool, out: &mut String, unit: fn([u8; 2]) -> u16) -> usize {
+    let mut at = 0;
+    while at + 2 <= input.len() {
+        let first = unit([input[at], input[at + 1]]);
+        if (0xD800..0xDC00).contains(&first) {
+            if at + 4 > input.len() && !last {
+                break;
+            }
+            let second = input
+                .get(at + 2..at + 4)
+                .map(|pair| unit([pair[0], pair[1]]))
+                .filter(|second| (0xDC00..0xE000).contains(second));
+            if let Some(second) = second {
+                let value = 0x10000 + ((first as u32 - 0xD800) << 10) + (second as u32 - 0xDC00);
+                out.push(char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER));
+                at += 4;
+                continue;
+            }
+        }
+        out.push(code_point(first));
+        at += 2;
+    }
+    if last && at < input.len() {
+        out.push(char::REPLACEMENT_CHARACTER);
+        at = input.len();
+    }
+    at
+}
+
+fn decode_shift_jis(input: &[u8], last: bool, out: &mut String) -> usize {
+    let mut at = 0;
+    while let Some(&lead) = input.get(at) {
+        match lead {
+            0x00..=0x80 => {
+                out.push(char::from(lead));
+                at += 1;
+            }
+            // Half-width katakana
+            0xA1..=0xDF => {
+                out.push(code_point(0xFF61 + (lead - 0xA1) as u16));
+                at += 1;
+            }
+            0x81..=0x9F | 0xE0..=0xFC => {
+                let Some(&trail) = input.get(at + 1) else {
+                    if !last {
+                        break;
+                    }
+                    out.push(char::REPLACEMENT_CHARACTER);
+                    at += 1;
+                    continue;
+                };
+                let row = (lead - if lead < 0xA0 { 0x81 } else { 0xC1 }) as usize;
+                let character = match trail {
+                    0x40..=0x7E => jis0208(row * 188 + trail as usize - 0x40),
+                    0x80..=0xFC => jis0208(row * 188 + trail as usize - 0x41),
+                    _ => None,
+                };
+                match character {
+                    Some(character) => {
+                        out.push(character);
+                        at += 2;
+                    }
+                    None => {
+                        out.push(char::REPLACEMENT_CHARACTER);
+                        // An ASCII byte after a bad lead byte is read again
+                        at += if trail < 0x80 { 1 } else { 2 };
+                    }
+                }
+            }
+            _ => {
+                out.push(char::REPLACEMENT_CHARACTER);
+                at += 1;
+            }
+        }
+    }
+    at
+}
+
+fn decode_euc_jp(input: &[u8], last: bool, out: &mut String) -> usize {
+    let mut at = 0;
+    while let Some(&lead) = input.get(at) {
+        let length = match lead {
+            0x8F => 3,
+            0x8E | 0xA1..=0xFE => 2,
+            _ => 1,
+        };
+        if at + length > input.len() && !last {
+            break;
+        }
+        let trail = input.get(at + 1).copied().unwrap_or(0);
+        let character = match lead {
+            0x00..=0x7F => Some(char::from(lead)),
+            // Half-width katakana
+            0x8E if (0xA1..=0xDF).contains(&trail) => {
+                Some(code_point(0xFF61 + (trail - 0xA1) as u16))
+            }
+            0xA1..=0xFE if (0xA1..=0xFE).contains(&trail) => {
+                jis0208((lead - 0xA1) as usize * 94 + (trail - 0xA1) as usize)
+            }
+            // JIS X 0212 is not read, but its three bytes are skipped
+            0x8F if (0xA1..=0xFE).contains(&trail)
+                && input
+                    .get(at + 2)
+                    .is_some_and(|byte| (0xA1..=0xFE).contains(byte)) =>
+            {
+                out.push(char::REPLACEMENT_CHARACTER);
+                at += 3;
+                continue;
+            }
+            _ => None,
+        };
+        match character {
+            Some(character) => {
+                out.push(character);
+                at += if lead < 0x80 { 1 } else { 2 };
+            }
+            None => {
+                out.push(char::REPLACEMENT_CHARACTER);
+                // A bad lead byte only takes its trail byte when that could
+                // not start a character itself
+                at += if lead >= 0x80 && (0xA1..=0xFE).contains(&trail) {
+                    2
+                } else {
+                    1
+                };
+            }
+        }
+    }
+    at
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(input: &[u8], encoding: Encoding) -> String {
+        let mut output = String::new();
+        Decoder::new(input, encoding)
+            .read_to_string(&mut output)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_shift_jis() {
+        // "名前,ﾃｽﾄ,①\r\n" as Excel saves it
+        let input = b"\x96\xbc\x91\x4f,\xc3\xbd\xc4,\x87\x40\r\n";
+        assert_eq!(decoded(input, Encoding::ShiftJis), "名前,ﾃｽﾄ,①\r\n");
+        // A lead byte without a valid trail byte, then one at the very end
+        assert_eq!(
+            decoded(b"\x81\x20a\x96", Encoding::ShiftJis),
+            "\u{fffd} a\u{fffd}"
+        );
+    }
+
+    #[test]
+    fn test_euc_jp() {
+        assert_eq!(
+            decoded(b"\xcc\xbe\xc1\xb0,\x8e\xc3\x8f\xb0\xa1x", Encoding::EucJp),
+            "名前,ﾃ\u{fffd}x"
+        );
+    }
+
+    #[test]
+    fn test_single_byte() {
+        assert_eq!(decoded(b"caf\xe9 \x80", Encoding::Latin1), "café \u{80}");
+        assert_eq!(
+            decoded(b"caf\xe9 \x80\x81\x93", Encoding::Windows1252),
+            "café €\u{81}“"
+        );
+    }
+
+    #[test]
+    fn test_utf16() {
+        assert_eq!(
+            decoded(
+                b"\xff\xfea\x00\x3d\xd8\x00\xde\x00\xd8b\x00",
+                Encoding::Utf16Le
+            ),
+            "\u{feff}a😀\u{fffd}b"
+        );
+        assert_eq!(decoded(b"\x00a\x00", Encoding::Utf16Be), "a\u{fffd}");
+        assert_eq!(decoded(b"\xfe\xff\x00a", Encoding::Utf16), "\u{feff}a");
+        assert_eq!(decoded(b"a\x00", Encoding::Utf16), "a");
+    }
+
+    #[test]
+    fn test_detect() {
+        let text = |encode: fn(u16) -> [u8; 2]| -> Vec<u8> {
+            "名前\tage\r\n".encode_utf16().flat_map(encode).collect()
+        };
+        assert_eq!(detect(b"\xff\xfen\x00"), Some(Encoding::Utf16Le));
+        assert_eq!(detect(b"\xfe\xff\x00n"), Some(Encoding::Utf16Be));
+        assert_eq!(detect(&text(u16::to_le_bytes)), Some(Encoding::Utf16Le));
+        assert_eq!(detect(&text(u16::to_be_bytes)), Some(Encoding::Utf16Be));
+        assert_eq!(detect("名前,age\n".as_bytes()), None);
+        assert_eq!(detect(b"a"), None);
+    }
+
+    #[test]
+    fn test_split_reads() {
+        /// Gives one byte per read.
+        struct Trickle<'a>(&'a [u8]);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let Some((&byte, rest)) = self.0.split_first() else {
+                    return Ok(0);
+                };
+                buf[0] = byte;
+                self.0 = rest;
+                Ok(1)
+            }
+        }
+        let mut output = String::new();
+        Decoder::new(Trickle(b"\x96\xbc\x91\x4f"), Encoding::ShiftJis)
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(output, "名前");
+    }
+}
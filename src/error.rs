@@ -0,0 +1,146 @@
+//! The error type for conversions, grouped so scripts can tell failures apart
+//! by exit code.
+
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid options or option files, e.g. an unknown column (exit code 2)
+    Config(String),
+    /// Reading input or writing output failed, e.g. a missing file (exit code 3)
+    Io(io::Error),
+    /// The input is malformed, e.g. not well-formed CSV (exit code 4)
+    Csv(String),
+    /// The output could not be serialized as JSON (exit code 5)
+    Json(serde_json::Error),
+    /// The input parsed but failed a check such as `--expect-rows` (exit code 6)
+    Validation(String),
+}
+
+impl Error {
+    /// The process exit code for this kind of error. Exit code 2 is shared
+    /// with the usage errors reported by the argument parser.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 2,
+            Error::Io(_) => 3,
+            Error::Csv(_) => 4,
+            Error::Json(_) => 5,
+            Error::Validation(_) => 6,
+        }
+    }
+
+    /// Names the file the error happened in, keeping the kind of error.
+    pub fn in_file(self, path: &str) -> Self {
+        match self {
+            Error::Config(message) => Error::Config(format!("{}: {}", path, message)),
+            Error::Io(e) => Error::Io(io::Error::new(e.kind(), format!("{}: {}", path, e))),
+            Error::Csv(message) => Error::Csv(format!("{}: {}", path, message)),
+            Error::Json(e) => Error::Json(e),
+            Error::Validation(message) => Error::Validation(format!("{}: {}", path, message)),
+        }
+    }
+
+    /// Replaces the message, keeping the kind of error and so the exit code.
+    /// A JSON error keeps its own message.
+    pub fn with_message(self, message: String) -> Self {
+        match self {
+            Error::Config(_) => Error::Config(message),
+            Error::Io(e) => Error::Io(io::Error::new(e.kind(), message)),
+            Error::Csv(_) => Error::Csv(message),
+            Error::Json(e) => Error::Json(e),
+            Error::Validation(_) => Error::Validation(message),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(message) | Error::Csv(message) | Error::Validation(message) => {
+                f.write_str(message)
+            }
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Json(e) => write!(f, "JSON error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Malformed input found by a check that runs while the input is read, such
+/// as `--strict-rfc4180`. It travels inside an `io::Error` and comes out as
+/// `Error::Csv`.
+#[derive(Debug)]
+pub struct Malformed(pub String);
+
+impl fmt::Display for Malformed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Malformed {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<Malformed>())
+        {
+            Some(malformed) => Error::Csv(malformed.0.clone()),
+            None => Error::Io(e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+/// CSV errors caused by reading or writing files are I/O errors; the rest
+/// mean the input is malformed.
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        let message = e.to_string();
+        match e.into_kind() {
+            csv::ErrorKind::Io(e) => Error::from(e),
+            _ => Error::Csv(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_errors_are_classified() {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader("a,b\nc\n".as_bytes());
+        let e: Error = reader.records().nth(1).unwrap().unwrap_err().into();
+        assert_eq!(e.exit_code(), 4);
+
+        let e: Error = csv::Reader::from_path("/nonexistent/input.csv")
+            .unwrap_err()
+            .into();
+        assert!(matches!(e, Error::Io(ref io) if io.kind() == io::ErrorKind::NotFound));
+        assert_eq!(e.exit_code(), 3);
+
+        let malformed = io::Error::new(io::ErrorKind::InvalidData, Malformed("bad".to_string()));
+        let e: Error = csv::Error::from(malformed).into();
+        assert!(matches!(e, Error::Csv(ref message) if message == "bad"));
+    }
+}
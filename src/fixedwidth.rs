@@ -0,0 +1,213 @@
+//! `--from fixed-width`: columns at fixed character positions, as mainframe
+//! and other legacy systems export them. Each line is cut into fields and
+//! written out as a CSV record, so the rest of the conversion is the same as
+//! for CSV input.
+
+use crate::error::Error;
+use std::io::{self, BufRead, Read};
+
+/// Where the columns of each line are, and their names when a spec file
+/// gives them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    widths: Vec<usize>,
+    names: Option<Vec<String>>,
+}
+
+impl Layout {
+    /// Columns of the given widths, named by the input's header row.
+    pub fn from_widths(widths: &[usize]) -> Self {
+        Layout {
+            widths: widths.to_vec(),
+            names: None,
+        }
+    }
+
+    /// Loads a spec file: a CSV file with `name` and `width` headers and a
+    /// row per column, in order.
+    pub fn load(path: &str) -> Result<Layout, Error> {
+        let invalid = |e: csv::Error| match e.kind() {
+            csv::ErrorKind::Io(_) => Error::from(e),
+            _ => Error::Config(format!("Invalid width spec {}: {}", path, e)),
+        };
+        let mut reader = csv::Reader::from_path(path).map_err(invalid)?;
+        let headers = reader.headers().map_err(invalid)?.clone();
+        let position = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.trim() == name)
+                .ok_or_else(|| Error::Config(format!("Width spec {} has no {} header", path, name)))
+        };
+        let (name, width) = (position("name")?, position("width")?);
+
+        let (mut names, mut widths) = (Vec::new(), Vec::new());
+        for (index, row) in reader.records().enumerate() {
+            let row = row.map_err(invalid)?;
+            let field = |at: usize| row.get(at).unwrap_or("").trim();
+            widths.push(parse_width(field(width)).map_err(|e| {
+                Error::Config(format!("Width spec {} row {}: {}", path, index + 1, e))
+            })?);
+            names.push(field(name).to_string());
+        }
+        if widths.is_empty() {
+            return Err(Error::Config(format!(
+                "Width spec {} lists no columns",
+                path
+            )));
+        }
+        Ok(Layout {
+            widths,
+            names: Some(names),
+        })
+    }
+
+    /// Cuts a line into its fields, trimmed of the spaces that pad them. A
+    /// short line has empty fields at the end, and text past the last
+    /// column is ignored.
+    fn split<'a>(&self, line: &'a str) -> Vec<&'a str> {
+        let mut rest = line;
+        self.widths
+            .iter()
+            .map(|&width| {
+                let end = rest
+                    .char_indices()
+                    .nth(width)
+                    .map_or(rest.len(), |(i, _)| i);
+                let (field, after) = rest.split_at(end);
+                rest = after;
+                field.trim()
+            })
+            .collect()
+    }
+}
+
+fn parse_width(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(width) if width > 0 => Ok(width),
+        _ => Err(format!("expected a positive column width, got '{}'", s)),
+    }
+}
+
+/// Parses `--widths`: column widths in characters, separated by commas.
+pub fn parse_widths(s: &str) -> Result<Vec<usize>, String> {
+    s.split(',')
+        .map(|width| parse_width(width.trim()))
+        .collect()
+}
+
+/// Reads fixed-width lines as CSV text, starting with the layout's names as
+/// a header row when it has them. Blank lines are skipped.
+pub struct ToCsv<R> {
+    input: R,
+    layout: Layout,
+    line: String,
+    pending: Vec<u8>,
+    at: usize,
+}
+
+impl<R: BufRead> ToCsv<R> {
+    pub fn new(input: R, layout: Layout) -> Self {
+        let mut pending = Vec::new();
+        if let Some(names) = &layout.names {
+            write_record(names.iter().map(String::as_str), &mut pending);
+        }
+        ToCsv {
+            input,
+            layout,
+            line: String::new(),
+            pending,
+            at: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Read for ToCsv<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.at == self.pending.len() {
+            self.pending.clear();
+            self.at = 0;
+            self.line.clear();
+            if self.input.read_line(&mut self.line)? == 0 {
+                return Ok(0);
+            }
+            let line = self.line.trim_end_matches(['\n', '\r']);
+            if !line.trim().is_empty() {
+                write_record(self.layout.split(line).into_iter(), &mut self.pending);
+            }
+        }
+        let n = buf.len().min(self.pending.len() - self.at);
+        buf[..n].copy_from_slice(&self.pending[self.at..self.at + n]);
+        self.at += n;
+        Ok(n)
+    }
+}
+
+/// Appends a CSV record, quoting the fields that need it.
+fn write_record<'a>(fields: impl Iterator<Item = &'a str>, out: &mut Vec<u8>) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            out.push(b'"');
+            out.extend_from_slice(field.replace('"', "\"\"").as_bytes());
+            out.push(b'"');
+        } else {
+            out.extend_from_slice(field.as_bytes());
+        }
+    }
+    out.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn to_csv(input: &str, layout: Layout) -> String {
+        let mut output = String::new();
+        ToCsv::new(input.as_bytes(), layout)
+            .read_to_string(&mut output)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_parse_widths() {
+        assert_eq!(parse_widths("10,5, 20"), Ok(vec![10, 5, 20]));
+        assert!(parse_widths("10,0").is_err());
+        assert!(parse_widths("10,,5").is_err());
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let input = "ID   NAME      AMOUNT\r\n\
+                     00001Smith, J   12.50\r\n\
+                     \r\n\
+                     00002Müller \"M\"   7\n\
+                     00003Short\n";
+        assert_eq!(
+            to_csv(input, Layout::from_widths(&[5, 10, 6])),
+            "ID,NAME,AMOUNT\n\
+             00001,\"Smith, J\",12.50\n\
+             00002,\"Müller \"\"M\"\"\",7\n\
+             00003,Short,\n"
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let mut spec = tempfile::NamedTempFile::new().unwrap();
+        write!(spec, "name,width,note\nid,5,key\nname,10,\namount,6,\n").unwrap();
+        let layout = Layout::load(&spec.path().to_string_lossy()).unwrap();
+        assert_eq!(
+            to_csv("00001Smith     12.50\n", layout),
+            "id,name,amount\n00001,Smith,12.50\n"
+        );
+
+        let mut spec = tempfile::NamedTempFile::new().unwrap();
+        write!(spec, "name,width\nid,five\n").unwrap();
+        let error = Layout::load(&spec.path().to_string_lossy()).unwrap_err();
+        assert!(matches!(error, Error::Config(ref message) if message.contains("row 1")));
+    }
+}
@@ -0,0 +1,732 @@
+//! Transparent decompression of gzip input (RFC 1952) with a small DEFLATE
+//! decoder (RFC 1951), so `.csv.gz` files and gzipped stdin need no `zcat`.
+
+use std::io::{self, BufRead, Read};
+
+/// Back-references reach at most this far into earlier output.
+const WINDOW_SIZE: usize = 32 * 1024;
+
+/// Decoded bytes to produce per refill.
+const CHUNK_SIZE: usize = 32 * 1024;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order in which code length code lengths are stored in a dynamic block.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+pub fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    let mut c = !crc;
+    for &byte in bytes {
+        c = CRC_TABLE[((c ^ byte as u32) & 0xff) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("gzip: {}", message))
+}
+
+/// Reads a DEFLATE stream least significant bit first.
+struct BitReader<R> {
+    inner: R,
+    bits: u64,
+    count: u32,
+}
+
+impl<R: BufRead> BitReader<R> {
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        let buf = self.inner.fill_buf()?;
+        let Some(&byte) = buf.first() else {
+            return Ok(None);
+        };
+        self.inner.consume(1);
+        Ok(Some(byte))
+    }
+
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            let byte = self
+                .next_byte()?
+                .ok_or_else(|| invalid("unexpected end of input"))?;
+            self.bits |= (byte as u64) << self.count;
+            self.count += 8;
+        }
+        let value = (self.bits & ((1 << n) - 1)) as u32;
+        self.bits >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Skips to the next byte boundary.
+    fn align(&mut self) {
+        let skip = self.count % 8;
+        self.bits >>= skip;
+        self.count -= skip;
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.bits(8)? as u8)
+    }
+
+    fn u16_le(&mut self) -> io::Result<u16> {
+        Ok(self.byte()? as u16 | (self.byte()? as u16) << 8)
+    }
+
+    fn u32_le(&mut self) -> io::Result<u32> {
+        Ok(self.u16_le()? as u32 | (self.u16_le()? as u32) << 16)
+    }
+
+    /// Whether the input is exhausted; only meaningful on a byte boundary.
+    fn at_end(&mut self) -> io::Result<bool> {
+        Ok(self.count == 0 && self.inner.fill_buf()?.is_empty())
+    }
+}
+
+/// A canonical Huffman code, decoded by walking the codes of each length.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Huffman> {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(invalid("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode<R: BufRead>(&self, input: &mut BitReader<R>) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= input.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+enum State {
+    /// Expecting a member header, or the end of input after the first member
+    Header,
+    /// Expecting a block header
+    Block,
+    /// Bytes left to copy from a stored block
+    Stored(usize),
+    /// Inside a compressed block with literal/length and distance codes
+    Compressed(Box<(Huffman, Huffman)>),
+    /// Expecting the CRC-32 and length that end a member
+    Trailer,
+    Done,
+}
+
+/// Decompresses gzip data as it is read. Concatenated members, as produced
+/// by `cat a.gz b.gz`, are decoded one after another.
+pub struct GzDecoder<R> {
+    input: BitReader<R>,
+    state: State,
+    last_block: bool,
+    first_member: bool,
+    /// Decoded output: up to `WINDOW_SIZE` bytes already read, for
+    /// back-references, followed by bytes not read yet from `position`
+    window: Vec<u8>,
+    position: usize,
+    /// How much of `window` the CRC and length cover
+    checked: usize,
+    crc: u32,
+    size: u32,
+    /// For a bare DEFLATE stream, the CRC-32 and length it must have, which
+    /// a ZIP archive stores apart from the data
+    expected: Option<(u32, u32)>,
+}
+
+impl<R: BufRead> GzDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        GzDecoder {
+            input: BitReader {
+                inner,
+                bits: 0,
+                count: 0,
+            },
+            state: State::Header,
+            last_block: false,
+            first_member: true,
+            window: Vec::with_capacity(WINDOW_SIZE + CHUNK_SIZE),
+            position: 0,
+            checked: 0,
+            crc: 0,
+            size: 0,
+            expected: None,
+        }
+    }
+
+    /// Decodes a bare DEFLATE stream with no gzip header or trailer, such as
+    /// a ZIP archive member, checking it against the given CRC-32 and length.
+    pub fn deflate(inner: R, crc: u32, size: u32) -> Self {
+        GzDecoder {
+            state: State::Block,
+            expected: Some((crc, size)),
+            ..GzDecoder::new(inner)
+        }
+    }
+
+    fn update_check(&mut self) {
+        let new = &self.window[self.checked..];
+        self.crc = crc32_update(self.crc, new);
+        self.size = self.size.wrapping_add(new.len() as u32);
+        self.checked = self.window.len();
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let input = &mut self.input;
+        if input.byte()? != 0x1f || input.byte()? != 0x8b {
+            return Err(invalid("not in gzip format"));
+        }
+        if input.byte()? != 8 {
+            return Err(invalid("unknown compression method"));
+        }
+        let flags = input.byte()?;
+        // Modification time, extra flags and operating system
+        for _ in 0..6 {
+            input.byte()?;
+        }
+        if flags & 0x04 != 0 {
+            let extra_len = input.u16_le()?;
+            for _ in 0..extra_len {
+                input.byte()?;
+            }
+        }
+        // File name and comment, each ended by a zero byte
+        for flag in [0x08, 0x10] {
+            if flags & flag != 0 {
+                while input.byte()? != 0 {}
+            }
+        }
+        if flags & 0x02 != 0 {
+            input.u16_le()?;
+        }
+        Ok(())
+    }
+
+    fn read_block_header(&mut self) -> io::Result<State> {
+        let input = &mut self.input;
+        self.last_block = input.bits(1)? == 1;
+        match input.bits(2)? {
+            0 => {
+                input.align();
+                let len = input.u16_le()?;
+                if input.u16_le()? != !len {
+                    return Err(invalid("corrupt stored block length"));
+                }
+                Ok(State::Stored(len as usize))
+            }
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                let literals = Huffman::new(&lengths)?;
+                let distances = Huffman::new(&[5; 30])?;
+                Ok(State::Compressed(Box::new((literals, distances))))
+            }
+            2 => {
+                let literal_count = input.bits(5)? as usize + 257;
+                let distance_count = input.bits(5)? as usize + 1;
+                let code_length_count = input.bits(4)? as usize + 4;
+                let mut code_lengths = [0u8; 19];
+                for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+                    code_lengths[index] = input.bits(3)? as u8;
+                }
+                let code_lengths = Huffman::new(&code_lengths)?;
+
+                let total = literal_count + distance_count;
+                let mut lengths = Vec::with_capacity(total);
+                while lengths.len() < total {
+                    let (length, repeat) = match code_lengths.decode(input)? {
+                        symbol @ 0..=15 => (symbol as u8, 1),
+                        16 => {
+                            let previous = *lengths
+                                .last()
+                                .ok_or_else(|| invalid("repeat with no previous length"))?;
+                            (previous, 3 + input.bits(2)? as usize)
+                        }
+                        17 => (0, 3 + input.bits(3)? as usize),
+                        _ => (0, 11 + input.bits(7)? as usize),
+                    };
+                    if lengths.len() + repeat > total {
+                        return Err(invalid("too many code lengths"));
+                    }
+                    lengths.extend(std::iter::repeat_n(length, repeat));
+                }
+                if lengths[256] == 0 {
+                    return Err(invalid("missing end-of-block code"));
+                }
+                let literals = Huffman::new(&lengths[..literal_count])?;
+                let distances = Huffman::new(&lengths[literal_count..])?;
+                Ok(State::Compressed(Box::new((literals, distances))))
+            }
+            _ => Err(invalid("invalid block type")),
+        }
+    }
+
+    /// Decodes until at least `CHUNK_SIZE` bytes are waiting or the input ends.
+    fn fill(&mut self) -> io::Result<()> {
+        // Keep a window of read output for back-references, dropping the rest
+        if self.position > WINDOW_SIZE {
+            self.update_check();
+            let drop = self.position - WINDOW_SIZE;
+            self.window.drain(..drop);
+            self.position -= drop;
+            self.checked -= drop;
+        }
+        let target = self.position + CHUNK_SIZE;
+
+        while self.window.len() < target {
+            self.state = match std::mem::replace(&mut self.state, State::Done) {
+                State::Header => {
+                    if !self.first_member && self.input.at_end()? {
+                        State::Done
+                    } else {
+                        self.read_header()?;
+                        self.update_check();
+                        (self.crc, self.size, self.last_block) = (0, 0, false);
+                        State::Block
+                    }
+                }
+                State::Block if self.last_block => State::Trailer,
+                State::Block => self.read_block_header()?,
+                State::Stored(left) => {
+                    let n = left.min(target - self.window.len());
+                    for _ in 0..n {
+                        let byte = self.input.byte()?;
+                        self.window.push(byte);
+                    }
+                    if left == n {
+                        State::Block
+                    } else {
+                        State::Stored(left - n)
+                    }
+                }
+                State::Compressed(codes) => {
+                    let (literals, distances) = &*codes;
+                    let mut end_of_block = false;
+                    while self.window.len() < target {
+                        let symbol = literals.decode(&mut self.input)?;
+                        if symbol < 256 {
+                            self.window.push(symbol as u8);
+                            continue;
+                        }
+                        if symbol == 256 {
+                            end_of_block = true;
+                            break;
+                        }
+                        let index = symbol as usize - 257;
+                        if index >= LENGTH_BASE.len() {
+                            return Err(invalid("invalid length code"));
+                        }
+                        let length = LENGTH_BASE[index] as usize
+                            + self.input.bits(LENGTH_EXTRA[index] as u32)? as usize;
+                        let index = distances.decode(&mut self.input)? as usize;
+                        if index >= DISTANCE_BASE.len() {
+                            return Err(invalid("invalid distance code"));
+                        }
+                        let distance = DISTANCE_BASE[index] as usize
+                            + self.input.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+                        if distance > self.window.len() {
+                            return Err(invalid("distance too far back"));
+                        }
+                        // Copies byte by byte, since a match may overlap itself
+                        let start = self.window.len() - distance;
+                        for i in 0..length {
+                            let byte = self.window[start + i];
+                            self.window.push(byte);
+                        }
+                    }
+                    if end_of_block {
+                        State::Block
+                    } else {
+                        State::Compressed(codes)
+                    }
+                }
+                State::Trailer => {
+                    self.input.align();
+                    self.update_check();
+                    let (crc, size) = match self.expected {
+                        Some(expected) => expected,
+                        None => (self.input.u32_le()?, self.input.u32_le()?),
+                    };
+                    if crc != self.crc || size != self.size {
+                        return Err(invalid("checksum mismatch, the data is corrupt"));
+                    }
+                    self.first_member = false;
+                    if self.expected.is_some() {
+                        State::Done
+                    } else {
+                        State::Header
+                    }
+                }
+                State::Done => return Ok(()),
+            };
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.window.len() {
+            self.fill()?;
+        }
+        let available = &self.window[self.position..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Input to compress per block.
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Candidates to try per position when looking for a match.
+const MAX_CHAIN: usize = 32;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+/// Compresses data written to it as a gzip member with fixed Huffman
+/// blocks, which suits JSON's repeated keys well without the cost of
+/// building dynamic codes. `finish` must be called to write the trailer.
+pub struct GzEncoder<W: io::Write> {
+    inner: W,
+    /// Up to `WINDOW_SIZE` bytes already compressed, followed by input
+    /// waiting to be compressed from `position`
+    data: Vec<u8>,
+    position: usize,
+    /// The latest position of each hash of three bytes, and for each
+    /// position the previous one with the same hash, or `usize::MAX`
+    head: Vec<usize>,
+    previous: Vec<usize>,
+    bits: u64,
+    count: u32,
+    output: Vec<u8>,
+    crc: u32,
+    size: u32,
+}
+
+impl<W: io::Write> GzEncoder<W> {
+    pub fn new(inner: W) -> Self {
+        let mut output = Vec::with_capacity(BLOCK_SIZE);
+        // Magic number, deflate, no flags, no time, no extra flags, unknown OS
+        output.extend_from_slice(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff]);
+        GzEncoder {
+            inner,
+            data: Vec::with_capacity(WINDOW_SIZE + BLOCK_SIZE),
+            position: 0,
+            head: vec![usize::MAX; 1 << HASH_BITS],
+            previous: Vec::with_capacity(WINDOW_SIZE + BLOCK_SIZE),
+            bits: 0,
+            count: 0,
+            output,
+            crc: 0,
+            size: 0,
+        }
+    }
+
+    fn put_bits(&mut self, value: u32, n: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.output.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which is stored most significant bit first.
+    fn put_code(&mut self, code: u32, n: u32) {
+        self.put_bits(code.reverse_bits() >> (32 - n), n);
+    }
+
+    fn put_literal(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.put_code(0x30 + symbol, 8),
+            144..=255 => self.put_code(0x190 + symbol - 144, 9),
+            256..=279 => self.put_code(symbol - 256, 7),
+            _ => self.put_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn put_match(&mut self, length: usize, distance: usize) {
+        let index = LENGTH_BASE.partition_point(|&base| base as usize <= length) - 1;
+        self.put_literal(257 + index as u16);
+        let extra = LENGTH_EXTRA[index] as u32;
+        self.put_bits((length - LENGTH_BASE[index] as usize) as u32, extra);
+
+        let index = DISTANCE_BASE.partition_point(|&base| base as usize <= distance) - 1;
+        self.put_code(index as u32, 5);
+        let extra = DISTANCE_EXTRA[index] as u32;
+        self.put_bits((distance - DISTANCE_BASE[index] as usize) as u32, extra);
+    }
+
+    fn hash(&self, at: usize) -> usize {
+        let bytes = &self.data[at..at + MIN_MATCH];
+        let key = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        (key.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, at: usize) {
+        if at + MIN_MATCH <= self.data.len() {
+            let hash = self.hash(at);
+            self.previous[at] = self.head[hash];
+            self.head[hash] = at;
+        }
+    }
+
+    fn longest_match(&self, at: usize) -> (usize, usize) {
+        let (mut best_length, mut best_distance) = (0, 0);
+        if at + MIN_MATCH > self.data.len() {
+            return (0, 0);
+        }
+        let limit = MAX_MATCH.min(self.data.len() - at);
+        let mut candidate = self.head[self.hash(at)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == usize::MAX || at - candidate > WINDOW_SIZE {
+                break;
+            }
+            let length = self.data[candidate..]
+                .iter()
+                .zip(&self.data[at..at + limit])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best_length {
+                (best_length, best_distance) = (length, at - candidate);
+                if length == limit {
+                    break;
+                }
+            }
+            candidate = self.previous[candidate];
+        }
+        (best_length, best_distance)
+    }
+
+    /// Compresses the waiting input as one block and writes it out.
+    fn compress_block(&mut self, last: bool) -> io::Result<()> {
+        self.put_bits(last as u32 | 1 << 1, 3);
+        self.previous.resize(self.data.len(), usize::MAX);
+        while self.position < self.data.len() {
+            let at = self.position;
+            let (length, distance) = self.longest_match(at);
+            if length >= MIN_MATCH {
+                self.put_match(length, distance);
+                for i in at..at + length {
+                    self.insert(i);
+                }
+                self.position += length;
+            } else {
+                self.put_literal(self.data[at] as u16);
+                self.insert(at);
+                self.position += 1;
+            }
+        }
+        self.put_literal(256);
+
+        // Keep only the window that later matches can refer to
+        if self.data.len() > WINDOW_SIZE {
+            let drop = self.data.len() - WINDOW_SIZE;
+            self.data.drain(..drop);
+            self.previous.drain(..drop);
+            self.position -= drop;
+            let rebase = |at: &mut usize| {
+                *at = if *at == usize::MAX || *at < drop {
+                    usize::MAX
+                } else {
+                    *at - drop
+                }
+            };
+            self.head.iter_mut().for_each(rebase);
+            self.previous.iter_mut().for_each(rebase);
+        }
+
+        self.inner.write_all(&self.output)?;
+        self.output.clear();
+        Ok(())
+    }
+
+    /// Compresses any waiting input, writes the trailer and returns the
+    /// underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.compress_block(true)?;
+        if self.count > 0 {
+            self.put_bits(0, 8 - self.count);
+        }
+        let (crc, size) = (self.crc, self.size);
+        self.output.extend_from_slice(&crc.to_le_bytes());
+        self.output.extend_from_slice(&size.to_le_bytes());
+        self.inner.write_all(&self.output)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: io::Write> io::Write for GzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(BLOCK_SIZE);
+        self.data.extend_from_slice(&buf[..n]);
+        self.crc = crc32_update(self.crc, &buf[..n]);
+        self.size = self.size.wrapping_add(n as u32);
+        if self.data.len() - self.position >= BLOCK_SIZE {
+            self.compress_block(false)?;
+        }
+        Ok(n)
+    }
+
+    /// Writes out what has been compressed so far. Input still waiting for a
+    /// full block stays buffered, so flushing often does not hurt the ratio.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        GzDecoder::new(data).read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    /// The CSV the fixtures below decompress to: a header and rows `i,name{i}`
+    /// for i in 0..rows.
+    fn csv(rows: usize) -> Vec<u8> {
+        let mut csv = String::from("id,name\n");
+        for i in 0..rows {
+            csv.push_str(&format!("{},name{}\n", i, i));
+        }
+        csv.into_bytes()
+    }
+
+    // Made with Python's gzip.compress(data, mtime=0) at the default level and
+    // at level 0 for a stored block
+    const FIXED: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\xcbL\xd1\xc9K\xccM\xe52\x00S\x06\x5c\x86`\xda\x90\x0b\x00\x15{ta\x18\x00\x00\x00";
+    const STORED: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x04\x03\x01\x18\x00\xe7\xffid,name\x0a0,name0\x0a1,name1\x0a\x15{ta\x18\x00\x00\x00";
+    const DYNAMIC: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03-\xcd+\x0e\xc3@\x10\xc0P\xee\xb3\x04\xc4\xfb\xdf\xe3DJ@A{\x7f\xd8j\xa6\xe81\xfbu\x1f\x9f\xeb\xfdp\x06'\x86R\xc2B\x0d+-l\xf4\xb03\xc2\xc1\x0c'+\x5c\xecpc\x06\xfd\x15\xffI1\xa3\x16\xcc\xac\x153l\xc3L\xdb1\xe3\x0e\xcc\xbc\x13s\xe0\xc2\x5c\xb8\xf9\x02\xd4\xa3\x8c\xb0\xbc\x00\x00\x00";
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32_update(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(
+            crc32_update(crc32_update(0, b"1234"), b"56789"),
+            0xcbf4_3926
+        );
+    }
+
+    #[test]
+    fn test_block_types() {
+        assert_eq!(decompress(FIXED).unwrap(), csv(2));
+        assert_eq!(decompress(STORED).unwrap(), csv(2));
+        assert_eq!(decompress(DYNAMIC).unwrap(), csv(20));
+    }
+
+    #[test]
+    fn test_concatenated_members() {
+        let twice = [FIXED, DYNAMIC].concat();
+        assert_eq!(decompress(&twice).unwrap(), [csv(2), csv(20)].concat());
+    }
+
+    #[test]
+    fn test_encoder_round_trip() {
+        // Long enough to span several blocks and slide the window
+        let mut data = csv(20_000);
+        data.extend((0..100_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8));
+        for input in [&data[..], b"", b"a"] {
+            let mut encoder = GzEncoder::new(Vec::new());
+            for chunk in input.chunks(1000) {
+                encoder.write_all(chunk).unwrap();
+            }
+            let compressed = encoder.finish().unwrap();
+            assert_eq!(decompress(&compressed).unwrap(), input);
+        }
+        let mut encoder = GzEncoder::new(Vec::new());
+        encoder.write_all(&csv(20_000)).unwrap();
+        assert!(encoder.finish().unwrap().len() < csv(20_000).len() / 2);
+    }
+
+    #[test]
+    fn test_corrupt_input() {
+        let mut corrupt = FIXED.to_vec();
+        let last = corrupt.len() - 5;
+        corrupt[last] ^= 0xff;
+        assert!(decompress(&corrupt).is_err());
+        assert!(decompress(&FIXED[..20]).is_err());
+        assert!(decompress(b"id,name\n").is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//! Assembles rows into trees using id and parent id columns.
+
+use crate::error::Error;
+use crate::Record;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A `--hierarchy` request: `ID,PARENT_ID[:CHILDREN]`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hierarchy {
+    pub id: String,
+    pub parent: String,
+    pub children: String,
+}
+
+pub fn parse_hierarchy(s: &str) -> Result<Hierarchy, String> {
+    let error = || format!("expected ID,PARENT_ID[:CHILDREN], got '{}'", s);
+    let (columns, children) = s.split_once(':').unwrap_or((s, "children"));
+    let (id, parent) = columns.split_once(',').ok_or_else(error)?;
+    let (id, parent, children) = (id.trim(), parent.trim(), children.trim());
+    if id.is_empty() || parent.is_empty() || children.is_empty() {
+        return Err(error());
+    }
+    Ok(Hierarchy {
+        id: id.to_string(),
+        parent: parent.to_string(),
+        children: children.to_string(),
+    })
+}
+
+/// Ids are compared by their text, so `7` matches whether or not it was
+/// parsed as a number. Blank and null values are no id at all.
+fn key(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::Null => None,
+        Value::String(s) if s.is_empty() => None,
+        Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Nests every record under the record whose id matches its parent id, in a
+/// `children` array that every node gets, and returns the roots. Records
+/// without a parent id, or whose parent is not in the input, are roots.
+pub fn nest(records: Vec<Record>, hierarchy: &Hierarchy) -> Result<Vec<Record>, Error> {
+    let mut index = HashMap::with_capacity(records.len());
+    for (i, record) in records.iter().enumerate() {
+        let id = key(record.get(hierarchy.id.as_str())).ok_or_else(|| {
+            Error::Validation(format!(
+                "Record {} has no {} for --hierarchy",
+                i + 1,
+                hierarchy.id
+            ))
+        })?;
+        if index.insert(id.clone(), i).is_some() {
+            return Err(Error::Validation(format!(
+                "Duplicate {} in --hierarchy: {}",
+                hierarchy.id, id
+            )));
+        }
+    }
+
+    let mut roots = Vec::new();
+    let mut children = vec![Vec::new(); records.len()];
+    for (i, record) in records.iter().enumerate() {
+        match key(record.get(hierarchy.parent.as_str())).and_then(|parent| index.get(&parent)) {
+            Some(&parent) => children[parent].push(i),
+            None => roots.push(i),
+        }
+    }
+
+    // Visit nodes breadth first from the roots, so that building them in
+    // reverse order finishes every child before its parent
+    let mut order = roots.clone();
+    let mut next = 0;
+    while next < order.len() {
+        order.extend_from_slice(&children[order[next]]);
+        next += 1;
+    }
+    if order.len() < records.len() {
+        return Err(Error::Validation(format!(
+            "--hierarchy contains a cycle in {}",
+            hierarchy.parent
+        )));
+    }
+
+    let children_key: Arc<str> = Arc::from(hierarchy.children.as_str());
+    let mut nodes: Vec<Option<Record>> = records.into_iter().map(Some).collect();
+    for &i in order.iter().rev() {
+        let nested = children[i]
+            .iter()
+            .filter_map(|&child| nodes[child].take())
+            .map(|child| {
+                Value::Object(child.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+            })
+            .collect();
+        if let Some(node) = nodes[i].as_mut() {
+            node.insert(Arc::clone(&children_key), Value::Array(nested));
+        }
+    }
+
+    Ok(roots.into_iter().filter_map(|i| nodes[i].take()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: i64, parent: Value) -> Record {
+        Record::from([
+            (Arc::from("id"), Value::from(id)),
+            (Arc::from("parent"), parent),
+        ])
+    }
+
+    #[test]
+    fn test_parse_hierarchy() {
+        let hierarchy = parse_hierarchy("id,parent_id:reports").unwrap();
+        assert_eq!(hierarchy.id, "id");
+        assert_eq!(hierarchy.parent, "parent_id");
+        assert_eq!(hierarchy.children, "reports");
+        assert_eq!(
+            parse_hierarchy("id,parent_id").unwrap().children,
+            "children"
+        );
+        assert!(parse_hierarchy("id").is_err());
+        assert!(parse_hierarchy("id,:children").is_err());
+    }
+
+    #[test]
+    fn test_nest() {
+        let hierarchy = parse_hierarchy("id,parent").unwrap();
+        let records = vec![
+            record(3, Value::from(1)),
+            record(1, Value::from("")),
+            record(2, Value::from(1)),
+            record(4, Value::from("3")),
+            record(5, Value::from(99)),
+        ];
+        let roots = nest(records, &hierarchy).unwrap();
+        assert_eq!(
+            serde_json::to_string(&roots).unwrap(),
+            concat!(
+                r#"[{"id":1,"parent":"","children":["#,
+                r#"{"id":3,"parent":1,"children":[{"id":4,"parent":"3","children":[]}]},"#,
+                r#"{"id":2,"parent":1,"children":[]}]},"#,
+                r#"{"id":5,"parent":99,"children":[]}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_nest_rejects_bad_trees() {
+        let hierarchy = parse_hierarchy("id,parent").unwrap();
+        let duplicate = vec![record(1, Value::Null), record(1, Value::Null)];
+        assert!(nest(duplicate, &hierarchy).is_err());
+        let cycle = vec![record(1, Value::from(2)), record(2, Value::from(1))];
+        assert!(nest(cycle, &hierarchy).is_err());
+    }
+}
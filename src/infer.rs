@@ -0,0 +1,240 @@
+//! `--infer-threshold`: types each CSV column from a sample of its values,
+//! so a column is only converted to numbers or booleans when nearly all of
+//! its values are, and a few stray numeric cells leave a text column alone.
+//! With `--schema-cache`, the types are saved and reused for later inputs
+//! with the same header instead of sampling again.
+
+use crate::error::Error;
+use crate::log;
+use crate::sha256;
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Rows sampled at the start of the input to type the columns.
+pub const SAMPLE_ROWS: usize = 1000;
+
+/// The type chosen for a column and the fraction of its sampled non-blank
+/// values that have that type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inference {
+    pub kind: &'static str,
+    pub confidence: f64,
+}
+
+impl Inference {
+    /// Whether values of the column are typed, rather than kept as text.
+    pub fn is_typed(&self) -> bool {
+        self.kind != "string"
+    }
+}
+
+/// Infers the type of every column from sampled rows. `parse` is the
+/// per-value inference used without a threshold.
+pub fn infer(
+    headers: &[Arc<str>],
+    rows: &[csv::StringRecord],
+    threshold: f64,
+    parse: impl Fn(&str) -> Value,
+) -> Vec<Inference> {
+    headers
+        .iter()
+        .enumerate()
+        .map(|(index, header)| {
+            let (mut present, mut booleans, mut integers, mut numbers) = (0, 0, 0, 0);
+            for field in rows.iter().filter_map(|row| row.get(index)) {
+                if field.is_empty() {
+                    continue;
+                }
+                present += 1;
+                match parse(field) {
+                    Value::Bool(_) => booleans += 1,
+                    Value::Number(n) if n.is_f64() => numbers += 1,
+                    Value::Number(_) => integers += 1,
+                    _ => {}
+                }
+            }
+            let (kind, matching) = if booleans > integers + numbers {
+                ("boolean", booleans)
+            } else if numbers > 0 {
+                ("number", integers + numbers)
+            } else {
+                ("integer", integers)
+            };
+            let inference = match present {
+                0 => Inference {
+                    kind: "string",
+                    confidence: 1.0,
+                },
+                _ if matching as f64 / present as f64 >= threshold => Inference {
+                    kind,
+                    confidence: round(matching as f64 / present as f64),
+                },
+                _ => Inference {
+                    kind: "string",
+                    confidence: round((present - matching) as f64 / present as f64),
+                },
+            };
+            log::info!(
+                "Column {}: {} ({:.1}% of {} sampled values)",
+                header,
+                inference.kind,
+                inference.confidence * 100.0,
+                present
+            );
+            inference
+        })
+        .collect()
+}
+
+/// A `--schema-cache` entry, named after the SHA-256 of the header and the
+/// threshold, holding the inferred type and confidence of every column.
+pub struct SchemaCache {
+    path: PathBuf,
+    columns: usize,
+    quiet: bool,
+}
+
+impl SchemaCache {
+    pub fn new(
+        dir: &str,
+        headers: &[Arc<str>],
+        threshold: f64,
+        quiet: bool,
+    ) -> Result<SchemaCache, Error> {
+        let key = sha256::hex_digest(&serde_json::to_vec(&json!([headers, threshold]))?);
+        Ok(SchemaCache {
+            path: Path::new(dir).join(format!("{}.json", key)),
+            columns: headers.len(),
+            quiet,
+        })
+    }
+
+    /// The cached column types, or None when there is no entry. An entry
+    /// that cannot be read or does not fit the header is warned about and
+    /// treated as missing, so it is inferred again and overwritten.
+    pub fn load(&self) -> Option<Vec<Inference>> {
+        let inferred = match self.read() {
+            Ok(inferred) => inferred?,
+            Err(reason) => {
+                if !self.quiet {
+                    eprintln!(
+                        "Warning: ignoring schema cache entry {}: {}",
+                        self.path.display(),
+                        reason
+                    );
+                }
+                return None;
+            }
+        };
+        log::info!("Reused column types from {}", self.path.display());
+        Some(inferred)
+    }
+
+    fn read(&self) -> Result<Option<Vec<Inference>>, String> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        };
+        let columns: Vec<Value> = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+        if columns.len() != self.columns {
+            return Err(format!(
+                "{} columns, but the header has {}",
+                columns.len(),
+                self.columns
+            ));
+        }
+        let inferred = columns
+            .iter()
+            .map(|column| {
+                let kind = match column["type"].as_str() {
+                    Some("boolean") => "boolean",
+                    Some("integer") => "integer",
+                    Some("number") => "number",
+                    Some("string") => "string",
+                    _ => return Err(format!("invalid column type {}", column["type"])),
+                };
+                let confidence = column["confidence"]
+                    .as_f64()
+                    .ok_or_else(|| format!("invalid confidence {}", column["confidence"]))?;
+                Ok(Inference { kind, confidence })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Some(inferred))
+    }
+
+    /// Saves inferred column types, renamed into place so a concurrent run
+    /// never reads a partial entry.
+    pub fn store(&self, inferred: &[Inference]) -> Result<(), Error> {
+        let dir = self
+            .path
+            .parent()
+            .expect("entries are inside the cache directory");
+        fs::create_dir_all(dir)?;
+        let columns: Vec<Value> = inferred
+            .iter()
+            .map(|column| json!({"type": column.kind, "confidence": column.confidence}))
+            .collect();
+        let temp = tempfile::NamedTempFile::new_in(dir)?;
+        fs::write(temp.path(), serde_json::to_vec(&columns)?)?;
+        temp.persist(&self.path).map_err(|e| e.error)?;
+        log::info!("Cached column types as {}", self.path.display());
+        Ok(())
+    }
+}
+
+fn round(fraction: f64) -> f64 {
+    (fraction * 1000.0).round() / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_field;
+
+    #[test]
+    fn test_infer() {
+        let headers: Vec<Arc<str>> = ["id", "code", "flag", "blank", "score"]
+            .into_iter()
+            .map(Arc::from)
+            .collect();
+        let mut rows: Vec<csv::StringRecord> = (0..19)
+            .map(|i| {
+                let id = i.to_string();
+                let code = format!("A{}", i);
+                let score = format!("{}.5", i);
+                csv::StringRecord::from(vec![&id, &code, "true", "", &score])
+            })
+            .collect();
+        rows.push(csv::StringRecord::from(vec!["n/a", "42", "false", "", "7"]));
+
+        let inferred = infer(&headers, &rows, 0.95, parse_field);
+        let summary: Vec<_> = inferred
+            .iter()
+            .map(|inference| (inference.kind, inference.confidence))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("integer", 0.95),
+                ("string", 0.95),
+                ("boolean", 1.0),
+                ("string", 1.0),
+                ("number", 1.0),
+            ]
+        );
+
+        let inferred = infer(&headers, &rows, 0.99, parse_field);
+        assert_eq!(
+            inferred[0],
+            Inference {
+                kind: "string",
+                confidence: 0.05
+            }
+        );
+        assert!(!inferred[0].is_typed());
+    }
+}
@@ -0,0 +1,221 @@
+//! Physical line numbers for CSV records. The csv crate ends a record at
+//! the `\r` of a `\r\n` and gives the next record the position of the `\n`,
+//! before it is counted, so after the header every record of a CRLF file
+//! would be a line early.
+//!
+//! The reader can also keep the bytes of recent records as they were read,
+//! so `--reject-file` copies malformed rows verbatim.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::rc::Rc;
+
+/// Line breaks the CSV reader has read past but not yet placed a record
+/// after: the offset of each `\r` and `\n`, and whether it is a `\n`.
+type Breaks = Rc<RefCell<VecDeque<(u64, bool)>>>;
+
+/// Input not yet claimed by a record, and the raw bytes of the last records.
+struct RawText {
+    /// Offset of the first byte of `unclaimed`
+    start: u64,
+    unclaimed: Vec<u8>,
+    /// Each recent record's starting offset and bytes, oldest first
+    recent: VecDeque<(u64, Vec<u8>)>,
+    keep: usize,
+    /// Lines starting with this byte are comments the CSV reader skips
+    comment: Option<u8>,
+}
+
+/// Reads input through, noting where its line breaks are.
+pub struct LineBreakReader<R> {
+    inner: R,
+    offset: u64,
+    breaks: Breaks,
+    raw: Option<Rc<RefCell<RawText>>>,
+}
+
+impl<R: Read> LineBreakReader<R> {
+    /// Wraps `inner`, returning the reader and what places records by it.
+    pub fn new(inner: R) -> (Self, RecordLines) {
+        let breaks = Breaks::default();
+        let reader = LineBreakReader {
+            inner,
+            offset: 0,
+            breaks: Rc::clone(&breaks),
+            raw: None,
+        };
+        (reader, RecordLines { breaks, raw: None })
+    }
+
+    /// Like `new`, but also keeps the raw bytes of the last `records` records,
+    /// leaving out the `comment` lines before them.
+    pub fn keeping_raw(inner: R, records: usize, comment: Option<u8>) -> (Self, RecordLines) {
+        let (mut reader, mut lines) = Self::new(inner);
+        let raw = Rc::new(RefCell::new(RawText {
+            start: 0,
+            unclaimed: Vec::new(),
+            recent: VecDeque::with_capacity(records),
+            keep: records.max(1),
+            comment,
+        }));
+        reader.raw = Some(Rc::clone(&raw));
+        lines.raw = Some(raw);
+        (reader, lines)
+    }
+}
+
+impl<R: Read> Read for LineBreakReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        let mut breaks = self.breaks.borrow_mut();
+        for (i, &byte) in buf[..n].iter().enumerate() {
+            if matches!(byte, b'\r' | b'\n') {
+                breaks.push_back((self.offset + i as u64, byte == b'\n'));
+            }
+        }
+        if let Some(raw) = &self.raw {
+            raw.borrow_mut().unclaimed.extend_from_slice(&buf[..n]);
+        }
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+/// Moves record positions past the line breaks they start on.
+pub struct RecordLines {
+    breaks: Breaks,
+    raw: Option<Rc<RefCell<RawText>>>,
+}
+
+impl RecordLines {
+    /// Sets the position of `record`, read after every earlier one, to
+    /// where its first field starts.
+    pub fn place(&self, record: &mut csv::ByteRecord) {
+        let Some(position) = record.position() else {
+            return;
+        };
+        let (mut byte, mut line) = (position.byte(), position.line());
+        let mut breaks = self.breaks.borrow_mut();
+        while breaks.front().is_some_and(|&(offset, _)| offset < byte) {
+            breaks.pop_front();
+        }
+        while let Some(&(offset, newline)) = breaks.front() {
+            if offset != byte {
+                break;
+            }
+            breaks.pop_front();
+            byte += 1;
+            line += newline as u64;
+        }
+        let mut placed = position.clone();
+        placed.set_byte(byte).set_line(line);
+        record.set_position(Some(placed));
+    }
+
+    /// Takes the input read before `end`, the offset where the header row
+    /// ends, when raw bytes are kept.
+    pub fn header(&self, end: u64) -> Option<Vec<u8>> {
+        let raw = self.raw.as_ref()?;
+        let mut raw = raw.borrow_mut();
+        let start = raw.start;
+        Some(raw.claim(start, end))
+    }
+
+    /// Keeps the raw bytes of `record`, placed and read up to `end`, if
+    /// raw bytes are kept.
+    pub fn keep(&self, record: &csv::ByteRecord, end: u64) {
+        let (Some(raw), Some(position)) = (&self.raw, record.position()) else {
+            return;
+        };
+        let mut raw = raw.borrow_mut();
+        let start = position.byte();
+        let bytes = raw.claim(start, end);
+        // A record's position is the start of any comment lines before it
+        let mut bytes = bytes.as_slice();
+        while raw.comment.is_some() && bytes.first() == raw.comment.as_ref() {
+            let line = bytes
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(bytes.len(), |i| i + 1);
+            bytes = &bytes[line..];
+        }
+        let bytes = bytes.to_vec();
+        if raw.recent.len() == raw.keep {
+            raw.recent.pop_front();
+        }
+        raw.recent.push_back((start, bytes));
+    }
+
+    /// The raw bytes of a record kept earlier, from its line break included.
+    pub fn raw(&self, record: &csv::ByteRecord) -> Option<Vec<u8>> {
+        let start = record.position()?.byte();
+        let raw = self.raw.as_ref()?.borrow();
+        raw.recent
+            .iter()
+            .find(|(offset, _)| *offset == start)
+            .map(|(_, bytes)| bytes.clone())
+    }
+}
+
+impl RawText {
+    /// Takes the bytes from `start` to `end`, and the `\n` of a `\r\n` that
+    /// the CSV reader leaves to the next record, dropping anything before.
+    fn claim(&mut self, start: u64, end: u64) -> Vec<u8> {
+        let from = (start.saturating_sub(self.start) as usize).min(self.unclaimed.len());
+        let mut to = (end.saturating_sub(self.start) as usize).clamp(from, self.unclaimed.len());
+        if self.unclaimed[from..to].ends_with(b"\r") && self.unclaimed.get(to) == Some(&b'\n') {
+            to += 1;
+        }
+        let bytes = self.unclaimed[from..to].to_vec();
+        self.unclaimed.drain(..to);
+        self.start += to as u64;
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crlf_lines() {
+        let input = "a\r\nx\r\n\"y\r\nz\"\r\nw\n\nv\r\r\nu";
+        let (reader, lines) = LineBreakReader::new(input.as_bytes());
+        let mut reader = csv::Reader::from_reader(reader);
+        let placed: Vec<(u64, u64)> = reader
+            .byte_records()
+            .map(|record| {
+                let mut record = record.unwrap();
+                lines.place(&mut record);
+                let position = record.position().unwrap();
+                (position.line(), position.byte())
+            })
+            .collect();
+        assert_eq!(placed, [(2, 3), (3, 6), (5, 14), (7, 17), (8, 21)]);
+    }
+
+    #[test]
+    fn test_raw_records() {
+        let input = "a,b\r\n# note\r\nx,\"y\r\nz\"\r\n\r\nw\n";
+        let (reader, lines) = LineBreakReader::keeping_raw(input.as_bytes(), 1, Some(b'#'));
+        let mut reader = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .flexible(true)
+            .from_reader(reader);
+        reader.headers().unwrap();
+        assert_eq!(lines.header(reader.position().byte()).unwrap(), b"a,b\r\n");
+        let mut records = Vec::new();
+        let mut raw = Vec::new();
+        let mut record = csv::ByteRecord::new();
+        while reader.read_byte_record(&mut record).unwrap() {
+            lines.place(&mut record);
+            lines.keep(&record, reader.position().byte());
+            raw.push(lines.raw(&record).unwrap());
+            records.push(record.clone());
+        }
+        assert_eq!(raw, [&b"x,\"y\r\nz\"\r\n"[..], b"w\n"]);
+        // Only the last record is kept
+        assert_eq!(lines.raw(&records[0]), None);
+    }
+}
@@ -0,0 +1,39 @@
+//! Leveled diagnostics on stderr, enabled with `-v` (info) or `-vv` (debug).
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+
+#[derive(Debug, Clone, Copy)]
+pub enum Level {
+    Info = 1,
+    Debug = 2,
+}
+
+pub fn set_verbosity(verbosity: u8) {
+    VERBOSITY.store(verbosity, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    VERBOSITY.load(Ordering::Relaxed) >= level as u8
+}
+
+/// Logs a message at `-v` and above.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Info) {
+            eprintln!("[info] {}", format_args!($($arg)*));
+        }
+    };
+}
+
+/// Logs a message at `-vv` and above.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            eprintln!("[debug] {}", format_args!($($arg)*));
+        }
+    };
+}
+
+pub(crate) use {debug, info};
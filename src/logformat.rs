@@ -0,0 +1,466 @@
+//! Parsers for line-oriented text formats: LTSV, the Apache/Nginx combined
+//! log format, whitespace-aligned tables and INI/properties files. Fields are returned as text, or
+//! null when absent, and typed by the caller like CSV fields.
+
+use serde_json::{Map, Value};
+
+/// Parses a Labeled Tab-separated Values line like `host:127.0.0.1\tstatus:200`.
+pub fn parse_ltsv(line: &str) -> Result<Map<String, Value>, String> {
+    let mut fields = Map::new();
+    for field in line.trim_end_matches(['\r', '\n']).split('\t') {
+        let (label, value) = field
+            .split_once(':')
+            .ok_or_else(|| format!("expected LABEL:VALUE, got '{}'", field))?;
+        if label.is_empty() {
+            return Err(format!("empty label in '{}'", field));
+        }
+        fields.insert(label.to_string(), Value::from(value));
+    }
+    Ok(fields)
+}
+
+const COMBINED_FIELDS: [&str; 9] = [
+    "host",
+    "ident",
+    "user",
+    "time",
+    "request",
+    "status",
+    "bytes",
+    "referer",
+    "user_agent",
+];
+
+/// The fields of every combined log line, in order: the request line is
+/// followed by its parts.
+pub fn combined_columns() -> Vec<String> {
+    let mut columns = Vec::new();
+    for name in COMBINED_FIELDS {
+        columns.push(name.to_string());
+        if name == "request" {
+            columns.extend(["method", "path", "protocol"].map(String::from));
+        }
+    }
+    columns
+}
+
+/// Parses a combined log line, `%h %l %u %t "%r" %>s %b "%{Referer}i"
+/// "%{User-agent}i"`. Lines in the common log format, which lack the last two
+/// fields, are accepted too. A `-` is an absent value, the request line is
+/// also split into `method`, `path` and `protocol`, and the time is rewritten
+/// in ISO 8601 so it can be used with the date options.
+pub fn parse_combined(line: &str) -> Result<Map<String, Value>, String> {
+    let tokens = tokenize(line.trim())?;
+    if tokens.len() < 7 || tokens.len() > COMBINED_FIELDS.len() {
+        return Err(format!(
+            "expected 7 to 9 fields in combined log format, found {}",
+            tokens.len()
+        ));
+    }
+
+    let mut fields = Map::new();
+    for (name, token) in COMBINED_FIELDS.iter().zip(&tokens) {
+        let value = match (*name, token.as_str()) {
+            (_, "-") => Value::Null,
+            ("time", time) => Value::from(iso_time(time).unwrap_or_else(|| time.to_string())),
+            (_, token) => Value::from(token),
+        };
+        fields.insert(name.to_string(), value);
+        if *name == "request" {
+            // The path may contain spaces, so the method ends at the first
+            // and the protocol starts after the last
+            let (method, rest) = match token.split_once(' ') {
+                Some((method, rest)) => (Some(method), Some(rest)),
+                None => (Some(token.as_str()), None),
+            };
+            let (path, protocol) = match rest.map(|rest| rest.rsplit_once(' ')) {
+                Some(Some((path, protocol))) => (Some(path), Some(protocol)),
+                Some(None) => (rest, None),
+                None => (None, None),
+            };
+            for (part, value) in [("method", method), ("path", path), ("protocol", protocol)] {
+                let value = value
+                    .filter(|_| token != "-")
+                    .map_or(Value::Null, Value::from);
+                fields.insert(part.to_string(), value);
+            }
+        }
+    }
+    for name in &COMBINED_FIELDS[tokens.len()..] {
+        fields.insert(name.to_string(), Value::Null);
+    }
+    Ok(fields)
+}
+
+/// A column of a whitespace-aligned table: its name and where the name
+/// stands in the header line, in characters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableColumn {
+    pub name: String,
+    start: usize,
+    end: usize,
+}
+
+/// The words of a line, with their character and byte ranges.
+fn table_words(line: &str) -> Vec<(usize, usize, usize, usize)> {
+    let mut words = Vec::new();
+    let mut word: Option<(usize, usize)> = None;
+    let mut column = 0;
+    for (byte, c) in line.char_indices() {
+        match (c.is_whitespace(), word) {
+            (false, None) => word = Some((column, byte)),
+            (true, Some((start, start_byte))) => {
+                words.push((start, column, start_byte, byte));
+                word = None;
+            }
+            _ => {}
+        }
+        column += 1;
+    }
+    if let Some((start, start_byte)) = word {
+        words.push((start, column, start_byte, line.len()));
+    }
+    words
+}
+
+/// The column a word from `start` to `end` falls under: the first whose
+/// name it overlaps, or else the nearest, but never one before `after`.
+fn table_column(columns: &[TableColumn], start: usize, end: usize, after: usize) -> usize {
+    let distance = |column: &TableColumn| {
+        if end <= column.start {
+            column.start - end
+        } else {
+            start.saturating_sub(column.end)
+        }
+    };
+    let nearest = (0..columns.len())
+        .min_by_key(|&index| distance(&columns[index]))
+        .unwrap_or(0);
+    nearest.max(after)
+}
+
+/// Finds the columns of a whitespace-aligned table from where the header
+/// names stand. A name that no row has a value under and that follows the
+/// previous one after a single space is part of it, as in `Mounted on`.
+pub fn table_columns(header: &str, rows: &[&str]) -> Vec<TableColumn> {
+    let words = table_words(header);
+    let columns: Vec<TableColumn> = words
+        .iter()
+        .map(|&(start, end, _, _)| TableColumn {
+            name: String::new(),
+            start,
+            end,
+        })
+        .collect();
+    let mut used = vec![false; columns.len()];
+    for row in rows {
+        let mut after = 0;
+        for (start, end, _, _) in table_words(row) {
+            after = table_column(&columns, start, end, after);
+            used[after] = true;
+        }
+    }
+    let mut merged: Vec<(TableColumn, usize, usize)> = Vec::new();
+    for (index, &(start, end, start_byte, end_byte)) in words.iter().enumerate() {
+        match merged.last_mut() {
+            Some((last, _, last_end_byte)) if !used[index] && start == last.end + 1 => {
+                last.end = end;
+                *last_end_byte = end_byte;
+            }
+            _ => merged.push((
+                TableColumn {
+                    name: String::new(),
+                    start,
+                    end,
+                },
+                start_byte,
+                end_byte,
+            )),
+        }
+    }
+    merged
+        .into_iter()
+        .map(|(column, start_byte, end_byte)| TableColumn {
+            name: header[start_byte..end_byte].to_string(),
+            ..column
+        })
+        .collect()
+}
+
+/// Splits a line of a whitespace-aligned table into a field per column,
+/// each word going to the column it stands under. Columns with nothing
+/// under them are empty, and the last field keeps the rest of the line,
+/// spaces included.
+pub fn split_table_line<'a>(line: &'a str, columns: &[TableColumn]) -> Vec<&'a str> {
+    let mut ranges: Vec<Option<(usize, usize)>> = vec![None; columns.len()];
+    let mut after = 0;
+    for (start, end, start_byte, end_byte) in table_words(line) {
+        after = table_column(columns, start, end, after);
+        let range = ranges[after].get_or_insert((start_byte, end_byte));
+        range.1 = end_byte;
+    }
+    ranges
+        .into_iter()
+        .map(|range| range.map_or("", |(start, end)| &line[start..end]))
+        .collect()
+}
+
+/// Parses an INI or Java properties file into one object. Keys before the
+/// first `[section]` are top-level fields and each section becomes a nested
+/// object. Keys are separated from values by `=` or `:`, lines starting with
+/// `;`, `#` or `!` are comments and a trailing backslash continues a value on
+/// the next line. Unquoted values are typed with `type_value`; quoted values
+/// stay strings. Errors carry the 1-based line number.
+pub fn parse_ini(
+    content: &str,
+    type_value: impl Fn(&str) -> Value,
+) -> Result<Map<String, Value>, (usize, String)> {
+    let mut root = Map::new();
+    let mut section: Option<String> = None;
+    let mut lines = content.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let mut line = line.trim().to_string();
+        while line.ends_with('\\') {
+            line.pop();
+            match lines.next() {
+                Some((_, next)) => line.push_str(next.trim_start()),
+                None => break,
+            }
+        }
+        if line.is_empty() || line.starts_with([';', '#', '!']) {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[') {
+            let name = name
+                .strip_suffix(']')
+                .ok_or_else(|| (index + 1, format!("unclosed section header '{}'", line)))?
+                .trim();
+            root.entry(name.to_string())
+                .or_insert_with(|| Value::Object(Map::new()));
+            section = Some(name.to_string());
+            continue;
+        }
+
+        let (key, value) = match line.find(['=', ':']) {
+            Some(at) => (line[..at].trim(), line[at + 1..].trim()),
+            None => (line.as_str(), ""),
+        };
+        if key.is_empty() {
+            return Err((index + 1, format!("missing key in '{}'", line)));
+        }
+        let value = match value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+        {
+            Some(quoted) => Value::from(quoted),
+            None => type_value(value),
+        };
+
+        let target = match &section {
+            Some(name) => match root.get_mut(name) {
+                Some(Value::Object(fields)) => fields,
+                // A top-level key of the same name came first
+                _ => {
+                    return Err((
+                        index + 1,
+                        format!("section [{}] conflicts with a key of the same name", name),
+                    ))
+                }
+            },
+            None => &mut root,
+        };
+        target.insert(key.to_string(), value);
+    }
+    Ok(root)
+}
+
+/// Splits a log line on spaces, keeping `[...]` and `"..."` tokens whole.
+/// Quoted tokens may contain backslash escapes.
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c == ' ' {
+            chars.next();
+            continue;
+        }
+        let mut token = String::new();
+        match c {
+            '"' => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => token.extend(chars.next()),
+                        Some(c) => token.push(c),
+                        None => return Err("unterminated quoted field".to_string()),
+                    }
+                }
+            }
+            '[' => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some(c) => token.push(c),
+                        None => return Err("unterminated [ field".to_string()),
+                    }
+                }
+            }
+            _ => {
+                while let Some(c) = chars.next_if(|&c| c != ' ') {
+                    token.push(c);
+                }
+            }
+        }
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Rewrites `10/Oct/2000:13:55:36 -0700` as `2000-10-10T13:55:36-07:00`.
+fn iso_time(time: &str) -> Option<String> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (timestamp, offset) = time.split_once(' ')?;
+    let (date, clock) = timestamp.split_once(':')?;
+    let mut date = date.split('/');
+    let (day, month, year) = (date.next()?, date.next()?, date.next()?);
+    let month = MONTHS.iter().position(|m| *m == month)? + 1;
+    if offset.len() != 5 || clock.len() != 8 {
+        return None;
+    }
+    Some(format!(
+        "{}-{:02}-{}T{}{}:{}",
+        year,
+        month,
+        day,
+        clock,
+        &offset[..3],
+        &offset[3..]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ltsv() {
+        let fields = parse_ltsv("host:127.0.0.1\treq:GET /a:b HTTP/1.1\tstatus:200").unwrap();
+        assert_eq!(fields["host"], "127.0.0.1");
+        assert_eq!(fields["req"], "GET /a:b HTTP/1.1");
+        assert_eq!(fields["status"], "200");
+        assert!(parse_ltsv("host:127.0.0.1\tbroken").is_err());
+    }
+
+    #[test]
+    fn test_split_table_line() {
+        let header = "    PID TTY          TIME CMD";
+        let rows = [
+            "     42 pts/0    00:00:01 vim notes.txt",
+            "      7 ?        00:00:00 sh",
+        ];
+        let columns = table_columns(header, &rows);
+        let names: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["PID", "TTY", "TIME", "CMD"]);
+        assert_eq!(
+            split_table_line(rows[0], &columns),
+            ["42", "pts/0", "00:00:01", "vim notes.txt"]
+        );
+        assert_eq!(split_table_line("   ", &columns), ["", "", "", ""]);
+    }
+
+    #[test]
+    fn test_table_blank_cells() {
+        let header = "NAME      READY   STATUS    RESTARTS   NOMINATED NODE";
+        let rows = [
+            "pod-a     1/1     Running   0          <none>",
+            "pod-b             Pending",
+        ];
+        let columns = table_columns(header, &rows);
+        let names: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["NAME", "READY", "STATUS", "RESTARTS", "NOMINATED NODE"]
+        );
+        assert_eq!(
+            split_table_line(rows[1], &columns),
+            ["pod-b", "", "Pending", "", ""]
+        );
+
+        let header = "Filesystem  Size  Mounted on";
+        let rows = ["/dev/sda1    10G  /mnt/data"];
+        let columns = table_columns(header, &rows);
+        assert_eq!(columns.len(), 3);
+        assert_eq!(columns[2].name, "Mounted on");
+        assert_eq!(split_table_line(rows[0], &columns)[2], "/mnt/data");
+    }
+
+    fn text(value: &str) -> Value {
+        Value::from(value)
+    }
+
+    #[test]
+    fn test_parse_ini() {
+        let content = "; global settings\nname = demo\nport: 8080\n\n[database]\nhost=db.local\nuser = \"admin\"\ncode = '0042'\nurl = jdbc:x\\\n  ;y\n";
+        let parsed = parse_ini(content, |v| {
+            v.parse::<i64>()
+                .map(Value::from)
+                .unwrap_or_else(|_| Value::from(v))
+        })
+        .unwrap();
+        assert_eq!(
+            serde_json::to_string(&parsed).unwrap(),
+            r#"{"name":"demo","port":8080,"database":{"host":"db.local","user":"admin","code":"0042","url":"jdbc:x;y"}}"#
+        );
+
+        assert_eq!(parse_ini("a=1\n[broken", text).unwrap_err().0, 2);
+        assert_eq!(parse_ini("=1", text).unwrap_err().0, 1);
+        assert!(parse_ini("db=1\n[db]\nx=2", text).is_err());
+    }
+
+    #[test]
+    fn test_parse_combined() {
+        let line = r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /apache_pb.gif HTTP/1.0" 200 2326 "http://www.example.com/start.html" "Mozilla/4.08 [en] (Win98; I ;Nav)""#;
+        let fields = parse_combined(line).unwrap();
+        assert_eq!(fields["host"], "127.0.0.1");
+        assert_eq!(fields["ident"], Value::Null);
+        assert_eq!(fields["user"], "frank");
+        assert_eq!(fields["time"], "2000-10-10T13:55:36-07:00");
+        assert_eq!(fields["method"], "GET");
+        assert_eq!(fields["path"], "/apache_pb.gif");
+        assert_eq!(fields["protocol"], "HTTP/1.0");
+        assert_eq!(fields["status"], "200");
+        assert_eq!(fields["user_agent"], "Mozilla/4.08 [en] (Win98; I ;Nav)");
+
+        let common = r#"::1 - - [01/Jan/2024:00:00:00 +0000] "-" 408 -"#;
+        let fields = parse_combined(common).unwrap();
+        assert_eq!(fields["request"], Value::Null);
+        assert_eq!(fields["path"], Value::Null);
+        assert_eq!(fields["bytes"], Value::Null);
+        assert_eq!(fields["referer"], Value::Null);
+        // Every line has the same columns, in the same order
+        for line in [line, common] {
+            let keys: Vec<String> = parse_combined(line).unwrap().keys().cloned().collect();
+            assert_eq!(keys, combined_columns());
+        }
+
+        let escaped = r#"h - - [01/Jan/2024:00:00:00 +0000] "GET /\"q\" HTTP/1.1" 200 5"#;
+        assert_eq!(parse_combined(escaped).unwrap()["path"], "/\"q\"");
+        let spaced = r#"h - - [01/Jan/2024:00:00:00 +0000] "GET /a b.gif HTTP/1.0" 200 5"#;
+        let fields = parse_combined(spaced).unwrap();
+        assert_eq!(fields["method"], "GET");
+        assert_eq!(fields["path"], "/a b.gif");
+        assert_eq!(fields["protocol"], "HTTP/1.0");
+        let simple = r#"h - - [01/Jan/2024:00:00:00 +0000] "GET /" 200 5"#;
+        let fields = parse_combined(simple).unwrap();
+        assert_eq!(fields["path"], "/");
+        assert_eq!(fields["protocol"], Value::Null);
+        assert!(parse_combined("not a log line").is_err());
+        assert!(parse_combined(r#"h - - [01/Jan/2024 "GET / HTTP/1.1" 200 5"#).is_err());
+    }
+}
@@ -1,22 +1,344 @@
 use clap::{Arg, Command};
 use csv::Reader;
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::error::Error;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::fs::File;
-use std::io::{self, BufReader, Read};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Serialize, Deserialize)]
+mod arrow;
+mod avro;
+mod base64;
+mod bson;
+mod cache;
+mod cbor;
+mod columns;
+mod compression;
+mod currency;
+mod datetime;
+mod encoding;
+mod error;
+mod fixedwidth;
+mod gzip;
+mod hierarchy;
+mod infer;
+mod jis0208;
+mod lines;
+mod log;
+mod logformat;
+mod mapping;
+mod msgpack;
+mod normalize;
+mod ods;
+mod parallel;
+mod parquet;
+mod partition;
+mod postgres;
+mod preflight;
+mod progress;
+mod repair;
+mod replace;
+mod report;
+mod rfc4180;
+mod scale;
+mod selfcheck;
+mod sha256;
+mod sniff;
+mod spreadsheet;
+mod sqlite;
+mod stats;
+mod template;
+mod truncate;
+mod unicode;
+mod unicode_data;
+mod warnings;
+mod writer;
+mod xlsx;
+mod xml;
+mod zip;
+
+use compression::{Compression, Sink};
+use currency::{CurrencySpec, Rates};
+use datetime::DatePart;
+use encoding::Encoding;
+use error::Error;
+use hierarchy::Hierarchy;
+use progress::ProgressReader;
+use scale::Scale;
+use stats::Stage;
+use template::OutputTemplate;
+use truncate::Truncation;
+use warnings::{Warning, Warnings};
+use writer::{AsciiEscaper, AvroWriter, PostgresWriter, RecordWriter, SqliteWriter};
+
+/// A converted CSV row. Keys are header names interned once per conversion,
+/// so building a row only bumps reference counts instead of allocating.
+type Record = IndexMap<Arc<str>, Value>;
+
+/// Buffer size used for reading input and writing output unless overridden.
+const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 struct Config {
     input: Option<String>,
+    /// Several positional inputs, converted one by one into a single output
+    inputs: Vec<String>,
     output: Option<String>,
     pretty: bool,
+    ascii: bool,
     no_header: bool,
+    select: Option<Vec<String>>,
+    drop: Option<Vec<String>>,
+    rename: Vec<(String, String)>,
+    rename_file: Option<String>,
+    read_buffer: Option<usize>,
+    write_buffer: Option<usize>,
+    headers: Option<Vec<String>>,
+    header_prefix: Option<String>,
+    stable_only: bool,
+    wait_for_quiescence: Option<u64>,
+    lock: bool,
+    fail_on_empty: bool,
+    skip_footer: usize,
+    comment: Option<u8>,
+    expect_rows: Option<RowCountRange>,
+    expect_header_hash: Option<String>,
+    print_header_hash: bool,
+    /// `--echo-header`, with the file to write to or `None` for stderr
+    echo_header: Option<Option<String>>,
+    on_ragged: RaggedPolicy,
+    between: Option<TimeWindow>,
+    skip_errors: bool,
+    date_parts: Vec<DateParts>,
+    timezone: i64,
+    max_errors: Option<usize>,
+    convert_currency: Vec<CurrencySpec>,
+    scale: Vec<Scale>,
+    truncate: Vec<Truncation>,
+    truncate_flag: bool,
+    reject_file: Option<String>,
+    explode: Option<String>,
+    collect: Vec<Collect>,
+    hierarchy: Option<Hierarchy>,
+    quiet: bool,
+    with_columns_meta: bool,
+    verbose: u8,
+    from: InputFormat,
+    encoding: Encoding,
+    widths: Option<Vec<usize>>,
+    width_spec: Option<String>,
+    progress: bool,
+    format: OutputFormat,
+    avro_schema: Option<String>,
+    table: Option<String>,
+    query: Option<String>,
+    self_check: bool,
+    preflight_space: bool,
+    threads: Option<usize>,
+    queue_size: Option<usize>,
+    rows_file: Option<String>,
+    exclude_rows_file: Option<String>,
+    dictionary: Option<String>,
+    max_warnings: Option<usize>,
+    fatal_warnings: bool,
+    report: Option<String>,
+    timing: bool,
+    also_output: Option<String>,
+    also_format: Option<OutputFormat>,
+    split_rows: Option<usize>,
+    partition_by: Option<String>,
+    append: bool,
+    backup: bool,
+    emit_mapping: Option<String>,
+    compress: Option<Compression>,
+    strict_rfc4180: bool,
+    repair_quotes: bool,
+    normalize_report: bool,
+    normalize: bool,
+    normalize_unicode: Option<unicode::Form>,
+    cache_dir: Option<String>,
+    source_field: Option<String>,
+    infer_threshold: Option<f64>,
+    schema_cache: Option<String>,
+    recursive: bool,
+    output_template: Option<OutputTemplate>,
+    auto_format: bool,
+    zip_member: Option<String>,
+    sheet: Option<String>,
+    temp_dir: Option<String>,
+}
+
+/// Calendar fields to derive from a date/time column, named `<column>_<part>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct DateParts {
+    column: String,
+    parts: Vec<DatePart>,
+}
+
+impl DateParts {
+    fn field_names(&self) -> impl Iterator<Item = String> + '_ {
+        self.parts
+            .iter()
+            .map(move |part| format!("{}_{}", self.column, part.name()))
+    }
+}
+
+/// Numbered column families such as `item_name_1, item_qty_1, item_name_2, ...`
+/// to gather into an array of objects keyed by the family names.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Collect {
+    name: String,
+    fields: Vec<String>,
+}
+
+/// A half-open time range `[start, end)` on a column, in epoch milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TimeWindow {
+    column: String,
+    start: Option<i64>,
+    end: Option<i64>,
+}
+
+/// The format of the input data.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+enum InputFormat {
+    /// Comma-separated values
+    #[default]
+    Csv,
+    /// JSON Lines: one JSON object per line
+    Jsonl,
+    /// Labeled Tab-separated Values: `label:value` fields separated by tabs
+    Ltsv,
+    /// Apache/Nginx combined (or common) access log lines
+    CombinedLog,
+    /// Whitespace-aligned command output with a header row, like `ps` or `df`
+    Table,
+    /// An INI or properties file, converted into a single record
+    Ini,
+    /// Tab-separated values
+    Tsv,
+    /// A JSON array of objects, or a single object
+    Json,
+    /// Columns at fixed character positions, as given by --widths or --width-spec
+    FixedWidth,
+    /// A Parquet file with a flat schema; detected without --from too
+    Parquet,
+    /// A table of a SQLite database, picked with --table; detected without --from too
+    Sqlite,
+}
+
+/// The format of the output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+enum OutputFormat {
+    /// A JSON array of records
+    #[default]
+    Json,
+    /// JSON Lines (NDJSON): one compact record per line
+    Jsonl,
+    /// An HTML table, or with `--pretty` a page holding one
+    Html,
+    /// A stream of MessagePack maps, one per record
+    Msgpack,
+    /// A CBOR array of maps
+    Cbor,
+    /// BSON documents, one per record
+    Bson,
+    /// An Avro object container file
+    Avro,
+    /// An Arrow IPC file, also known as Feather
+    #[value(alias = "feather")]
+    Arrow,
+    /// A SQLite database with one table
+    Sqlite,
+    /// A PostgreSQL script that creates a table and loads it with COPY
+    #[value(alias = "postgresql")]
+    Postgres,
+}
+
+impl OutputFormat {
+    /// The file extension for this format, without the dot.
+    fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Html => "html",
+            OutputFormat::Msgpack => "msgpack",
+            OutputFormat::Cbor => "cbor",
+            OutputFormat::Bson => "bson",
+            OutputFormat::Avro => "avro",
+            OutputFormat::Arrow => "arrow",
+            OutputFormat::Sqlite => "sqlite",
+            OutputFormat::Postgres => "sql",
+        }
+    }
+
+    /// Whether the output is JSON, which `--self-check` and `--append` read.
+    fn is_json(&self) -> bool {
+        matches!(self, OutputFormat::Json | OutputFormat::Jsonl)
+    }
+}
+
+/// What to do with rows whose field count differs from the header.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+enum RaggedPolicy {
+    /// Abort the conversion
+    #[default]
+    Error,
+    /// Fill missing fields with null and discard extra fields
+    Pad,
+    /// Discard extra fields and omit missing ones
+    Truncate,
+    /// Leave the row out of the output
+    Skip,
+}
+
+/// Inclusive bounds on the number of converted records, e.g. `1000..50000`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct RowCountRange {
+    min: Option<usize>,
+    max: Option<usize>,
+}
+
+impl RowCountRange {
+    fn contains(&self, count: usize) -> bool {
+        self.min.is_none_or(|min| count >= min) && self.max.is_none_or(|max| count <= max)
+    }
+}
+
+impl Config {
+    /// Whether the first CSV row holds the column names.
+    fn has_header_row(&self) -> bool {
+        !self.no_header && self.headers.is_none()
+    }
+
+    /// Whether malformed rows are skipped rather than aborting the conversion.
+    fn skips_errors(&self) -> bool {
+        self.skip_errors || self.max_errors.is_some() || self.reject_file.is_some()
+    }
+
+    /// Whether an output format writes a named table, as `--table` gives.
+    fn writes_table(&self) -> bool {
+        let has_table = |format| matches!(format, OutputFormat::Sqlite | OutputFormat::Postgres);
+        has_table(self.format) || self.also_format.is_some_and(has_table)
+    }
+
+    /// Threads for converting rows; `--threads 0` means one per CPU, as does the default.
+    fn worker_threads(&self) -> usize {
+        match self.threads {
+            Some(threads) if threads > 0 => threads,
+            _ => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let matches = Command::new("ctj")
+/// The command-line interface.
+fn cli() -> Command {
+    Command::new("ctj")
         .about("Convert CSV to JSON from files or piped input")
         .version(env!("CARGO_PKG_VERSION"))
         .arg(
@@ -24,12 +346,13 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .short('i')
                 .long("input")
                 .value_name("FILE")
-                .help("Input CSV file (reads from stdin if not provided)"),
+                .help("Input CSV file, or a directory whose CSV files are each converted to a file next to them (reads from stdin if not provided)"),
         )
         .arg(
             Arg::new("file")
                 .value_name("FILE")
-                .help("Input CSV file (reads from stdin if not provided)")
+                .help("Input CSV file (reads from stdin if not provided); several files are written as an object keyed by file name")
+                .num_args(1..)
                 .index(1),
         )
         .arg(
@@ -46,6 +369,12 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .help("Pretty print JSON output")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("ascii")
+                .long("ascii")
+                .help("Escape every non-ASCII character of JSON output as \\uXXXX, for consumers that cannot read UTF-8")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("no_header")
                 .short('n')
@@ -53,495 +382,6492 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .help("Treat the first row as data, not headers")
                 .action(clap::ArgAction::SetTrue),
         )
-        .get_matches();
+        .arg(
+            Arg::new("select")
+                .long("select")
+                .value_name("COLUMNS")
+                .value_delimiter(',')
+                .help("Comma-separated list of columns to include, in output order"),
+        )
+        .arg(
+            Arg::new("drop")
+                .long("drop")
+                .value_name("COLUMNS")
+                .value_delimiter(',')
+                .help("Comma-separated list of columns to omit from the output"),
+        )
+        .arg(
+            Arg::new("rename")
+                .long("rename")
+                .value_name("OLD=NEW")
+                .value_parser(parse_rename)
+                .action(clap::ArgAction::Append)
+                .help("Rename a column in the output (can be repeated)"),
+        )
+        .arg(
+            Arg::new("rename_file")
+                .long("rename-file")
+                .value_name("FILE")
+                .help("JSON file mapping CSV header names to output names"),
+        )
+        .arg(
+            Arg::new("read_buffer")
+                .long("read-buffer")
+                .value_name("SIZE")
+                .value_parser(parse_byte_size)
+                .help("Input buffer size in bytes, with optional K/M/G suffix (default: 8K)"),
+        )
+        .arg(
+            Arg::new("write_buffer")
+                .long("write-buffer")
+                .value_name("SIZE")
+                .value_parser(parse_byte_size)
+                .help("Output buffer size in bytes, with optional K/M/G suffix (default: 8K)"),
+        )
+        .arg(
+            Arg::new("headers")
+                .long("headers")
+                .value_name("NAMES")
+                .value_delimiter(',')
+                .help("Comma-separated column names for a CSV without a header row"),
+        )
+        .arg(
+            Arg::new("header_prefix")
+                .long("header-prefix")
+                .value_name("PREFIX")
+                .help("Prefix for generated column names, e.g. col for col_0, col_1 (or a template with a 0-based or 1-based number, see the README)"),
+        )
+        .arg(
+            Arg::new("stable_only")
+                .long("stable-only")
+                .help("Only read the input file up to the size observed when conversion starts")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("wait_for_quiescence")
+                .long("wait-for-quiescence")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64))
+                .help("Wait until the input file has not changed for SECONDS before reading it"),
+        )
+        .arg(
+            Arg::new("lock")
+                .long("lock")
+                .help("Take an advisory lock on the output file, failing if another ctj holds it")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("fail_on_empty")
+                .long("fail-on-empty")
+                .help("Exit with an error if the input contains no records")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("skip_footer")
+                .long("skip-footer")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .help("Ignore the last N rows of the input (e.g. summary or total rows)"),
+        )
+        .arg(
+            Arg::new("comment")
+                .long("comment")
+                .value_name("CHAR")
+                .value_parser(parse_single_byte)
+                .help("Ignore lines starting with this character"),
+        )
+        .arg(
+            Arg::new("expect_rows")
+                .long("expect-rows")
+                .value_name("MIN..MAX")
+                .value_parser(parse_row_count_range)
+                .help("Fail unless the number of records is within this inclusive range"),
+        )
+        .arg(
+            Arg::new("echo_header")
+                .long("echo-header")
+                .value_name("FILE")
+                .num_args(0..=1)
+                .require_equals(true)
+                .help("Print the resolved header names as a JSON array to stderr, or to FILE with --echo-header=FILE, before converting"),
+        )
+        .arg(
+            Arg::new("expect_header_hash")
+                .long("expect-header-hash")
+                .value_name("SHA256")
+                .help("Fail before converting unless the header row has this SHA-256 fingerprint"),
+        )
+        .arg(
+            Arg::new("print_header_hash")
+                .long("print-header-hash")
+                .help("Print the SHA-256 fingerprint of the header row and exit")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("on_ragged")
+                .long("on-ragged")
+                .value_name("POLICY")
+                .value_parser(clap::value_parser!(RaggedPolicy))
+                .default_value("error")
+                .help("How to handle rows with a different number of fields than the header"),
+        )
+        .arg(
+            Arg::new("between")
+                .long("between")
+                .value_name("COLUMN:START..END")
+                .value_parser(parse_time_window)
+                .help(
+                    "Only convert rows whose date/time COLUMN is at or after START and before END",
+                ),
+        )
+        .arg(
+            Arg::new("skip_errors")
+                .long("skip-errors")
+                .help("Report malformed rows on stderr and continue instead of aborting")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("date_parts")
+                .long("date-parts")
+                .value_name("COLUMN:PARTS")
+                .value_parser(parse_date_parts)
+                .action(clap::ArgAction::Append)
+                .help("Append fields like COLUMN_year derived from a date/time column (can be repeated)"),
+        )
+        .arg(
+            Arg::new("timezone")
+                .long("timezone")
+                .value_name("OFFSET")
+                .value_parser(parse_utc_offset)
+                .allow_hyphen_values(true)
+                .help("UTC offset used for --date-parts, e.g. +09:00 (default: UTC)"),
+        )
+        .arg(
+            Arg::new("max_errors")
+                .long("max-errors")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .help("Skip up to N malformed rows, failing if there are more (implies --skip-errors)"),
+        )
+        .arg(
+            Arg::new("convert_currency")
+                .long("convert-currency")
+                .value_name("COLUMN:FROM->TO:RATES[:DATE_COLUMN]")
+                .value_parser(currency::parse_currency_spec)
+                .action(clap::ArgAction::Append)
+                .help("Multiply a numeric column by exchange rates from a JSON file (can be repeated)"),
+        )
+        .arg(
+            Arg::new("scale")
+                .long("scale")
+                .value_name("COLUMN:OPS,...")
+                .value_parser(scale::parse_scales)
+                .action(clap::ArgAction::Append)
+                .help("Apply linear conversions like temp_f:(-32)*5/9 to numeric columns"),
+        )
+        .arg(
+            Arg::new("truncate")
+                .long("truncate")
+                .value_name("COLUMN:MAX,...")
+                .value_parser(truncate::parse_truncations)
+                .action(clap::ArgAction::Append)
+                .help("Cut text in a column to at most MAX characters, ending with an ellipsis (can be repeated)"),
+        )
+        .arg(
+            Arg::new("truncate_flag")
+                .long("truncate-flag")
+                .help("Add a COLUMN_truncated boolean after each --truncate column")
+                .requires("truncate")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("reject_file")
+                .long("reject-file")
+                .visible_alias("quarantine")
+                .value_name("FILE")
+                .help("Copy skipped malformed rows, as they were read, to this file (implies --skip-errors)"),
+        )
+        .arg(
+            Arg::new("explode")
+                .long("explode")
+                .value_name("COLUMN")
+                .help("Emit one record per element of a JSON array column, duplicating the other fields"),
+        )
+        .arg(
+            Arg::new("collect")
+                .long("collect")
+                .value_name("NAME[]:FIELDS")
+                .value_parser(parse_collect)
+                .action(clap::ArgAction::Append)
+                .help("Gather numbered columns like FIELD_1, FIELD_2 into an array of objects (can be repeated)"),
+        )
+        .arg(
+            Arg::new("hierarchy")
+                .long("hierarchy")
+                .value_name("ID,PARENT_ID[:CHILDREN]")
+                .value_parser(hierarchy::parse_hierarchy)
+                .help("Nest records under their parent record in a children array, emitting only the roots"),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Suppress status messages on stderr; errors are still reported")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("with_columns_meta")
+                .long("with-columns-meta")
+                .help("Wrap the output in an object with a columns array describing each column's type, null count and an example value")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Report input, header handling, row counts and timing on stderr (-vv for more detail)")
+                .action(clap::ArgAction::Count)
+                .conflicts_with("quiet"),
+        )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .value_name("FORMAT")
+                .value_parser(clap::value_parser!(InputFormat))
+                .default_value("csv")
+                .help("Input format: CSV, TSV, JSON, JSON Lines, LTSV, access logs, aligned tables, INI, fixed-width columns, Parquet or SQLite"),
+        )
+        .arg(
+            Arg::new("encoding")
+                .long("encoding")
+                .value_name("ENCODING")
+                .value_parser(clap::value_parser!(Encoding))
+                .default_value("utf-8")
+                .help("Character encoding of the input, such as shift_jis for Japanese Excel exports; it is transcoded to UTF-8 before parsing"),
+        )
+        .arg(
+            Arg::new("widths")
+                .long("widths")
+                .value_name("WIDTHS")
+                .value_parser(fixedwidth::parse_widths)
+                .help("Column widths in characters for --from fixed-width, e.g. 10,5,20; names come from the header row"),
+        )
+        .arg(
+            Arg::new("width_spec")
+                .long("width-spec")
+                .value_name("FILE")
+                .conflicts_with("widths")
+                .help("CSV file with name and width headers listing the columns for --from fixed-width, whose input then has no header row"),
+        )
+        .arg(
+            Arg::new("auto_format")
+                .long("auto-format")
+                .help("Detect whether the input is CSV, TSV, JSON Lines or JSON from its first bytes, e.g. for piped input")
+                .conflicts_with("from")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("progress")
+                .long("progress")
+                .help("Show a progress bar with rows/sec on stderr when reading a file")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .value_parser(clap::value_parser!(OutputFormat))
+                .default_value("json")
+                .help("Output format: a JSON array, JSON Lines with one record per line, an HTML table (a styled page with --pretty), a stream of MessagePack maps, a CBOR array, BSON documents, an Avro container file, an Arrow IPC (Feather) file, a SQLite database, or a PostgreSQL script of CREATE TABLE and COPY"),
+        )
+        .arg(
+            Arg::new("avro_schema")
+                .long("avro-schema")
+                .value_name("FILE")
+                .help("Avro schema (JSON) of the records for --format avro, instead of inferring one from all records"),
+        )
+        .arg(
+            Arg::new("table")
+                .long("table")
+                .value_name("NAME")
+                .help("Table to read from a SQLite database input, and the table name for --format sqlite and postgres (default: records)"),
+        )
+        .arg(
+            Arg::new("query")
+                .long("query")
+                .value_name("SQL")
+                .help("SELECT statement to run on a SQLite database input with the sqlite3 command, instead of reading a whole table"),
+        )
+        .arg(
+            Arg::new("self_check")
+                .long("self-check")
+                .help("Re-read the output file after writing and verify its record count and sampled records")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("preflight_space")
+                .long("preflight-space")
+                .help("Estimate the output size from a sample of records and fail early if the output's filesystem lacks the space")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("threads")
+                .long("threads")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .help("Number of threads converting CSV rows, or files of a directory input (default: one per CPU; 1 converts on the reading thread)"),
+        )
+        .arg(
+            Arg::new("queue_size")
+                .long("queue-size")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Chunks of rows converting on --threads or waiting to be written before reading pauses (default: 4 per thread)"),
+        )
+        .arg(
+            Arg::new("rows_file")
+                .long("rows-file")
+                .value_name("FILE")
+                .help("Only convert the rows starting on the 1-based line numbers listed in FILE, one per line"),
+        )
+        .arg(
+            Arg::new("exclude_rows_file")
+                .long("exclude-rows-file")
+                .value_name("FILE")
+                .conflicts_with("rows_file")
+                .help("Leave out the rows starting on the 1-based line numbers listed in FILE, one per line"),
+        )
+        .arg(
+            Arg::new("dictionary")
+                .long("dictionary")
+                .value_name("FILE")
+                .help("CSV data dictionary (column, description, unit) to include in --with-columns-meta output"),
+        )
+        .arg(
+            Arg::new("max_warnings")
+                .long("max-warnings")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .help("Print up to N warnings of each kind, such as ragged rows or precision loss (default: 1)"),
+        )
+        .arg(
+            Arg::new("fatal_warnings")
+                .long("fatal-warnings")
+                .help("Fail on the first warning")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .value_name("FILE")
+                .help("Write a JSON report of the checks run, such as --expect-rows, and whether the conversion passed, even when it fails"),
+        )
+        .arg(
+            Arg::new("timing")
+                .long("timing")
+                .help("Print records converted, bytes read, wall time, throughput and peak memory to stderr when done")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("also_output")
+                .long("also-output")
+                .value_name("FILE")
+                .help("Also write the records to FILE in the same pass, e.g. for consumers of an older format"),
+        )
+        .arg(
+            Arg::new("also_format")
+                .long("also-format")
+                .value_name("FORMAT")
+                .value_parser(clap::value_parser!(OutputFormat))
+                .requires("also_output")
+                .help("Output format for --also-output (default: the --format of the main output)"),
+        )
+        .arg(
+            Arg::new("split_rows")
+                .long("split-rows")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Write at most N records per output file, numbering the files, e.g. out_0001.json, out_0002.json"),
+        )
+        .arg(
+            Arg::new("partition_by")
+                .long("partition-by")
+                .value_name("COLUMN")
+                .conflicts_with("split_rows")
+                .help("Write one file per value of COLUMN into the --output directory, e.g. out/country=JP.json"),
+        )
+        .arg(
+            Arg::new("append")
+                .long("append")
+                .help("Add the records to an existing output file: JSON Lines are appended, and a JSON array is extended")
+                .conflicts_with_all(["split_rows", "partition_by"])
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("backup")
+                .long("backup")
+                .help("Rename an existing output file to FILE.bak before writing a new one")
+                .conflicts_with("append")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("zip_member")
+                .long("zip-member")
+                .value_name("NAME")
+                .help("Member of a ZIP archive input to convert, by path or file name (default: every CSV file in it)"),
+        )
+        .arg(
+            Arg::new("sheet")
+                .long("sheet")
+                .value_name("NAME")
+                .help("Worksheet of an Excel (.xlsx) or OpenDocument (.ods) input to convert, by name or 1-based position (default: the first)"),
+        )
+        .arg(
+            Arg::new("strict_rfc4180")
+                .long("strict-rfc4180")
+                .help("Reject CSV input that violates RFC 4180 (bare CR, stray quotes, text after a closing quote); LF line breaks are accepted")
+                .conflicts_with("skip_errors")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("repair_quotes")
+                .long("repair-quotes")
+                .help("Repair stray and unbalanced quotes instead of failing, reporting each repair")
+                .conflicts_with("strict_rfc4180")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("infer_threshold")
+                .long("infer-threshold")
+                .value_name("FRACTION")
+                .value_parser(parse_threshold)
+                .help("Type a column as numbers or booleans only if at least FRACTION (e.g. 0.95) of its sampled values are; otherwise keep it as text"),
+        )
+        .arg(
+            Arg::new("schema_cache")
+                .long("schema-cache")
+                .value_name("DIR")
+                .requires("infer_threshold")
+                .help("Save the column types inferred by --infer-threshold in DIR and reuse them for inputs with the same header instead of sampling"),
+        )
+        .arg(
+            Arg::new("normalize_report")
+                .long("normalize-report")
+                .help("Report mixed CRLF/LF line endings, trailing whitespace and non-breaking spaces in the input")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("normalize")
+                .long("normalize")
+                .help("Fix the problems --normalize-report finds while converting, and report them")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("normalize_unicode")
+                .long("normalize-unicode")
+                .value_name("FORM")
+                .value_parser(clap::value_parser!(unicode::Form))
+                .help("Normalize headers and values to Unicode NFC, or NFKC to also fold full-width and half-width variants"),
+        )
+        .arg(
+            Arg::new("recursive")
+                .short('r')
+                .long("recursive")
+                .help("With a directory input, also convert the CSV files in its subdirectories, keeping their layout under --output")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("output_template")
+                .long("output-template")
+                .value_name("TEMPLATE")
+                .value_parser(template::parse_template)
+                .help("With a directory input, name each output file from {dir}, {stem}, {name} and {ext}, e.g. '{dir}/{stem}.json'"),
+        )
+        .arg(
+            Arg::new("source_field")
+                .long("source-field")
+                .value_name("NAME")
+                .help("With several input files, write one list of records with each file name in field NAME instead of an object keyed by file name"),
+        )
+        .arg(
+            Arg::new("cache_dir")
+                .long("cache-dir")
+                .value_name("DIR")
+                .help("Reuse the output of an earlier run with the same input and options from DIR, e.g. .ctj-cache"),
+        )
+        .arg(
+            Arg::new("temp_dir")
+                .long("temp-dir")
+                .value_name("DIR")
+                .help("Directory for scratch files, such as --partition-by records waiting for a file (default: $TMPDIR)"),
+        )
+        .arg(
+            Arg::new("compress")
+                .long("compress")
+                .value_name("FORMAT")
+                .value_parser(clap::value_parser!(Compression))
+                .help("Compress the output (default: inferred from the output file extension, e.g. .json.gz or .jsonl.zst)"),
+        )
+        .arg(
+            Arg::new("emit_mapping")
+                .long("emit-mapping")
+                .value_name("FILE")
+                .help("Write each output key's source columns, transforms and type to FILE as JSON"),
+        )
+        .arg(
+            Arg::new("capabilities")
+                .long("capabilities")
+                .help("Print a JSON description of the formats and options this build supports and exit")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+/// Names of the values of an option such as `--from`.
+fn value_names<T: clap::ValueEnum>() -> Vec<String> {
+    T::value_variants()
+        .iter()
+        .filter_map(T::to_possible_value)
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
+
+/// Describes what this build supports for `--capabilities`, so tools can
+/// check a ctj binary before building a command line for it.
+fn capabilities() -> Value {
+    let options: Vec<String> = cli()
+        .get_arguments()
+        .filter_map(|arg| arg.get_long())
+        .map(|long| format!("--{}", long))
+        .collect();
+    // Whether each command ctj runs was found: the compressors, and sqlite3
+    // for --query
+    let external_commands: serde_json::Map<String, Value> = compression::FORMATS
+        .iter()
+        .filter_map(|format| format.command())
+        .chain(["sqlite3"])
+        .map(|command| {
+            (
+                command.to_string(),
+                compression::command_exists(command).into(),
+            )
+        })
+        .collect();
+    serde_json::json!({
+        "name": "ctj",
+        "version": env!("CARGO_PKG_VERSION"),
+        "capabilities_version": 1,
+        "input_formats": value_names::<InputFormat>(),
+        "output_formats": value_names::<OutputFormat>(),
+        "sinks": ["stdout", "file"],
+        "encodings": value_names::<Encoding>(),
+        // Formats other than gzip need their command
+        "compression": compression::FORMATS
+            .into_iter()
+            .filter(|format| format.command().is_none_or(compression::command_exists))
+            .map(|format| format.name())
+            .collect::<Vec<_>>(),
+        "external_commands": external_commands,
+        "archives": ["zip"],
+        "options": options,
+        "features": {
+            "parallel_conversion": true,
+            "preflight_space": cfg!(unix),
+            "peak_memory": cfg!(unix),
+        },
+        "exit_codes": {
+            "success": 0,
+            "config": 2,
+            "io": 3,
+            "malformed_input": 4,
+            "json": 5,
+            "validation": 6,
+        },
+    })
+}
+
+fn main() {
+    let matches = cli().get_matches();
+    if matches.get_flag("capabilities") {
+        println!("{:#}", capabilities());
+        return;
+    }
 
+    let files: Vec<String> = matches
+        .get_many::<String>("file")
+        .map(|values| values.cloned().collect())
+        .unwrap_or_default();
     let input_file = matches
         .get_one::<String>("input")
-        .or_else(|| matches.get_one::<String>("file"));
+        .or_else(|| files.first().filter(|_| files.len() == 1));
 
     // If no input file specified, we'll read from stdin
     // The error will be handled in convert_csv_to_json if stdin is empty/closed
 
     let config = Config {
         input: input_file.cloned(),
+        inputs: if files.len() > 1 {
+            files.clone()
+        } else {
+            Vec::new()
+        },
         output: matches.get_one::<String>("output").cloned(),
         pretty: matches.get_flag("pretty"),
+        ascii: matches.get_flag("ascii"),
         no_header: matches.get_flag("no_header"),
+        select: matches
+            .get_many::<String>("select")
+            .map(|values| values.cloned().collect()),
+        drop: matches
+            .get_many::<String>("drop")
+            .map(|values| values.cloned().collect()),
+        rename: matches
+            .get_many::<(String, String)>("rename")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        rename_file: matches.get_one::<String>("rename_file").cloned(),
+        read_buffer: matches.get_one::<usize>("read_buffer").copied(),
+        write_buffer: matches.get_one::<usize>("write_buffer").copied(),
+        headers: matches
+            .get_many::<String>("headers")
+            .map(|values| values.cloned().collect()),
+        header_prefix: matches.get_one::<String>("header_prefix").cloned(),
+        stable_only: matches.get_flag("stable_only"),
+        wait_for_quiescence: matches.get_one::<u64>("wait_for_quiescence").copied(),
+        lock: matches.get_flag("lock"),
+        fail_on_empty: matches.get_flag("fail_on_empty"),
+        skip_footer: matches
+            .get_one::<usize>("skip_footer")
+            .copied()
+            .unwrap_or_default(),
+        comment: matches.get_one::<u8>("comment").copied(),
+        expect_rows: matches.get_one::<RowCountRange>("expect_rows").copied(),
+        expect_header_hash: matches.get_one::<String>("expect_header_hash").cloned(),
+        print_header_hash: matches.get_flag("print_header_hash"),
+        echo_header: matches
+            .contains_id("echo_header")
+            .then(|| matches.get_one::<String>("echo_header").cloned()),
+        on_ragged: *matches
+            .get_one::<RaggedPolicy>("on_ragged")
+            .expect("has a default value"),
+        between: matches.get_one::<TimeWindow>("between").cloned(),
+        skip_errors: matches.get_flag("skip_errors"),
+        date_parts: matches
+            .get_many::<DateParts>("date_parts")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        timezone: matches
+            .get_one::<i64>("timezone")
+            .copied()
+            .unwrap_or_default(),
+        max_errors: matches.get_one::<usize>("max_errors").copied(),
+        convert_currency: matches
+            .get_many::<CurrencySpec>("convert_currency")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        scale: matches
+            .get_many::<Vec<Scale>>("scale")
+            .map(|values| values.flatten().cloned().collect())
+            .unwrap_or_default(),
+        truncate: matches
+            .get_many::<Vec<Truncation>>("truncate")
+            .map(|values| values.flatten().cloned().collect())
+            .unwrap_or_default(),
+        truncate_flag: matches.get_flag("truncate_flag"),
+        reject_file: matches.get_one::<String>("reject_file").cloned(),
+        explode: matches.get_one::<String>("explode").cloned(),
+        collect: matches
+            .get_many::<Collect>("collect")
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default(),
+        hierarchy: matches.get_one::<Hierarchy>("hierarchy").cloned(),
+        quiet: matches.get_flag("quiet"),
+        with_columns_meta: matches.get_flag("with_columns_meta"),
+        verbose: matches.get_count("verbose"),
+        from: *matches
+            .get_one::<InputFormat>("from")
+            .expect("has a default value"),
+        encoding: *matches
+            .get_one::<Encoding>("encoding")
+            .expect("has a default value"),
+        widths: matches.get_one::<Vec<usize>>("widths").cloned(),
+        width_spec: matches.get_one::<String>("width_spec").cloned(),
+        progress: matches.get_flag("progress"),
+        format: *matches
+            .get_one::<OutputFormat>("format")
+            .expect("has a default value"),
+        self_check: matches.get_flag("self_check"),
+        preflight_space: matches.get_flag("preflight_space"),
+        threads: matches.get_one::<usize>("threads").copied(),
+        queue_size: matches.get_one::<u64>("queue_size").map(|&n| n as usize),
+        rows_file: matches.get_one::<String>("rows_file").cloned(),
+        exclude_rows_file: matches.get_one::<String>("exclude_rows_file").cloned(),
+        dictionary: matches.get_one::<String>("dictionary").cloned(),
+        avro_schema: matches.get_one::<String>("avro_schema").cloned(),
+        table: matches.get_one::<String>("table").cloned(),
+        query: matches.get_one::<String>("query").cloned(),
+        max_warnings: matches.get_one::<usize>("max_warnings").copied(),
+        fatal_warnings: matches.get_flag("fatal_warnings"),
+        report: matches.get_one::<String>("report").cloned(),
+        timing: matches.get_flag("timing"),
+        also_output: matches.get_one::<String>("also_output").cloned(),
+        split_rows: matches.get_one::<u64>("split_rows").map(|&n| n as usize),
+        partition_by: matches.get_one::<String>("partition_by").cloned(),
+        append: matches.get_flag("append"),
+        backup: matches.get_flag("backup"),
+        also_format: matches.get_one::<OutputFormat>("also_format").copied(),
+        emit_mapping: matches.get_one::<String>("emit_mapping").cloned(),
+        compress: matches.get_one::<Compression>("compress").copied(),
+        strict_rfc4180: matches.get_flag("strict_rfc4180"),
+        repair_quotes: matches.get_flag("repair_quotes"),
+        normalize_report: matches.get_flag("normalize_report"),
+        normalize: matches.get_flag("normalize"),
+        normalize_unicode: matches
+            .get_one::<unicode::Form>("normalize_unicode")
+            .copied(),
+        cache_dir: matches.get_one::<String>("cache_dir").cloned(),
+        source_field: matches.get_one::<String>("source_field").cloned(),
+        infer_threshold: matches.get_one::<f64>("infer_threshold").copied(),
+        schema_cache: matches.get_one::<String>("schema_cache").cloned(),
+        recursive: matches.get_flag("recursive"),
+        output_template: matches
+            .get_one::<OutputTemplate>("output_template")
+            .cloned(),
+        auto_format: matches.get_flag("auto_format"),
+        zip_member: matches.get_one::<String>("zip_member").cloned(),
+        sheet: matches.get_one::<String>("sheet").cloned(),
+        temp_dir: matches.get_one::<String>("temp_dir").cloned(),
     };
 
-    convert_csv_to_json(&config)?;
+    log::set_verbosity(config.verbose);
+    log::debug!("{:?}", config);
+    let started = Instant::now();
+    if let Err(e) = convert_csv_to_json(&config) {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+    log::info!("Finished in {:.1?}", started.elapsed());
+}
 
-    Ok(())
+fn parse_rename(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((old, new)) if !old.is_empty() && !new.is_empty() => {
+            Ok((old.to_string(), new.to_string()))
+        }
+        _ => Err(format!("expected OLD=NEW, got '{}'", s)),
+    }
 }
 
-fn parse_boolean(s: &str) -> Option<bool> {
-    match s.to_lowercase().as_str() {
-        "true" => Some(true),
-        "false" => Some(false),
-        _ => None,
+fn parse_byte_size(s: &str) -> Result<usize, String> {
+    let (digits, multiplier) = match s.to_uppercase().chars().last() {
+        Some('K') => (&s[..s.len() - 1], 1024),
+        Some('M') => (&s[..s.len() - 1], 1024 * 1024),
+        Some('G') => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    match digits.parse::<usize>() {
+        Ok(size) if size > 0 => size
+            .checked_mul(multiplier)
+            .ok_or_else(|| format!("size too large: '{}'", s)),
+        _ => Err(format!(
+            "expected a positive size like 64K or 1M, got '{}'",
+            s
+        )),
     }
 }
 
-fn parse_number(s: &str) -> Value {
-    if let Ok(int_val) = s.parse::<i64>() {
-        serde_json::Value::Number(serde_json::Number::from(int_val))
-    } else if let Ok(float_val) = s.parse::<f64>() {
-        serde_json::Value::Number(serde_json::Number::from_f64(float_val).unwrap())
-    } else {
-        serde_json::Value::String(s.to_string())
+fn parse_threshold(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(fraction),
+        _ => Err(format!(
+            "expected a fraction greater than 0 and at most 1, got '{}'",
+            s
+        )),
     }
 }
 
-fn convert_csv_to_json(config: &Config) -> Result<(), Box<dyn Error>> {
-    let mut reader: Reader<Box<dyn Read>> = match &config.input {
-        Some(file_path) => {
-            let file = File::open(file_path)?;
-            let boxed_reader: Box<dyn Read> = Box::new(BufReader::new(file));
-            if config.no_header {
-                csv::ReaderBuilder::new()
-                    .has_headers(false)
-                    .from_reader(boxed_reader)
-            } else {
-                Reader::from_reader(boxed_reader)
-            }
+fn parse_single_byte(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [byte] if byte.is_ascii() => Ok(*byte),
+        _ => Err(format!("expected a single ASCII character, got '{}'", s)),
+    }
+}
+
+fn parse_row_count_range(s: &str) -> Result<RowCountRange, String> {
+    let error = || {
+        format!(
+            "expected a range like 1000..50000, 1000.. or ..50000, got '{}'",
+            s
+        )
+    };
+    let (min, max) = s.split_once("..").ok_or_else(error)?;
+    let bound = |value: &str| -> Result<Option<usize>, String> {
+        if value.is_empty() {
+            Ok(None)
+        } else {
+            value.parse().map(Some).map_err(|_| error())
         }
-        None => {
-            let boxed_reader: Box<dyn Read> = Box::new(BufReader::new(io::stdin()));
-            if config.no_header {
-                csv::ReaderBuilder::new()
-                    .has_headers(false)
-                    .from_reader(boxed_reader)
-            } else {
-                Reader::from_reader(boxed_reader)
-            }
+    };
+    let range = RowCountRange {
+        min: bound(min)?,
+        max: bound(max)?,
+    };
+    match (range.min, range.max) {
+        (None, None) => Err(error()),
+        (Some(min), Some(max)) if min > max => Err(error()),
+        _ => Ok(range),
+    }
+}
+
+fn parse_time_window(s: &str) -> Result<TimeWindow, String> {
+    let error = || {
+        format!(
+            "expected COLUMN:START..END with ISO 8601 dates, got '{}'",
+            s
+        )
+    };
+    let (column, range) = s.split_once(':').ok_or_else(error)?;
+    let (start, end) = range.split_once("..").ok_or_else(error)?;
+    let bound = |value: &str| -> Result<Option<i64>, String> {
+        if value.is_empty() {
+            Ok(None)
+        } else {
+            datetime::parse_timestamp(value).map(Some).ok_or_else(error)
         }
     };
+    let window = TimeWindow {
+        column: column.to_string(),
+        start: bound(start)?,
+        end: bound(end)?,
+    };
+    if column.is_empty() || (window.start.is_none() && window.end.is_none()) {
+        return Err(error());
+    }
+    Ok(window)
+}
+
+fn parse_date_parts(s: &str) -> Result<DateParts, String> {
+    let error = || {
+        format!(
+            "expected COLUMN:PART[,PART...] with parts year, quarter, month, day, hour, minute, dow, doy or date, got '{}'",
+            s
+        )
+    };
+    let (column, parts) = s.rsplit_once(':').ok_or_else(error)?;
+    if column.is_empty() {
+        return Err(error());
+    }
+    let parts = parts
+        .split(',')
+        .map(|part| DatePart::parse(part.trim()).ok_or_else(error))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(DateParts {
+        column: column.to_string(),
+        parts,
+    })
+}
+
+fn parse_collect(s: &str) -> Result<Collect, String> {
+    let error = || format!("expected NAME[]:FIELD[,FIELD...], got '{}'", s);
+    let (name, fields) = s.split_once(':').ok_or_else(error)?;
+    let name = name.strip_suffix("[]").ok_or_else(error)?;
+    let fields: Vec<String> = fields.split(',').map(|f| f.trim().to_string()).collect();
+    if name.is_empty() || fields.iter().any(String::is_empty) {
+        return Err(error());
+    }
+    Ok(Collect {
+        name: name.to_string(),
+        fields,
+    })
+}
+
+fn parse_utc_offset(s: &str) -> Result<i64, String> {
+    datetime::parse_offset(s)
+        .ok_or_else(|| format!("expected a UTC offset like +09:00 or -05:00, got '{}'", s))
+}
+
+fn parse_boolean(s: &str) -> Option<bool> {
+    // Compared in place, since lowercasing would allocate for every field
+    if s.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if s.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn parse_number(s: &str) -> Value {
+    if let Ok(int_val) = s.parse::<i64>() {
+        serde_json::Value::Number(serde_json::Number::from(int_val))
+    } else if let Ok(float_val) = s.parse::<f64>() {
+        serde_json::Value::Number(serde_json::Number::from_f64(float_val).unwrap())
+    } else {
+        serde_json::Value::String(s.to_string())
+    }
+}
 
-    let headers = if config.no_header {
-        // Generate column names: column_0, column_1, column_2, ...
-        let mut all_records = Vec::new();
-        let mut max_columns = 0;
+/// Infers the JSON type of a text field: a boolean, a number or a string.
+fn parse_field(field: &str) -> Value {
+    match parse_boolean(field) {
+        Some(bool_val) => Value::Bool(bool_val),
+        None => parse_number(field),
+    }
+}
 
-        // First pass: collect all records and find max columns
-        for result in reader.records() {
-            let record = result?;
-            max_columns = max_columns.max(record.len());
-            all_records.push(record);
+fn convert_csv_to_json(config: &Config) -> Result<(), Error> {
+    if let Some(dir) = config
+        .input
+        .as_deref()
+        .filter(|path| Path::new(path).is_dir())
+    {
+        return convert_directory(dir, config);
+    }
+    if config.recursive || config.output_template.is_some() {
+        return Err(Error::Config(
+            "--recursive and --output-template require a directory as input".to_string(),
+        ));
+    }
+    let started = Instant::now();
+    if config.timing {
+        stats::enable_stage_timing();
+    }
+    let stages = stats::StageTimes::now();
+    let warnings = Warnings::new(config.max_warnings, config.fatal_warnings, config.quiet);
+    let cache = cache_entry(config)?;
+    if let Some((entry, output)) = &cache {
+        let _lock = acquire_output_lock(config)?;
+        if config.backup && entry.exists() {
+            replace::back_up(output)?;
+        }
+        if entry.restore(output)? {
+            return Ok(());
+        }
+    }
+    // Whatever the other stages do not time counts as reading
+    let converted = stats::timed(Stage::Read, || {
+        if config.inputs.is_empty() {
+            let records = convert(config, &warnings)?;
+            Ok::<_, Error>((records, stats::input_read()))
+        } else {
+            convert_inputs(config, &warnings)
         }
+    });
+    if let Some(path) = &config.report {
+        let result = converted.as_ref().map(|(records, _)| *records);
+        let written = report::write(path, result, &warnings);
+        // An error converting matters more than one writing the report
+        if converted.is_ok() {
+            written?;
+        }
+    }
+    let (records, bytes_read) = converted?;
+    warnings.finish();
+    if let Some((entry, output)) = &cache {
+        entry.store(output)?;
+    }
+    if config.timing {
+        let timing = stats::Timing {
+            records,
+            bytes_read,
+            elapsed: started.elapsed(),
+            peak_memory: stats::peak_memory(),
+            stages: stats::StageTimes::now().since(&stages),
+        };
+        eprint!("{}", timing);
+    }
+    Ok(())
+}
 
-        if all_records.is_empty() {
-            // Empty file
-            let records: Vec<IndexMap<String, Value>> = Vec::new();
-            let json_output = if config.pretty {
-                serde_json::to_string_pretty(&records)?
-            } else {
-                serde_json::to_string(&records)?
-            };
+/// Converts several input files into one output: a JSON object keyed by file
+/// name, or with `--source-field` a single list of records naming their file.
+/// Each file is converted on its own into a temporary file first. Returns
+/// the number of records written and the bytes of input read.
+fn convert_inputs(config: &Config, warnings: &Warnings) -> Result<(usize, u64), Error> {
+    if config.input.is_some() {
+        return Err(Error::Config(
+            "--input cannot be combined with several input files".to_string(),
+        ));
+    }
+    let unsupported = [
+        (config.also_output.is_some(), "--also-output"),
+        (config.emit_mapping.is_some(), "--emit-mapping"),
+        (config.reject_file.is_some(), "--reject-file"),
+        (config.self_check, "--self-check"),
+        (config.split_rows.is_some(), "--split-rows"),
+        (config.partition_by.is_some(), "--partition-by"),
+        (config.append, "--append"),
+        (config.preflight_space, "--preflight-space"),
+        (config.avro_schema.is_some(), "--avro-schema"),
+        (
+            config.with_columns_meta && config.source_field.is_some(),
+            "--with-columns-meta with --source-field",
+        ),
+    ];
+    if let Some((_, option)) = unsupported.iter().find(|(used, _)| *used) {
+        return Err(Error::Config(format!(
+            "{} is not supported with several input files",
+            option
+        )));
+    }
+    if config.source_field.is_none() && config.format != OutputFormat::Json {
+        return Err(Error::Config(
+            "Several input files are written as a JSON object; use --source-field for --format jsonl"
+                .to_string(),
+        ));
+    }
+    // Each file is a key of the output object, or a value of --source-field
+    let mut seen = HashSet::new();
+    if let Some(input) = config.inputs.iter().find(|input| !seen.insert(*input)) {
+        return Err(Error::Config(format!(
+            "{} is given more than once as an input",
+            input
+        )));
+    }
+    let _lock = acquire_output_lock(config)?;
+
+    let (mut records, mut bytes_read) = (0, 0);
+    let mut convert_input = |input: &str, format| -> Result<BufReader<File>, Error> {
+        let temp = match &config.temp_dir {
+            Some(dir) => tempfile::NamedTempFile::new_in(dir)?,
+            None => tempfile::NamedTempFile::new()?,
+        };
+        let input_config = Config {
+            input: Some(input.to_string()),
+            inputs: Vec::new(),
+            output: Some(temp.path().to_string_lossy().to_string()),
+            format,
+            compress: None,
+            lock: false,
+            // Reported for the output as a whole, not each temporary file
+            quiet: true,
+            fail_on_empty: false,
+            expect_rows: None,
+            ..config.clone()
+        };
+        log::info!("Converting {}", input);
+        records += convert(&input_config, warnings)?;
+        bytes_read += stats::input_read();
+        // Opened by path, as the finished output replaced the file created
+        Ok(BufReader::new(File::open(temp.path())?))
+    };
+
+    // Every file is converted before any output, so the counts are checked
+    // on the whole and a failure leaves no partial output
+    let format = match config.source_field {
+        Some(_) => OutputFormat::Jsonl,
+        None => OutputFormat::Json,
+    };
+    let converted = config
+        .inputs
+        .iter()
+        .map(|input| convert_input(input, format))
+        .collect::<Result<Vec<_>, _>>()?;
+    check_record_count(config, records)?;
 
-            match &config.output {
-                Some(output_file) => {
-                    std::fs::write(output_file, json_output)?;
-                    println!("JSON output written to: {}", output_file);
+    let mut output = open_output(config.output.as_deref(), config)?;
+    if let Some(field) = &config.source_field {
+        let field: Arc<str> = Arc::from(field.as_str());
+        let mut writer = RecordWriter::new(output, config.format, config.pretty);
+        for (input, mut converted) in config.inputs.iter().zip(converted) {
+            let mut line = String::new();
+            while converted.read_line(&mut line)? > 0 {
+                let converted_record: Record = serde_json::from_str(&line)?;
+                if converted_record.contains_key(&field) {
+                    return Err(Error::Config(format!(
+                        "--source-field {} is already a field of {}",
+                        field, input
+                    )));
                 }
-                None => {
-                    println!("{}", json_output);
+                let mut record = Record::with_capacity(converted_record.len() + 1);
+                record.insert(Arc::clone(&field), Value::from(input.as_str()));
+                record.extend(converted_record);
+                writer.write_record(&record)?;
+                line.clear();
+            }
+        }
+        output = writer.finish()?;
+    } else {
+        for (i, (input, mut converted)) in config.inputs.iter().zip(converted).enumerate() {
+            output.write_all(if i == 0 { b"{" } else { b"," })?;
+            if config.pretty {
+                output.write_all(b"\n  ")?;
+            }
+            serde_json::to_writer(&mut output, input)?;
+            output.write_all(if config.pretty { b": " } else { b":" })?;
+            // Nested one level down, as the array writer nests records
+            let mut line = Vec::new();
+            while converted.read_until(b'\n', &mut line)? > 0 {
+                output.write_all(&line)?;
+                if line.ends_with(b"\n") {
+                    output.write_all(b"  ")?;
                 }
+                line.clear();
             }
-
-            return Ok(());
         }
+        output.write_all(if config.pretty { b"\n}" } else { b"}" })?;
+    }
+    output.finish()?;
+    if let (false, Some(output_file)) = (config.quiet, &config.output) {
+        eprintln!("Output written to: {}", output_file);
+    }
+    Ok((records, bytes_read))
+}
 
-        // Generate headers
-        let mut generated_headers = Vec::new();
-        for i in 0..max_columns {
-            generated_headers.push(format!("column_{}", i));
+/// Checks the number of records converted against `--fail-on-empty` and
+/// `--expect-rows`.
+fn check_record_count(config: &Config, count: usize) -> Result<(), Error> {
+    if config.fail_on_empty {
+        let passed = count > 0;
+        report::record(
+            "fail_on_empty",
+            passed,
+            serde_json::json!({ "records": count }),
+        );
+        if !passed {
+            return Err(Error::Validation("Input contains no records".to_string()));
+        }
+    }
+    if let Some(range) = &config.expect_rows {
+        let expected = format!(
+            "{}..{}",
+            range.min.map_or(String::new(), |min| min.to_string()),
+            range.max.map_or(String::new(), |max| max.to_string())
+        );
+        let passed = range.contains(count);
+        report::record(
+            "expect_rows",
+            passed,
+            serde_json::json!({ "expected": expected, "actual": count }),
+        );
+        if !passed {
+            return Err(Error::Validation(format!(
+                "Expected {} records, but the input contains {}",
+                expected, count
+            )));
         }
+    }
+    Ok(())
+}
 
-        // Process all records
-        let mut json_records = Vec::new();
-        for record in all_records {
-            let mut map = IndexMap::new();
-            for (i, field) in record.iter().enumerate() {
-                if let Some(header) = generated_headers.get(i) {
-                    let value: Value = if let Some(bool_val) = parse_boolean(field) {
-                        serde_json::Value::Bool(bool_val)
-                    } else {
-                        parse_number(field)
-                    };
-                    map.insert(header.to_string(), value);
+/// Converts every `.csv` file in a directory to a file named after it with
+/// the extension of the output format, e.g. `sales.json`. Output goes next
+/// to each CSV file, or into the `--output` directory at the same relative
+/// path. Files are converted on `--threads` threads, and a file that fails
+/// does not stop the others; the failures are listed at the end.
+fn convert_directory(dir: &str, config: &Config) -> Result<(), Error> {
+    if !config.inputs.is_empty() {
+        return Err(Error::Config(
+            "A directory input cannot be combined with other inputs".to_string(),
+        ));
+    }
+    if config.also_output.is_some()
+        || config.emit_mapping.is_some()
+        || config.reject_file.is_some()
+        || config.partition_by.is_some()
+        || config.report.is_some()
+    {
+        return Err(Error::Config(
+            "--also-output, --emit-mapping, --reject-file, --partition-by and --report require a single input file"
+                .to_string(),
+        ));
+    }
+    let mut inputs = Vec::new();
+    find_csv_files(Path::new(dir), config.recursive, &mut inputs)?;
+    inputs.sort();
+    log::info!("Converting {} CSV files in {}", inputs.len(), dir);
+
+    let output_dir = Path::new(config.output.as_deref().unwrap_or(dir));
+    let extension = config.format.extension();
+    let mut jobs = Vec::with_capacity(inputs.len());
+    let mut outputs = HashSet::new();
+    for input in inputs {
+        let relative = input.strip_prefix(dir).expect("found inside the directory");
+        let output = match &config.output_template {
+            // The template names the whole path, compression extension included
+            Some(template) => PathBuf::from(
+                template.render(&template::Fields {
+                    dir: &output_dir
+                        .join(relative)
+                        .parent()
+                        .unwrap_or(output_dir)
+                        .to_string_lossy(),
+                    stem: &input.file_stem().unwrap_or_default().to_string_lossy(),
+                    name: &input.file_name().unwrap_or_default().to_string_lossy(),
+                    ext: extension,
+                }),
+            ),
+            None => {
+                let mut output = output_dir
+                    .join(relative)
+                    .with_extension(extension)
+                    .into_os_string();
+                if let Some(format) = config.compress {
+                    output.push(format.extension());
                 }
+                PathBuf::from(output)
             }
-            json_records.push(map);
+        };
+        if !outputs.insert(output.clone()) {
+            return Err(Error::Config(format!(
+                "--output-template gives {} for more than one input file",
+                output.display()
+            )));
         }
+        if let Some(parent) = output.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        jobs.push((
+            input.to_string_lossy().to_string(),
+            output.to_string_lossy().to_string(),
+        ));
+    }
 
-        let json_output = if config.pretty {
-            serde_json::to_string_pretty(&json_records)?
-        } else {
-            serde_json::to_string(&json_records)?
+    // Files are spread over the threads, so each file is converted on one
+    let threads = config.worker_threads().min(jobs.len());
+    log::debug!("Converting files on up to {} threads", threads);
+    let results = parallel::map_each(threads, &jobs, |(input, output)| {
+        let file_config = Config {
+            input: Some(input.clone()),
+            output: Some(output.clone()),
+            recursive: false,
+            output_template: None,
+            threads: if threads > 1 { Some(1) } else { config.threads },
+            ..config.clone()
         };
+        convert_csv_to_json(&file_config).map_err(|e| e.in_file(input))
+    });
 
-        match &config.output {
-            Some(output_file) => {
-                std::fs::write(output_file, json_output)?;
-                println!("JSON output written to: {}", output_file);
+    let failures: Vec<Error> = results.into_iter().filter_map(Result::err).collect();
+    if failures.is_empty() {
+        if jobs.len() > 1 && !config.quiet {
+            eprintln!("Converted {} files", jobs.len());
+        }
+        return Ok(());
+    }
+    for failure in &failures {
+        eprintln!("Failed to convert {}", failure);
+    }
+    // The first failure decides the exit code
+    let summary = format!(
+        "{} of {} files failed to convert",
+        failures.len(),
+        jobs.len()
+    );
+    let first = failures.into_iter().next().expect("there is a failure");
+    Err(first.with_message(summary))
+}
+
+/// Collects the `.csv` files in `dir`, and with `recursive` in the
+/// directories below it. Symbolic links to directories are not followed.
+fn find_csv_files(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if recursive {
+                find_csv_files(&path, recursive, files)?;
             }
-            None => {
-                println!("{}", json_output);
+            continue;
+        }
+        let is_csv = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+        if is_csv && path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Options that do not change the output, left out of cache keys.
+const UNCACHED_OPTIONS: &[&str] = &[
+    "input",
+    "output",
+    "cache_dir",
+    "read_buffer",
+    "write_buffer",
+    "wait_for_quiescence",
+    "lock",
+    "quiet",
+    "verbose",
+    "progress",
+    "threads",
+    "queue_size",
+    "timing",
+    "temp_dir",
+];
+
+/// Resolves the `--cache-dir` entry for this conversion and its output file.
+fn cache_entry(config: &Config) -> Result<Option<(cache::Entry, &str)>, Error> {
+    let Some(dir) = &config.cache_dir else {
+        return Ok(None);
+    };
+    let (Some(input), Some(output)) = (&config.input, &config.output) else {
+        return Err(Error::Config(
+            "--cache-dir requires an input file and an output file".to_string(),
+        ));
+    };
+    if config.also_output.is_some()
+        || config.emit_mapping.is_some()
+        || config.reject_file.is_some()
+        || config.split_rows.is_some()
+        || config.partition_by.is_some()
+        || config.append
+        || config.report.is_some()
+    {
+        return Err(Error::Config(
+            "--cache-dir cannot be combined with --also-output, --emit-mapping, --reject-file, --split-rows, --partition-by, --append or --report"
+                .to_string(),
+        ));
+    }
+    let mut options = serde_json::to_value(config)?;
+    let options = options
+        .as_object_mut()
+        .expect("Config serializes to an object");
+    for name in UNCACHED_OPTIONS {
+        options.remove(*name);
+    }
+    // The output path is left out, but the compression it implies is not
+    options.insert(
+        "compress".to_string(),
+        serde_json::to_value(config.compress.or(Compression::from_path(output)))?,
+    );
+    let mut files = vec![input.as_str()];
+    files.extend(
+        [
+            &config.rename_file,
+            &config.rows_file,
+            &config.exclude_rows_file,
+            &config.dictionary,
+            &config.avro_schema,
+            &config.width_spec,
+        ]
+        .into_iter()
+        .flatten()
+        .map(String::as_str),
+    );
+    files.extend(
+        config
+            .convert_currency
+            .iter()
+            .map(|spec| spec.rates_file.as_str()),
+    );
+    let entry = cache::Entry::new(dir, &serde_json::to_vec(options)?, &files)?;
+    Ok(Some((entry, output.as_str())))
+}
+
+/// Converts the input and returns the number of records written.
+fn convert(config: &Config, warnings: &Warnings) -> Result<usize, Error> {
+    // Held until conversion finishes; dropping the file releases the lock
+    let _lock = acquire_output_lock(config)?;
+
+    let capacity = config.read_buffer.unwrap_or(DEFAULT_BUFFER_SIZE);
+    let mut detected = None;
+    let raw_reader: Box<dyn Read + Send> = match &config.input {
+        Some(file_path) => {
+            if let Some(seconds) = config.wait_for_quiescence {
+                wait_for_quiescence(file_path, Duration::from_secs(seconds))?;
+            }
+            let mut file = File::open(file_path)?;
+            let is_zip = zip::is_zip(&mut file)?;
+            let entries = if is_zip {
+                zip::entries(&mut file)?
+            } else {
+                Vec::new()
+            };
+            let spreadsheet = spreadsheet::detect(&file, &entries)?;
+            if config.sheet.is_some() && spreadsheet.is_none() {
+                return Err(Error::Config(
+                    "--sheet requires an Excel (.xlsx) or OpenDocument (.ods) spreadsheet as input"
+                        .to_string(),
+                ));
             }
+            if let Some(kind) = spreadsheet {
+                if config.encoding != Encoding::Utf8 {
+                    return Err(Error::Config(
+                        "--encoding cannot be used with a spreadsheet, whose text is always Unicode"
+                            .to_string(),
+                    ));
+                }
+                if config.zip_member.is_some() {
+                    return Err(Error::Config(
+                        "--zip-member cannot be used with a spreadsheet; choose a worksheet with --sheet"
+                            .to_string(),
+                    ));
+                }
+                let csv = spreadsheet::sheet_csv(&file, &entries, kind, config.sheet.as_deref())?;
+                Box::new(io::Cursor::new(csv))
+            } else if is_zip {
+                let entries = zip::select(entries, config.zip_member.as_deref())?;
+                Box::new(zip::Members::new(file, entries, config.has_header_row()))
+            } else {
+                if config.zip_member.is_some() {
+                    return Err(Error::Config(
+                        "--zip-member requires a ZIP archive as input".to_string(),
+                    ));
+                }
+                if parquet::is_parquet(&mut file)? {
+                    detected = Some(InputFormat::Parquet);
+                } else if sqlite::is_database(&mut file)? {
+                    detected = Some(InputFormat::Sqlite);
+                }
+                let size = file.metadata()?.len();
+                let mut file: Box<dyn Read + Send> = if config.stable_only {
+                    // Ignore anything a concurrent writer appends after we start
+                    Box::new(file.take(size))
+                } else {
+                    Box::new(file)
+                };
+                // Only drawn for an interactive terminal, so logs stay clean
+                if config.progress && !config.quiet && io::stderr().is_terminal() {
+                    file = Box::new(ProgressReader::new(file, size));
+                }
+                file
+            }
+        }
+        None => {
+            if config.stable_only || config.wait_for_quiescence.is_some() {
+                return Err(Error::Config(
+                    "--stable-only and --wait-for-quiescence require an input file".to_string(),
+                ));
+            }
+            Box::new(io::stdin())
+        }
+    };
+    // Counts bytes as stored, so compressed input is measured compressed
+    let raw_reader: Box<dyn Read + Send> = if config.preflight_space || config.timing {
+        Box::new(stats::CountingReader::new(raw_reader))
+    } else {
+        raw_reader
+    };
+    // Sniffed as well as taken from the extension, so piped input works too
+    let boxed_reader = compression::decompress(
+        BufReader::with_capacity(capacity, raw_reader),
+        config.input.as_deref(),
+    )?;
+    // Before sniffing, so the format is detected from the transcoded text
+    let boxed_reader: Box<dyn Read> = if detected.is_some() {
+        boxed_reader
+    } else if config.encoding != Encoding::Utf8 {
+        Box::new(encoding::Decoder::new(boxed_reader, config.encoding))
+    } else {
+        // UTF-16 is read as such, since it cannot be UTF-8
+        let mut reader = BufReader::with_capacity(capacity, boxed_reader);
+        match encoding::detect(reader.fill_buf()?) {
+            Some(utf16) => {
+                log::info!("Detected UTF-16 input ({:?})", utf16);
+                Box::new(encoding::Decoder::new(reader, utf16))
+            }
+            None => Box::new(reader),
+        }
+    };
+    let (from, boxed_reader): (InputFormat, Box<dyn Read>) = if let Some(from) = detected {
+        (from, boxed_reader)
+    } else if config.auto_format {
+        let mut reader = BufReader::with_capacity(capacity, boxed_reader);
+        (sniff::detect(reader.fill_buf()?), Box::new(reader))
+    } else {
+        (config.from, boxed_reader)
+    };
+    log::info!(
+        "Reading {:?} from {}",
+        from,
+        config.input.as_deref().unwrap_or("stdin")
+    );
+    log::debug!("Read buffer: {} bytes", capacity);
+    let delimited = matches!(from, InputFormat::Csv | InputFormat::Tsv);
+    if !delimited && (config.expect_header_hash.is_some() || config.print_header_hash) {
+        return Err(Error::Config(
+            "Header fingerprints require CSV input".to_string(),
+        ));
+    }
+    if from != InputFormat::Csv && (config.strict_rfc4180 || config.repair_quotes) {
+        return Err(Error::Config(
+            "--strict-rfc4180 and --repair-quotes require CSV input".to_string(),
+        ));
+    }
+    if !delimited && config.infer_threshold.is_some() {
+        return Err(Error::Config(
+            "--infer-threshold requires CSV input".to_string(),
+        ));
+    }
+    if config.schema_cache.is_some() && config.infer_threshold.is_none() {
+        return Err(Error::Config(
+            "--schema-cache requires --infer-threshold".to_string(),
+        ));
+    }
+    if from != InputFormat::Csv && (config.normalize_report || config.normalize) {
+        return Err(Error::Config(
+            "--normalize-report and --normalize require CSV input".to_string(),
+        ));
+    }
+    if matches!(from, InputFormat::Parquet | InputFormat::Sqlite)
+        && config.encoding != Encoding::Utf8
+    {
+        return Err(Error::Config(
+            "--encoding applies to text input, not Parquet or SQLite".to_string(),
+        ));
+    }
+    if from != InputFormat::Sqlite && config.query.is_some() {
+        return Err(Error::Config(
+            "--query requires a SQLite database as input".to_string(),
+        ));
+    }
+    let csv_rows = delimited || from == InputFormat::FixedWidth;
+    if !csv_rows
+        && (config.skip_footer > 0
+            || config.headers.is_some()
+            || config.comment.is_some()
+            || config.on_ragged != RaggedPolicy::Error)
+    {
+        return Err(Error::Config(
+            "--skip-footer, --headers, --comment and --on-ragged require CSV, TSV or fixed-width input"
+                .to_string(),
+        ));
+    }
+    if from != InputFormat::FixedWidth && (config.widths.is_some() || config.width_spec.is_some()) {
+        return Err(Error::Config(
+            "--widths and --width-spec require --from fixed-width".to_string(),
+        ));
+    }
+    let boxed_reader: Box<dyn Read> = if config.normalize_report || config.normalize {
+        Box::new(normalize::Normalizer::new(boxed_reader, config.normalize))
+    } else {
+        boxed_reader
+    };
+    let boxed_reader: Box<dyn Read> = if config.strict_rfc4180 {
+        Box::new(rfc4180::Validator::new(boxed_reader, config.comment))
+    } else if config.repair_quotes {
+        Box::new(repair::QuoteRepairer::new(
+            BufReader::with_capacity(capacity, boxed_reader),
+            config.comment,
+            config.quiet,
+        ))
+    } else {
+        boxed_reader
+    };
+    let boxed_reader: Box<dyn Read> = match from {
+        InputFormat::FixedWidth => Box::new(fixedwidth::ToCsv::new(
+            BufReader::with_capacity(capacity, boxed_reader),
+            fixed_width_layout(config)?,
+        )),
+        _ => boxed_reader,
+    };
+    match from {
+        InputFormat::Csv | InputFormat::Tsv | InputFormat::FixedWidth => {}
+        InputFormat::Json => return convert_json(boxed_reader, config, warnings),
+        InputFormat::Table => return convert_table(boxed_reader, config, warnings),
+        InputFormat::Ini => return convert_ini(boxed_reader, config, warnings),
+        InputFormat::Parquet => return convert_parquet(boxed_reader, config, warnings),
+        InputFormat::Sqlite => return convert_sqlite(boxed_reader, config, warnings),
+        InputFormat::Jsonl => {
+            return convert_lines(boxed_reader, config, warnings, None, parse_json_line)
         }
+        InputFormat::Ltsv => {
+            return convert_lines(boxed_reader, config, warnings, None, |line| {
+                logformat::parse_ltsv(line).map(infer_types)
+            })
+        }
+        InputFormat::CombinedLog => {
+            let columns = logformat::combined_columns();
+            return convert_lines(boxed_reader, config, warnings, Some(columns), |line| {
+                logformat::parse_combined(line).map(infer_types)
+            });
+        }
+    }
 
-        return Ok(());
+    // DataRows checks each record before reading past the --skip-footer rows
+    // after it, so only those need their raw bytes kept for --reject-file
+    let (boxed_reader, record_lines) = if config.reject_file.is_some() {
+        lines::LineBreakReader::keeping_raw(boxed_reader, config.skip_footer + 1, config.comment)
     } else {
-        reader.headers()?.clone()
+        lines::LineBreakReader::new(boxed_reader)
     };
+    let mut reader: Reader<Box<dyn Read>> = csv::ReaderBuilder::new()
+        .delimiter(if from == InputFormat::Tsv {
+            b'\t'
+        } else {
+            b','
+        })
+        .has_headers(config.has_header_row())
+        .buffer_capacity(capacity)
+        // DataRows checks field counts itself, so errors can show the row
+        .flexible(true)
+        .comment(config.comment)
+        .from_reader(Box::new(boxed_reader));
 
-    let mut records = Vec::new();
+    if !config.has_header_row() {
+        if config.expect_header_hash.is_some() || config.print_header_hash {
+            return Err(Error::Config(
+                "Header fingerprints require a CSV header row".to_string(),
+            ));
+        }
 
-    for result in reader.records() {
-        let record = result?;
-        let mut map = IndexMap::new();
+        let mut output = Output::new(config)?;
+        let mut row_errors = RowErrors::new(config, None)?;
+        let records = read_records(&mut reader, &record_lines);
+        let mut rows = DataRows::new(records, config, warnings, &record_lines, None)
+            .filter_map(|result| row_errors.check(result).transpose());
+        // Columns are named once the first row shows how many there are;
+        // DataRows holds every later row to the same width
+        if let Some(first) = rows.next().transpose()? {
+            let headers = prepare_headers(generated_headers(first.len(), config)?, config)?;
+            let filters = RowFilters::new(&headers, config)?;
+            let mut builder = RecordBuilder::new(headers, config, warnings)?;
+            let rows = std::iter::once(Ok(first)).chain(rows);
+            convert_rows(rows, &filters, &mut builder, &mut output, config)?;
+        }
+        row_errors.finish()?;
 
-        for (i, field) in record.iter().enumerate() {
-            if let Some(header) = headers.get(i) {
-                let value: Value = if let Some(bool_val) = parse_boolean(field) {
-                    serde_json::Value::Bool(bool_val)
-                } else {
-                    parse_number(field)
-                };
-                map.insert(header.to_string(), value);
+        return output.finish();
+    }
+
+    let headers: Vec<String> = reader.headers()?.iter().map(|h| h.to_string()).collect();
+    log::info!("Header row has {} columns", headers.len());
+    log::debug!("Headers: {:?}", headers);
+
+    if config.print_header_hash {
+        println!("{}", header_hash(&headers)?);
+        return Ok(0);
+    }
+    if let Some(expected) = &config.expect_header_hash {
+        let actual = header_hash(&headers)?;
+        let passed = actual.eq_ignore_ascii_case(expected);
+        report::record(
+            "expect_header_hash",
+            passed,
+            serde_json::json!({ "expected": expected, "actual": actual }),
+        );
+        if !passed {
+            return Err(Error::Validation(format!(
+                "Header fingerprint mismatch: expected {}, found {}",
+                expected, actual
+            )));
+        }
+    }
+
+    let headers = prepare_headers(headers, config)?;
+    let filters = RowFilters::new(&headers, config)?;
+    let mut builder = RecordBuilder::new(headers, config, warnings)?;
+
+    let mut output = Output::new(config)?;
+    let header = record_lines.header(reader.position().byte());
+    let mut row_errors = RowErrors::new(config, header.as_deref())?;
+
+    let rows = DataRows::new(
+        read_records(&mut reader, &record_lines),
+        config,
+        warnings,
+        &record_lines,
+        Some(builder.headers.len()),
+    )
+    .filter_map(|result| row_errors.check(result).transpose());
+    convert_rows(rows, &filters, &mut builder, &mut output, config)?;
+    row_errors.finish()?;
+
+    output.finish()
+}
+
+/// Reads CSV records, placing each on the line it starts on, for messages and
+/// `--rows-file`, and keeping its raw bytes if `lines` does.
+fn read_records<'a>(
+    reader: &'a mut Reader<Box<dyn Read>>,
+    lines: &'a lines::RecordLines,
+) -> impl Iterator<Item = csv::Result<csv::ByteRecord>> + 'a {
+    std::iter::from_fn(move || {
+        let mut record = csv::ByteRecord::new();
+        match reader.read_byte_record(&mut record) {
+            Ok(true) => {
+                lines.place(&mut record);
+                lines.keep(&record, reader.position().byte());
+                Some(Ok(record))
             }
+            Ok(false) => None,
+            Err(e) => Some(Err(e)),
         }
+    })
+}
 
-        records.push(map);
+/// Filters and converts CSV rows on `--threads` worker threads, writing the
+/// records in input order. Rows are read, and errors reported, on this thread.
+/// With `--infer-threshold`, the first rows are sampled to type the columns,
+/// unless `--schema-cache` already has their types for this header.
+fn convert_rows(
+    mut rows: impl Iterator<Item = Result<csv::StringRecord, Error>>,
+    filters: &RowFilters,
+    builder: &mut RecordBuilder,
+    output: &mut Output,
+    config: &Config,
+) -> Result<(), Error> {
+    let mut sample = Vec::new();
+    if let Some(threshold) = config.infer_threshold {
+        let cache = config
+            .schema_cache
+            .as_deref()
+            .map(|dir| infer::SchemaCache::new(dir, &builder.headers, threshold, config.quiet))
+            .transpose()?;
+        let inferred = match cache.as_ref().and_then(infer::SchemaCache::load) {
+            Some(inferred) => inferred,
+            None => {
+                sample = rows
+                    .by_ref()
+                    .take(infer::SAMPLE_ROWS)
+                    .map(|row| row.map(|record| normalize_record(record, config)))
+                    .collect::<Result<_, _>>()?;
+                let inferred = infer::infer(&builder.headers, &sample, threshold, parse_field);
+                if let Some(cache) = &cache {
+                    cache.store(&inferred)?;
+                }
+                inferred
+            }
+        };
+        builder.text_columns = inferred.iter().map(|column| !column.is_typed()).collect();
+        output.inferred = Some(builder.headers.iter().cloned().zip(inferred).collect());
     }
+    let rows = sample.into_iter().map(Ok).chain(rows);
+    let builder = &*builder;
 
-    let json_output = if config.pretty {
-        serde_json::to_string_pretty(&records)?
-    } else {
-        serde_json::to_string(&records)?
+    let threads = config.worker_threads();
+    let queue = config
+        .queue_size
+        .unwrap_or(threads * parallel::QUEUE_PER_THREAD);
+    log::debug!(
+        "Converting rows on up to {} threads, {} chunks of {} rows in flight",
+        threads,
+        queue,
+        parallel::CHUNK_SIZE
+    );
+    parallel::map_ordered(
+        threads,
+        queue,
+        rows,
+        |record| {
+            stats::timed(Stage::Transform, || {
+                let record = normalize_record(record, config);
+                if !filters.keep(&record) {
+                    return Ok(Vec::new());
+                }
+                Ok(explode(builder.build(&record)?, config))
+            })
+        },
+        |records| {
+            for record in records {
+                output.push(record)?;
+            }
+            Ok(())
+        },
+    )
+}
+
+/// Applies `--normalize-unicode` to the fields of a row, before it is
+/// filtered and typed.
+fn normalize_record(record: csv::StringRecord, config: &Config) -> csv::StringRecord {
+    let Some(form) = config.normalize_unicode else {
+        return record;
     };
+    if record.iter().all(|field| field.is_ascii()) {
+        return record;
+    }
+    let mut normalized: csv::StringRecord = record
+        .iter()
+        .map(|field| unicode::normalize(field, form))
+        .collect();
+    normalized.set_position(record.position().cloned());
+    normalized
+}
+
+/// A row that could not be converted, with its bytes as they were read when
+/// they are kept for `--reject-file`.
+struct RowError {
+    error: Error,
+    line: Option<Vec<u8>>,
+}
+
+/// Prefixes an error message with where the row starts in the input.
+fn locate(position: Option<&csv::Position>, reason: impl std::fmt::Display) -> String {
+    match position {
+        Some(pos) => format!(
+            "record {} (line: {}, byte: {}): {}",
+            pos.record(),
+            pos.line(),
+            pos.byte(),
+            reason
+        ),
+        None => reason.to_string(),
+    }
+}
 
-    match &config.output {
-        Some(output_file) => {
-            std::fs::write(output_file, json_output)?;
-            println!("JSON output written to: {}", output_file);
+/// Describes a field for error messages by its 1-based column number and its
+/// content, shortened if long.
+fn describe_field(index: usize, content: &[u8]) -> String {
+    const MAX_CHARS: usize = 40;
+    let content = String::from_utf8_lossy(content);
+    let mut shown: String = content.chars().take(MAX_CHARS).collect();
+    if shown.len() < content.len() {
+        shown.push_str("...");
+    }
+    format!("column {} {:?}", index + 1, shown)
+}
+
+impl From<csv::Error> for RowError {
+    fn from(e: csv::Error) -> Self {
+        RowError {
+            error: e.into(),
+            line: None,
         }
-        None => {
-            println!("{}", json_output);
+    }
+}
+
+/// Decides what happens to rows that fail to parse: abort by default, or
+/// report and skip them with `--skip-errors`, up to `--max-errors` of them,
+/// copying them verbatim to the `--reject-file` if one is given.
+struct RowErrors {
+    skip_errors: bool,
+    max_errors: Option<usize>,
+    read: usize,
+    skipped: usize,
+    rejects: Option<BufWriter<File>>,
+    quiet: bool,
+    /// The first errors, for `--report`
+    examples: Vec<String>,
+}
+
+impl RowErrors {
+    /// Opens the `--reject-file`, starting it with the input's `header` line
+    /// if it has one.
+    fn new(config: &Config, header: Option<&[u8]>) -> Result<Self, Error> {
+        let rejects = match &config.reject_file {
+            Some(path) => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                if let Some(header) = header {
+                    write_line(&mut writer, header)?;
+                }
+                Some(writer)
+            }
+            None => None,
+        };
+
+        Ok(RowErrors {
+            skip_errors: config.skips_errors(),
+            max_errors: config.max_errors,
+            read: 0,
+            skipped: 0,
+            rejects,
+            quiet: config.quiet,
+            examples: Vec::new(),
+        })
+    }
+
+    /// Returns the parsed record, or `None` if it was malformed and skipped.
+    fn check<T>(&mut self, result: Result<T, RowError>) -> Result<Option<T>, Error> {
+        self.read += 1;
+        match result {
+            Ok(record) => Ok(Some(record)),
+            // Only malformed rows are skipped; I/O errors always abort
+            Err(RowError {
+                error: Error::Csv(message),
+                line,
+            }) if self.skip_errors => {
+                self.skipped += 1;
+                if self.examples.len() < report::MAX_EXAMPLES {
+                    self.examples.push(message.clone());
+                }
+                if self.max_errors.is_some_and(|max| self.skipped > max) {
+                    self.record(false);
+                    return Err(Error::Csv(format!(
+                        "Too many malformed rows (more than {}); last error: {}",
+                        self.skipped - 1,
+                        message
+                    )));
+                }
+                if !self.quiet {
+                    eprintln!("Skipping malformed row: {}", message);
+                }
+                if let (Some(rejects), Some(line)) = (&mut self.rejects, line) {
+                    write_line(rejects, &line)?;
+                }
+                Ok(None)
+            }
+            Err(e) => Err(e.error),
         }
     }
 
+    fn finish(&mut self) -> Result<(), Error> {
+        if let Some(rejects) = &mut self.rejects {
+            rejects.flush()?;
+        }
+        log::info!("Read {} data rows", self.read);
+        if self.skipped > 0 && !self.quiet {
+            eprintln!("Skipped {} malformed row(s)", self.skipped);
+        }
+        if self.skip_errors {
+            self.record(true);
+        }
+        Ok(())
+    }
+
+    fn record(&self, passed: bool) {
+        report::record(
+            "malformed_rows",
+            passed,
+            serde_json::json!({
+                "count": self.skipped,
+                "max": self.max_errors,
+                "examples": self.examples,
+            }),
+        );
+    }
+}
+
+/// Writes `line` as it was read, ending it with a line break if it had none.
+fn write_line(writer: &mut impl Write, line: &[u8]) -> io::Result<()> {
+    writer.write_all(line)?;
+    if !line.ends_with(b"\n") {
+        writer.write_all(b"\n")?;
+    }
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use tempfile::NamedTempFile;
+/// Row filters with their columns resolved against the final header names.
+struct RowFilters<'a> {
+    between: Option<(usize, &'a TimeWindow)>,
+    /// Line numbers from `--rows-file` (`true`) or `--exclude-rows-file` (`false`)
+    lines: Option<(HashSet<u64>, bool)>,
+}
+
+impl<'a> RowFilters<'a> {
+    fn new(headers: &[Arc<str>], config: &'a Config) -> Result<Self, Error> {
+        let between = match &config.between {
+            Some(window) => {
+                let index = headers
+                    .iter()
+                    .position(|header| **header == *window.column)
+                    .ok_or_else(|| {
+                        Error::Config(format!("Time window column not found: {}", window.column))
+                    })?;
+                Some((index, window))
+            }
+            None => None,
+        };
+        let lines = match (&config.rows_file, &config.exclude_rows_file) {
+            (Some(path), _) => Some((load_line_numbers(path)?, true)),
+            (None, Some(path)) => Some((load_line_numbers(path)?, false)),
+            (None, None) => None,
+        };
+        Ok(RowFilters { between, lines })
+    }
+
+    fn keep(&self, record: &csv::StringRecord) -> bool {
+        if let Some((lines, include)) = &self.lines {
+            let line = record.position().map_or(0, |position| position.line());
+            if lines.contains(&line) != *include {
+                return false;
+            }
+        }
+        if let Some((index, window)) = self.between {
+            // Rows without a parseable timestamp are outside every window
+            let Some(time) = record.get(index).and_then(datetime::parse_timestamp) else {
+                return false;
+            };
+            if window.start.is_some_and(|start| time < start)
+                || window.end.is_some_and(|end| time >= end)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Reads a `--rows-file`: one 1-based line number per line, ignoring blank
+/// lines and `#` comments.
+fn load_line_numbers(path: &str) -> Result<HashSet<u64>, Error> {
+    let content = std::fs::read_to_string(path)?;
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.parse()
+                .map_err(|_| Error::Config(format!("Invalid line number in {}: {}", path, line)))
+        })
+        .collect()
+}
+
+/// Yields CSV records while holding back the last `skip_footer` of them, so
+/// trailing summary rows are never converted.
+///
+/// The records that are emitted get their field count checked against the
+/// header's, or the first row's when there is no header row, and mismatches
+/// are resolved according to `--on-ragged`. Records are read as bytes so that
+/// rows with invalid UTF-8 can still be reported verbatim.
+struct DataRows<'a, I> {
+    records: I,
+    pending: VecDeque<csv::ByteRecord>,
+    skip_footer: usize,
+    on_ragged: RaggedPolicy,
+    expected_len: Option<usize>,
+    warnings: &'a Warnings,
+    lines: &'a lines::RecordLines,
+}
 
+impl<'a, I> DataRows<'a, I> {
+    fn new(
+        records: I,
+        config: &Config,
+        warnings: &'a Warnings,
+        lines: &'a lines::RecordLines,
+        expected_len: Option<usize>,
+    ) -> Self {
+        DataRows {
+            records,
+            pending: VecDeque::with_capacity(config.skip_footer + 1),
+            skip_footer: config.skip_footer,
+            on_ragged: config.on_ragged,
+            expected_len,
+            warnings,
+            lines,
+        }
+    }
+
+    /// A malformed row, with its raw bytes if they were kept.
+    fn reject(&self, record: &csv::ByteRecord, reason: impl std::fmt::Display) -> RowError {
+        RowError {
+            error: Error::Csv(format!("CSV error: {}", locate(record.position(), reason))),
+            line: self.lines.raw(record),
+        }
+    }
+
+    /// Returns the record to emit, or `None` if it should be skipped.
+    fn check(&mut self, record: csv::ByteRecord) -> Result<Option<csv::StringRecord>, RowError> {
+        let expected = *self.expected_len.get_or_insert(record.len());
+        if record.len() != expected {
+            match self.on_ragged {
+                RaggedPolicy::Error => {
+                    // Point at the first extra field, or the last one present
+                    let index = expected.min(record.len().saturating_sub(1));
+                    let reason = format!(
+                        "found record with {} fields, but expected {} (at {})",
+                        record.len(),
+                        expected,
+                        describe_field(index, record.get(index).unwrap_or_default())
+                    );
+                    return Err(self.reject(&record, reason));
+                }
+                RaggedPolicy::Skip => return Ok(None),
+                // Missing fields are filled in by RecordBuilder
+                RaggedPolicy::Pad | RaggedPolicy::Truncate => {
+                    let reason = format!(
+                        "found record with {} fields, but expected {}; {}",
+                        record.len(),
+                        expected,
+                        match (record.len() < expected, self.on_ragged) {
+                            (true, RaggedPolicy::Pad) => "padded with null",
+                            (true, _) => "missing fields left out",
+                            (false, _) => "extra fields discarded",
+                        }
+                    );
+                    self.warnings
+                        .warn(Warning::RaggedRow, || locate(record.position(), reason))
+                        .map_err(|error| RowError { error, line: None })?;
+                }
+            }
+        }
+
+        let mut record = csv::StringRecord::from_byte_record(record).map_err(|e| {
+            let index = e.utf8_error().field();
+            let record = e.into_byte_record();
+            let reason = format!(
+                "invalid UTF-8 in {}",
+                describe_field(index, record.get(index).unwrap_or_default())
+            );
+            self.reject(&record, reason)
+        })?;
+        record.truncate(expected);
+        Ok(Some(record))
+    }
+}
+
+impl<I: Iterator<Item = csv::Result<csv::ByteRecord>>> Iterator for DataRows<'_, I> {
+    type Item = Result<csv::StringRecord, RowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.records.next()? {
+                Ok(record) => {
+                    self.pending.push_back(record);
+                    if self.pending.len() > self.skip_footer {
+                        let record = self.pending.pop_front()?;
+                        match self.check(record) {
+                            Ok(Some(record)) => return Some(Ok(record)),
+                            Ok(None) => continue,
+                            Err(e) => return Some(Err(e)),
+                        }
+                    }
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+/// Takes an exclusive advisory lock on `<output>.lock` when `--lock` is set.
+/// The lock file is left in place, since removing it would race with other processes.
+fn acquire_output_lock(config: &Config) -> Result<Option<File>, Error> {
+    if !config.lock {
+        return Ok(None);
+    }
+    let output_file = config
+        .output
+        .as_ref()
+        .ok_or_else(|| Error::Config("--lock requires an output file".to_string()))?;
+    let lock_path = format!("{}.lock", output_file);
+    let lock_file = File::create(&lock_path)?;
+    match try_lock(&lock_file) {
+        Ok(()) => Ok(Some(lock_file)),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Err(Error::Io(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            format!("{} is locked by another ctj process", lock_path),
+        ))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Takes an exclusive `flock` on `file` without waiting; it is released when
+/// the file is closed, including when the process exits.
+#[cfg(unix)]
+fn try_lock(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--lock is only supported on Unix",
+    ))
+}
+
+/// Blocks until the file's size and modification time stay unchanged for `quiet`.
+fn wait_for_quiescence(path: &str, quiet: Duration) -> Result<(), Error> {
+    let snapshot = |path: &str| -> io::Result<(u64, std::time::SystemTime)> {
+        let metadata = std::fs::metadata(path)?;
+        Ok((metadata.len(), metadata.modified()?))
+    };
+
+    let mut last = snapshot(path)?;
+    loop {
+        std::thread::sleep(quiet);
+        let current = snapshot(path)?;
+        if current == last {
+            return Ok(());
+        }
+        last = current;
+    }
+}
+
+/// Fingerprints the CSV header row as the SHA-256 of its names serialized as a
+/// compact JSON array, e.g. `["name","age"]`, so any rename or reorder changes it.
+fn header_hash(headers: &[String]) -> Result<String, Error> {
+    Ok(sha256::hex_digest(&serde_json::to_vec(headers)?))
+}
+
+/// Names the columns of input without a header row, from `--headers` or by
+/// numbering them: column_0, column_1, column_2, ...
+fn generated_headers(columns: usize, config: &Config) -> Result<Vec<String>, Error> {
+    if let Some(names) = &config.headers {
+        if names.len() != columns {
+            return Err(Error::Config(format!(
+                "--headers supplied {} names but the data has {} columns",
+                names.len(),
+                columns
+            )));
+        }
+        log::info!("No header row; using {} names from --headers", names.len());
+        return Ok(names.clone());
+    }
+
+    let prefix = config.header_prefix.as_deref().unwrap_or("column");
+    log::info!(
+        "No header row; generating {} column names from '{}'",
+        columns,
+        prefix
+    );
+    Ok((0..columns)
+        .map(|i| generated_header_name(prefix, i))
+        .collect())
+}
+
+/// Builds the name of a generated column. A plain prefix yields `prefix_0`,
+/// while a template may use `{i}` (0-based) or `{n}` (1-based) placeholders.
+fn generated_header_name(prefix: &str, index: usize) -> String {
+    if prefix.contains("{i}") || prefix.contains("{n}") {
+        prefix
+            .replace("{i}", &index.to_string())
+            .replace("{n}", &(index + 1).to_string())
+    } else {
+        format!("{}_{}", prefix, index)
+    }
+}
+
+/// Applies header renames, validates column options against the final header names,
+/// echoes them for `--echo-header` and interns them for use as record keys.
+fn prepare_headers(mut headers: Vec<String>, config: &Config) -> Result<Vec<Arc<str>>, Error> {
+    // Before anything else, so options name the columns as they are output
+    if let Some(form) = config.normalize_unicode {
+        for header in &mut headers {
+            if let Cow::Owned(normalized) = unicode::normalize(header, form) {
+                *header = normalized;
+            }
+        }
+    }
+    for (old, new) in &load_renames(config)? {
+        match headers.iter_mut().find(|header| *header == old) {
+            Some(header) => *header = new.clone(),
+            None => return Err(Error::Config(format!("Renamed column not found: {}", old))),
+        }
+    }
+
+    // Derived fields can be selected or dropped like any other column
+    let mut columns = headers.clone();
+    for spec in &config.date_parts {
+        if !headers.contains(&spec.column) {
+            return Err(Error::Config(format!(
+                "Date parts column not found: {}",
+                spec.column
+            )));
+        }
+        columns.extend(spec.field_names());
+    }
+    columns.extend(config.collect.iter().map(|spec| spec.name.clone()));
+
+    check_selected_columns(&columns, config)?;
+    match &config.echo_header {
+        Some(Some(path)) => {
+            let mut file = BufWriter::new(File::create(path)?);
+            serde_json::to_writer(&mut file, &headers)?;
+            writeln!(file)?;
+            file.flush()?;
+        }
+        Some(None) => eprintln!("{}", serde_json::to_string(&headers)?),
+        None => {}
+    }
+    Ok(headers.into_iter().map(Arc::from).collect())
+}
+
+/// Merges renames from `--rename-file` with `--rename` flags, letting flags win on conflicts.
+fn load_renames(config: &Config) -> Result<IndexMap<String, String>, Error> {
+    let mut renames: IndexMap<String, String> = match &config.rename_file {
+        Some(path) => {
+            let content = std::fs::read_to_string(path)?;
+            serde_json::from_str(&content)
+                .map_err(|e| Error::Config(format!("Invalid rename file {}: {}", path, e)))?
+        }
+        None => IndexMap::new(),
+    };
+
+    for (old, new) in &config.rename {
+        renames.insert(old.clone(), new.clone());
+    }
+
+    Ok(renames)
+}
+
+fn check_selected_columns(headers: &[String], config: &Config) -> Result<(), Error> {
+    if let Some(columns) = &config.select {
+        for column in columns {
+            if !headers.contains(column) {
+                return Err(Error::Config(format!(
+                    "Selected column not found: {}",
+                    column
+                )));
+            }
+        }
+    }
+    if let Some(columns) = &config.drop {
+        for column in columns {
+            if !headers.contains(column) {
+                return Err(Error::Config(format!(
+                    "Dropped column not found: {}",
+                    column
+                )));
+            }
+        }
+    }
+    if let Some(column) = &config.explode {
+        if !headers.contains(column) {
+            return Err(Error::Config(format!(
+                "Exploded column not found: {}",
+                column
+            )));
+        }
+    }
+    if let Some(hierarchy) = &config.hierarchy {
+        for column in [&hierarchy.id, &hierarchy.parent] {
+            if !headers.contains(column) {
+                return Err(Error::Config(format!(
+                    "Hierarchy column not found: {}",
+                    column
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Turns CSV rows into records. Header and derived field names are resolved
+/// and interned once, so building a row allocates nothing for its keys.
+struct RecordBuilder<'a> {
+    config: &'a Config,
+    warnings: &'a Warnings,
+    headers: Vec<Arc<str>>,
+    date_parts: Vec<DatePartFields>,
+    currencies: Vec<CurrencyConversion<'a>>,
+    scales: Vec<(usize, &'a Scale)>,
+    truncations: Vec<(usize, &'a Truncation)>,
+    collections: Vec<Collection>,
+    /// Columns kept as text by `--infer-threshold`, by index
+    text_columns: Vec<bool>,
+}
+
+/// A `--collect` array name, its field names and, for each number found in
+/// the headers, the column index of every field (if present).
+struct Collection {
+    name: Arc<str>,
+    fields: Vec<String>,
+    groups: Vec<Vec<Option<usize>>>,
+}
+
+/// A `--convert-currency` request with its columns resolved and rates loaded.
+struct CurrencyConversion<'a> {
+    spec: &'a CurrencySpec,
+    index: usize,
+    date_index: Option<usize>,
+    rates: Rates,
+}
+
+/// A `--date-parts` source column index and the `(part, field name)` pairs derived from it.
+struct DatePartFields {
+    index: usize,
+    fields: Vec<(DatePart, Arc<str>)>,
+}
+
+impl<'a> RecordBuilder<'a> {
+    fn new(
+        headers: Vec<Arc<str>>,
+        config: &'a Config,
+        warnings: &'a Warnings,
+    ) -> Result<Self, Error> {
+        let mut seen = HashSet::new();
+        for header in &headers {
+            if !seen.insert(header) {
+                warnings.warn(Warning::DuplicateHeader, || {
+                    format!(
+                        "duplicate column name {:?}; the later column's values are kept",
+                        header
+                    )
+                })?;
+            }
+        }
+
+        let mut date_parts = Vec::new();
+        for spec in &config.date_parts {
+            let index = headers
+                .iter()
+                .position(|header| **header == *spec.column)
+                .ok_or_else(|| {
+                    Error::Config(format!("Date parts column not found: {}", spec.column))
+                })?;
+            let fields = spec
+                .parts
+                .iter()
+                .zip(spec.field_names())
+                .map(|(part, name)| (*part, Arc::from(name)))
+                .collect();
+            date_parts.push(DatePartFields { index, fields });
+        }
+
+        let column_index = |column: &str| {
+            headers
+                .iter()
+                .position(|header| **header == *column)
+                .ok_or_else(|| {
+                    Error::Config(format!("Currency conversion column not found: {}", column))
+                })
+        };
+        let mut currencies = Vec::new();
+        for spec in &config.convert_currency {
+            currencies.push(CurrencyConversion {
+                spec,
+                index: column_index(&spec.column)?,
+                date_index: spec.date_column.as_deref().map(column_index).transpose()?,
+                rates: Rates::load(spec)?,
+            });
+        }
+
+        let scales = config
+            .scale
+            .iter()
+            .map(|scale| {
+                headers
+                    .iter()
+                    .position(|header| **header == *scale.column)
+                    .map(|index| (index, scale))
+                    .ok_or_else(|| {
+                        Error::Config(format!("Scaled column not found: {}", scale.column))
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+
+        let truncations = config
+            .truncate
+            .iter()
+            .map(|truncation| {
+                headers
+                    .iter()
+                    .position(|header| **header == *truncation.column)
+                    .map(|index| (index, truncation))
+                    .ok_or_else(|| {
+                        Error::Config(format!("Truncated column not found: {}", truncation.column))
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut collections = Vec::new();
+        for spec in &config.collect {
+            let mut groups: BTreeMap<u64, Vec<Option<usize>>> = BTreeMap::new();
+            for (index, header) in headers.iter().enumerate() {
+                for (slot, field) in spec.fields.iter().enumerate() {
+                    let number = header
+                        .strip_prefix(field.as_str())
+                        .and_then(|rest| rest.strip_prefix('_'))
+                        .filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+                        .and_then(|n| n.parse().ok());
+                    if let Some(number) = number {
+                        groups
+                            .entry(number)
+                            .or_insert_with(|| vec![None; spec.fields.len()])[slot] = Some(index);
+                    }
+                }
+            }
+            if groups.is_empty() {
+                return Err(Error::Config(format!(
+                    "No numbered columns found to collect into {}",
+                    spec.name
+                )));
+            }
+            collections.push(Collection {
+                name: Arc::from(spec.name.as_str()),
+                fields: spec.fields.clone(),
+                groups: groups.into_values().collect(),
+            });
+        }
+
+        Ok(RecordBuilder {
+            config,
+            warnings,
+            headers,
+            date_parts,
+            currencies,
+            scales,
+            truncations,
+            collections,
+            text_columns: Vec::new(),
+        })
+    }
+
+    fn build(&self, record: &csv::StringRecord) -> Result<Record, Error> {
+        let config = self.config;
+        let headers = &self.headers;
+        let mut map = IndexMap::with_capacity(headers.len());
+
+        for (i, field) in record.iter().enumerate() {
+            if let Some(header) = headers.get(i) {
+                let value = if self.text_columns.get(i) == Some(&true) {
+                    Value::String(field.to_string())
+                } else {
+                    parse_field(field)
+                };
+                if value.is_f64()
+                    && warnings::loses_precision(field, value.as_f64().unwrap_or_default())
+                {
+                    self.warnings.warn(Warning::PrecisionLoss, || {
+                        let reason = format!(
+                            "{} loses precision as a JSON number",
+                            describe_field(i, field.as_bytes())
+                        );
+                        locate(record.position(), reason)
+                    })?;
+                }
+                map.insert(Arc::clone(header), value);
+            }
+        }
+
+        if config.on_ragged == RaggedPolicy::Pad {
+            for header in headers.iter().skip(record.len()) {
+                map.insert(Arc::clone(header), Value::Null);
+            }
+        }
+
+        self.transform(map, record)
+    }
+
+    /// Builds a record from values that already have JSON types, one per
+    /// header, such as a line of JSON Lines input. `record` holds the same
+    /// values as text for the conversions that parse them.
+    fn build_typed(&self, values: Vec<Value>, record: &csv::StringRecord) -> Result<Record, Error> {
+        let map = self.headers.iter().cloned().zip(values).collect();
+        self.transform(map, record)
+    }
+
+    /// Applies scales, currency conversions, truncation, derived fields,
+    /// collected arrays and column selection to a record whose values have
+    /// been typed.
+    fn transform(&self, mut map: Record, record: &csv::StringRecord) -> Result<Record, Error> {
+        let config = self.config;
+        let headers = &self.headers;
+
+        for (index, scale) in &self.scales {
+            if let Some(value) = map.get_mut(&headers[*index]) {
+                *value = scale.apply(value);
+            }
+        }
+
+        for conversion in &self.currencies {
+            let header = &headers[conversion.index];
+            // Only numeric values are converted; blanks and text pass through
+            if let Some(amount) = map.get(header).and_then(Value::as_f64) {
+                let date = conversion.date_index.and_then(|index| record.get(index));
+                let rate = conversion.rates.rate(conversion.spec, date).map_err(|e| {
+                    let index = conversion.date_index.unwrap_or(conversion.index);
+                    let field = record.get(index).unwrap_or_default().as_bytes();
+                    Error::Validation(locate(
+                        record.position(),
+                        format!("{} at {}", e, describe_field(index, field)),
+                    ))
+                })?;
+                map.insert(Arc::clone(header), Value::from(amount * rate));
+            }
+        }
+
+        for (index, truncation) in &self.truncations {
+            let header = &headers[*index];
+            let Some(position) = map.get_index_of(header) else {
+                continue;
+            };
+            let truncated = truncation.apply(&mut map[position]);
+            if config.truncate_flag {
+                let flag = Arc::from(truncation.flag_name());
+                map.shift_insert(position + 1, flag, Value::Bool(truncated));
+            }
+        }
+
+        for DatePartFields { index, fields } in &self.date_parts {
+            let value = record.get(*index).unwrap_or_default();
+            let parsed = datetime::parse_date_or_time(value);
+            for (part, name) in fields {
+                let value = parsed.map_or(Value::Null, |(millis, date_only)| {
+                    // A date alone is not moved to another day by --timezone
+                    let offset = if date_only { 0 } else { config.timezone };
+                    part.extract(millis, offset)
+                });
+                map.insert(Arc::clone(name), value);
+            }
+        }
+
+        for collection in &self.collections {
+            // The array takes the place of the first collected column
+            let position = collection
+                .groups
+                .iter()
+                .flatten()
+                .flatten()
+                .filter_map(|&index| map.get_index_of(&headers[index]))
+                .min()
+                .unwrap_or(map.len());
+            let mut items = Vec::new();
+            for group in &collection.groups {
+                let mut item = serde_json::Map::new();
+                for (field, index) in collection.fields.iter().zip(group) {
+                    let value = index
+                        .and_then(|index| map.shift_remove(&headers[index]))
+                        .unwrap_or(Value::Null);
+                    item.insert(field.clone(), value);
+                }
+                // Unused slots of a fixed-width export are left blank
+                if item.values().any(|value| !(value.is_null() || value == "")) {
+                    items.push(Value::Object(item));
+                }
+            }
+            map.shift_insert(position, Arc::clone(&collection.name), Value::Array(items));
+        }
+
+        if let Some(columns) = &config.drop {
+            map.retain(|key, _| !columns.iter().any(|column| column.as_str() == &**key));
+        }
+
+        Ok(match &config.select {
+            // Emit only the selected columns, in the order they were requested
+            Some(columns) => columns
+                .iter()
+                .filter_map(|column| map.shift_remove_entry(column.as_str()))
+                .collect(),
+            None => map,
+        })
+    }
+}
+
+fn parse_json_line(line: &str) -> Result<serde_json::Map<String, Value>, String> {
+    match serde_json::from_str(line) {
+        Ok(Value::Object(object)) => Ok(object),
+        Ok(_) => Err("expected a JSON object".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Types the text fields of a parsed log line the same way as CSV fields.
+fn infer_types(fields: serde_json::Map<String, Value>) -> serde_json::Map<String, Value> {
+    fields
+        .into_iter()
+        .map(|(key, value)| match value {
+            Value::String(text) => (key, parse_field(&text)),
+            value => (key, value),
+        })
+        .collect()
+}
+
+/// Converts line-oriented input, such as JSON Lines, through the same filters
+/// and transforms as CSV rows, using `parse_line` to turn each line into
+/// fields. Lines are streamed to the output. The columns are `keys` when the
+/// format fixes them, or else the keys of the first lines in the order they
+/// are first seen; keys missing from a line are null. Blank lines are ignored.
+fn convert_lines(
+    input: Box<dyn Read>,
+    config: &Config,
+    warnings: &Warnings,
+    keys: Option<Vec<String>>,
+    parse_line: impl Fn(&str) -> Result<serde_json::Map<String, Value>, String>,
+) -> Result<usize, Error> {
+    let mut row_errors = RowErrors::new(config, None)?;
+    let mut objects = BufReader::new(input)
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let line = match line {
+                Ok(line) if line.trim().is_empty() => return None,
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            let line_number = index as u64 + 1;
+            let result = parse_line(&line)
+                .map(|object| (line_number, object))
+                .map_err(|reason| RowError {
+                    error: Error::Csv(format!("Malformed line {}: {}", line_number, reason)),
+                    line: Some(line.into_bytes()),
+                });
+            row_errors.check(result).transpose()
+        });
+    let mut sample = Vec::new();
+    let keys = match keys {
+        Some(keys) => keys,
+        None => {
+            sample = objects
+                .by_ref()
+                .take(infer::SAMPLE_ROWS)
+                .collect::<Result<_, _>>()?;
+            let mut keys: IndexSet<String> = IndexSet::new();
+            for (_, object) in &sample {
+                keys.extend(object.keys().cloned());
+            }
+            keys.into_iter().collect()
+        }
+    };
+    let mut output = Output::new(config)?;
+    write_objects(
+        keys,
+        sample.into_iter().map(Ok).chain(objects),
+        &mut output,
+        config,
+        warnings,
+    )?;
+    row_errors.finish()?;
+    output.finish()
+}
+
+/// Converts a JSON document: an array of objects, one record each, or a
+/// single object as one record. Values keep their JSON types.
+fn convert_json(
+    mut input: Box<dyn Read>,
+    config: &Config,
+    warnings: &Warnings,
+) -> Result<usize, Error> {
+    let mut content = Vec::new();
+    input.read_to_end(&mut content)?;
+    let content = content.strip_prefix(b"\xef\xbb\xbf").unwrap_or(&content);
+    let invalid = |reason: String| Error::Csv(format!("Malformed JSON input: {}", reason));
+    let items = match serde_json::from_slice(content).map_err(|e| invalid(e.to_string()))? {
+        Value::Array(items) => items,
+        object @ Value::Object(_) => vec![object],
+        _ => {
+            return Err(invalid(
+                "expected an array of objects or an object".to_string(),
+            ))
+        }
+    };
+    let mut keys: IndexSet<String> = IndexSet::new();
+    let mut objects = Vec::with_capacity(items.len());
+    for (index, item) in items.into_iter().enumerate() {
+        let Value::Object(object) = item else {
+            return Err(invalid(format!("item {} is not an object", index + 1)));
+        };
+        keys.extend(object.keys().cloned());
+        objects.push((index as u64 + 1, object));
+    }
+    convert_objects(keys.into_iter().collect(), objects, config, warnings)
+}
+
+/// Converts whitespace-aligned command output such as `ps`, `df` or
+/// `kubectl get`, whose first non-blank line names the columns.
+fn convert_table(
+    input: Box<dyn Read>,
+    config: &Config,
+    warnings: &Warnings,
+) -> Result<usize, Error> {
+    let mut lines = Vec::new();
+    for (index, line) in BufReader::new(input).lines().enumerate() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            lines.push((index as u64 + 1, line));
+        }
+    }
+    if lines.is_empty() {
+        return Output::new(config)?.finish();
+    }
+    let (_, header) = lines.remove(0);
+
+    let rows: Vec<&str> = lines.iter().map(|(_, line)| line.as_str()).collect();
+    let columns = logformat::table_columns(&header, &rows);
+    let keys: Vec<String> = columns.iter().map(|column| column.name.clone()).collect();
+
+    let objects = lines
+        .iter()
+        .map(|(line_number, line)| {
+            let fields = logformat::split_table_line(line, &columns);
+            let object = keys
+                .iter()
+                .zip(fields)
+                .map(|(key, field)| (key.clone(), parse_field(field)))
+                .collect();
+            (*line_number, object)
+        })
+        .collect();
+    convert_objects(keys, objects, config, warnings)
+}
+
+/// The columns of `--from fixed-width` input, from `--widths` or `--width-spec`.
+fn fixed_width_layout(config: &Config) -> Result<fixedwidth::Layout, Error> {
+    match (&config.widths, &config.width_spec) {
+        (Some(widths), _) => Ok(fixedwidth::Layout::from_widths(widths)),
+        (None, Some(path)) => {
+            if !config.has_header_row() {
+                return Err(Error::Config(
+                    "--width-spec names the columns, so it cannot be combined with --no-header or --headers"
+                        .to_string(),
+                ));
+            }
+            fixedwidth::Layout::load(path)
+        }
+        (None, None) => Err(Error::Config(
+            "--from fixed-width requires --widths or --width-spec".to_string(),
+        )),
+    }
+}
+
+/// Converts a Parquet file, whose values keep their column types.
+fn convert_parquet(
+    mut input: Box<dyn Read>,
+    config: &Config,
+    warnings: &Warnings,
+) -> Result<usize, Error> {
+    let mut content = Vec::new();
+    input.read_to_end(&mut content)?;
+    let (keys, records) = parquet::read(&content)?;
+    let objects = records
+        .into_iter()
+        .enumerate()
+        .map(|(index, record)| (index as u64 + 1, record))
+        .collect();
+    convert_objects(keys, objects, config, warnings)
+}
+
+/// Converts a table of a SQLite database, or the rows `--query` selects.
+fn convert_sqlite(
+    mut input: Box<dyn Read>,
+    config: &Config,
+    warnings: &Warnings,
+) -> Result<usize, Error> {
+    let table = match (&config.query, &config.input) {
+        (Some(sql), Some(path)) => sqlite::query(path, sql)?,
+        (Some(_), None) => {
+            return Err(Error::Config(
+                "--query requires the database as an input file".to_string(),
+            ))
+        }
+        (None, _) => {
+            let mut content = Vec::new();
+            input.read_to_end(&mut content)?;
+            sqlite::read_table(&content, config.table.as_deref())?
+        }
+    };
+    // --table named the table read; it also names the one written, if any
+    let output_config;
+    let config = if config.query.is_none() && !config.writes_table() {
+        output_config = Config {
+            table: None,
+            ..config.clone()
+        };
+        &output_config
+    } else {
+        config
+    };
+    let objects = table
+        .rows
+        .into_iter()
+        .enumerate()
+        .map(|(index, row)| (index as u64 + 1, row))
+        .collect();
+    convert_objects(table.columns, objects, config, warnings)
+}
+
+/// Converts an INI or properties file into a single record whose sections
+/// are nested objects.
+fn convert_ini(
+    mut input: Box<dyn Read>,
+    config: &Config,
+    warnings: &Warnings,
+) -> Result<usize, Error> {
+    let mut content = String::new();
+    input.read_to_string(&mut content)?;
+    let object = logformat::parse_ini(&content, parse_field)
+        .map_err(|(line, reason)| Error::Csv(format!("Malformed line {}: {}", line, reason)))?;
+    let keys = object.keys().cloned().collect();
+    convert_objects(keys, vec![(1, object)], config, warnings)
+}
+
+/// Converts records read from a non-CSV format through the same filters and
+/// transforms as CSV rows. Each object is tagged with its line number, and
+/// keys missing from an object are null.
+fn convert_objects(
+    keys: Vec<String>,
+    objects: Vec<(u64, serde_json::Map<String, Value>)>,
+    config: &Config,
+    warnings: &Warnings,
+) -> Result<usize, Error> {
+    let mut output = Output::new(config)?;
+    write_objects(
+        keys,
+        objects.into_iter().map(Ok),
+        &mut output,
+        config,
+        warnings,
+    )?;
+    output.finish()
+}
+
+/// Writes objects to `output` as `convert_objects` does, as they are read.
+/// Keys that are not among `keys` are left out.
+fn write_objects(
+    keys: Vec<String>,
+    objects: impl Iterator<Item = Result<(u64, serde_json::Map<String, Value>), Error>>,
+    output: &mut Output,
+    config: &Config,
+    warnings: &Warnings,
+) -> Result<(), Error> {
+    let headers = prepare_headers(keys.clone(), config)?;
+    let filters = RowFilters::new(&headers, config)?;
+    let builder = RecordBuilder::new(headers, config, warnings)?;
+
+    for (record_number, object) in objects.enumerate() {
+        let (line_number, mut object) = object?;
+        let mut values = Vec::with_capacity(keys.len());
+        for key in &keys {
+            values.push(match object.remove(key) {
+                Some(mut value) => {
+                    if let Some(form) = config.normalize_unicode {
+                        unicode::normalize_value(&mut value, form);
+                    }
+                    value
+                }
+                None => {
+                    warnings.warn(Warning::MissingField, || {
+                        format!("line {} has no {:?} field; using null", line_number, key)
+                    })?;
+                    Value::Null
+                }
+            });
+        }
+        for key in object.keys() {
+            warnings.warn(Warning::ExtraField, || {
+                format!(
+                    "line {} has a {:?} field not seen in the first {} records; leaving it out",
+                    line_number,
+                    key,
+                    infer::SAMPLE_ROWS
+                )
+            })?;
+        }
+        // Filters and date parsing read fields as text, as they appear in CSV
+        let mut text: csv::StringRecord = values
+            .iter()
+            .map(|value| match value {
+                Value::String(s) => s.clone(),
+                Value::Null => String::new(),
+                other => other.to_string(),
+            })
+            .collect();
+        let mut position = csv::Position::new();
+        position
+            .set_line(line_number)
+            .set_record(record_number as u64);
+        text.set_position(Some(position));
+
+        let records = stats::timed(Stage::Transform, || {
+            if !filters.keep(&text) {
+                return Ok(Vec::new());
+            }
+            Ok::<_, Error>(explode(builder.build_typed(values, &text)?, config))
+        })?;
+        for record in records {
+            output.push(record)?;
+        }
+    }
+    Ok(())
+}
+
+/// Splits a record into one record per element of its `--explode` column,
+/// which holds a JSON array cell like `["a","b"]`. An empty array becomes
+/// null; records whose column holds anything else are passed through as-is.
+fn explode(record: Record, config: &Config) -> Vec<Record> {
+    let Some(column) = &config.explode else {
+        return vec![record];
+    };
+    let elements = match record.get(column.as_str()) {
+        Some(Value::Array(elements)) => elements.clone(),
+        Some(Value::String(cell)) if cell.trim_start().starts_with('[') => {
+            match serde_json::from_str(cell) {
+                Ok(Value::Array(elements)) => elements,
+                _ => return vec![record],
+            }
+        }
+        _ => return vec![record],
+    };
+    if elements.is_empty() {
+        let mut record = record;
+        if let Some(value) = record.get_mut(column.as_str()) {
+            *value = Value::Null;
+        }
+        return vec![record];
+    }
+
+    elements
+        .into_iter()
+        .map(|element| {
+            let mut row = record.clone();
+            if let Some(value) = row.get_mut(column.as_str()) {
+                *value = element;
+            }
+            row
+        })
+        .collect()
+}
+
+/// Opens the output for serialized records, escaped for `--ascii`.
+fn open_output(path: Option<&str>, config: &Config) -> Result<Box<dyn Sink>, Error> {
+    Ok(ascii_escaped(open_sink(path, config)?, config))
+}
+
+/// Escapes non-ASCII characters above the buffer for `--ascii`.
+fn ascii_escaped(sink: Box<dyn Sink>, config: &Config) -> Box<dyn Sink> {
+    if config.ascii {
+        return Box::new(AsciiEscaper::new(sink));
+    }
+    sink
+}
+
+/// Opens an output file, or stdout if there is none. A file is written under
+/// a temporary name and replaces `path` when the sink is finished.
+fn open_sink(path: Option<&str>, config: &Config) -> Result<Box<dyn Sink>, Error> {
+    let (sink, replacement) = open_uncommitted_sink(path, config)?;
+    Ok(match replacement {
+        Some(replacement) => Box::new(replace::Replacing::new(sink, replacement)),
+        None => sink,
+    })
+}
+
+/// Opens an output file, or stdout, that stays under its temporary name
+/// when the sink is finished, until the returned replacement is committed.
+fn open_uncommitted_sink(
+    path: Option<&str>,
+    config: &Config,
+) -> Result<(Box<dyn Sink>, Option<replace::Replacement>), Error> {
+    let (file, replacement) = match path {
+        Some(path) => {
+            let (file, replacement) = replace::create(path, config.backup)?;
+            (Some(file), replacement)
+        }
+        None => (None, None),
+    };
+    Ok((open_file_sink(path, file, config)?, replacement))
+}
+
+/// Puts the output behind a buffer, so serialized output is flushed in
+/// `--write-buffer` sized batches, or behind a compressor.
+fn open_file_sink(
+    path: Option<&str>,
+    file: Option<File>,
+    config: &Config,
+) -> Result<Box<dyn Sink>, Error> {
+    let capacity = config.write_buffer.unwrap_or(DEFAULT_BUFFER_SIZE);
+    // Compression is inferred from the extension unless --compress is given
+    if let Some(format) = config.compress.or(path.and_then(Compression::from_path)) {
+        let sink = compression::compress(format, file, capacity)?;
+        // Compressing counts as writing for --timing
+        if config.timing {
+            return Ok(Box::new(stats::TimedWriter::new(sink)));
+        }
+        return Ok(sink);
+    }
+    let writer: Box<dyn Write> = match file {
+        Some(file) => Box::new(file),
+        None => Box::new(io::stdout().lock()),
+    };
+    Ok(Box::new(BufWriter::with_capacity(
+        capacity,
+        timed_writer(writer, config),
+    )))
+}
+
+/// Times writes below the buffer for `--timing`, so only flushes count.
+fn timed_writer(writer: Box<dyn Write>, config: &Config) -> Box<dyn Write> {
+    if config.timing {
+        return Box::new(stats::TimedWriter::new(writer));
+    }
+    writer
+}
+
+/// Opens an existing output file to add records to it for `--append`. JSON
+/// Lines are appended; a JSON array has its closing bracket cut so that the
+/// writer continues it. A missing or empty file is written as new.
+fn open_append(path: &str, config: &Config) -> Result<RecordWriter<Box<dyn Sink>>, Error> {
+    let capacity = config.write_buffer.unwrap_or(DEFAULT_BUFFER_SIZE);
+    let created = !Path::new(path).exists();
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let length = file.metadata()?.len();
+    // Where the new records start; what follows is cut and kept to put back
+    let mut offset = length;
+    let mut separator: &[u8] = b"";
+    let continues = match config.format {
+        OutputFormat::Jsonl => {
+            let mut last = [b'\n'];
+            if length > 0 {
+                file.seek(io::SeekFrom::Start(length - 1))?;
+                file.read_exact(&mut last)?;
+            }
+            // A last record without a line break would run into the first new one
+            if last[0] != b'\n' {
+                separator = b"\n";
+            }
+            false
+        }
+        OutputFormat::Json => {
+            let not_array = || {
+                Error::Config(format!(
+                    "Cannot append to {}: it does not hold a JSON array",
+                    path
+                ))
+            };
+            let (kept, continues) = match last_non_whitespace(&mut file, length)? {
+                None => (0, false),
+                Some((end, b']')) => {
+                    let (kept, byte) =
+                        last_non_whitespace(&mut file, end)?.ok_or_else(not_array)?;
+                    // An empty array is rewritten from its opening bracket
+                    match byte {
+                        b'[' => (kept, false),
+                        _ => (kept + 1, true),
+                    }
+                }
+                Some(_) => return Err(not_array()),
+            };
+            offset = kept;
+            continues
+        }
+        OutputFormat::Html
+        | OutputFormat::Msgpack
+        | OutputFormat::Cbor
+        | OutputFormat::Bson
+        | OutputFormat::Avro
+        | OutputFormat::Arrow
+        | OutputFormat::Sqlite
+        | OutputFormat::Postgres => {
+            unreachable!("--append is only allowed for JSON")
+        }
+    };
+    let mut tail = Vec::new();
+    file.seek(io::SeekFrom::Start(offset))?;
+    file.read_to_end(&mut tail)?;
+    file.set_len(offset)?;
+    file.seek(io::SeekFrom::Start(offset))?;
+    file.write_all(separator)?;
+    log::info!("Appending to {} ({} bytes)", path, length);
+    let handle = file.try_clone()?;
+    let file = timed_writer(Box::new(file), config);
+    let output: Box<dyn Sink> = Box::new(BufWriter::with_capacity(capacity, file));
+    // Put back as it was if the conversion fails
+    let output = Box::new(replace::Restoring::new(
+        output, handle, path, offset, tail, created,
+    ));
+    let output = ascii_escaped(output, config);
+    Ok(if continues {
+        RecordWriter::appending(output, config.format, config.pretty)
+    } else {
+        RecordWriter::new(output, config.format, config.pretty)
+    })
+}
+
+/// Finds the last byte before `end` that is not whitespace, reading the
+/// file backwards from there, with its offset.
+fn last_non_whitespace(file: &mut File, end: u64) -> io::Result<Option<(u64, u8)>> {
+    let mut buffer = [0; 4096];
+    let mut end = end;
+    while end > 0 {
+        let start = end.saturating_sub(buffer.len() as u64);
+        let chunk = &mut buffer[..(end - start) as usize];
+        file.seek(io::SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(index) = chunk.iter().rposition(|b| !b.is_ascii_whitespace()) {
+            return Ok(Some((start + index as u64, chunk[index])));
+        }
+        end = start;
+    }
+    Ok(None)
+}
+
+/// The path of the `index`th file of `--split-rows` output, numbered from 1.
+/// The number goes before the format extension, so `out.json.gz` gives
+/// `out_0001.json.gz`.
+fn split_path(output: &str, index: usize) -> String {
+    let name_start = output
+        .rfind(std::path::is_separator)
+        .map_or(0, |separator| separator + 1);
+    let name = &output[name_start..];
+    let split = name
+        .rfind(".json")
+        .or_else(|| name.rfind('.'))
+        .filter(|&dot| dot > 0)
+        .map_or(output.len(), |dot| name_start + dot);
+    format!("{}_{:04}{}", &output[..split], index, &output[split..])
+}
+
+/// Output for `--with-columns-meta`: the records with a description of their columns.
+#[derive(Serialize)]
+struct Envelope<'a> {
+    columns: Vec<columns::ColumnMeta>,
+    records: &'a [Record],
+}
+
+/// A writer in `format`, with the `--avro-schema` for Avro output and the
+/// `--table` for SQLite and PostgreSQL output.
+fn record_writer(
+    output: Box<dyn Sink>,
+    format: OutputFormat,
+    config: &Config,
+    avro_schema: Option<&Arc<avro::Schema>>,
+) -> RecordWriter<Box<dyn Sink>> {
+    match (format, avro_schema, &config.table) {
+        (OutputFormat::Avro, Some(schema), _) => {
+            RecordWriter::Avro(AvroWriter::new(output, Some(Arc::clone(schema))))
+        }
+        (OutputFormat::Sqlite, _, Some(table)) => {
+            RecordWriter::Sqlite(SqliteWriter::new(output, table))
+        }
+        (OutputFormat::Postgres, _, Some(table)) => {
+            RecordWriter::Postgres(PostgresWriter::new(output, table))
+        }
+        _ => RecordWriter::new(output, format, config.pretty),
+    }
+}
+
+/// Opens `--partition-by` files in the `-o` directory, on the writer threads.
+fn partition_opener(config: &Config, avro_schema: Option<&Arc<avro::Schema>>) -> partition::Open {
+    let config = config.clone();
+    let avro_schema = avro_schema.cloned();
+    Arc::new(move |stem: &str| {
+        let dir = Path::new(config.output.as_deref().expect("checked in new"));
+        std::fs::create_dir_all(dir)?;
+        let mut path = dir.join(stem).into_os_string();
+        path.push(".");
+        path.push(config.format.extension());
+        if let Some(format) = config.compress {
+            path.push(format.extension());
+        }
+        let output = open_output(Some(&path.to_string_lossy()), &config)?;
+        Ok(record_writer(
+            output,
+            config.format,
+            &config,
+            avro_schema.as_ref(),
+        ))
+    })
+}
+
+/// Receives converted records and writes each one as it arrives, so memory
+/// use does not grow with the input. Options that need every record before
+/// anything is written (`--hierarchy`, `--with-columns-meta` and
+/// `--expect-rows`) make it collect them and write them in `finish`. The
+/// output is opened on the first write, and files only replace an existing
+/// output when finished, so a failed conversion leaves the output as it was.
+struct Output<'a> {
+    config: &'a Config,
+    writer: Option<RecordWriter<Box<dyn Sink>>>,
+    /// The `--also-output` writer, opened along with the main one
+    also: Option<RecordWriter<Box<dyn Sink>>>,
+    buffered: Option<Vec<Record>>,
+    count: usize,
+    samples: Option<selfcheck::Samples>,
+    /// Input size for `--preflight-space`
+    input_size: Option<u64>,
+    /// Records held back until the free space check has run
+    pending: Option<Vec<Record>>,
+    /// The main output file, committed once it is finished and checked
+    replacement: Option<replace::Replacement>,
+    /// Earlier `--split-rows` files, committed with the last one
+    finished: Vec<(String, Option<replace::Replacement>)>,
+    dictionary: Option<columns::Dictionary>,
+    /// The schema from `--avro-schema`
+    avro_schema: Option<Arc<avro::Schema>>,
+    /// Output key lineage for `--emit-mapping`
+    mapping: Option<mapping::Mapping>,
+    /// Column types sampled for `--infer-threshold`
+    inferred: Option<IndexMap<Arc<str>, infer::Inference>>,
+    /// Output files opened so far, for `--split-rows`
+    files: usize,
+    /// Records written to the current output file
+    file_count: usize,
+    /// The threads writing the files of `--partition-by` values, up to
+    /// `partition::max_open` of them
+    partitions: Option<partition::Writers>,
+    /// Opens a partition file
+    open_partition: Option<partition::Open>,
+    /// Records of the partitions past those
+    spill: Option<partition::Spill>,
+}
+
+impl<'a> Output<'a> {
+    fn new(config: &'a Config) -> Result<Self, Error> {
+        if config.with_columns_meta && config.format != OutputFormat::Json {
+            return Err(Error::Config(
+                "--with-columns-meta requires --format json".to_string(),
+            ));
+        }
+        if config.also_output.is_some() && config.also_output == config.output {
+            return Err(Error::Config(
+                "--also-output must differ from the main output".to_string(),
+            ));
+        }
+        if config.self_check && config.output.is_none() {
+            return Err(Error::Config(
+                "--self-check requires an output file".to_string(),
+            ));
+        }
+        if config.ascii
+            && !(config.format.is_json() && config.also_format.is_none_or(|f| f.is_json()))
+        {
+            return Err(Error::Config(
+                "--ascii requires --format json or jsonl".to_string(),
+            ));
+        }
+        if (config.self_check || config.append) && !config.format.is_json() {
+            return Err(Error::Config(
+                "--self-check and --append require --format json or jsonl".to_string(),
+            ));
+        }
+        if config.split_rows.is_some() {
+            if config.output.is_none() {
+                return Err(Error::Config(
+                    "--split-rows requires an output file".to_string(),
+                ));
+            }
+            if config.with_columns_meta || config.self_check {
+                return Err(Error::Config(
+                    "--split-rows cannot be combined with --with-columns-meta or --self-check"
+                        .to_string(),
+                ));
+            }
+        }
+        if config.append {
+            let Some(output) = &config.output else {
+                return Err(Error::Config(
+                    "--append requires an output file".to_string(),
+                ));
+            };
+            if config.compress.or(Compression::from_path(output)).is_some() {
+                return Err(Error::Config(
+                    "--append cannot add to compressed output".to_string(),
+                ));
+            }
+            if config.with_columns_meta || config.self_check {
+                return Err(Error::Config(
+                    "--append cannot be combined with --with-columns-meta or --self-check"
+                        .to_string(),
+                ));
+            }
+        }
+        if config.partition_by.is_some() {
+            if config.output.is_none() {
+                return Err(Error::Config(
+                    "--partition-by requires an output directory".to_string(),
+                ));
+            }
+            if config.with_columns_meta || config.self_check || config.preflight_space {
+                return Err(Error::Config(
+                    "--partition-by cannot be combined with --with-columns-meta, --self-check or --preflight-space"
+                        .to_string(),
+                ));
+            }
+        }
+        let dictionary = match &config.dictionary {
+            Some(_) if !config.with_columns_meta => {
+                return Err(Error::Config(
+                    "--dictionary requires --with-columns-meta".to_string(),
+                ))
+            }
+            Some(path) => Some(columns::Dictionary::load(path)?),
+            None => None,
+        };
+        if config.table.is_some() && !config.writes_table() {
+            return Err(Error::Config(
+                "--table requires --format sqlite or postgres, or a SQLite database as input"
+                    .to_string(),
+            ));
+        }
+        let avro_schema = match &config.avro_schema {
+            Some(_)
+                if config.format != OutputFormat::Avro
+                    && config.also_format != Some(OutputFormat::Avro) =>
+            {
+                return Err(Error::Config(
+                    "--avro-schema requires --format avro".to_string(),
+                ))
+            }
+            Some(path) => {
+                let schema = avro::parse_schema(&std::fs::read_to_string(path)?)
+                    .map_err(|e| Error::Config(format!("Invalid Avro schema {}: {}", path, e)))?;
+                Some(Arc::new(schema))
+            }
+            None => None,
+        };
+        let input_size = match (&config.input, &config.output) {
+            _ if !config.preflight_space => None,
+            (Some(input_file), Some(_)) => Some(std::fs::metadata(input_file)?.len()),
+            _ => {
+                return Err(Error::Config(
+                    "--preflight-space requires an input file and an output file".to_string(),
+                ))
+            }
+        };
+        // Files are only moved into place once the record count is checked,
+        // but records streamed to stdout cannot be taken back
+        let buffers = config.hierarchy.is_some()
+            || config.with_columns_meta
+            || (config.expect_rows.is_some() && config.output.is_none());
+        log::debug!(
+            "Write buffer: {} bytes; {}",
+            config.write_buffer.unwrap_or(DEFAULT_BUFFER_SIZE),
+            if buffers {
+                "collecting records before writing"
+            } else {
+                "streaming records"
+            }
+        );
+        Ok(Output {
+            config,
+            writer: None,
+            also: None,
+            buffered: buffers.then(Vec::new),
+            count: 0,
+            samples: config.self_check.then(selfcheck::Samples::default),
+            input_size,
+            pending: (input_size.is_some() && !buffers).then(Vec::new),
+            replacement: None,
+            finished: Vec::new(),
+            dictionary,
+            avro_schema,
+            mapping: config
+                .emit_mapping
+                .is_some()
+                .then(mapping::Mapping::default),
+            inferred: None,
+            files: 0,
+            file_count: 0,
+            partitions: None,
+            open_partition: None,
+            spill: None,
+        })
+    }
+
+    fn record_writer(
+        &self,
+        output: Box<dyn Sink>,
+        format: OutputFormat,
+    ) -> RecordWriter<Box<dyn Sink>> {
+        record_writer(output, format, self.config, self.avro_schema.as_ref())
+    }
+
+    /// The path of the current output file, or `None` for stdout.
+    fn output_path(&self) -> Option<String> {
+        let output = self.config.output.as_deref()?;
+        Some(match self.config.split_rows {
+            Some(_) => split_path(output, self.files),
+            None => output.to_string(),
+        })
+    }
+
+    fn writer(&mut self) -> Result<&mut RecordWriter<Box<dyn Sink>>, Error> {
+        if self.writer.is_none() {
+            let config = self.config;
+            self.files += 1;
+            self.file_count = 0;
+            let path = self.output_path();
+            self.writer = Some(match &path {
+                Some(path) if config.append => open_append(path, config)?,
+                _ => {
+                    let output = self.open_main(path.as_deref())?;
+                    self.record_writer(output, config.format)
+                }
+            });
+        }
+        Ok(self.writer.as_mut().expect("writer was just opened"))
+    }
+
+    /// Opens the main output, keeping its file under a temporary name
+    /// until `commit`.
+    fn open_main(&mut self, path: Option<&str>) -> Result<Box<dyn Sink>, Error> {
+        let (sink, replacement) = open_uncommitted_sink(path, self.config)?;
+        self.replacement = replacement;
+        Ok(ascii_escaped(sink, self.config))
+    }
+
+    /// Moves the finished main output files into place.
+    fn commit(&mut self) -> Result<(), Error> {
+        for (path, replacement) in self.finished.drain(..) {
+            if let Some(replacement) = replacement {
+                replacement.commit()?;
+            }
+            if !self.config.quiet {
+                eprintln!("Output written to: {}", path);
+            }
+        }
+        if let Some(replacement) = self.replacement.take() {
+            replacement.commit()?;
+        }
+        Ok(())
+    }
+
+    fn also_writer(&mut self) -> Result<Option<&mut RecordWriter<Box<dyn Sink>>>, Error> {
+        let config = self.config;
+        let Some(path) = &config.also_output else {
+            return Ok(None);
+        };
+        if self.also.is_none() {
+            let format = config.also_format.unwrap_or(config.format);
+            let output = open_output(Some(path), config)?;
+            self.also = Some(self.record_writer(output, format));
+        }
+        Ok(self.also.as_mut())
+    }
+
+    fn push(&mut self, record: Record) -> Result<(), Error> {
+        self.count += 1;
+        if let Some(records) = &mut self.buffered {
+            records.push(record);
+        } else if let Some(pending) = &mut self.pending {
+            pending.push(record);
+            if pending.len() >= preflight::SAMPLE_RECORDS {
+                self.release_pending(false)?;
+            }
+        } else {
+            self.write(&record)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), Error> {
+        self.write_extra(record)?;
+        if let Some(column) = &self.config.partition_by {
+            return self.write_partition(column, record);
+        }
+        if self.config.split_rows == Some(self.file_count) {
+            // The current file is full, so the next record starts another
+            self.close()?.finish()?;
+            let path = self.output_path().expect("checked in new");
+            self.finished.push((path, self.replacement.take()));
+        }
+        let writer = self.writer()?;
+        stats::timed(Stage::Serialize, || writer.write_record(record))?;
+        self.file_count += 1;
+        Ok(())
+    }
+
+    /// Hands a record to the writer threads for the file of its
+    /// `--partition-by` value. Once too many files are open, records of
+    /// values without one are spilled to be written later.
+    fn write_partition(&mut self, column: &str, record: &Record) -> Result<(), Error> {
+        let stem = partition::file_stem(column, record.get(column));
+        let partitions = match &mut self.partitions {
+            Some(partitions) => partitions,
+            None => {
+                let open = partition_opener(self.config, self.avro_schema.as_ref());
+                let threads = self.config.worker_threads().min(partition::max_open());
+                let partitions = self
+                    .partitions
+                    .insert(partition::Writers::new(threads, &open));
+                self.open_partition = Some(open);
+                partitions
+            }
+        };
+        if !partitions.contains(&stem) && partitions.len() >= partition::max_open() {
+            let spill = match &mut self.spill {
+                Some(spill) => spill,
+                None => self
+                    .spill
+                    .insert(partition::Spill::new(self.config.temp_dir.as_deref())?),
+            };
+            return spill.push(&stem, record);
+        }
+        partitions.push(stem, record.clone())
+    }
+
+    /// Finishes the open partition files, then writes the spilled records
+    /// in turns of as many partitions as may be open at once. Returns the
+    /// number of files written.
+    fn finish_partitions(&mut self) -> Result<usize, Error> {
+        let Some(mut partitions) = self.partitions.take() else {
+            return Ok(0);
+        };
+        let open = self.open_partition.take().expect("opened with the writers");
+        let threads = self.config.worker_threads().min(partition::max_open());
+        let mut files = 0;
+        loop {
+            files += partitions.finish()?;
+            let Some(spill) = self.spill.take() else {
+                return Ok(files);
+            };
+            partitions = partition::Writers::new(threads, &open);
+            let mut rest = None;
+            for spilled in spill.records()? {
+                let (stem, record) = spilled?;
+                if !partitions.contains(&stem) && partitions.len() >= partition::max_open() {
+                    let rest = match &mut rest {
+                        Some(rest) => rest,
+                        None => {
+                            rest.insert(partition::Spill::new(self.config.temp_dir.as_deref())?)
+                        }
+                    };
+                    rest.push(&stem, &record)?;
+                } else {
+                    partitions.push(stem, record)?;
+                }
+            }
+            self.spill = rest;
+        }
+    }
+
+    fn report_written(&self) {
+        // Status goes to stderr so it never mixes with piped output
+        if let (false, Some(output_file)) = (self.config.quiet, self.output_path()) {
+            eprintln!("Output written to: {}", output_file);
+        }
+    }
+
+    /// Does everything with a written record but write it to the main output:
+    /// samples it for `--self-check`, notes its types for `--emit-mapping`
+    /// and writes it to `--also-output`.
+    fn write_extra(&mut self, record: &Record) -> Result<(), Error> {
+        if let Some(samples) = &mut self.samples {
+            samples.add(record)?;
+        }
+        if let Some(mapping) = &mut self.mapping {
+            mapping.observe(record);
+        }
+        if let Some(also) = self.also_writer()? {
+            stats::timed(Stage::Serialize, || also.write_record(record))?;
+        }
+        Ok(())
+    }
+
+    /// Checks the free space against the records held back for
+    /// `--preflight-space`, then writes them. `complete` means they are the
+    /// whole output rather than a sample of it.
+    fn release_pending(&mut self, complete: bool) -> Result<(), Error> {
+        let Some(pending) = self.pending.take() else {
+            return Ok(());
+        };
+        self.check_space(&pending, complete)?;
+        for record in &pending {
+            self.write(record)?;
+        }
+        Ok(())
+    }
+
+    fn check_space(&self, records: &[Record], complete: bool) -> Result<(), Error> {
+        let (Some(output_file), Some(input_size)) = (&self.config.output, self.input_size) else {
+            return Ok(());
+        };
+        let mut sample_bytes = 0;
+        for record in records {
+            let serialized = if self.config.pretty {
+                serde_json::to_vec_pretty(record)?
+            } else {
+                serde_json::to_vec(record)?
+            };
+            // One more byte for the separator or line break
+            sample_bytes += serialized.len() as u64 + 1;
+        }
+        let estimated = if complete {
+            sample_bytes
+        } else {
+            preflight::estimate(sample_bytes, stats::input_read(), input_size)
+        };
+        log::info!("Estimated output size: {} bytes", estimated);
+        preflight::check(output_file, estimated)
+    }
+
+    /// Ends the output format, opening the output first if nothing was written.
+    fn close(&mut self) -> Result<Box<dyn Sink>, Error> {
+        self.writer()?;
+        self.writer.take().expect("writer was just opened").finish()
+    }
+
+    /// Writes out anything held back and ends the output, returning the
+    /// number of records written.
+    fn finish(mut self) -> Result<usize, Error> {
+        let config = self.config;
+        check_record_count(config, self.count)?;
+        log::info!(
+            "Wrote {} records to {}",
+            self.count,
+            config.output.as_deref().unwrap_or("stdout")
+        );
+
+        let writer = match self.buffered.take() {
+            Some(records) => {
+                let records = match &config.hierarchy {
+                    Some(hierarchy) => hierarchy::nest(records, hierarchy)?,
+                    None => records,
+                };
+                self.check_space(&records, true)?;
+                if config.with_columns_meta {
+                    for record in &records {
+                        self.write_extra(record)?;
+                    }
+                    // The metadata summarizes every record, so the envelope is written whole
+                    let mut columns = columns::describe(&records);
+                    if let Some(dictionary) = &self.dictionary {
+                        dictionary.annotate(&mut columns);
+                    }
+                    if let Some(inferred) = &self.inferred {
+                        for column in &mut columns {
+                            column.confidence = inferred.get(&column.name).map(|i| i.confidence);
+                        }
+                    }
+                    let envelope = Envelope {
+                        columns,
+                        records: &records,
+                    };
+                    let mut writer = self.open_main(config.output.as_deref())?;
+                    stats::timed(Stage::Serialize, || {
+                        if config.pretty {
+                            serde_json::to_writer_pretty(&mut writer, &envelope)
+                        } else {
+                            serde_json::to_writer(&mut writer, &envelope)
+                        }
+                    })?;
+                    Some(writer)
+                } else {
+                    for record in &records {
+                        self.write(record)?;
+                    }
+                    None
+                }
+            }
+            None => {
+                self.release_pending(true)?;
+                None
+            }
+        };
+
+        if config.partition_by.is_some() {
+            let files = self.finish_partitions()?;
+            if !config.quiet {
+                eprintln!(
+                    "Output written to {} partition files in: {}",
+                    files,
+                    config.output.as_deref().expect("checked in new")
+                );
+            }
+        } else {
+            let writer = match writer {
+                Some(writer) => writer,
+                None => self.close()?,
+            };
+            self.finish_main(writer)?;
+        }
+
+        if let Some(also_output) = &config.also_output {
+            self.also_writer()?;
+            let also = self.also.take().expect("opened above").finish()?;
+            also.finish()?;
+            if !config.quiet {
+                eprintln!("Output written to: {}", also_output);
+            }
+        }
+
+        if let (Some(mapping), Some(path)) = (&self.mapping, &config.emit_mapping) {
+            mapping.write(path, config)?;
+            log::info!("Wrote field mapping to {}", path);
+        }
+
+        Ok(self.count)
+    }
+
+    /// Flushes the main output, then self-checks it before moving it into
+    /// place, so output that fails the check never replaces the old one.
+    fn finish_main(&mut self, mut writer: Box<dyn Sink>) -> Result<(), Error> {
+        let config = self.config;
+        match &config.output {
+            Some(output_file) => {
+                writer.finish()?;
+                if let Some(samples) = &self.samples {
+                    let records_key = config.with_columns_meta.then_some("records");
+                    let json_lines = config.format == OutputFormat::Jsonl;
+                    let written = match &self.replacement {
+                        Some(replacement) => replacement.temp_path().to_string_lossy(),
+                        None => Cow::from(output_file),
+                    };
+                    let verified = selfcheck::verify(&written, json_lines, records_key, samples);
+                    report::record(
+                        "self_check",
+                        verified.is_ok(),
+                        serde_json::json!({
+                            "error": verified.as_ref().err().map(|e| e.to_string())
+                        }),
+                    );
+                    verified?;
+                    log::info!("Self-check passed for {}", output_file);
+                }
+                self.commit()?;
+                self.report_written();
+            }
+            None => {
+                // JSON Lines output already ends with a newline, and a
+                // newline would be a stray byte after binary output
+                if matches!(config.format, OutputFormat::Json | OutputFormat::Html) {
+                    writeln!(writer)?;
+                }
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_convert_csv_to_json_basic() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,age,city\nJohn,30,Tokyo\nJane,25,Osaka";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            pretty: false,
+            no_header: false,
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0]["name"], "John");
+        assert_eq!(parsed[0]["age"], 30.0);
+        assert_eq!(parsed[0]["city"], "Tokyo");
+        assert_eq!(parsed[1]["name"], "Jane");
+        assert_eq!(parsed[1]["age"], 25.0);
+        assert_eq!(parsed[1]["city"], "Osaka");
+    }
+
+    #[test]
+    fn test_convert_csv_to_json_pretty() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,active\nTest,true";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            pretty: true,
+            no_header: false,
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert!(output_content.contains("  "));
+        assert!(output_content.contains("\n"));
+
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+        assert_eq!(parsed[0]["name"], "Test");
+        assert_eq!(parsed[0]["active"], true);
+    }
+
+    #[test]
+    fn test_convert_csv_with_numbers_and_booleans() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,score,passed\nAlice,95.5,true\nBob,80,false";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            pretty: false,
+            no_header: false,
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+
+        assert_eq!(parsed[0]["name"], "Alice");
+        assert_eq!(parsed[0]["score"], 95.5);
+        assert_eq!(parsed[0]["passed"], true);
+        assert_eq!(parsed[1]["name"], "Bob");
+        assert_eq!(parsed[1]["score"], 80.0);
+        assert_eq!(parsed[1]["passed"], false);
+    }
+
+    #[test]
+    fn test_convert_csv_empty_fields() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,age,city\nJohn,,\n,25,Osaka";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            pretty: false,
+            no_header: false,
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+
+        assert_eq!(parsed[0]["name"], "John");
+        assert_eq!(parsed[0]["age"], "");
+        assert_eq!(parsed[0]["city"], "");
+        assert_eq!(parsed[1]["name"], "");
+        assert_eq!(parsed[1]["age"], 25.0);
+        assert_eq!(parsed[1]["city"], "Osaka");
+    }
+
+    #[test]
+    fn test_convert_csv_file_not_found() {
+        let config = Config {
+            input: Some("non_existent_file.csv".to_string()),
+            output: None,
+            pretty: false,
+            no_header: false,
+            ..Default::default()
+        };
+
+        let result = convert_csv_to_json(&config);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_convert_csv_invalid_format() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,age\nJohn,30\nJane";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            pretty: false,
+            no_header: false,
+            ..Default::default()
+        };
+
+        let result = convert_csv_to_json(&config);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_convert_csv_single_column() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name\nJohn\nJane";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            pretty: false,
+            no_header: false,
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0]["name"], "John");
+        assert_eq!(parsed[1]["name"], "Jane");
+    }
+
+    #[test]
+    fn test_convert_csv_no_data_rows() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,age,city";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            pretty: false,
+            no_header: false,
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+
+        assert_eq!(parsed.len(), 0);
+    }
+
+    #[test]
+    fn test_convert_csv_no_header() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "John,30,Tokyo\nJane,25,Osaka";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            pretty: false,
+            no_header: true,
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0]["column_0"], "John");
+        assert_eq!(parsed[0]["column_1"], 30.0);
+        assert_eq!(parsed[0]["column_2"], "Tokyo");
+        assert_eq!(parsed[1]["column_0"], "Jane");
+        assert_eq!(parsed[1]["column_1"], 25.0);
+        assert_eq!(parsed[1]["column_2"], "Osaka");
+    }
+
+    #[test]
+    fn test_convert_csv_no_header_empty_file() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            pretty: false,
+            no_header: true,
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+
+        assert_eq!(parsed.len(), 0);
+    }
+
+    #[test]
+    fn test_integer_vs_float_detection() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,age,score,active\nJohn,25,95.5,TRUE\nJane,30,100,False";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            pretty: false,
+            no_header: false,
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+
+        assert_eq!(parsed.len(), 2);
+
+        // First record
+        assert_eq!(parsed[0]["name"], "John");
+        assert_eq!(parsed[0]["age"], 25); // Integer
+        assert_eq!(parsed[0]["score"], 95.5); // Float
+        assert_eq!(parsed[0]["active"], true); // Boolean
+
+        // Second record
+        assert_eq!(parsed[1]["name"], "Jane");
+        assert_eq!(parsed[1]["age"], 30); // Integer
+        assert_eq!(parsed[1]["score"], 100); // Integer (not 100.0)
+        assert_eq!(parsed[1]["active"], false); // Boolean
+    }
+
+    #[test]
+    fn test_case_insensitive_booleans() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "test,value\ncase1,true\ncase2,FALSE\ncase3,True\ncase4,false";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            pretty: false,
+            no_header: false,
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+
+        assert_eq!(parsed.len(), 4);
+        assert_eq!(parsed[0]["value"], true);
+        assert_eq!(parsed[1]["value"], false);
+        assert_eq!(parsed[2]["value"], true);
+        assert_eq!(parsed[3]["value"], false);
+    }
+
+    #[test]
+    fn test_select_columns_in_requested_order() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,age,city\nJohn,30,Tokyo\nJane,25,Osaka";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            select: Some(vec!["city".to_string(), "name".to_string()]),
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            r#"[{"city":"Tokyo","name":"John"},{"city":"Osaka","name":"Jane"}]"#
+        );
+    }
+
+    #[test]
+    fn test_select_unknown_column() {
+        let temp_input = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,age\nJohn,30";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            select: Some(vec!["email".to_string()]),
+            ..Default::default()
+        };
+
+        let result = convert_csv_to_json(&config);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_drop_columns() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,password,age,ssn\nJohn,secret,30,123-45-6789";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            drop: Some(vec!["password".to_string(), "ssn".to_string()]),
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(output_content, r#"[{"name":"John","age":30}]"#);
+    }
+
+    #[test]
+    fn test_rename_columns() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "First Name,Age\nJohn,30";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            rename: vec![
+                ("First Name".to_string(), "first_name".to_string()),
+                ("Age".to_string(), "age".to_string()),
+            ],
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(output_content, r#"[{"first_name":"John","age":30}]"#);
+    }
+
+    #[test]
+    fn test_parse_rename() {
+        assert_eq!(
+            parse_rename("First Name=first_name").unwrap(),
+            ("First Name".to_string(), "first_name".to_string())
+        );
+        assert!(parse_rename("first_name").is_err());
+        assert!(parse_rename("=first_name").is_err());
+    }
+
+    #[test]
+    fn test_rename_file() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_mapping = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "First Name,Last Name,Age\nJohn,Smith,30";
+        fs::write(temp_input.path(), csv_content).unwrap();
+        fs::write(
+            temp_mapping.path(),
+            r#"{"First Name": "first_name", "Last Name": "last_name"}"#,
+        )
+        .unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            rename: vec![("Last Name".to_string(), "surname".to_string())],
+            rename_file: Some(temp_mapping.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            r#"[{"first_name":"John","surname":"Smith","Age":30}]"#
+        );
+    }
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("4096").unwrap(), 4096);
+        assert_eq!(parse_byte_size("64K").unwrap(), 64 * 1024);
+        assert_eq!(parse_byte_size("1m").unwrap(), 1024 * 1024);
+        assert!(parse_byte_size("0").is_err());
+        assert!(parse_byte_size("big").is_err());
+    }
+
+    #[test]
+    fn test_small_buffers() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,age,city\nJohn,30,Tokyo\nJane,25,Osaka";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            read_buffer: Some(1),
+            write_buffer: Some(1),
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            r#"[{"name":"John","age":30,"city":"Tokyo"},{"name":"Jane","age":25,"city":"Osaka"}]"#
+        );
+    }
+
+    #[test]
+    fn test_write_buffer_holds_output() {
+        let temp_output = NamedTempFile::new().unwrap();
+        let path = temp_output.path().to_string_lossy().to_string();
+        let config = Config {
+            write_buffer: Some(64),
+            ..Default::default()
+        };
+        let file = File::create(&path).unwrap();
+        let mut sink = open_file_sink(Some(&path), Some(file), &config).unwrap();
+
+        // A write smaller than the buffer stays in it until the end
+        sink.write_all(b"[{\"a\":1}]").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        sink.finish().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[{\"a\":1}]");
+
+        // One larger than the buffer is written straight through
+        let file = File::create(&path).unwrap();
+        let mut sink = open_file_sink(Some(&path), Some(file), &config).unwrap();
+        let large = "x".repeat(100);
+        sink.write_all(large.as_bytes()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), large);
+        sink.finish().unwrap();
+    }
+
+    #[test]
+    fn test_explicit_headers() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "John,30,Tokyo\nJane,25,Osaka";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            headers: Some(vec![
+                "name".to_string(),
+                "age".to_string(),
+                "city".to_string(),
+            ]),
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            r#"[{"name":"John","age":30,"city":"Tokyo"},{"name":"Jane","age":25,"city":"Osaka"}]"#
+        );
+    }
+
+    #[test]
+    fn test_explicit_headers_count_mismatch() {
+        let temp_input = NamedTempFile::new().unwrap();
+
+        let csv_content = "John,30,Tokyo";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            headers: Some(vec!["name".to_string(), "age".to_string()]),
+            ..Default::default()
+        };
+
+        assert!(convert_csv_to_json(&config).is_err());
+    }
+
+    #[test]
+    fn test_generated_header_name() {
+        assert_eq!(generated_header_name("column", 0), "column_0");
+        assert_eq!(generated_header_name("col", 2), "col_2");
+        assert_eq!(generated_header_name("c{n}", 0), "c1");
+        assert_eq!(generated_header_name("field{i}", 3), "field3");
+    }
+
+    #[test]
+    fn test_convert_csv_no_header_with_prefix() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "John,30";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            no_header: true,
+            header_prefix: Some("field{n}".to_string()),
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(output_content, r#"[{"field1":"John","field2":30}]"#);
+    }
+
+    #[test]
+    fn test_stable_only_and_quiescence() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,age\nJohn,30\n";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            stable_only: true,
+            wait_for_quiescence: Some(0),
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(output_content, r#"[{"name":"John","age":30}]"#);
+    }
+
+    #[test]
+    fn test_stable_only_requires_file() {
+        let config = Config {
+            stable_only: true,
+            ..Default::default()
+        };
+
+        assert!(convert_csv_to_json(&config).is_err());
+    }
+
+    #[test]
+    fn test_lock_held_by_another_process() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let temp_input = NamedTempFile::new().unwrap();
+        let output_path = temp_dir.path().join("output.json");
+
+        fs::write(temp_input.path(), "name\nJohn").unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(output_path.to_string_lossy().to_string()),
+            lock: true,
+            ..Default::default()
+        };
+
+        let held = acquire_output_lock(&config).unwrap();
+        assert!(held.is_some());
+        assert!(convert_csv_to_json(&config).is_err());
+
+        drop(held);
+        convert_csv_to_json(&config).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output_path).unwrap(),
+            r#"[{"name":"John"}]"#
+        );
+    }
+
+    #[test]
+    fn test_fail_on_empty() {
+        let temp_input = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,age";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            fail_on_empty: true,
+            ..Default::default()
+        };
+
+        assert!(convert_csv_to_json(&config).is_err());
+    }
+
+    #[test]
+    fn test_skip_footer() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,score\nJohn,30\nJane,25\nTotal: 55\nGenerated 2024-01-01,by,export";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            skip_footer: 2,
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            r#"[{"name":"John","score":30},{"name":"Jane","score":25}]"#
+        );
+    }
+
+    #[test]
+    fn test_skip_footer_still_rejects_ragged_data() {
+        let temp_input = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,score\nJohn\nJane,25\nTotal";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            skip_footer: 1,
+            ..Default::default()
+        };
+
+        assert!(convert_csv_to_json(&config).is_err());
+    }
+
+    #[test]
+    fn test_comment_lines() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "# instrument: A-1\nname,value\n# calibrated\nprobe,1.5";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            comment: Some(b'#'),
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(output_content, r#"[{"name":"probe","value":1.5}]"#);
+    }
+
+    #[test]
+    fn test_parse_single_byte() {
+        assert_eq!(parse_single_byte("#").unwrap(), b'#');
+        assert!(parse_single_byte("##").is_err());
+        assert!(parse_single_byte("").is_err());
+    }
+
+    #[test]
+    fn test_parse_row_count_range() {
+        assert_eq!(
+            parse_row_count_range("1000..50000").unwrap(),
+            RowCountRange {
+                min: Some(1000),
+                max: Some(50000)
+            }
+        );
+        assert_eq!(
+            parse_row_count_range("10..").unwrap(),
+            RowCountRange {
+                min: Some(10),
+                max: None
+            }
+        );
+        assert!(parse_row_count_range("..").is_err());
+        assert!(parse_row_count_range("5..1").is_err());
+        assert!(parse_row_count_range("100").is_err());
+    }
+
+    #[test]
+    fn test_expect_rows() {
+        let temp_input = NamedTempFile::new().unwrap();
+
+        let csv_content = "name\nJohn\nJane";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            expect_rows: Some(RowCountRange {
+                min: Some(3),
+                max: None,
+            }),
+            ..Default::default()
+        };
+        assert!(convert_csv_to_json(&config).is_err());
+
+        let temp_output = NamedTempFile::new().unwrap();
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            expect_rows: Some(RowCountRange {
+                min: Some(1),
+                max: Some(2),
+            }),
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+        // Records are streamed to a file rather than held until the count is known
+        assert!(Output::new(&config).unwrap().buffered.is_none());
+
+        // A count out of range leaves the existing output as it was
+        let temp_dir = tempfile::tempdir().unwrap();
+        let output_file = temp_dir.path().join("out.json");
+        fs::write(&output_file, "[]").unwrap();
+        for split_rows in [None, Some(1)] {
+            let config = Config {
+                output: Some(output_file.to_string_lossy().to_string()),
+                expect_rows: Some(RowCountRange {
+                    min: Some(3),
+                    max: None,
+                }),
+                split_rows,
+                quiet: true,
+                ..config.clone()
+            };
+            assert!(matches!(
+                convert_csv_to_json(&config),
+                Err(Error::Validation(_))
+            ));
+            assert_eq!(fs::read_to_string(&output_file).unwrap(), "[]");
+            assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_expect_header_hash() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,age\nJohn,30";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let hash = header_hash(&["name".to_string(), "age".to_string()]).unwrap();
+        assert_eq!(hash, sha256::hex_digest(br#"["name","age"]"#));
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            expect_header_hash: Some(hash.to_uppercase()),
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+
+        let reordered = header_hash(&["age".to_string(), "name".to_string()]).unwrap();
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            expect_header_hash: Some(reordered),
+            ..Default::default()
+        };
+        assert!(convert_csv_to_json(&config).is_err());
+    }
+
+    fn convert_ragged(policy: RaggedPolicy) -> Result<String, Error> {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,age,city\nJohn,30\nJane,25,Osaka,extra\nBob,35,Kyoto";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            on_ragged: policy,
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config)?;
+        Ok(fs::read_to_string(temp_output.path()).unwrap())
+    }
+
+    #[test]
+    fn test_ragged_policies() {
+        assert!(convert_ragged(RaggedPolicy::Error).is_err());
+        assert_eq!(
+            convert_ragged(RaggedPolicy::Pad).unwrap(),
+            r#"[{"name":"John","age":30,"city":null},{"name":"Jane","age":25,"city":"Osaka"},{"name":"Bob","age":35,"city":"Kyoto"}]"#
+        );
+        assert_eq!(
+            convert_ragged(RaggedPolicy::Truncate).unwrap(),
+            r#"[{"name":"John","age":30},{"name":"Jane","age":25,"city":"Osaka"},{"name":"Bob","age":35,"city":"Kyoto"}]"#
+        );
+        assert_eq!(
+            convert_ragged(RaggedPolicy::Skip).unwrap(),
+            r#"[{"name":"Bob","age":35,"city":"Kyoto"}]"#
+        );
+    }
+
+    #[test]
+    fn test_between_time_window() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "id,created_at\n1,2023-12-31T23:59:59Z\n2,2024-01-01\n3,2024-01-31 12:00\n4,2024-02-01\n5,unknown";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            between: Some(parse_time_window("created_at:2024-01-01..2024-02-01").unwrap()),
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+        let ids: Vec<i64> = parsed.iter().map(|r| r["id"].as_i64().unwrap()).collect();
+        assert_eq!(ids, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_parse_time_window() {
+        let window = parse_time_window("ts:..2024-01-01T00:00:00Z").unwrap();
+        assert_eq!(window.column, "ts");
+        assert_eq!(window.start, None);
+        assert_eq!(window.end, datetime::parse_timestamp("2024-01-01"));
+        assert!(parse_time_window("ts:..").is_err());
+        assert!(parse_time_window("ts:2024-01-01").is_err());
+        assert!(parse_time_window(":2024-01-01..").is_err());
+    }
+
+    #[test]
+    fn test_skip_errors() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,age\nJohn,30\nbroken\nJane,25\nextra,1,2";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            skip_errors: true,
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            r#"[{"name":"John","age":30},{"name":"Jane","age":25}]"#
+        );
+    }
+
+    #[test]
+    fn test_date_parts() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "id,created_at\n1,2024-03-31T23:30:00Z\n2,n/a\n3,2024-03-10";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            date_parts: vec![parse_date_parts("created_at:year,month,dow").unwrap()],
+            timezone: parse_utc_offset("+09:00").unwrap(),
+            drop: Some(vec!["created_at".to_string()]),
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            r#"[{"id":1,"created_at_year":2024,"created_at_month":4,"created_at_dow":1},{"id":2,"created_at_year":null,"created_at_month":null,"created_at_dow":null},{"id":3,"created_at_year":2024,"created_at_month":3,"created_at_dow":7}]"#
+        );
+
+        // West of UTC, the date alone stays on its day
+        let config = Config {
+            timezone: parse_utc_offset("-05:00").unwrap(),
+            ..config
+        };
+        convert_csv_to_json(&config).unwrap();
+        let output: Value =
+            serde_json::from_str(&fs::read_to_string(temp_output.path()).unwrap()).unwrap();
+        assert_eq!(output[2]["created_at_dow"], 7);
+    }
+
+    #[test]
+    fn test_parse_date_parts() {
+        let spec = parse_date_parts("ts:year,dow").unwrap();
+        assert_eq!(spec.column, "ts");
+        assert_eq!(spec.parts, vec![DatePart::Year, DatePart::Dow]);
+        assert!(parse_date_parts("ts:century").is_err());
+        assert!(parse_date_parts("year,month").is_err());
+    }
+
+    #[test]
+    fn test_max_errors() {
+        let temp_input = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,age\nJohn,30\nbroken\nJane,25\nalso broken";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            max_errors: Some(1),
+            ..Default::default()
+        };
+        assert!(convert_csv_to_json(&config).is_err());
+
+        let temp_output = NamedTempFile::new().unwrap();
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            max_errors: Some(2),
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+    }
+
+    #[test]
+    fn test_convert_currency() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_rates = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "booked_on,amount\n2024-01-02,100\n2024-01-03,10\n2024-01-03,";
+        fs::write(temp_input.path(), csv_content).unwrap();
+        fs::write(
+            temp_rates.path(),
+            r#"{"2024-01-02": {"EUR->USD": 1.5}, "2024-01-03": {"EUR->USD": 2}}"#,
+        )
+        .unwrap();
+
+        let spec = format!(
+            "amount:EUR->USD:{}:booked_on",
+            temp_rates.path().to_string_lossy()
+        );
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            convert_currency: vec![currency::parse_currency_spec(&spec).unwrap()],
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+        assert_eq!(parsed[0]["amount"], 150.0);
+        assert_eq!(parsed[1]["amount"], 20.0);
+        assert_eq!(parsed[2]["amount"], "");
+    }
+
+    #[test]
+    fn test_scale_columns() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "temp_f,size_kb\n212,3\n,1.5";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            scale: scale::parse_scales("temp_f:(-32)*5/9,size_kb:*1024").unwrap(),
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            r#"[{"temp_f":100,"size_kb":3072},{"temp_f":"","size_kb":1536.0}]"#
+        );
+    }
+
+    #[test]
+    fn test_reject_file() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+        let temp_rejects = NamedTempFile::new().unwrap();
+
+        let mut csv_content = b"name,age\nJohn,30\nbroken\nJane,25\n".to_vec();
+        csv_content.extend_from_slice(b"\xff\xfe,40\n");
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            reject_file: Some(temp_rejects.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            r#"[{"name":"John","age":30},{"name":"Jane","age":25}]"#
+        );
+
+        // Rejected rows are copied as they were read, under the header line
+        assert_eq!(
+            fs::read(temp_rejects.path()).unwrap(),
+            b"name,age\nbroken\n\xff\xfe,40\n"
+        );
+
+        // A quoted row spanning lines, with doubled quotes and CRLF line
+        // breaks, is copied byte for byte
+        let malformed = b"\"Smith, \"\"J\"\"\r\nJr.\" ,30,extra\r\n";
+        let mut csv_content = b"# export\r\nname,age\r\nJohn,30\r\n".to_vec();
+        csv_content.extend_from_slice(malformed);
+        csv_content.extend_from_slice(b"# end\r\nJane,25\r\nJim\r\n");
+        fs::write(temp_input.path(), csv_content).unwrap();
+        let config = Config {
+            comment: Some(b'#'),
+            ..config
+        };
+        convert_csv_to_json(&config).unwrap();
+        let mut expected = b"# export\r\nname,age\r\n".to_vec();
+        expected.extend_from_slice(malformed);
+        expected.extend_from_slice(b"Jim\r\n");
+        assert_eq!(fs::read(temp_rejects.path()).unwrap(), expected);
+
+        // Without a header row, and with rows held back by --skip-footer
+        fs::write(temp_input.path(), "a,1\nb,2,3\nc,3\ntotal\n").unwrap();
+        let config = Config {
+            no_header: true,
+            skip_footer: 1,
+            comment: None,
+            ..config
+        };
+        convert_csv_to_json(&config).unwrap();
+        assert_eq!(fs::read(temp_rejects.path()).unwrap(), b"b,2,3\n");
+    }
+
+    #[test]
+    fn test_explode() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "id,tags,score\n1,\"[\"\"a\"\",\"\"b\"\"]\",5\n2,[],6\n3,none,7\n4,[1,\n";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            explode: Some("tags".to_string()),
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            concat!(
+                r#"[{"id":1,"tags":"a","score":5},{"id":1,"tags":"b","score":5},"#,
+                r#"{"id":2,"tags":null,"score":6},{"id":3,"tags":"none","score":7},"#,
+                r#"{"id":4,"tags":"[1","score":""}]"#
+            )
+        );
+
+        let config = Config {
+            explode: Some("labels".to_string()),
+            ..config
+        };
+        let result = convert_csv_to_json(&config);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Exploded column not found: labels"));
+    }
+
+    #[test]
+    fn test_parse_collect() {
+        let spec = parse_collect("items[]:item_name, item_qty").unwrap();
+        assert_eq!(spec.name, "items");
+        assert_eq!(spec.fields, vec!["item_name", "item_qty"]);
+        assert!(parse_collect("items:item_name").is_err());
+        assert!(parse_collect("[]:item_name").is_err());
+        assert!(parse_collect("items[]:item_name,").is_err());
+    }
+
+    #[test]
+    fn test_collect_numbered_columns() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "order,item_name_1,item_qty_1,item_name_2,item_qty_2,total\n\
+                           A1,pen,2,ink,1,9.5\n\
+                           A2,pad,5,,,4\n";
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            collect: vec![parse_collect("items[]:item_name,item_qty").unwrap()],
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            concat!(
+                r#"[{"order":"A1","items":[{"item_name":"pen","item_qty":2},{"item_name":"ink","item_qty":1}],"total":9.5},"#,
+                r#"{"order":"A2","items":[{"item_name":"pad","item_qty":5}],"total":4}]"#
+            )
+        );
+
+        let config = Config {
+            collect: vec![parse_collect("lines[]:line_sku").unwrap()],
+            ..config
+        };
+        assert!(convert_csv_to_json(&config).is_err());
+    }
+
+    #[test]
+    fn test_errors_report_line_and_column() {
+        let temp_input = NamedTempFile::new().unwrap();
+        fs::write(
+            temp_input.path(),
+            "name,age\nJohn,30\nJane,25,extra_field\n",
+        )
+        .unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let message = convert_csv_to_json(&config).unwrap_err().to_string();
+        assert!(message.contains("line: 3"), "{}", message);
+        assert!(message.contains(r#"column 3 "extra_field""#), "{}", message);
+
+        // The same with CRLF line endings, which the csv crate counts late
+        fs::write(temp_input.path(), "name,age\r\nJane,25,extra_field\r\n").unwrap();
+        let message = convert_csv_to_json(&config).unwrap_err().to_string();
+        assert!(message.contains("line: 2, byte: 10"), "{}", message);
+
+        let mut csv_content = b"name,age\nJohn,30\nJane,".to_vec();
+        csv_content.extend_from_slice(b"2\xff\n");
+        fs::write(temp_input.path(), csv_content).unwrap();
+        let message = convert_csv_to_json(&config).unwrap_err().to_string();
+        assert!(message.contains("line: 3"), "{}", message);
+        assert!(message.contains("invalid UTF-8 in column 2"), "{}", message);
+
+        assert_eq!(
+            describe_field(0, "x".repeat(50).as_bytes()),
+            format!("column 1 \"{}...\"", "x".repeat(40))
+        );
+    }
+
+    #[test]
+    fn test_with_columns_meta() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        fs::write(temp_input.path(), "name,age\nJohn,30\nJane,\n").unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            with_columns_meta: true,
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            concat!(
+                r#"{"columns":[{"name":"name","type":"string","null_count":0,"example":"John"},"#,
+                r#"{"name":"age","type":"integer","null_count":1,"example":30}],"#,
+                r#""records":[{"name":"John","age":30},{"name":"Jane","age":""}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_jsonl_input() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let jsonl_content = concat!(
+            r#"{"name":"John","zip":"01234","tags":["a"],"ts":"2024-03-31T12:00:00Z"}"#,
+            "\n\n",
+            r#"{"name":"Jane","zip":"98765","extra":null,"ts":"2023-01-01"}"#,
+            "\n",
+            r#"{"name":"Old","ts":"1999-01-01"}"#,
+            "\n"
+        );
+        fs::write(temp_input.path(), jsonl_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            from: InputFormat::Jsonl,
+            between: Some(parse_time_window("ts:2020-01-01..").unwrap()),
+            drop: Some(vec!["ts".to_string()]),
+            rename: vec![("zip".to_string(), "postcode".to_string())],
+            ..Default::default()
+        };
+
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            concat!(
+                r#"[{"name":"John","postcode":"01234","tags":["a"],"extra":null},"#,
+                r#"{"name":"Jane","postcode":"98765","tags":null,"extra":null}]"#
+            )
+        );
+
+        fs::write(temp_input.path(), "{\"name\":\"John\"}\n[1,2]\n").unwrap();
+        let config = Config {
+            between: None,
+            drop: None,
+            rename: Vec::new(),
+            ..config
+        };
+        let message = convert_csv_to_json(&config).unwrap_err().to_string();
+        assert!(
+            message.contains("line 2: expected a JSON object"),
+            "{}",
+            message
+        );
+
+        // The columns come from the first lines; later keys are left out
+        let mut jsonl_content = "{\"id\":0}\n".repeat(infer::SAMPLE_ROWS);
+        jsonl_content.push_str("{\"id\":1,\"late\":true}\n");
+        fs::write(temp_input.path(), jsonl_content).unwrap();
+        let config = Config {
+            format: OutputFormat::Jsonl,
+            ..config
+        };
+        convert_csv_to_json(&config).unwrap();
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(output_content.lines().last(), Some(r#"{"id":1}"#));
+        let config = Config {
+            fatal_warnings: true,
+            ..config
+        };
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Validation(_))
+        ));
+
+        // Options for CSV rows are rejected rather than ignored
+        for config in [
+            Config {
+                skip_footer: 1,
+                ..config.clone()
+            },
+            Config {
+                headers: Some(vec!["id".to_string()]),
+                ..config.clone()
+            },
+            Config {
+                comment: Some(b'#'),
+                ..config.clone()
+            },
+            Config {
+                on_ragged: RaggedPolicy::Pad,
+                ..config.clone()
+            },
+        ] {
+            assert!(matches!(
+                convert_csv_to_json(&config),
+                Err(Error::Config(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_log_format_input() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        fs::write(
+            temp_input.path(),
+            "host:10.0.0.1\tstatus:200\tsize:512\nhost:10.0.0.2\tstatus:404\n",
+        )
+        .unwrap();
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            from: InputFormat::Ltsv,
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            r#"[{"host":"10.0.0.1","status":200,"size":512},{"host":"10.0.0.2","status":404,"size":null}]"#
+        );
+
+        fs::write(
+            temp_input.path(),
+            concat!(
+                r#"10.0.0.1 - - [31/Mar/2024:23:30:00 +0000] "GET / HTTP/1.1" 200 612 "-" "curl/8.0""#,
+                "\n"
+            ),
+        )
+        .unwrap();
+        let config = Config {
+            from: InputFormat::CombinedLog,
+            select: Some(vec![
+                "path".to_string(),
+                "status".to_string(),
+                "time_dow".to_string(),
+            ]),
+            date_parts: vec![parse_date_parts("time:dow").unwrap()],
+            ..config
+        };
+        convert_csv_to_json(&config).unwrap();
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            r#"[{"path":"/","status":200,"time_dow":7}]"#
+        );
+    }
+
+    #[test]
+    fn test_table_input() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let table = "Filesystem     1K-blocks    Used Available Use% Mounted on\n\
+                     /dev/sda1       40000000 1000000  39000000   3% /\n\
+                     tmpfs             100000       0    100000   0% /run/user/1000\n";
+        fs::write(temp_input.path(), table).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            from: InputFormat::Table,
+            select: Some(vec![
+                "Filesystem".to_string(),
+                "Used".to_string(),
+                "Mounted on".to_string(),
+            ]),
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            concat!(
+                r#"[{"Filesystem":"/dev/sda1","Used":1000000,"Mounted on":"/"},"#,
+                r#"{"Filesystem":"tmpfs","Used":0,"Mounted on":"/run/user/1000"}]"#
+            )
+        );
+
+        fs::write(
+            temp_input.path(),
+            "  PID TTY          TIME CMD\n    1 ?        00:00:02 /sbin/init splash\n",
+        )
+        .unwrap();
+        let config = Config {
+            select: None,
+            ..config
+        };
+        convert_csv_to_json(&config).unwrap();
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            r#"[{"PID":1,"TTY":"?","TIME":"00:00:02","CMD":"/sbin/init splash"}]"#
+        );
+    }
+
+    #[test]
+    fn test_jsonl_output() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        fs::write(temp_input.path(), "name,age\nJohn,30\nJane,25\n").unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            format: OutputFormat::Jsonl,
+            pretty: true,
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+
+        let output_content = fs::read_to_string(temp_output.path()).unwrap();
+        assert_eq!(
+            output_content,
+            "{\"name\":\"John\",\"age\":30}\n{\"name\":\"Jane\",\"age\":25}\n"
+        );
+
+        let config = Config {
+            with_columns_meta: true,
+            ..config
+        };
+        assert!(convert_csv_to_json(&config).is_err());
+    }
+
+    #[test]
+    fn test_streaming_writes_nothing_for_empty_input() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let output_dir = tempfile::tempdir().unwrap();
+        let output_path = output_dir.path().join("out.json");
+
+        fs::write(temp_input.path(), "name,age\n").unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(output_path.to_string_lossy().to_string()),
+            fail_on_empty: true,
+            ..Default::default()
+        };
+        assert!(convert_csv_to_json(&config).is_err());
+        assert!(!output_path.exists());
+    }
+
+    #[test]
+    fn test_self_check() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        fs::write(temp_input.path(), "name,age\nJohn,30\nJane,25.5\nJim,\n").unwrap();
+
+        for format in [OutputFormat::Json, OutputFormat::Jsonl] {
+            for pretty in [false, true] {
+                let config = Config {
+                    input: Some(temp_input.path().to_string_lossy().to_string()),
+                    output: Some(temp_output.path().to_string_lossy().to_string()),
+                    self_check: true,
+                    format,
+                    pretty,
+                    ..Default::default()
+                };
+                convert_csv_to_json(&config).unwrap();
+            }
+        }
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            self_check: true,
+            ..Default::default()
+        };
+        assert!(convert_csv_to_json(&config).is_err());
+    }
+
+    #[test]
+    fn test_self_check_failure_keeps_old_output() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let output_file = temp_dir.path().join("out.json");
+        fs::write(&output_file, "[\"old\"]").unwrap();
+        let config = Config {
+            output: Some(output_file.to_string_lossy().to_string()),
+            self_check: true,
+            backup: true,
+            quiet: true,
+            ..Default::default()
+        };
+        let mut output = Output::new(&config).unwrap();
+        let record: Record = serde_json::from_str(r#"{"name":"John"}"#).unwrap();
+        output.push(record.clone()).unwrap();
+        // A sample the output does not hold makes the check fail
+        output.samples.as_mut().unwrap().add(&record).unwrap();
+        assert!(matches!(output.finish(), Err(Error::Validation(_))));
+
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), "[\"old\"]");
+        // Neither a backup nor the temporary file is left behind
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_preflight_space() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let mut csv_content = String::from("id,name\n");
+        for i in 0..2500 {
+            csv_content.push_str(&format!("{},name{}\n", i, i));
+        }
+        fs::write(temp_input.path(), csv_content).unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            preflight_space: true,
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+        let output: Vec<Value> =
+            serde_json::from_str(&fs::read_to_string(temp_output.path()).unwrap()).unwrap();
+        assert_eq!(output.len(), 2500);
+        assert_eq!(output[2499]["name"], "name2499");
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            preflight_space: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_threads_option() {
+        let threads = |args: &[&str]| {
+            let matches = cli().try_get_matches_from(args).unwrap();
+            Config {
+                threads: matches.get_one::<usize>("threads").copied(),
+                ..Default::default()
+            }
+            .worker_threads()
+        };
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        assert_eq!(threads(&["ctj", "--threads", "1"]), 1);
+        assert_eq!(threads(&["ctj", "--threads", "3"]), 3);
+        assert_eq!(threads(&["ctj", "--threads", "0"]), cpus);
+        assert_eq!(threads(&["ctj"]), cpus);
+        assert!(cli()
+            .try_get_matches_from(["ctj", "--threads", "many"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_parallel_conversion_preserves_order() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let mut csv_content = String::from("id,flag\n");
+        for i in 0..5000 {
+            csv_content.push_str(&format!("{},{}\n", i, i % 2 == 0));
+        }
+        fs::write(temp_input.path(), &csv_content).unwrap();
+
+        let mut outputs = Vec::new();
+        for threads in [1, 4] {
+            let config = Config {
+                input: Some(temp_input.path().to_string_lossy().to_string()),
+                output: Some(temp_output.path().to_string_lossy().to_string()),
+                threads: Some(threads),
+                ..Default::default()
+            };
+            convert_csv_to_json(&config).unwrap();
+            outputs.push(fs::read_to_string(temp_output.path()).unwrap());
+        }
+        assert_eq!(outputs[0], outputs[1]);
+        let output: Vec<Value> = serde_json::from_str(&outputs[1]).unwrap();
+        assert_eq!(output.len(), 5000);
+        assert!(output
+            .iter()
+            .enumerate()
+            .all(|(i, record)| record["id"] == i && record["flag"] == (i % 2 == 0)));
+    }
+
+    #[test]
+    fn test_rows_file() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+        let temp_rows = NamedTempFile::new().unwrap();
+
+        // Line numbers count the header and quoted line breaks
+        let csv_content = "name,note\nJohn,a\nJane,\"two\nlines\"\nJim,c\nJill,d";
+        fs::write(temp_input.path(), csv_content).unwrap();
+        fs::write(temp_rows.path(), "# flagged by QA\n3\n\n5\n").unwrap();
+
+        let rows_file = Some(temp_rows.path().to_string_lossy().to_string());
+        let names = |config: &Config| {
+            convert_csv_to_json(config).unwrap();
+            let output: Vec<Value> =
+                serde_json::from_str(&fs::read_to_string(temp_output.path()).unwrap()).unwrap();
+            output
+                .iter()
+                .map(|record| record["name"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            rows_file: rows_file.clone(),
+            ..Default::default()
+        };
+        assert_eq!(names(&config), ["Jane", "Jim"]);
+
+        let config = Config {
+            rows_file: None,
+            exclude_rows_file: rows_file,
+            ..config
+        };
+        assert_eq!(names(&config), ["John", "Jill"]);
+
+        // As many lines with CRLF line endings
+        fs::write(temp_input.path(), csv_content.replace('\n', "\r\n")).unwrap();
+        assert_eq!(names(&config), ["John", "Jill"]);
+
+        fs::write(temp_rows.path(), "3\nfour\n").unwrap();
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_warnings() {
+        let temp_input = NamedTempFile::new().unwrap();
+        fs::write(
+            temp_input.path(),
+            "id,id,big\n1,2,12345678901234567890\n3,4\n",
+        )
+        .unwrap();
+
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            on_ragged: RaggedPolicy::Pad,
+            quiet: true,
+            ..Default::default()
+        };
+        let warnings = Warnings::new(None, false, true);
+        convert(&config, &warnings).unwrap();
+        assert_eq!(
+            warnings.summary().unwrap(),
+            "duplicate header (1), ragged row (1), precision loss (1)"
+        );
+
+        let config = Config {
+            fatal_warnings: true,
+            ..config
+        };
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_also_output() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+        let temp_also = NamedTempFile::new().unwrap();
+
+        fs::write(temp_input.path(), "name,age\nJohn,30\nJane,25").unwrap();
+
+        // Also written when records are collected before writing
+        let collected = RowCountRange {
+            min: Some(1),
+            max: None,
+        };
+        for expect_rows in [None, Some(collected)] {
+            let config = Config {
+                input: Some(temp_input.path().to_string_lossy().to_string()),
+                output: Some(temp_output.path().to_string_lossy().to_string()),
+                also_output: Some(temp_also.path().to_string_lossy().to_string()),
+                also_format: Some(OutputFormat::Jsonl),
+                expect_rows,
+                ..Default::default()
+            };
+            convert_csv_to_json(&config).unwrap();
+            assert_eq!(
+                fs::read_to_string(temp_also.path()).unwrap(),
+                "{\"name\":\"John\",\"age\":30}\n{\"name\":\"Jane\",\"age\":25}\n"
+            );
+        }
+        assert_eq!(
+            fs::read_to_string(temp_output.path()).unwrap(),
+            r#"[{"name":"John","age":30},{"name":"Jane","age":25}]"#
+        );
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = capabilities();
+        assert_eq!(capabilities["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            capabilities["input_formats"],
+            serde_json::json!([
+                "csv",
+                "jsonl",
+                "ltsv",
+                "combined-log",
+                "table",
+                "ini",
+                "tsv",
+                "json",
+                "fixed-width",
+                "parquet",
+                "sqlite"
+            ])
+        );
+        assert_eq!(
+            capabilities["output_formats"],
+            serde_json::json!([
+                "json", "jsonl", "html", "msgpack", "cbor", "bson", "avro", "arrow", "sqlite",
+                "postgres"
+            ])
+        );
+        assert_eq!(capabilities["compression"][0], "gzip");
+        let commands = capabilities["external_commands"].as_object().unwrap();
+        let names: Vec<&str> = commands.keys().map(String::as_str).collect();
+        assert_eq!(names, ["zstd", "bzip2", "xz", "sqlite3"]);
+        assert_eq!(
+            capabilities["compression"].as_array().unwrap().len(),
+            1 + commands
+                .values()
+                .take(3)
+                .filter(|found| **found == true)
+                .count()
+        );
+        let options = capabilities["options"].as_array().unwrap();
+        assert!(options.contains(&Value::from("--input")));
+        assert!(options.contains(&Value::from("--capabilities")));
+    }
+
+    #[test]
+    fn test_gzip_input() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        // gzip of "name,age\nJohn,30\nJane,25\n"
+        let compressed = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\xcbK\xccM\xd5ILO\xe5\xf2\xca\xcf\xc8\xd316\xe0\xf2J\xccK\xd512\xe5\x02\x00\xa1\xa1\x06<\x19\x00\x00\x00";
+        fs::write(temp_input.path(), compressed).unwrap();
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+        let output: Value =
+            serde_json::from_str(&fs::read_to_string(temp_output.path()).unwrap()).unwrap();
+        assert_eq!(
+            output,
+            serde_json::json!([{"name": "John", "age": 30}, {"name": "Jane", "age": 25}])
+        );
+
+        // A damaged file is an I/O error, not malformed CSV
+        fs::write(temp_input.path(), &compressed[..30]).unwrap();
+        assert!(matches!(convert_csv_to_json(&config), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_emit_mapping() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+        let temp_mapping = NamedTempFile::new().unwrap();
+
+        fs::write(temp_input.path(), "id,amt,note\n1,2.5,\n2,3,x").unwrap();
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            rename: vec![("amt".to_string(), "amount".to_string())],
+            drop: Some(vec!["note".to_string()]),
+            emit_mapping: Some(temp_mapping.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+
+        let mapping: Value =
+            serde_json::from_str(&fs::read_to_string(temp_mapping.path()).unwrap()).unwrap();
+        assert_eq!(
+            mapping,
+            serde_json::json!([
+                {"output": "id", "source": ["id"], "transforms": [], "type": "integer"},
+                {"output": "amount", "source": ["amt"], "transforms": ["rename"], "type": "number"},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_compressed_output() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_input.path(), "name,age\nJohn,30\nJane,25").unwrap();
+
+        // Inferred from the extension, and read back by the self-check
+        let output = temp_dir.path().join("out.json.gz");
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(output.to_string_lossy().to_string()),
+            self_check: true,
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+        let mut json = String::new();
+        gzip::GzDecoder::new(BufReader::new(File::open(&output).unwrap()))
+            .read_to_string(&mut json)
+            .unwrap();
+        assert_eq!(
+            json,
+            r#"[{"name":"John","age":30},{"name":"Jane","age":25}]"#
+        );
+
+        let output = temp_dir.path().join("out.jsonl");
+        let config = Config {
+            output: Some(output.to_string_lossy().to_string()),
+            format: OutputFormat::Jsonl,
+            compress: Some(Compression::Gzip),
+            ..config
+        };
+        convert_csv_to_json(&config).unwrap();
+        assert_eq!(
+            Compression::detect(&fs::read(&output).unwrap()),
+            Some(Compression::Gzip)
+        );
+    }
+
+    #[test]
+    fn test_strict_rfc4180() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        fs::write(temp_input.path(), "name,note\nJohn,5\" tall\n").unwrap();
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+
+        let config = Config {
+            strict_rfc4180: true,
+            ..config
+        };
+        match convert_csv_to_json(&config) {
+            Err(Error::Csv(message)) => assert!(message.contains("line 2, column 7 (field 2)")),
+            other => panic!("expected a CSV error, got {:?}", other),
+        }
+
+        fs::write(temp_input.path(), "name,note\r\nJohn,\"5\"\" tall\"\r\n").unwrap();
+        convert_csv_to_json(&config).unwrap();
+    }
+
+    #[test]
+    fn test_repair_quotes() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        // The unclosed quote would otherwise take in every later row
+        let csv_content = "name,note\nJohn,\"says \"hi\"\"\nJane,\"open\nJim,5\" tall\n\
+                           \"Jo\"e,1\n\"he said \"hi\"\",2\n\"unterminated,3\n";
+        fs::write(temp_input.path(), csv_content).unwrap();
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            repair_quotes: true,
+            quiet: true,
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+        let output: Value =
+            serde_json::from_str(&fs::read_to_string(temp_output.path()).unwrap()).unwrap();
+        assert_eq!(
+            output,
+            serde_json::json!([
+                {"name": "John", "note": "says \"hi\""},
+                {"name": "Jane", "note": "open"},
+                {"name": "Jim", "note": "5\" tall"},
+                {"name": "Jo\"e", "note": 1},
+                {"name": "he said \"hi\"", "note": 2},
+                {"name": "unterminated", "note": 3},
+            ])
+        );
+    }
+    #[test]
+    fn test_normalize() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+
+        let csv_content = "name,city\r\nJohn ,Tokyo\nJane,New\u{a0}York\t\r\n";
+        fs::write(temp_input.path(), csv_content).unwrap();
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            normalize_report: true,
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+        let output: Value =
+            serde_json::from_str(&fs::read_to_string(temp_output.path()).unwrap()).unwrap();
+        assert_eq!(output[1]["city"], "New\u{a0}York\t");
+
+        let config = Config {
+            normalize: true,
+            ..config
+        };
+        convert_csv_to_json(&config).unwrap();
+        let output: Value =
+            serde_json::from_str(&fs::read_to_string(temp_output.path()).unwrap()).unwrap();
+        assert_eq!(
+            output,
+            serde_json::json!([
+                {"name": "John", "city": "Tokyo"},
+                {"name": "Jane", "city": "New York"},
+            ])
+        );
+    }
+    #[test]
+    fn test_cache_dir() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("input.csv");
+        let output = temp_dir.path().join("output.json");
+        let cache_dir = temp_dir.path().join(".ctj-cache");
+
+        fs::write(&input, "name,age\nJohn,25\n").unwrap();
+        let config = Config {
+            input: Some(input.to_string_lossy().to_string()),
+            output: Some(output.to_string_lossy().to_string()),
+            cache_dir: Some(cache_dir.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 1);
+
+        // The second run is a hit, restoring the deleted output
+        fs::remove_file(&output).unwrap();
+        convert_csv_to_json(&config).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            r#"[{"name":"John","age":25}]"#
+        );
+
+        let pretty = Config {
+            pretty: true,
+            ..config
+        };
+        convert_csv_to_json(&pretty).unwrap();
+        assert!(fs::read_to_string(&output).unwrap().contains('\n'));
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 2);
+
+        // A compressed output is not a copy of the uncompressed one
+        let compressed = temp_dir.path().join("output.json.gz");
+        let gzip = Config {
+            output: Some(compressed.to_string_lossy().to_string()),
+            ..pretty.clone()
+        };
+        convert_csv_to_json(&gzip).unwrap();
+        assert_eq!(fs::read(&compressed).unwrap()[..2], [0x1f, 0x8b]);
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 3);
+
+        let config = Config {
+            reject_file: Some("rejects.csv".to_string()),
+            ..pretty
+        };
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Config(_))
+        ));
+    }
+    #[test]
+    fn test_multiple_inputs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let first = temp_dir.path().join("first.csv");
+        let second = temp_dir.path().join("second.csv");
+        let output = temp_dir.path().join("output.json");
+        fs::write(&first, "name,age\nJohn,30\nJane,25\n").unwrap();
+        fs::write(&second, "name,age\n").unwrap();
+
+        let inputs = vec![
+            first.to_string_lossy().to_string(),
+            second.to_string_lossy().to_string(),
+        ];
+        let config = Config {
+            inputs: inputs.clone(),
+            output: Some(output.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+        let expected = serde_json::json!({
+            inputs[0].as_str(): [{"name": "John", "age": 30}, {"name": "Jane", "age": 25}],
+            inputs[1].as_str(): [],
+        });
+        let content = fs::read_to_string(&output).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&content).unwrap(), expected);
+
+        // Pretty output matches serde_json's own layout
+        let config = Config {
+            pretty: true,
+            ..config
+        };
+        convert_csv_to_json(&config).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            serde_json::to_string_pretty(&expected).unwrap()
+        );
+
+        let config = Config {
+            source_field: Some("source".to_string()),
+            format: OutputFormat::Jsonl,
+            pretty: false,
+            ..config
+        };
+        convert_csv_to_json(&config).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            format!(
+                "{{\"source\":{0:?},\"name\":\"John\",\"age\":30}}\n\
+                 {{\"source\":{0:?},\"name\":\"Jane\",\"age\":25}}\n",
+                inputs[0]
+            )
+        );
+
+        let config = Config {
+            source_field: Some("name".to_string()),
+            ..config
+        };
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Config(_))
+        ));
+
+        // Each file is converted to a scratch file in --temp-dir
+        let scratch = temp_dir.path().join("scratch");
+        let config = Config {
+            source_field: None,
+            format: OutputFormat::Json,
+            temp_dir: Some(scratch.to_string_lossy().to_string()),
+            ..config
+        };
+        assert!(matches!(convert_csv_to_json(&config), Err(Error::Io(_))));
+        fs::create_dir(&scratch).unwrap();
+        convert_csv_to_json(&config).unwrap();
+        assert_eq!(fs::read_dir(&scratch).unwrap().count(), 0);
+
+        // Record counts are checked on all files together
+        let config = Config {
+            temp_dir: None,
+            source_field: None,
+            format: OutputFormat::Json,
+            expect_rows: Some(RowCountRange {
+                min: Some(2),
+                max: Some(2),
+            }),
+            fail_on_empty: true,
+            ..config
+        };
+        convert_csv_to_json(&config).unwrap();
+        let config = Config {
+            expect_rows: Some(RowCountRange {
+                min: Some(3),
+                max: None,
+            }),
+            ..config
+        };
+        let written = fs::read_to_string(&output).unwrap();
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Validation(_))
+        ));
+        assert_eq!(fs::read_to_string(&output).unwrap(), written);
+
+        let config = Config {
+            inputs: vec![inputs[0].clone(), inputs[0].clone()],
+            expect_rows: None,
+            ..config
+        };
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Config(_))
+        ));
+    }
     #[test]
-    fn test_convert_csv_to_json_basic() {
+    fn test_infer_threshold() {
         let temp_input = NamedTempFile::new().unwrap();
         let temp_output = NamedTempFile::new().unwrap();
 
-        let csv_content = "name,age,city\nJohn,30,Tokyo\nJane,25,Osaka";
+        let csv_content = "code,qty\nA1,1\nB2,2\n300,3\nC4,x\n";
         fs::write(temp_input.path(), csv_content).unwrap();
-
         let config = Config {
             input: Some(temp_input.path().to_string_lossy().to_string()),
             output: Some(temp_output.path().to_string_lossy().to_string()),
-            pretty: false,
-            no_header: false,
+            with_columns_meta: true,
+            ..Default::default()
         };
+        convert_csv_to_json(&config).unwrap();
+        let output: Value =
+            serde_json::from_str(&fs::read_to_string(temp_output.path()).unwrap()).unwrap();
+        assert_eq!(output["records"][2]["code"], 300);
+        assert!(output["columns"][0].get("confidence").is_none());
 
+        let config = Config {
+            infer_threshold: Some(0.75),
+            ..config
+        };
+        convert_csv_to_json(&config).unwrap();
+        let output: Value =
+            serde_json::from_str(&fs::read_to_string(temp_output.path()).unwrap()).unwrap();
+        assert_eq!(output["records"][2]["code"], "300");
+        assert_eq!(output["records"][2]["qty"], 3);
+        assert_eq!(output["columns"][0]["type"], "string");
+        assert_eq!(output["columns"][0]["confidence"], 0.75);
+        assert_eq!(output["columns"][1]["confidence"], 0.75);
+    }
+    #[test]
+    fn test_schema_cache() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let schema_cache = temp_dir.path().join("schemas");
+        let input = temp_dir.path().join("day1.csv");
+        let output = temp_dir.path().join("day1.json");
+        fs::write(&input, "code,qty\nA1,1\nB2,2\n").unwrap();
+        let config = Config {
+            input: Some(input.to_string_lossy().to_string()),
+            output: Some(output.to_string_lossy().to_string()),
+            infer_threshold: Some(0.9),
+            schema_cache: Some(schema_cache.to_string_lossy().to_string()),
+            ..Default::default()
+        };
         convert_csv_to_json(&config).unwrap();
+        assert_eq!(fs::read_dir(&schema_cache).unwrap().count(), 1);
 
-        let output_content = fs::read_to_string(temp_output.path()).unwrap();
-        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+        // The cached types are reused, so the numeric codes stay text
+        fs::write(&input, "code,qty\n100,3\n200,4\n").unwrap();
+        convert_csv_to_json(&config).unwrap();
+        let output_json: Value =
+            serde_json::from_str(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(output_json[0]["code"], "100");
+        assert_eq!(output_json[0]["qty"], 3);
+        assert_eq!(fs::read_dir(&schema_cache).unwrap().count(), 1);
 
-        assert_eq!(parsed.len(), 2);
-        assert_eq!(parsed[0]["name"], "John");
-        assert_eq!(parsed[0]["age"], 30.0);
-        assert_eq!(parsed[0]["city"], "Tokyo");
-        assert_eq!(parsed[1]["name"], "Jane");
-        assert_eq!(parsed[1]["age"], 25.0);
-        assert_eq!(parsed[1]["city"], "Osaka");
-    }
+        // Another header is sampled and cached separately
+        let entries = || -> Vec<PathBuf> {
+            let entries = fs::read_dir(&schema_cache).unwrap();
+            entries.map(|entry| entry.unwrap().path()).collect()
+        };
+        let first = entries();
+        fs::write(&input, "id,qty\n100,3\n").unwrap();
+        convert_csv_to_json(&config).unwrap();
+        let output_json: Value =
+            serde_json::from_str(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(output_json[0]["id"], 100);
+        assert_eq!(fs::read_dir(&schema_cache).unwrap().count(), 2);
+
+        // A corrupt entry, or one with the wrong number of columns, is
+        // inferred again and overwritten
+        let entry = entries()
+            .into_iter()
+            .find(|path| !first.contains(path))
+            .unwrap();
+        for corrupt in ["[{\"type\":", r#"[{"type":"string","confidence":1.0}]"#] {
+            fs::write(&entry, corrupt).unwrap();
+            convert_csv_to_json(&config).unwrap();
+            let output_json: Value =
+                serde_json::from_str(&fs::read_to_string(&output).unwrap()).unwrap();
+            assert_eq!(output_json[0]["id"], 100);
+            let entry: Value = serde_json::from_str(&fs::read_to_string(&entry).unwrap()).unwrap();
+            assert_eq!(entry.as_array().unwrap().len(), 2);
+        }
 
+        let config = Config {
+            infer_threshold: None,
+            ..config
+        };
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Config(_))
+        ));
+    }
     #[test]
-    fn test_convert_csv_to_json_pretty() {
-        let temp_input = NamedTempFile::new().unwrap();
-        let temp_output = NamedTempFile::new().unwrap();
+    fn test_directory_input() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        fs::write(dir.join("a.csv"), "name\nJohn\n").unwrap();
+        fs::write(dir.join("B.CSV"), "city\nTokyo\n").unwrap();
+        fs::write(dir.join("notes.txt"), "not,csv\n").unwrap();
+        fs::create_dir(dir.join("nested.csv")).unwrap();
 
-        let csv_content = "name,active\nTest,true";
-        fs::write(temp_input.path(), csv_content).unwrap();
+        let config = Config {
+            input: Some(dir.to_string_lossy().to_string()),
+            quiet: true,
+            ..Default::default()
+        };
+        convert_csv_to_json(&config).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("a.json")).unwrap(),
+            r#"[{"name":"John"}]"#
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("B.json")).unwrap(),
+            r#"[{"city":"Tokyo"}]"#
+        );
+        assert!(!dir.join("notes.json").exists());
 
         let config = Config {
-            input: Some(temp_input.path().to_string_lossy().to_string()),
-            output: Some(temp_output.path().to_string_lossy().to_string()),
-            pretty: true,
-            no_header: false,
+            format: OutputFormat::Jsonl,
+            compress: Some(Compression::Gzip),
+            ..config
         };
+        convert_csv_to_json(&config).unwrap();
+        assert!(dir.join("a.jsonl.gz").exists());
+
+        // The other files are still converted when one fails
+        fs::write(dir.join("0-bad.csv"), "a,b\n1,2,3\n").unwrap();
+        fs::remove_file(dir.join("a.jsonl.gz")).unwrap();
+        match convert_csv_to_json(&config) {
+            Err(Error::Csv(message)) => assert_eq!(message, "1 of 3 files failed to convert"),
+            other => panic!("expected a CSV error, got {:?}", other),
+        }
+        assert!(dir.join("a.jsonl.gz").exists());
+    }
+    #[test]
+    fn test_recursive_directory_input() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("input");
+        let output = temp_dir.path().join("output");
+        fs::create_dir_all(input.join("2024/q1")).unwrap();
+        fs::write(input.join("top.csv"), "a\n1\n").unwrap();
+        fs::write(input.join("2024/q1/sales.csv"), "a\n2\n").unwrap();
 
+        let config = Config {
+            input: Some(input.to_string_lossy().to_string()),
+            output: Some(output.to_string_lossy().to_string()),
+            quiet: true,
+            ..Default::default()
+        };
         convert_csv_to_json(&config).unwrap();
+        assert!(output.join("top.json").exists());
+        assert!(!output.join("2024").exists());
 
-        let output_content = fs::read_to_string(temp_output.path()).unwrap();
-        assert!(output_content.contains("  "));
-        assert!(output_content.contains("\n"));
+        let config = Config {
+            recursive: true,
+            ..config
+        };
+        convert_csv_to_json(&config).unwrap();
+        assert_eq!(
+            fs::read_to_string(output.join("2024/q1/sales.json")).unwrap(),
+            r#"[{"a":2}]"#
+        );
 
-        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
-        assert_eq!(parsed[0]["name"], "Test");
-        assert_eq!(parsed[0]["active"], true);
+        let config = Config {
+            input: Some(input.join("top.csv").to_string_lossy().to_string()),
+            ..config
+        };
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Config(_))
+        ));
     }
 
     #[test]
-    fn test_convert_csv_with_numbers_and_booleans() {
-        let temp_input = NamedTempFile::new().unwrap();
-        let temp_output = NamedTempFile::new().unwrap();
-
-        let csv_content = "name,score,passed\nAlice,95.5,true\nBob,80,false";
-        fs::write(temp_input.path(), csv_content).unwrap();
+    fn test_output_template() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let input = temp_dir.path().join("input");
+        fs::create_dir_all(input.join("2024")).unwrap();
+        fs::write(input.join("top.csv"), "a\n1\n").unwrap();
+        fs::write(input.join("2024/sales.csv"), "a\n2\n").unwrap();
 
+        let template = format!(
+            "{}/{{stem}}.{{ext}}",
+            temp_dir.path().join("flat").display()
+        );
         let config = Config {
-            input: Some(temp_input.path().to_string_lossy().to_string()),
-            output: Some(temp_output.path().to_string_lossy().to_string()),
-            pretty: false,
-            no_header: false,
+            input: Some(input.to_string_lossy().to_string()),
+            recursive: true,
+            format: OutputFormat::Jsonl,
+            output_template: Some(template::parse_template(&template).unwrap()),
+            quiet: true,
+            ..Default::default()
         };
+        convert_csv_to_json(&config).unwrap();
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("flat/sales.jsonl")).unwrap(),
+            "{\"a\":2}\n"
+        );
+        assert!(temp_dir.path().join("flat/top.jsonl").exists());
 
+        let config = Config {
+            output_template: Some(template::parse_template("{dir}/{name}.json").unwrap()),
+            ..config
+        };
         convert_csv_to_json(&config).unwrap();
+        assert!(input.join("2024/sales.csv.json").exists());
 
-        let output_content = fs::read_to_string(temp_output.path()).unwrap();
-        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+        let config = Config {
+            output_template: Some(template::parse_template("all.json").unwrap()),
+            ..config
+        };
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Config(_))
+        ));
+    }
 
-        assert_eq!(parsed[0]["name"], "Alice");
-        assert_eq!(parsed[0]["score"], 95.5);
-        assert_eq!(parsed[0]["passed"], true);
-        assert_eq!(parsed[1]["name"], "Bob");
-        assert_eq!(parsed[1]["score"], 80.0);
-        assert_eq!(parsed[1]["passed"], false);
+    #[test]
+    fn test_split_path() {
+        assert_eq!(split_path("out.json", 1), "out_0001.json");
+        assert_eq!(
+            split_path("data/out.jsonl.gz", 12),
+            "data/out_0012.jsonl.gz"
+        );
+        assert_eq!(split_path("v1.2/out.v2.json", 3), "v1.2/out.v2_0003.json");
+        assert_eq!(split_path("out.txt", 2), "out_0002.txt");
+        assert_eq!(split_path("data.d/out", 2), "data.d/out_0002");
     }
 
     #[test]
-    fn test_convert_csv_empty_fields() {
+    fn test_split_rows() {
         let temp_input = NamedTempFile::new().unwrap();
-        let temp_output = NamedTempFile::new().unwrap();
-
-        let csv_content = "name,age,city\nJohn,,\n,25,Osaka";
-        fs::write(temp_input.path(), csv_content).unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let output = temp_dir.path().join("out.json");
+        fs::write(temp_input.path(), "a\n1\n2\n3\n4\n5\n").unwrap();
 
         let config = Config {
             input: Some(temp_input.path().to_string_lossy().to_string()),
-            output: Some(temp_output.path().to_string_lossy().to_string()),
-            pretty: false,
-            no_header: false,
+            output: Some(output.to_string_lossy().to_string()),
+            split_rows: Some(2),
+            quiet: true,
+            ..Default::default()
         };
-
         convert_csv_to_json(&config).unwrap();
+        let read = |name: &str| fs::read_to_string(temp_dir.path().join(name)).unwrap();
+        assert_eq!(read("out_0001.json"), r#"[{"a":1},{"a":2}]"#);
+        assert_eq!(read("out_0002.json"), r#"[{"a":3},{"a":4}]"#);
+        assert_eq!(read("out_0003.json"), r#"[{"a":5}]"#);
+        assert!(!temp_dir.path().join("out_0004.json").exists());
+        assert!(!output.exists());
 
-        let output_content = fs::read_to_string(temp_output.path()).unwrap();
-        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
-
-        assert_eq!(parsed[0]["name"], "John");
-        assert_eq!(parsed[0]["age"], "");
-        assert_eq!(parsed[0]["city"], "");
-        assert_eq!(parsed[1]["name"], "");
-        assert_eq!(parsed[1]["age"], 25.0);
-        assert_eq!(parsed[1]["city"], "Osaka");
+        let config = Config {
+            output: None,
+            ..config
+        };
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Config(_))
+        ));
     }
 
     #[test]
-    fn test_convert_csv_file_not_found() {
+    fn test_partition_by() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let output = temp_dir.path().join("out");
+        fs::write(
+            temp_input.path(),
+            "name,country\nJohn,JP\nJane,US\nTaro,JP\nAnon,\n",
+        )
+        .unwrap();
+
         let config = Config {
-            input: Some("non_existent_file.csv".to_string()),
-            output: None,
-            pretty: false,
-            no_header: false,
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(output.to_string_lossy().to_string()),
+            partition_by: Some("country".to_string()),
+            format: OutputFormat::Jsonl,
+            quiet: true,
+            ..Default::default()
         };
+        convert_csv_to_json(&config).unwrap();
+        let read = |name: &str| fs::read_to_string(output.join(name)).unwrap();
+        assert_eq!(
+            read("country=JP.jsonl"),
+            "{\"name\":\"John\",\"country\":\"JP\"}\n{\"name\":\"Taro\",\"country\":\"JP\"}\n"
+        );
+        assert_eq!(
+            read("country=US.jsonl"),
+            "{\"name\":\"Jane\",\"country\":\"US\"}\n"
+        );
+        assert_eq!(
+            read("country=__HIVE_DEFAULT_PARTITION__.jsonl"),
+            "{\"name\":\"Anon\",\"country\":\"\"}\n"
+        );
+        assert_eq!(fs::read_dir(&output).unwrap().count(), 3);
 
-        let result = convert_csv_to_json(&config);
-        assert!(result.is_err());
+        let config = Config {
+            output: None,
+            ..config
+        };
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Config(_))
+        ));
     }
 
     #[test]
-    fn test_convert_csv_invalid_format() {
+    fn test_failed_append_restores_output() {
         let temp_input = NamedTempFile::new().unwrap();
         let temp_output = NamedTempFile::new().unwrap();
-
-        let csv_content = "name,age\nJohn,30\nJane";
-        fs::write(temp_input.path(), csv_content).unwrap();
-
+        let mut content = String::from("a\n");
+        for i in 0..5000 {
+            content.push_str(&format!("{}\n", i));
+        }
+        content.push_str("bad,row\n");
+        fs::write(temp_input.path(), content).unwrap();
         let config = Config {
             input: Some(temp_input.path().to_string_lossy().to_string()),
             output: Some(temp_output.path().to_string_lossy().to_string()),
-            pretty: false,
-            no_header: false,
+            append: true,
+            quiet: true,
+            ..Default::default()
         };
-
-        let result = convert_csv_to_json(&config);
-        assert!(result.is_err());
+        for (format, existing) in [
+            (OutputFormat::Json, "[{\"a\":1}]\n"),
+            (OutputFormat::Jsonl, "{\"a\":1}"),
+        ] {
+            fs::write(temp_output.path(), existing).unwrap();
+            let config = Config {
+                format,
+                ..config.clone()
+            };
+            assert!(matches!(convert_csv_to_json(&config), Err(Error::Csv(_))));
+            assert_eq!(fs::read_to_string(temp_output.path()).unwrap(), existing);
+        }
     }
-
     #[test]
-    fn test_convert_csv_single_column() {
+    fn test_append() {
         let temp_input = NamedTempFile::new().unwrap();
         let temp_output = NamedTempFile::new().unwrap();
-
-        let csv_content = "name\nJohn\nJane";
-        fs::write(temp_input.path(), csv_content).unwrap();
-
+        fs::write(temp_input.path(), "a\n3\n").unwrap();
         let config = Config {
             input: Some(temp_input.path().to_string_lossy().to_string()),
             output: Some(temp_output.path().to_string_lossy().to_string()),
-            pretty: false,
-            no_header: false,
+            append: true,
+            quiet: true,
+            ..Default::default()
         };
 
-        convert_csv_to_json(&config).unwrap();
+        for (existing, appended) in [
+            ("[{\"a\":1},{\"a\":2}]\n", "[{\"a\":1},{\"a\":2},{\"a\":3}]"),
+            (" [ ] ", " [{\"a\":3}]"),
+            ("", "[{\"a\":3}]"),
+        ] {
+            fs::write(temp_output.path(), existing).unwrap();
+            convert_csv_to_json(&config).unwrap();
+            assert_eq!(fs::read_to_string(temp_output.path()).unwrap(), appended);
+        }
 
-        let output_content = fs::read_to_string(temp_output.path()).unwrap();
-        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
+        let pretty = Config {
+            pretty: true,
+            ..config.clone()
+        };
+        fs::write(temp_output.path(), "[\n  {\n    \"a\": 1\n  }\n]\n").unwrap();
+        convert_csv_to_json(&pretty).unwrap();
+        assert_eq!(
+            fs::read_to_string(temp_output.path()).unwrap(),
+            "[\n  {\n    \"a\": 1\n  },\n  {\n    \"a\": 3\n  }\n]"
+        );
 
-        assert_eq!(parsed.len(), 2);
-        assert_eq!(parsed[0]["name"], "John");
-        assert_eq!(parsed[1]["name"], "Jane");
+        fs::write(temp_output.path(), "{\"a\":1}").unwrap();
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Config(_))
+        ));
+
+        let lines = Config {
+            format: OutputFormat::Jsonl,
+            ..config
+        };
+        convert_csv_to_json(&lines).unwrap();
+        convert_csv_to_json(&lines).unwrap();
+        assert_eq!(
+            fs::read_to_string(temp_output.path()).unwrap(),
+            "{\"a\":1}\n{\"a\":3}\n{\"a\":3}\n"
+        );
     }
 
     #[test]
-    fn test_convert_csv_no_data_rows() {
+    fn test_backup() {
         let temp_input = NamedTempFile::new().unwrap();
-        let temp_output = NamedTempFile::new().unwrap();
-
-        let csv_content = "name,age,city";
-        fs::write(temp_input.path(), csv_content).unwrap();
-
+        let temp_dir = tempfile::tempdir().unwrap();
+        let output = temp_dir.path().join("out.json");
+        let backup = temp_dir.path().join("out.json.bak");
+        fs::write(temp_input.path(), "a\n1\n").unwrap();
         let config = Config {
             input: Some(temp_input.path().to_string_lossy().to_string()),
-            output: Some(temp_output.path().to_string_lossy().to_string()),
-            pretty: false,
-            no_header: false,
+            output: Some(output.to_string_lossy().to_string()),
+            backup: true,
+            quiet: true,
+            ..Default::default()
         };
 
         convert_csv_to_json(&config).unwrap();
+        assert!(!backup.exists());
 
-        let output_content = fs::read_to_string(temp_output.path()).unwrap();
-        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
-
-        assert_eq!(parsed.len(), 0);
+        fs::write(temp_input.path(), "a\n2\n").unwrap();
+        convert_csv_to_json(&config).unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), r#"[{"a":1}]"#);
+        assert_eq!(fs::read_to_string(&output).unwrap(), r#"[{"a":2}]"#);
     }
 
     #[test]
-    fn test_convert_csv_no_header() {
+    fn test_truncate() {
         let temp_input = NamedTempFile::new().unwrap();
         let temp_output = NamedTempFile::new().unwrap();
 
-        let csv_content = "John,30,Tokyo\nJane,25,Osaka";
+        let csv_content = "id,description\n1,A very long description\n2,Short\n";
         fs::write(temp_input.path(), csv_content).unwrap();
-
         let config = Config {
             input: Some(temp_input.path().to_string_lossy().to_string()),
             output: Some(temp_output.path().to_string_lossy().to_string()),
-            pretty: false,
-            no_header: true,
+            truncate: truncate::parse_truncations("description:10").unwrap(),
+            truncate_flag: true,
+            ..Default::default()
         };
-
         convert_csv_to_json(&config).unwrap();
+        let output: Value =
+            serde_json::from_str(&fs::read_to_string(temp_output.path()).unwrap()).unwrap();
+        assert_eq!(
+            output,
+            serde_json::json!([
+                {"id": 1, "description": "A very lo…", "description_truncated": true},
+                {"id": 2, "description": "Short", "description_truncated": false},
+            ])
+        );
 
-        let output_content = fs::read_to_string(temp_output.path()).unwrap();
-        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
-
-        assert_eq!(parsed.len(), 2);
-        assert_eq!(parsed[0]["column_0"], "John");
-        assert_eq!(parsed[0]["column_1"], 30.0);
-        assert_eq!(parsed[0]["column_2"], "Tokyo");
-        assert_eq!(parsed[1]["column_0"], "Jane");
-        assert_eq!(parsed[1]["column_1"], 25.0);
-        assert_eq!(parsed[1]["column_2"], "Osaka");
+        let config = Config {
+            truncate: truncate::parse_truncations("summary:10").unwrap(),
+            ..config
+        };
+        assert!(matches!(
+            convert_csv_to_json(&config),
+            Err(Error::Config(_))
+        ));
     }
-
     #[test]
-    fn test_convert_csv_no_header_empty_file() {
+    fn test_echo_header() {
         let temp_input = NamedTempFile::new().unwrap();
         let temp_output = NamedTempFile::new().unwrap();
+        let temp_header = NamedTempFile::new().unwrap();
 
-        let csv_content = "";
-        fs::write(temp_input.path(), csv_content).unwrap();
-
+        fs::write(temp_input.path(), "\u{feff}name,age\nJohn,30\n").unwrap();
         let config = Config {
             input: Some(temp_input.path().to_string_lossy().to_string()),
             output: Some(temp_output.path().to_string_lossy().to_string()),
-            pretty: false,
-            no_header: true,
+            rename: vec![("age".to_string(), "years".to_string())],
+            echo_header: Some(Some(temp_header.path().to_string_lossy().to_string())),
+            ..Default::default()
         };
-
         convert_csv_to_json(&config).unwrap();
-
-        let output_content = fs::read_to_string(temp_output.path()).unwrap();
-        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
-
-        assert_eq!(parsed.len(), 0);
+        assert_eq!(
+            fs::read_to_string(temp_header.path()).unwrap(),
+            "[\"name\",\"years\"]\n"
+        );
     }
-
     #[test]
-    fn test_integer_vs_float_detection() {
+    fn test_auto_format() {
         let temp_input = NamedTempFile::new().unwrap();
         let temp_output = NamedTempFile::new().unwrap();
-
-        let csv_content = "name,age,score,active\nJohn,25,95.5,TRUE\nJane,30,100,False";
-        fs::write(temp_input.path(), csv_content).unwrap();
-
         let config = Config {
             input: Some(temp_input.path().to_string_lossy().to_string()),
             output: Some(temp_output.path().to_string_lossy().to_string()),
-            pretty: false,
-            no_header: false,
+            auto_format: true,
+            ..Default::default()
         };
+        let expected = serde_json::json!([{"name": "John", "age": 30}]);
+        for content in [
+            "name,age\nJohn,30\n",
+            "name\tage\nJohn\t30\n",
+            "{\"name\":\"John\",\"age\":30}\n",
+            "[\n  {\"name\": \"John\", \"age\": 30}\n]\n",
+            "{\n  \"name\": \"John\",\n  \"age\": 30\n}\n",
+        ] {
+            fs::write(temp_input.path(), content).unwrap();
+            convert_csv_to_json(&config).unwrap();
+            let output: Value =
+                serde_json::from_str(&fs::read_to_string(temp_output.path()).unwrap()).unwrap();
+            assert_eq!(output, expected, "input {:?}", content);
+        }
 
-        convert_csv_to_json(&config).unwrap();
-
-        let output_content = fs::read_to_string(temp_output.path()).unwrap();
-        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
-
-        assert_eq!(parsed.len(), 2);
-
-        // First record
-        assert_eq!(parsed[0]["name"], "John");
-        assert_eq!(parsed[0]["age"], 25); // Integer
-        assert_eq!(parsed[0]["score"], 95.5); // Float
-        assert_eq!(parsed[0]["active"], true); // Boolean
-
-        // Second record
-        assert_eq!(parsed[1]["name"], "Jane");
-        assert_eq!(parsed[1]["age"], 30); // Integer
-        assert_eq!(parsed[1]["score"], 100); // Integer (not 100.0)
-        assert_eq!(parsed[1]["active"], false); // Boolean
+        fs::write(temp_input.path(), "[1, 2]").unwrap();
+        assert!(matches!(convert_csv_to_json(&config), Err(Error::Csv(_))));
     }
-
     #[test]
-    fn test_case_insensitive_booleans() {
+    fn test_failed_conversion_keeps_output() {
         let temp_input = NamedTempFile::new().unwrap();
         let temp_output = NamedTempFile::new().unwrap();
-
-        let csv_content = "test,value\ncase1,true\ncase2,FALSE\ncase3,True\ncase4,false";
-        fs::write(temp_input.path(), csv_content).unwrap();
-
+        let mut content = String::from("name,n\n");
+        for i in 0..5000 {
+            content.push_str(&format!("x{},{}\n", i, i));
+        }
+        content.push_str("bad\n");
+        fs::write(temp_input.path(), content).unwrap();
+        fs::write(temp_output.path(), "[]").unwrap();
         let config = Config {
             input: Some(temp_input.path().to_string_lossy().to_string()),
             output: Some(temp_output.path().to_string_lossy().to_string()),
-            pretty: false,
-            no_header: false,
+            ..Default::default()
         };
-
-        convert_csv_to_json(&config).unwrap();
-
-        let output_content = fs::read_to_string(temp_output.path()).unwrap();
-        let parsed: Vec<serde_json::Value> = serde_json::from_str(&output_content).unwrap();
-
-        assert_eq!(parsed.len(), 4);
-        assert_eq!(parsed[0]["value"], true);
-        assert_eq!(parsed[1]["value"], false);
-        assert_eq!(parsed[2]["value"], true);
-        assert_eq!(parsed[3]["value"], false);
+        assert!(matches!(convert_csv_to_json(&config), Err(Error::Csv(_))));
+        assert_eq!(fs::read_to_string(temp_output.path()).unwrap(), "[]");
+    }
+    #[test]
+    fn test_utf16_input() {
+        let temp_input = NamedTempFile::new().unwrap();
+        let temp_output = NamedTempFile::new().unwrap();
+        let config = Config {
+            input: Some(temp_input.path().to_string_lossy().to_string()),
+            output: Some(temp_output.path().to_string_lossy().to_string()),
+            from: InputFormat::Tsv,
+            ..Default::default()
+        };
+        let text = "\u{feff}名前\tage\r\nJohn\t30\r\n";
+        let expected = serde_json::json!([{"名前": "John", "age": 30}]);
+        let little: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let big: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        // With and without the byte order mark
+        for content in [&little[..], &little[2..], &big[..], &big[2..]] {
+            fs::write(temp_input.path(), content).unwrap();
+            convert_csv_to_json(&config).unwrap();
+            let output: Value =
+                serde_json::from_str(&fs::read_to_string(temp_output.path()).unwrap()).unwrap();
+            assert_eq!(output, expected);
+        }
     }
 }
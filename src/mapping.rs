@@ -0,0 +1,181 @@
+//! Field-level lineage for `--emit-mapping`: for each output key, the source
+//! columns it came from, the transforms applied to it and its final type.
+
+use crate::columns::{is_null, merge_type};
+use crate::error::Error;
+use crate::{load_renames, Config, Record};
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+
+/// The lineage of one output key.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FieldMapping {
+    pub output: Arc<str>,
+    pub source: Vec<String>,
+    pub transforms: Vec<String>,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+}
+
+/// Collects the type of every output key from the records as they are
+/// written, so the mapping does not need the records kept in memory.
+#[derive(Debug, Default)]
+pub struct Mapping {
+    types: IndexMap<Arc<str>, &'static str>,
+}
+
+impl Mapping {
+    pub fn observe(&mut self, record: &Record) {
+        for (key, value) in record {
+            let kind = self.types.entry(Arc::clone(key)).or_insert("null");
+            if !is_null(value) {
+                *kind = merge_type(kind, value);
+            }
+        }
+    }
+
+    /// Traces each output key, in the order it first appeared, back through
+    /// the options that produced it.
+    pub fn fields(&self, config: &Config) -> Result<Vec<FieldMapping>, Error> {
+        let renames = load_renames(config)?;
+        let source_of = |name: &str| {
+            renames
+                .iter()
+                .find(|(_, new)| new.as_str() == name)
+                .map_or(name, |(old, _)| old.as_str())
+                .to_string()
+        };
+
+        let mut fields = Vec::new();
+        for (key, kind) in &self.types {
+            let name = &**key;
+            let mut source = Vec::new();
+            let mut transforms = Vec::new();
+            let date_part = config.date_parts.iter().find_map(|spec| {
+                spec.parts
+                    .iter()
+                    .zip(spec.field_names())
+                    .find(|(_, field)| field == name)
+                    .map(|(part, _)| (spec, part))
+            });
+            if let Some((spec, part)) = date_part {
+                source.push(source_of(&spec.column));
+                transforms.push(format!("date part {}", part.name()));
+            } else if let Some(spec) = config.collect.iter().find(|spec| spec.name == name) {
+                source.extend(spec.fields.iter().map(|field| format!("{}_<n>", field)));
+                transforms.push(format!("collect {}", spec.fields.join(",")));
+            } else if let Some(truncation) = config
+                .truncate
+                .iter()
+                .find(|truncation| config.truncate_flag && truncation.flag_name() == name)
+            {
+                source.push(source_of(&truncation.column));
+                transforms.push("truncated flag".to_string());
+            } else if config
+                .hierarchy
+                .as_ref()
+                .is_some_and(|hierarchy| hierarchy.children == name)
+            {
+                transforms.push("hierarchy children".to_string());
+            } else {
+                source.push(source_of(name));
+                if renames.values().any(|new| new == name) {
+                    transforms.push("rename".to_string());
+                }
+                for scale in config.scale.iter().filter(|scale| scale.column == name) {
+                    transforms.push(format!("scale * {} + {}", scale.factor, scale.offset));
+                }
+                for spec in config
+                    .convert_currency
+                    .iter()
+                    .filter(|spec| spec.column == name)
+                {
+                    transforms.push(format!("currency {} -> {}", spec.from, spec.to));
+                }
+                for truncation in config
+                    .truncate
+                    .iter()
+                    .filter(|truncation| truncation.column == name)
+                {
+                    transforms.push(format!("truncate {}", truncation.max_chars));
+                }
+                if config.explode.as_deref() == Some(name) {
+                    transforms.push("explode".to_string());
+                }
+            }
+            fields.push(FieldMapping {
+                output: Arc::clone(key),
+                source,
+                transforms,
+                kind,
+            });
+        }
+        Ok(fields)
+    }
+
+    /// Writes the mapping to `path` as a pretty-printed JSON array.
+    pub fn write(&self, path: &str, config: &Config) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, &self.fields(config)?)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datetime::DatePart;
+    use crate::scale::Scale;
+    use crate::DateParts;
+    use serde_json::Value;
+
+    #[test]
+    fn test_fields() {
+        let config = Config {
+            rename: vec![("amt".to_string(), "amount".to_string())],
+            scale: vec![Scale {
+                column: "amount".to_string(),
+                factor: 100.0,
+                offset: 0.0,
+            }],
+            date_parts: vec![DateParts {
+                column: "at".to_string(),
+                parts: vec![DatePart::Year],
+            }],
+            ..Default::default()
+        };
+        let record = |amount: Value| {
+            Record::from([
+                (Arc::from("amount"), amount),
+                (Arc::from("at"), Value::from("2024-01-02")),
+                (Arc::from("at_year"), Value::from(2024)),
+            ])
+        };
+        let mut mapping = Mapping::default();
+        mapping.observe(&record(Value::from(150)));
+        mapping.observe(&record(Value::from(2.5)));
+        mapping.observe(&record(Value::Null));
+
+        let fields = mapping.fields(&config).unwrap();
+        assert_eq!(
+            fields[0],
+            FieldMapping {
+                output: Arc::from("amount"),
+                source: vec!["amt".to_string()],
+                transforms: vec!["rename".to_string(), "scale * 100 + 0".to_string()],
+                kind: "number",
+            }
+        );
+        assert_eq!(fields[1].source, ["at"]);
+        assert!(fields[1].transforms.is_empty());
+        assert_eq!(fields[1].kind, "string");
+        assert_eq!(fields[2].source, ["at"]);
+        assert_eq!(fields[2].transforms, ["date part year"]);
+        assert_eq!(fields[2].kind, "integer");
+    }
+}
@@ -0,0 +1,158 @@
+//! `--format msgpack`: records encoded as MessagePack maps, with the same
+//! keys and value types as the JSON output.
+
+use crate::Record;
+use serde_json::Value;
+
+/// Appends a record as a map of its fields in order.
+pub fn encode_record(record: &Record, out: &mut Vec<u8>) {
+    length_header(record.len(), [0x80, 0xde, 0xdf], out);
+    for (key, value) in record {
+        encode_str(key, out);
+        encode(value, out);
+    }
+}
+
+/// Appends a JSON value. Integers take the smallest encoding that holds
+/// them, and other numbers are 64-bit floats.
+pub fn encode(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(false) => out.push(0xc2),
+        Value::Bool(true) => out.push(0xc3),
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                encode_uint(n, out);
+            } else if let Some(n) = n.as_i64() {
+                encode_int(n, out);
+            } else {
+                out.push(0xcb);
+                out.extend_from_slice(&n.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        }
+        Value::String(text) => encode_str(text, out),
+        Value::Array(elements) => {
+            length_header(elements.len(), [0x90, 0xdc, 0xdd], out);
+            for element in elements {
+                encode(element, out);
+            }
+        }
+        Value::Object(fields) => {
+            length_header(fields.len(), [0x80, 0xde, 0xdf], out);
+            for (key, value) in fields {
+                encode_str(key, out);
+                encode(value, out);
+            }
+        }
+    }
+}
+
+fn encode_uint(n: u64, out: &mut Vec<u8>) {
+    if n < 0x80 {
+        out.push(n as u8);
+    } else if let Ok(n) = u8::try_from(n) {
+        out.extend_from_slice(&[0xcc, n]);
+    } else if let Ok(n) = u16::try_from(n) {
+        out.push(0xcd);
+        out.extend_from_slice(&n.to_be_bytes());
+    } else if let Ok(n) = u32::try_from(n) {
+        out.push(0xce);
+        out.extend_from_slice(&n.to_be_bytes());
+    } else {
+        out.push(0xcf);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+/// Encodes a negative integer; `as_u64` catches the others first.
+fn encode_int(n: i64, out: &mut Vec<u8>) {
+    if n >= -32 {
+        out.push(n as u8);
+    } else if let Ok(n) = i8::try_from(n) {
+        out.extend_from_slice(&[0xd0, n as u8]);
+    } else if let Ok(n) = i16::try_from(n) {
+        out.push(0xd1);
+        out.extend_from_slice(&n.to_be_bytes());
+    } else if let Ok(n) = i32::try_from(n) {
+        out.push(0xd2);
+        out.extend_from_slice(&n.to_be_bytes());
+    } else {
+        out.push(0xd3);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+fn encode_str(text: &str, out: &mut Vec<u8>) {
+    let len = text.len();
+    if len < 32 {
+        out.push(0xa0 | len as u8);
+    } else if let Ok(len) = u8::try_from(len) {
+        out.extend_from_slice(&[0xd9, len]);
+    } else if let Ok(len) = u16::try_from(len) {
+        out.push(0xda);
+        out.extend_from_slice(&len.to_be_bytes());
+    } else {
+        out.push(0xdb);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+    out.extend_from_slice(text.as_bytes());
+}
+
+/// Writes the header of an array or map, given its fixed-size marker and
+/// its 16- and 32-bit length markers. Lengths over 32 bits are not
+/// representable.
+fn length_header(len: usize, [fixed, len16, len32]: [u8; 3], out: &mut Vec<u8>) {
+    if len < 16 {
+        out.push(fixed | len as u8);
+    } else if let Ok(len) = u16::try_from(len) {
+        out.push(len16);
+        out.extend_from_slice(&len.to_be_bytes());
+    } else {
+        out.push(len32);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn encoded(value: Value) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode(&value, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_scalars() {
+        assert_eq!(encoded(json!(null)), [0xc0]);
+        assert_eq!(encoded(json!(true)), [0xc3]);
+        assert_eq!(encoded(json!(5)), [0x05]);
+        assert_eq!(encoded(json!(200)), [0xcc, 200]);
+        assert_eq!(encoded(json!(65536)), [0xce, 0, 1, 0, 0]);
+        assert_eq!(encoded(json!(-1)), [0xff]);
+        assert_eq!(encoded(json!(-200)), [0xd1, 0xff, 0x38]);
+        assert_eq!(encoded(json!(1.5)), [0xcb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(encoded(json!("ab")), [0xa2, b'a', b'b']);
+        let long = "x".repeat(40);
+        assert_eq!(encoded(json!(long))[..2], [0xd9, 40]);
+        let longer = "x".repeat(300);
+        assert_eq!(encoded(json!(longer))[..3], [0xda, 0x01, 0x2c]);
+    }
+
+    #[test]
+    fn test_record() {
+        let record = Record::from([
+            (Arc::from("id"), json!(1)),
+            (Arc::from("tags"), json!(["a"])),
+        ]);
+        let mut out = Vec::new();
+        encode_record(&record, &mut out);
+        assert_eq!(
+            out,
+            [0x82, 0xa2, b'i', b'd', 0x01, 0xa4, b't', b'a', b'g', b's', 0x91, 0xa1, b'a']
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! `--normalize-report` and `--normalize`: finds, and optionally fixes,
+//! whitespace problems that are invisible in most editors: mixed CRLF and LF
+//! line endings, trailing whitespace in fields and non-breaking spaces.
+
+use std::fmt;
+use std::io::{self, Read};
+
+/// Counts of each problem, with the line where it first appears.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Report {
+    pub crlf: u64,
+    pub lf: u64,
+    /// Line of the first line break that differs from the first one
+    pub first_mixed: Option<u64>,
+    pub trailing_whitespace: u64,
+    pub first_trailing_whitespace: Option<u64>,
+    pub non_breaking_spaces: u64,
+    pub first_non_breaking_space: Option<u64>,
+    pub fixed: bool,
+}
+
+impl Report {
+    fn is_clean(&self) -> bool {
+        self.first_mixed.is_none() && self.trailing_whitespace == 0 && self.non_breaking_spaces == 0
+    }
+}
+
+fn first_at(line: Option<u64>) -> String {
+    line.map_or(String::new(), |line| format!(" (first at line {})", line))
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Normalization report:")?;
+        if self.is_clean() {
+            return writeln!(f, "  No whitespace problems found");
+        }
+        let fixed = if self.fixed { ", fixed" } else { "" };
+        let mixed = match self.first_mixed {
+            Some(line) => format!(" (mixed; first change at line {}{})", line, fixed),
+            None => String::new(),
+        };
+        writeln!(
+            f,
+            "  Line endings:        {} CRLF, {} LF{}",
+            self.crlf, self.lf, mixed
+        )?;
+        writeln!(
+            f,
+            "  Trailing whitespace: {} fields{}{}",
+            self.trailing_whitespace,
+            first_at(self.first_trailing_whitespace),
+            fixed
+        )?;
+        writeln!(
+            f,
+            "  Non-breaking spaces: {}{}{}",
+            self.non_breaking_spaces,
+            first_at(self.first_non_breaking_space),
+            fixed
+        )
+    }
+}
+
+/// Scans CSV text as it is read. With `fix`, it also rewrites CRLF as LF,
+/// drops trailing spaces, tabs and non-breaking spaces from unquoted fields
+/// and replaces other non-breaking spaces with plain ones. The report is printed on stderr
+/// when the input ends.
+pub struct Normalizer<R> {
+    inner: R,
+    fix: bool,
+    report: Report,
+    line: u64,
+    in_quotes: bool,
+    /// A carriage return waiting to see if a line feed follows
+    carriage_return: bool,
+    /// Spaces and tabs waiting to see if the field ends after them
+    whitespace: Vec<u8>,
+    /// The first byte of a possible two-byte non-breaking space
+    lead_byte: bool,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    position: usize,
+    done: bool,
+}
+
+impl<R: Read> Normalizer<R> {
+    pub fn new(inner: R, fix: bool) -> Self {
+        Normalizer {
+            inner,
+            fix,
+            report: Report {
+                fixed: fix,
+                ..Report::default()
+            },
+            line: 1,
+            in_quotes: false,
+            carriage_return: false,
+            whitespace: Vec::new(),
+            lead_byte: false,
+            input: vec![0; 8 * 1024],
+            output: Vec::new(),
+            position: 0,
+            done: false,
+        }
+    }
+
+    fn line_break(&mut self, crlf: bool) {
+        let (count, other) = if crlf {
+            (&mut self.report.crlf, self.report.lf)
+        } else {
+            (&mut self.report.lf, self.report.crlf)
+        };
+        *count += 1;
+        // A break of the other kind has been seen before, so this one is mixed
+        if other > 0 && self.report.first_mixed.is_none() {
+            self.report.first_mixed = Some(self.line);
+        }
+    }
+
+    /// Ends a run of whitespace, which was trailing if the field ends here.
+    fn end_whitespace(&mut self, field_ends: bool) {
+        if self.whitespace.is_empty() {
+            return;
+        }
+        if field_ends {
+            self.report.trailing_whitespace += 1;
+            self.report
+                .first_trailing_whitespace
+                .get_or_insert(self.line);
+            if self.fix {
+                self.whitespace.clear();
+                return;
+            }
+        }
+        self.output.append(&mut self.whitespace);
+    }
+
+    fn scan(&mut self, byte: u8) {
+        let mut crlf = false;
+        if self.carriage_return {
+            self.carriage_return = false;
+            crlf = byte == b'\n';
+            if crlf {
+                self.line_break(true);
+            }
+            if !(crlf && self.fix) {
+                self.output.push(b'\r');
+            }
+        }
+        if self.lead_byte {
+            self.lead_byte = false;
+            if byte == 0xa0 {
+                self.report.non_breaking_spaces += 1;
+                self.report
+                    .first_non_breaking_space
+                    .get_or_insert(self.line);
+                let space: &[u8] = if self.fix { b" " } else { "\u{a0}".as_bytes() };
+                if self.in_quotes {
+                    self.output.extend_from_slice(space);
+                } else {
+                    // Whitespace too, so one that ends a field is trimmed
+                    self.whitespace.extend_from_slice(space);
+                }
+                return;
+            }
+            self.end_whitespace(false);
+            self.output.push(0xc2);
+        }
+        match byte {
+            b' ' | b'\t' if !self.in_quotes => {
+                self.whitespace.push(byte);
+                return;
+            }
+            b'\r' => {
+                self.end_whitespace(!self.in_quotes);
+                self.carriage_return = true;
+                return;
+            }
+            // Whitespace is held until it is known whether a non-breaking
+            // space follows
+            0xc2 => {
+                self.lead_byte = true;
+                return;
+            }
+            b',' | b'\n' => self.end_whitespace(!self.in_quotes),
+            _ => self.end_whitespace(false),
+        }
+        if byte == b'"' {
+            self.in_quotes = !self.in_quotes;
+        }
+        if byte == b'\n' {
+            if !crlf {
+                self.line_break(false);
+            }
+            self.line += 1;
+        }
+        self.output.push(byte);
+    }
+
+    fn finish(&mut self) {
+        if self.carriage_return {
+            self.carriage_return = false;
+            self.output.push(b'\r');
+        }
+        if self.lead_byte {
+            self.lead_byte = false;
+            self.end_whitespace(false);
+            self.output.push(0xc2);
+        }
+        self.end_whitespace(!self.in_quotes);
+        eprint!("{}", self.report);
+    }
+}
+
+impl<R: Read> Read for Normalizer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.output.len() && !self.done {
+            self.output.clear();
+            self.position = 0;
+            let n = self.inner.read(&mut self.input)?;
+            if n == 0 {
+                self.done = true;
+                self.finish();
+            }
+            for i in 0..n {
+                self.scan(self.input[i]);
+            }
+        }
+        let available = &self.output[self.position..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(input: &str, fix: bool) -> (String, Report) {
+        let mut normalizer = Normalizer::new(input.as_bytes(), fix);
+        let mut output = String::new();
+        normalizer.read_to_string(&mut output).unwrap();
+        (output, normalizer.report)
+    }
+
+    #[test]
+    fn test_clean_input() {
+        let input = "a,b\n\"x \",\"two\r\nlines\"\n";
+        let (output, report) = normalize(input, true);
+        assert_eq!(output, "a,b\n\"x \",\"two\nlines\"\n");
+        assert_eq!(report.first_mixed, Some(2));
+        assert_eq!(report.trailing_whitespace, 0);
+
+        let (output, report) = normalize("a,b\r\n1,2\r\n", false);
+        assert_eq!(output, "a,b\r\n1,2\r\n");
+        assert!(report.is_clean());
+        assert_eq!(report.crlf, 2);
+    }
+
+    #[test]
+    fn test_report_and_fix() {
+        let input = "name,city\r\nJohn ,Tokyo\t\nJane,New\u{a0}York \r\n";
+        let (output, report) = normalize(input, false);
+        assert_eq!(output, input);
+        assert_eq!((report.crlf, report.lf), (2, 1));
+        assert_eq!(report.first_mixed, Some(2));
+        assert_eq!(report.trailing_whitespace, 3);
+        assert_eq!(report.first_trailing_whitespace, Some(2));
+        assert_eq!(report.non_breaking_spaces, 1);
+        assert_eq!(report.first_non_breaking_space, Some(3));
+
+        let (output, report) = normalize(input, true);
+        assert_eq!(output, "name,city\nJohn,Tokyo\nJane,New York\n");
+        assert!(report
+            .to_string()
+            .contains("3 fields (first at line 2), fixed"));
+
+        // A trailing non-breaking space is trimmed like any other
+        let input = "a,b\n3\u{a0},x \u{a0}\n\"4\u{a0}\", \u{a3}1\n";
+        let (output, report) = normalize(input, true);
+        assert_eq!(output, "a,b\n3,x\n\"4 \", \u{a3}1\n");
+        assert_eq!(report.trailing_whitespace, 2);
+        assert_eq!(report.non_breaking_spaces, 3);
+        assert_eq!(normalize(input, false).0, input);
+    }
+}
@@ -0,0 +1,228 @@
+//! Reading a sheet out of an OpenDocument spreadsheet (.ods), as LibreOffice
+//! and Google Sheets write them. All sheets are tables in `content.xml`.
+
+use crate::error::Error;
+use crate::log;
+use crate::spreadsheet;
+use crate::xml::{attribute, Event, Events};
+use std::io;
+
+/// The content of the `mimetype` member of an OpenDocument spreadsheet.
+pub const MIMETYPE: &str = "application/vnd.oasis.opendocument.spreadsheet";
+
+const CONTENT: &str = "content.xml";
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("ods: {}", message.into()),
+    ))
+}
+
+/// Reads the rows of the sheet `sheet` picks, with `read` giving the text of
+/// each part, or `None` for a part the file lacks.
+pub fn read_sheet(
+    mut read: impl FnMut(&str) -> Result<Option<String>, Error>,
+    sheet: Option<&str>,
+) -> Result<Vec<Vec<String>>, Error> {
+    let xml = read(CONTENT)?.ok_or_else(|| invalid("missing content.xml"))?;
+    let names = table_names(&xml)?;
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    let index = spreadsheet::pick(&names, sheet)?;
+    log::info!("Reading sheet {} from the spreadsheet", names[index]);
+    parse_table(&xml, index)
+}
+
+fn table_names(xml: &str) -> Result<Vec<String>, Error> {
+    let mut names = Vec::new();
+    let mut events = Events::new(xml);
+    while let Some(event) = events.next()? {
+        if let Event::Start("table", attributes) = event {
+            names.push(
+                attribute(attributes, "name")?
+                    .unwrap_or_default()
+                    .into_owned(),
+            );
+        }
+    }
+    Ok(names)
+}
+
+/// A number attribute such as `number-columns-repeated`, 1 when absent.
+fn repeated(attributes: &str, name: &str) -> Result<usize, Error> {
+    match attribute(attributes, name)? {
+        Some(count) => count
+            .parse()
+            .map_err(|_| invalid(format!("bad {} {}", name, count))),
+        None => Ok(1),
+    }
+}
+
+/// The value of a cell: the number, date, time or boolean it holds, or else
+/// its text. Times like `PT36H05M00S` become `36:05:00`.
+fn cell_value(attributes: &str, text: String) -> Result<String, Error> {
+    let value = match attribute(attributes, "value-type")?.as_deref() {
+        Some("float" | "percentage" | "currency") => attribute(attributes, "value")?,
+        Some("date") => attribute(attributes, "date-value")?,
+        Some("boolean") => attribute(attributes, "boolean-value")?,
+        Some("time") => {
+            return Ok(attribute(attributes, "time-value")?
+                .and_then(|duration| format_duration(&duration))
+                .unwrap_or(text))
+        }
+        _ => None,
+    };
+    Ok(value.map_or(text, |value| value.into_owned()))
+}
+
+fn format_duration(duration: &str) -> Option<String> {
+    let rest = duration.strip_prefix("PT")?;
+    let (hours, rest) = rest.split_once('H')?;
+    let (minutes, rest) = rest.split_once('M')?;
+    let seconds = rest.strip_suffix('S')?;
+    // Fractions of a second are dropped
+    let seconds = seconds.split('.').next()?;
+    Some(format!(
+        "{:02}:{:02}:{:02}",
+        hours.parse::<u64>().ok()?,
+        minutes.parse::<u64>().ok()?,
+        seconds.parse::<u64>().ok()?
+    ))
+}
+
+/// The rows of the `index`th table. Empty cells and rows are often written
+/// once with a repeat count reaching the edge of the sheet, so repeats only
+/// take room when they hold values; rows without any are left out, and so
+/// are the empty cells at the end of a row.
+fn parse_table(xml: &str, index: usize) -> Result<Vec<Vec<String>>, Error> {
+    let mut rows = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let (mut row_repeat, mut column) = (1, 0);
+    let (mut cell, mut text) = ("", String::new());
+    let (mut in_cell, mut in_annotation) = (false, false);
+    let (mut paragraphs, mut in_paragraph) = (0, false);
+    let mut tables = 0;
+    let mut events = Events::new(xml);
+    while let Some(event) = events.next()? {
+        match event {
+            Event::Start("table", _) => tables += 1,
+            _ if tables != index + 1 => {}
+            Event::End("table") => break,
+            Event::Start("table-row", attributes) => {
+                row.clear();
+                row_repeat = repeated(attributes, "number-rows-repeated")?;
+                column = 0;
+            }
+            Event::End("table-row") if !row.is_empty() => {
+                for _ in 1..row_repeat {
+                    rows.push(row.clone());
+                }
+                rows.push(std::mem::take(&mut row));
+            }
+            Event::Start("table-cell" | "covered-table-cell", attributes) => {
+                (cell, in_cell, paragraphs) = (attributes, true, 0);
+                text.clear();
+            }
+            Event::End("table-cell" | "covered-table-cell") => {
+                in_cell = false;
+                let count = repeated(cell, "number-columns-repeated")?;
+                let value = cell_value(cell, std::mem::take(&mut text))?;
+                if !value.is_empty() {
+                    row.resize(column, String::new());
+                    row.extend(std::iter::repeat_n(value, count));
+                }
+                column += count;
+            }
+            // Comments hold paragraphs of their own
+            Event::Start("annotation", _) => in_annotation = true,
+            Event::End("annotation") => in_annotation = false,
+            _ if !in_cell || in_annotation => {}
+            // A cell's paragraphs are its lines
+            Event::Start("p", _) => {
+                if paragraphs > 0 {
+                    text.push('\n');
+                }
+                paragraphs += 1;
+                in_paragraph = true;
+            }
+            Event::End("p") => in_paragraph = false,
+            _ if !in_paragraph => {}
+            Event::Start("s", attributes) => {
+                let count = match attribute(attributes, "c")? {
+                    Some(count) => count.parse().unwrap_or(1),
+                    None => 1,
+                };
+                text.extend(std::iter::repeat_n(' ', count));
+            }
+            Event::Start("tab", _) => text.push('\t'),
+            Event::Start("line-break", _) => text.push('\n'),
+            Event::Text(chunk) => text.push_str(&chunk),
+            _ => {}
+        }
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT_XML: &str = "<?xml version=\"1.0\"?>\
+        <office:document-content xmlns:office=\"o\" xmlns:table=\"t\" xmlns:text=\"x\">\
+        <office:body><office:spreadsheet>\
+        <table:table table:name=\"Summary\"><table:table-row><table:table-cell office:value-type=\"string\">\
+        <text:p>total</text:p></table:table-cell></table:table-row></table:table>\
+        <table:table table:name=\"Data\">\
+        <table:table-column table:number-columns-repeated=\"1024\"/>\
+        <table:table-row>\
+          <table:table-cell office:value-type=\"string\"><text:p>id</text:p></table:table-cell>\
+          <table:table-cell office:value-type=\"string\"><text:p>name</text:p></table:table-cell>\
+          <table:table-cell office:value-type=\"string\"><text:p>joined</text:p></table:table-cell>\
+          <table:table-cell office:value-type=\"string\"><text:p>ok</text:p></table:table-cell>\
+          <table:table-cell table:number-columns-repeated=\"1020\"/>\
+        </table:table-row>\
+        <table:table-row table:number-rows-repeated=\"2\">\
+          <table:table-cell office:value-type=\"float\" office:value=\"1.5\"><text:p>1.50</text:p></table:table-cell>\
+          <table:table-cell office:value-type=\"string\"><text:p>Jane<text:s text:c=\"2\"/>Doe</text:p><text:p>line two</text:p>\
+          <office:annotation><text:p>a comment</text:p></office:annotation></table:table-cell>\
+          <table:table-cell office:value-type=\"date\" office:date-value=\"2024-04-01\"><text:p>01/04/24</text:p></table:table-cell>\
+          <table:table-cell office:value-type=\"boolean\" office:boolean-value=\"true\"><text:p>TRUE</text:p></table:table-cell>\
+        </table:table-row>\
+        <table:table-row table:number-rows-repeated=\"1048570\"><table:table-cell table:number-columns-repeated=\"1024\"/></table:table-row>\
+        <table:table-row>\
+          <table:table-cell table:number-columns-repeated=\"2\"/>\
+          <table:table-cell office:value-type=\"time\" office:time-value=\"PT36H05M09S\"><text:p>36:05:09</text:p></table:table-cell>\
+        </table:table-row>\
+        </table:table></office:spreadsheet></office:body></office:document-content>";
+
+    fn read(sheet: Option<&str>) -> Result<Vec<Vec<String>>, Error> {
+        read_sheet(
+            |name| Ok((name == CONTENT).then(|| CONTENT_XML.to_string())),
+            sheet,
+        )
+    }
+
+    #[test]
+    fn test_read_sheet() {
+        assert_eq!(read(None).unwrap(), vec![vec!["total"]]);
+        let data = vec![
+            vec!["id", "name", "joined", "ok"],
+            vec!["1.5", "Jane  Doe\nline two", "2024-04-01", "true"],
+            vec!["1.5", "Jane  Doe\nline two", "2024-04-01", "true"],
+            vec!["", "", "36:05:09"],
+        ];
+        assert_eq!(read(Some("Data")).unwrap(), data);
+        assert_eq!(read(Some("2")).unwrap(), data);
+        assert!(matches!(read(Some("Other")), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration("PT09H30M00S").as_deref(), Some("09:30:00"));
+        assert_eq!(
+            format_duration("PT00H00M01.5S").as_deref(),
+            Some("00:00:01")
+        );
+        assert_eq!(format_duration("P1DT00H"), None);
+    }
+}
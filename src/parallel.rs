@@ -0,0 +1,302 @@
+//! Order-preserving parallel maps for converting rows, or whole files, on
+//! several threads.
+
+use crate::stats::{self, Stage};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+
+/// Number of items handed to a worker at a time.
+pub const CHUNK_SIZE: usize = 1024;
+
+/// Chunks in flight per worker thread unless a queue size is given.
+pub const QUEUE_PER_THREAD: usize = 4;
+
+/// Applies `convert` to each item on up to `threads` worker threads and hands
+/// the results to `sink` in input order. Items are read and sunk on the
+/// calling thread, so readers and writers need not be `Send`.
+///
+/// At most `queue` chunks are in flight between reading and sinking, whether
+/// waiting for a worker, being converted or waiting for earlier chunks. When
+/// the sink is slow, reading waits for it, so memory use stays bounded.
+///
+/// The first error, whether from `items`, `convert` or `sink`, stops the
+/// conversion; results that come after it in input order are discarded.
+/// Input that fits in a single chunk is converted on the calling thread.
+pub fn map_ordered<T, U, E, I, F, S>(
+    threads: usize,
+    queue: usize,
+    mut items: I,
+    convert: F,
+    mut sink: S,
+) -> Result<(), E>
+where
+    T: Send,
+    U: Send,
+    E: Send,
+    I: Iterator<Item = Result<T, E>>,
+    F: Fn(T) -> Result<U, E> + Sync,
+    S: FnMut(U) -> Result<(), E>,
+{
+    let first = next_chunk(&mut items)?;
+    if threads <= 1 || first.len() < CHUNK_SIZE {
+        for item in first {
+            sink(convert(item)?)?;
+        }
+        for item in items {
+            sink(convert(item?)?)?;
+        }
+        return Ok(());
+    }
+
+    let queue = queue.max(1);
+    let (job_sender, job_receiver) = mpsc::sync_channel::<(usize, Vec<T>)>(queue);
+    let (result_sender, result_receiver) = mpsc::channel::<(usize, Result<Vec<U>, E>)>();
+    let job_receiver = Mutex::new(job_receiver);
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let result_sender = result_sender.clone();
+            let (job_receiver, convert) = (&job_receiver, &convert);
+            scope.spawn(move || loop {
+                let job = job_receiver.lock().expect("job queue poisoned").recv();
+                let Ok((sequence, chunk)) = job else {
+                    break;
+                };
+                let results = chunk.into_iter().map(convert).collect();
+                if result_sender.send((sequence, results)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(result_sender);
+
+        let mut reorder = Reorder::new();
+        let mut sent = 0;
+        let mut chunk = first;
+        let outcome = loop {
+            if chunk.is_empty() {
+                break Ok(());
+            }
+            // The queue is full, so wait for the next chunk in order to be sunk
+            let mut drained = Ok(());
+            while drained.is_ok() && sent - reorder.next >= queue {
+                let Ok((sequence, results)) = stats::timed(Stage::Wait, || result_receiver.recv())
+                else {
+                    break;
+                };
+                reorder.insert(sequence, results);
+                drained = reorder.drain(&mut sink);
+            }
+            if let Err(e) = drained {
+                break Err(e);
+            }
+            if job_sender.send((sent, chunk)).is_err() {
+                break Ok(());
+            }
+            sent += 1;
+            for (sequence, results) in result_receiver.try_iter() {
+                reorder.insert(sequence, results);
+            }
+            if let Err(e) = reorder.drain(&mut sink) {
+                break Err(e);
+            }
+            chunk = match next_chunk(&mut items) {
+                Ok(chunk) => chunk,
+                Err(e) => break Err(e),
+            };
+        };
+        // Workers stop once the queue is closed and empty
+        drop(job_sender);
+        outcome?;
+
+        while reorder.next < sent {
+            let Ok((sequence, results)) = stats::timed(Stage::Wait, || result_receiver.recv())
+            else {
+                break;
+            };
+            reorder.insert(sequence, results);
+            reorder.drain(&mut sink)?;
+        }
+        Ok(())
+    })
+}
+
+/// Applies `convert` to every item on up to `threads` worker threads and
+/// returns the results in input order. Each worker takes the next item as
+/// soon as it is free, which suits a few slow items, such as whole files,
+/// that `map_ordered` would put in a single chunk. Every item is converted,
+/// whatever the others return.
+pub fn map_each<T, U, F>(threads: usize, items: &[T], convert: F) -> Vec<U>
+where
+    T: Sync,
+    U: Send,
+    F: Fn(&T) -> U + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<U>>> = items.iter().map(|_| Mutex::new(None)).collect();
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                let result = convert(item);
+                *results[index].lock().expect("result slot poisoned") = Some(result);
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|slot| {
+            slot.into_inner()
+                .expect("result slot poisoned")
+                .expect("every item is converted")
+        })
+        .collect()
+}
+
+fn next_chunk<T, E>(items: &mut impl Iterator<Item = Result<T, E>>) -> Result<Vec<T>, E> {
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    for item in items.take(CHUNK_SIZE) {
+        chunk.push(item?);
+    }
+    Ok(chunk)
+}
+
+/// Holds chunk results that arrive early until those before them are done.
+struct Reorder<U, E> {
+    next: usize,
+    waiting: BTreeMap<usize, Result<Vec<U>, E>>,
+}
+
+impl<U, E> Reorder<U, E> {
+    fn new() -> Self {
+        Reorder {
+            next: 0,
+            waiting: BTreeMap::new(),
+        }
+    }
+
+    fn insert(&mut self, sequence: usize, results: Result<Vec<U>, E>) {
+        self.waiting.insert(sequence, results);
+    }
+
+    /// Sinks every chunk that is next in order.
+    fn drain(&mut self, sink: &mut impl FnMut(U) -> Result<(), E>) -> Result<(), E> {
+        while let Some(results) = self.waiting.remove(&self.next) {
+            self.next += 1;
+            for result in results? {
+                sink(result)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(threads: usize, count: usize, fail_at: Option<usize>) -> Result<Vec<usize>, String> {
+        let mut output = Vec::new();
+        map_ordered(
+            threads,
+            threads * QUEUE_PER_THREAD,
+            (0..count).map(Ok),
+            |n| match fail_at {
+                Some(fail) if n == fail => Err(format!("failed at {}", n)),
+                _ => Ok(n * 2),
+            },
+            |n| {
+                output.push(n);
+                Ok(())
+            },
+        )?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_preserves_order() {
+        let expected: Vec<usize> = (0..10_000).map(|n| n * 2).collect();
+        assert_eq!(run(4, 10_000, None).unwrap(), expected);
+        assert_eq!(run(1, 10_000, None).unwrap(), expected);
+        assert_eq!(run(4, 10, None).unwrap(), expected[..10]);
+        assert!(run(4, 0, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_one_thread_converts_on_caller() {
+        let caller = thread::current().id();
+        let mut converted = 0;
+        map_ordered(
+            1,
+            QUEUE_PER_THREAD,
+            (0..CHUNK_SIZE * 3).map(Ok::<_, ()>),
+            |n| {
+                assert_eq!(thread::current().id(), caller);
+                Ok(n)
+            },
+            |_| {
+                converted += 1;
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(converted, CHUNK_SIZE * 3);
+    }
+
+    #[test]
+    fn test_stops_at_first_error() {
+        assert_eq!(run(4, 10_000, Some(5000)).unwrap_err(), "failed at 5000");
+        assert_eq!(run(1, 10_000, Some(3)).unwrap_err(), "failed at 3");
+
+        let result = map_ordered(
+            4,
+            16,
+            (0..10_000).map(|n| if n == 4000 { Err(n) } else { Ok(n) }),
+            Ok,
+            |_| Ok(()),
+        );
+        assert_eq!(result, Err(4000));
+    }
+
+    #[test]
+    fn test_queue_bounds_chunks_in_flight() {
+        let converted = AtomicUsize::new(0);
+        let mut sunk = 0;
+        let mut most_ahead = 0;
+        map_ordered(
+            4,
+            2,
+            (0..CHUNK_SIZE * 20).map(Ok::<_, ()>),
+            |n| {
+                converted.fetch_add(1, Ordering::SeqCst);
+                Ok(n)
+            },
+            |_| {
+                sunk += 1;
+                most_ahead = most_ahead.max(converted.load(Ordering::SeqCst) - sunk);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(sunk, CHUNK_SIZE * 20);
+        // Only the chunk being sunk and one more can have been converted
+        assert!(most_ahead < CHUNK_SIZE * 2);
+    }
+
+    #[test]
+    fn test_map_each() {
+        let items: Vec<usize> = (0..100).collect();
+        let doubled: Vec<usize> = items.iter().map(|n| n * 2).collect();
+        assert_eq!(map_each(4, &items, |n| n * 2), doubled);
+        assert_eq!(map_each(1, &items, |n| n * 2), doubled);
+        assert!(map_each(4, &[] as &[usize], |n| n * 2).is_empty());
+
+        let results = map_each(3, &items, |&n| if n % 10 == 0 { Err(n) } else { Ok(n) });
+        assert_eq!(results.iter().filter(|result| result.is_err()).count(), 10);
+    }
+}
@@ -0,0 +1,1742 @@
+//! Parquet input, the columnar format Spark, pandas and DuckDB write to data
+//! lakes. The file is read whole; each row group's column chunks are decoded
+//! and zipped into records whose values keep their types. Only flat schemas
+//! are read: a column in a group, or a repeated one, is an error.
+
+use crate::base64;
+use crate::compression::{self, Compression};
+use crate::datetime::civil_from_days;
+use crate::error::Error;
+use crate::log;
+use serde_json::{Map, Number, Value};
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Read, Seek};
+
+/// The four bytes a Parquet file starts and ends with.
+pub const MAGIC: &[u8] = b"PAR1";
+
+// Physical types
+const BOOLEAN: i64 = 0;
+const INT32: i64 = 1;
+const INT64: i64 = 2;
+const INT96: i64 = 3;
+const FLOAT: i64 = 4;
+const DOUBLE: i64 = 5;
+const BYTE_ARRAY: i64 = 6;
+const FIXED_LEN_BYTE_ARRAY: i64 = 7;
+
+// Encodings
+const PLAIN: i64 = 0;
+const PLAIN_DICTIONARY: i64 = 2;
+const RLE: i64 = 3;
+const DELTA_BINARY_PACKED: i64 = 5;
+const DELTA_LENGTH_BYTE_ARRAY: i64 = 6;
+const DELTA_BYTE_ARRAY: i64 = 7;
+const RLE_DICTIONARY: i64 = 8;
+const BYTE_STREAM_SPLIT: i64 = 9;
+
+// Page types
+const DATA_PAGE: i64 = 0;
+const DICTIONARY_PAGE: i64 = 2;
+const DATA_PAGE_V2: i64 = 3;
+
+/// The Julian day of 1970-01-01, which INT96 timestamps count from.
+const UNIX_JULIAN_DAY: i64 = 2_440_588;
+
+/// Checks whether a file starts like a Parquet file, leaving it at its start.
+pub fn is_parquet(file: &mut File) -> io::Result<bool> {
+    let mut magic = [0; 4];
+    let is_parquet = match file.read_exact(&mut magic) {
+        Ok(()) => magic == MAGIC,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e),
+    };
+    file.rewind()?;
+    Ok(is_parquet)
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("parquet: {}", message.into()),
+    ))
+}
+
+/// A record per row, keyed by column name.
+pub type Rows = Vec<Map<String, Value>>;
+
+/// Reads every row of a Parquet file, returning the column names and a
+/// record per row.
+pub fn read(data: &[u8]) -> Result<(Vec<String>, Rows), Error> {
+    let metadata = footer(data)?;
+    let columns = schema(&metadata)?;
+    let row_groups = metadata.list(4);
+    log::info!(
+        "Reading {} columns in {} row groups from the Parquet file",
+        columns.len(),
+        row_groups.len()
+    );
+
+    let mut records = Vec::new();
+    for group in row_groups {
+        let rows = size(group.required(3, "row group row count")?)?;
+        let chunks = group.list(1);
+        if chunks.len() != columns.len() {
+            return Err(invalid(format!(
+                "row group has {} column chunks for {} columns",
+                chunks.len(),
+                columns.len()
+            )));
+        }
+        let mut values = Vec::with_capacity(columns.len());
+        for (column, chunk) in columns.iter().zip(chunks) {
+            let decoded = read_chunk(data, column, chunk)?;
+            if decoded.len() != rows {
+                return Err(invalid(format!(
+                    "column {} has {} values for {} rows",
+                    column.name,
+                    decoded.len(),
+                    rows
+                )));
+            }
+            values.push(decoded.into_iter());
+        }
+        records.reserve(rows);
+        for _ in 0..rows {
+            records.push(
+                columns
+                    .iter()
+                    .zip(values.iter_mut())
+                    .map(|(column, values)| {
+                        (column.name.clone(), values.next().expect("counted above"))
+                    })
+                    .collect(),
+            );
+        }
+    }
+    let names = columns.into_iter().map(|column| column.name).collect();
+    Ok((names, records))
+}
+
+/// Parses the file metadata at the end of the file.
+fn footer(data: &[u8]) -> Result<Thrift<'_>, Error> {
+    if data.len() >= 8 && data.ends_with(b"PARE") {
+        return Err(invalid("encrypted files are not supported"));
+    }
+    if !data.starts_with(MAGIC) {
+        return Err(invalid("not a Parquet file"));
+    }
+    if data.len() < 12 || !data.ends_with(MAGIC) {
+        return Err(invalid("file is truncated"));
+    }
+    let end = data.len() - 8;
+    let length = u32::from_le_bytes(data[end..end + 4].try_into().expect("4 bytes")) as usize;
+    let start = end
+        .checked_sub(length)
+        .filter(|&start| start >= MAGIC.len())
+        .ok_or_else(|| invalid("file metadata is longer than the file"))?;
+    Compact::new(&data[start..end]).structure()
+}
+
+/// A column of a flat schema.
+#[derive(Debug)]
+struct Column {
+    name: String,
+    physical: i64,
+    type_length: usize,
+    optional: bool,
+    logical: Logical,
+}
+
+/// What a column's values mean beyond their physical type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Logical {
+    None,
+    Text,
+    Date,
+    Time(Unit),
+    Timestamp(Unit, bool),
+    Decimal(i64),
+    Unsigned,
+    Uuid,
+    Float16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unit {
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl Unit {
+    fn per_second(&self) -> i64 {
+        match self {
+            Unit::Millis => 1_000,
+            Unit::Micros => 1_000_000,
+            Unit::Nanos => 1_000_000_000,
+        }
+    }
+
+    fn digits(&self) -> usize {
+        match self {
+            Unit::Millis => 3,
+            Unit::Micros => 6,
+            Unit::Nanos => 9,
+        }
+    }
+
+    /// The unit a TimeUnit union holds.
+    fn from_thrift(unit: Option<&Thrift>) -> Unit {
+        match unit {
+            Some(unit) if unit.field(1).is_some() => Unit::Millis,
+            Some(unit) if unit.field(3).is_some() => Unit::Nanos,
+            _ => Unit::Micros,
+        }
+    }
+}
+
+/// The columns of the schema, a depth-first list of elements whose first is
+/// the root group.
+fn schema(metadata: &Thrift) -> Result<Vec<Column>, Error> {
+    let elements = metadata.list(2);
+    let Some((root, elements)) = elements.split_first() else {
+        return Err(invalid("file has no schema"));
+    };
+    let children = root.int(5).unwrap_or(0);
+    if children != elements.len() as i64 {
+        return Err(invalid(format!(
+            "{} is nested; only flat schemas can be read",
+            elements
+                .iter()
+                .find(|element| element.int(5).unwrap_or(0) > 0)
+                .map_or("a column".to_string(), |element| name(element))
+        )));
+    }
+    elements
+        .iter()
+        .map(|element| {
+            let name = name(element);
+            if element.int(3) == Some(2) {
+                return Err(invalid(format!(
+                    "{} is repeated; only flat schemas can be read",
+                    name
+                )));
+            }
+            Ok(Column {
+                physical: element.required(1, "column type")?,
+                type_length: size(element.int(2).unwrap_or(0))?,
+                optional: element.int(3) == Some(1),
+                logical: logical(element),
+                name,
+            })
+        })
+        .collect()
+}
+
+fn name(element: &Thrift) -> String {
+    String::from_utf8_lossy(element.binary(4).unwrap_or_default()).into_owned()
+}
+
+/// The logical type of a schema element, or failing that the converted type
+/// older writers give instead.
+fn logical(element: &Thrift) -> Logical {
+    if let Some(logical) = element.field(10) {
+        let has = |id| logical.field(id).is_some();
+        if has(1) || has(4) || has(12) {
+            return Logical::Text;
+        } else if let Some(decimal) = logical.field(5) {
+            return Logical::Decimal(decimal.int(1).unwrap_or(0));
+        } else if has(6) {
+            return Logical::Date;
+        } else if let Some(time) = logical.field(7) {
+            return Logical::Time(Unit::from_thrift(time.field(2)));
+        } else if let Some(timestamp) = logical.field(8) {
+            return Logical::Timestamp(
+                Unit::from_thrift(timestamp.field(2)),
+                timestamp.boolean(1).unwrap_or(false),
+            );
+        } else if let Some(integer) = logical.field(10) {
+            if integer.boolean(2) == Some(false) {
+                return Logical::Unsigned;
+            }
+        } else if has(14) {
+            return Logical::Uuid;
+        } else if has(15) {
+            return Logical::Float16;
+        }
+    }
+    match element.int(6) {
+        Some(0 | 4 | 19) => Logical::Text,
+        Some(5) => Logical::Decimal(element.int(7).unwrap_or(0)),
+        Some(6) => Logical::Date,
+        Some(7) => Logical::Time(Unit::Millis),
+        Some(8) => Logical::Time(Unit::Micros),
+        Some(9) => Logical::Timestamp(Unit::Millis, true),
+        Some(10) => Logical::Timestamp(Unit::Micros, true),
+        Some(11..=14) => Logical::Unsigned,
+        _ => Logical::None,
+    }
+}
+
+fn size(value: i64) -> Result<usize, Error> {
+    usize::try_from(value).map_err(|_| invalid(format!("bad size {}", value)))
+}
+
+/// Reads the values of one column chunk, with nulls where rows have none.
+fn read_chunk(data: &[u8], column: &Column, chunk: &Thrift) -> Result<Vec<Value>, Error> {
+    if chunk.field(1).is_some() {
+        return Err(invalid(format!(
+            "column {} is stored in another file",
+            column.name
+        )));
+    }
+    let metadata = chunk
+        .field(3)
+        .ok_or_else(|| invalid(format!("column {} has no metadata", column.name)))?;
+    let codec = metadata.required(4, "compression codec")?;
+    let count = size(metadata.required(5, "value count")?)?;
+    let data_offset = metadata.required(9, "data page offset")?;
+    // Some writers give the dictionary offset as 0 when there is none
+    let start = match metadata.int(11) {
+        Some(offset) if offset > 0 && offset < data_offset => offset,
+        _ => data_offset,
+    };
+    let start = size(start)?;
+    let end = start
+        .checked_add(size(metadata.required(7, "compressed size")?)?)
+        .filter(|&end| end <= data.len() - 8)
+        .ok_or_else(|| {
+            invalid(format!(
+                "column {} runs past the end of the file",
+                column.name
+            ))
+        })?;
+    let chunk = &data[start..end];
+
+    let mut values = Vec::with_capacity(count);
+    let mut dictionary = Vec::new();
+    let mut at = 0;
+    while values.len() < count && at < chunk.len() {
+        let mut parser = Compact::new(&chunk[at..]);
+        let header = parser.structure()?;
+        at += parser.at;
+        let length = size(header.required(3, "page size")?)?;
+        let page = chunk
+            .get(at..at + length)
+            .ok_or_else(|| invalid(format!("page of column {} is truncated", column.name)))?;
+        at += length;
+        match header.int(1) {
+            Some(DICTIONARY_PAGE) => {
+                let dictionary_header = header
+                    .field(7)
+                    .ok_or_else(|| invalid("dictionary page has no header"))?;
+                let entries = size(dictionary_header.required(1, "dictionary size")?)?;
+                let page = decompress(codec, page)?;
+                dictionary = plain(column, &page, entries)?;
+            }
+            Some(DATA_PAGE) => {
+                let page_header = header
+                    .field(5)
+                    .ok_or_else(|| invalid("data page has no header"))?;
+                let rows = size(page_header.required(1, "page value count")?)?;
+                let encoding = page_header.required(2, "page encoding")?;
+                let page = decompress(codec, page)?;
+                let (levels, page) = if column.optional {
+                    let length = page
+                        .get(..4)
+                        .map(|length| u32::from_le_bytes(length.try_into().expect("4 bytes")))
+                        .ok_or_else(|| invalid("data page is truncated"))?
+                        as usize;
+                    let levels = page
+                        .get(4..4 + length)
+                        .ok_or_else(|| invalid("data page is truncated"))?;
+                    (Some(rle_hybrid(levels, 1, rows)?), &page[4 + length..])
+                } else {
+                    (None, &page[..])
+                };
+                let present = levels.as_ref().map_or(rows, |levels| count_present(levels));
+                let decoded = decode(column, encoding, page, present, &dictionary)?;
+                with_nulls(&mut values, levels, decoded);
+            }
+            Some(DATA_PAGE_V2) => {
+                let page_header = header
+                    .field(8)
+                    .ok_or_else(|| invalid("data page has no header"))?;
+                let rows = size(page_header.required(1, "page value count")?)?;
+                let encoding = page_header.required(4, "page encoding")?;
+                let levels_length = size(page_header.int(5).unwrap_or(0))?;
+                let skipped = size(page_header.int(6).unwrap_or(0))? + levels_length;
+                if page.len() < skipped {
+                    return Err(invalid("data page is truncated"));
+                }
+                let levels = if column.optional {
+                    let levels = &page[skipped - levels_length..skipped];
+                    Some(rle_hybrid(levels, 1, rows)?)
+                } else {
+                    None
+                };
+                let page = if page_header.boolean(7).unwrap_or(true) {
+                    decompress(codec, &page[skipped..])?
+                } else {
+                    Cow::Borrowed(&page[skipped..])
+                };
+                let present = levels.as_ref().map_or(rows, |levels| count_present(levels));
+                let decoded = decode(column, encoding, &page, present, &dictionary)?;
+                with_nulls(&mut values, levels, decoded);
+            }
+            // Index pages and any newer kinds hold no values
+            _ => {}
+        }
+    }
+    Ok(values)
+}
+
+fn count_present(levels: &[u32]) -> usize {
+    levels.iter().filter(|&&level| level == 1).count()
+}
+
+/// Appends a page's values, with a null for each definition level of 0.
+fn with_nulls(values: &mut Vec<Value>, levels: Option<Vec<u32>>, decoded: Vec<Value>) {
+    match levels {
+        None => values.extend(decoded),
+        Some(levels) => {
+            let mut decoded = decoded.into_iter();
+            values.extend(levels.into_iter().map(|level| match level {
+                1 => decoded.next().unwrap_or(Value::Null),
+                _ => Value::Null,
+            }));
+        }
+    }
+}
+
+/// Undoes a page's compression.
+fn decompress(codec: i64, data: &[u8]) -> Result<Cow<'_, [u8]>, Error> {
+    Ok(Cow::Owned(match codec {
+        0 => return Ok(Cow::Borrowed(data)),
+        1 => snappy(data)?,
+        2 => compression::decompress_bytes(Compression::Gzip, data)?,
+        5 => hadoop_lz4(data)?,
+        6 => compression::decompress_bytes(Compression::Zstd, data)?,
+        7 => {
+            let mut output = Vec::new();
+            lz4_block(data, &mut output)?;
+            output
+        }
+        3 => return Err(invalid("LZO compression is not supported")),
+        4 => return Err(invalid("Brotli compression is not supported")),
+        other => return Err(invalid(format!("unknown compression codec {}", other))),
+    }))
+}
+
+/// Decodes `count` values of a page encoded with `encoding`.
+fn decode(
+    column: &Column,
+    encoding: i64,
+    data: &[u8],
+    count: usize,
+    dictionary: &[Value],
+) -> Result<Vec<Value>, Error> {
+    match encoding {
+        PLAIN => plain(column, data, count),
+        PLAIN_DICTIONARY | RLE_DICTIONARY => {
+            let (&width, indexes) = data
+                .split_first()
+                .ok_or_else(|| invalid("dictionary indexes are missing"))?;
+            rle_hybrid(indexes, width as u32, count)?
+                .into_iter()
+                .map(|index| {
+                    dictionary.get(index as usize).cloned().ok_or_else(|| {
+                        invalid(format!(
+                            "column {} has no dictionary entry {}",
+                            column.name, index
+                        ))
+                    })
+                })
+                .collect()
+        }
+        RLE if column.physical == BOOLEAN => {
+            let values = data
+                .get(4..)
+                .ok_or_else(|| invalid("boolean page is truncated"))?;
+            Ok(rle_hybrid(values, 1, count)?
+                .into_iter()
+                .map(|bit| Value::Bool(bit == 1))
+                .collect())
+        }
+        DELTA_BINARY_PACKED => {
+            let values = delta_binary_packed(data, &mut 0)?;
+            Ok(values
+                .into_iter()
+                .take(count)
+                .map(|value| {
+                    column.convert(Raw::Int(if column.physical == INT32 {
+                        value as i32 as i64
+                    } else {
+                        value
+                    }))
+                })
+                .collect())
+        }
+        DELTA_LENGTH_BYTE_ARRAY => {
+            let mut at = 0;
+            let lengths = delta_binary_packed(data, &mut at)?;
+            lengths
+                .into_iter()
+                .take(count)
+                .map(|length| {
+                    let bytes = take(data, &mut at, size(length)?)?;
+                    Ok(column.convert(Raw::Bytes(bytes)))
+                })
+                .collect()
+        }
+        DELTA_BYTE_ARRAY => {
+            let mut at = 0;
+            let prefixes = delta_binary_packed(data, &mut at)?;
+            let suffixes = delta_binary_packed(data, &mut at)?;
+            let mut previous: Vec<u8> = Vec::new();
+            let mut values = Vec::with_capacity(count);
+            for (prefix, suffix) in prefixes.into_iter().zip(suffixes).take(count) {
+                let mut value = previous
+                    .get(..size(prefix)?)
+                    .ok_or_else(|| invalid("delta prefix is longer than the previous value"))?
+                    .to_vec();
+                value.extend_from_slice(take(data, &mut at, size(suffix)?)?);
+                values.push(column.convert(Raw::Bytes(&value)));
+                previous = value;
+            }
+            Ok(values)
+        }
+        BYTE_STREAM_SPLIT => {
+            let width = match column.physical {
+                INT32 | FLOAT => 4,
+                INT64 | DOUBLE => 8,
+                FIXED_LEN_BYTE_ARRAY => column.type_length,
+                _ => return Err(invalid("BYTE_STREAM_SPLIT on an unsupported type")),
+            };
+            if data.len() < width * count {
+                return Err(invalid("byte stream split page is truncated"));
+            }
+            // All first bytes, then all second bytes and so on
+            let streams = data.len() / width;
+            let mut bytes = vec![0; width];
+            Ok((0..count)
+                .map(|index| {
+                    for (byte, value) in bytes.iter_mut().enumerate() {
+                        *value = data[byte * streams + index];
+                    }
+                    column.convert(raw(column.physical, &bytes))
+                })
+                .collect())
+        }
+        other => Err(invalid(format!(
+            "column {} uses unsupported encoding {}",
+            column.name, other
+        ))),
+    }
+}
+
+/// Decodes `count` values stored one after another.
+fn plain(column: &Column, data: &[u8], count: usize) -> Result<Vec<Value>, Error> {
+    if column.physical == BOOLEAN {
+        if data.len() * 8 < count {
+            return Err(invalid("boolean page is truncated"));
+        }
+        return Ok((0..count)
+            .map(|bit| Value::Bool(data[bit / 8] >> (bit % 8) & 1 == 1))
+            .collect());
+    }
+    let mut at = 0;
+    (0..count)
+        .map(|_| {
+            let width = match column.physical {
+                INT32 | FLOAT => 4,
+                INT64 | DOUBLE => 8,
+                INT96 => 12,
+                FIXED_LEN_BYTE_ARRAY => column.type_length,
+                BYTE_ARRAY => {
+                    let length = take(data, &mut at, 4)?;
+                    u32::from_le_bytes(length.try_into().expect("4 bytes")) as usize
+                }
+                other => {
+                    return Err(invalid(format!(
+                        "column {} has unknown type {}",
+                        column.name, other
+                    )))
+                }
+            };
+            Ok(column.convert(raw(column.physical, take(data, &mut at, width)?)))
+        })
+        .collect()
+}
+
+/// A value as stored, before its logical type is applied.
+enum Raw<'a> {
+    Int(i64),
+    Float(f64),
+    Bytes(&'a [u8]),
+}
+
+fn raw(physical: i64, bytes: &[u8]) -> Raw<'_> {
+    match physical {
+        INT32 => Raw::Int(i32::from_le_bytes(bytes.try_into().expect("4 bytes")) as i64),
+        INT64 => Raw::Int(i64::from_le_bytes(bytes.try_into().expect("8 bytes"))),
+        // Printed as written, so 0.1 is not 0.10000000149011612
+        FLOAT => Raw::Float(shortest(f32::from_le_bytes(
+            bytes.try_into().expect("4 bytes"),
+        ))),
+        DOUBLE => Raw::Float(f64::from_le_bytes(bytes.try_into().expect("8 bytes"))),
+        _ => Raw::Bytes(bytes),
+    }
+}
+
+fn shortest(value: f32) -> f64 {
+    value.to_string().parse().unwrap_or(value as f64)
+}
+
+impl Column {
+    /// The JSON value of a stored value. Dates and times become ISO 8601
+    /// text, decimals numbers, and bytes that are not UTF-8 base64.
+    fn convert(&self, raw: Raw) -> Value {
+        match (raw, self.logical) {
+            (Raw::Int(days), Logical::Date) => {
+                let (year, month, day) = civil_from_days(days);
+                Value::from(format!("{:04}-{:02}-{:02}", year, month, day))
+            }
+            (Raw::Int(value), Logical::Time(unit)) => {
+                let seconds = value.div_euclid(unit.per_second());
+                Value::from(format!(
+                    "{:02}:{:02}:{:02}{}",
+                    seconds / 3600,
+                    seconds / 60 % 60,
+                    seconds % 60,
+                    fraction(value.rem_euclid(unit.per_second()), unit.digits())
+                ))
+            }
+            (Raw::Int(value), Logical::Timestamp(unit, utc)) => {
+                let seconds = value.div_euclid(unit.per_second());
+                Value::from(timestamp(
+                    seconds,
+                    &fraction(value.rem_euclid(unit.per_second()), unit.digits()),
+                    utc,
+                ))
+            }
+            (Raw::Int(value), Logical::Decimal(scale)) => decimal(value as i128, scale),
+            (Raw::Int(value), Logical::Unsigned) if self.physical == INT32 => {
+                Value::from(value as u32)
+            }
+            (Raw::Int(value), Logical::Unsigned) => Value::from(value as u64),
+            (Raw::Int(value), _) => Value::from(value),
+            (Raw::Float(value), _) => Number::from_f64(value).map_or(Value::Null, Value::Number),
+            (Raw::Bytes(bytes), _) if self.physical == INT96 => {
+                // Nanoseconds into the day, then the Julian day
+                let nanos = i64::from_le_bytes(bytes[..8].try_into().expect("8 bytes"));
+                let day = i32::from_le_bytes(bytes[8..].try_into().expect("4 bytes")) as i64;
+                let seconds = (day - UNIX_JULIAN_DAY) * 86_400 + nanos.div_euclid(1_000_000_000);
+                Value::from(timestamp(
+                    seconds,
+                    &fraction(nanos.rem_euclid(1_000_000_000), 9),
+                    true,
+                ))
+            }
+            (Raw::Bytes(bytes), Logical::Decimal(scale)) if bytes.len() <= 16 => {
+                // Big-endian two's complement
+                let unscaled = bytes.iter().fold(
+                    if bytes.first().is_some_and(|&byte| byte >= 0x80) {
+                        -1
+                    } else {
+                        0
+                    },
+                    |unscaled: i128, &byte| unscaled << 8 | byte as i128,
+                );
+                decimal(unscaled, scale)
+            }
+            (Raw::Bytes(bytes), Logical::Uuid) if bytes.len() == 16 => {
+                let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                Value::from(format!(
+                    "{}-{}-{}-{}-{}",
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..]
+                ))
+            }
+            (Raw::Bytes(bytes), Logical::Float16) if bytes.len() == 2 => {
+                let value = float16(u16::from_le_bytes([bytes[0], bytes[1]]));
+                Number::from_f64(shortest(value)).map_or(Value::Null, Value::Number)
+            }
+            (Raw::Bytes(bytes), _) => match std::str::from_utf8(bytes) {
+                Ok(text) => Value::from(text),
+                Err(_) => Value::from(base64::encode(bytes)),
+            },
+        }
+    }
+}
+
+/// The digits after the decimal point of a fraction of a second, or nothing
+/// for whole seconds.
+fn fraction(value: i64, digits: usize) -> String {
+    if value == 0 {
+        return String::new();
+    }
+    let fraction = format!(".{:0width$}", value, width = digits);
+    fraction.trim_end_matches('0').to_string()
+}
+
+/// A date and time from seconds since 1970-01-01, marked as UTC when the
+/// writer says the value is adjusted to it.
+fn timestamp(seconds: i64, fraction: &str, utc: bool) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let time = seconds.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        fraction,
+        if utc { "Z" } else { "" }
+    )
+}
+
+/// A decimal from its unscaled value, as a number.
+fn decimal(unscaled: i128, scale: i64) -> Value {
+    if scale <= 0 {
+        return match i64::try_from(unscaled) {
+            Ok(value) => Value::from(value),
+            Err(_) => Number::from_f64(unscaled as f64).map_or(Value::Null, Value::Number),
+        };
+    }
+    let digits = format!(
+        "{:0width$}",
+        unscaled.unsigned_abs(),
+        width = scale as usize + 1
+    );
+    let (whole, fraction) = digits.split_at(digits.len() - scale as usize);
+    let sign = if unscaled < 0 { "-" } else { "" };
+    format!("{}{}.{}", sign, whole, fraction)
+        .parse()
+        .ok()
+        .and_then(Number::from_f64)
+        .map_or(Value::Null, Value::Number)
+}
+
+fn float16(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (bits >> 10 & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn take<'a>(data: &'a [u8], at: &mut usize, length: usize) -> Result<&'a [u8], Error> {
+    let bytes = at
+        .checked_add(length)
+        .and_then(|end| data.get(*at..end))
+        .ok_or_else(|| invalid("page is truncated"))?;
+    *at += length;
+    Ok(bytes)
+}
+
+/// An unsigned LEB128 varint.
+fn varint(data: &[u8], at: &mut usize) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data
+            .get(*at)
+            .ok_or_else(|| invalid("varint is truncated"))?;
+        *at += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint is too long"))
+}
+
+fn zigzag(data: &[u8], at: &mut usize) -> Result<i64, Error> {
+    let value = varint(data, at)?;
+    Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+}
+
+/// Unpacks `count` values of `width` bits, least significant bit first.
+fn unpack(data: &[u8], width: u32, count: usize) -> Vec<u64> {
+    let mask = if width == 64 {
+        u64::MAX
+    } else {
+        (1 << width) - 1
+    };
+    (0..count)
+        .map(|index| {
+            let bit = index * width as usize;
+            let window = data[bit / 8..]
+                .iter()
+                .take((bit % 8 + width as usize).div_ceil(8))
+                .rev()
+                .fold(0u128, |window, &byte| window << 8 | byte as u128);
+            (window >> (bit % 8)) as u64 & mask
+        })
+        .collect()
+}
+
+/// Decodes `count` values of the RLE/bit-packing hybrid that levels,
+/// dictionary indexes and booleans are stored in.
+fn rle_hybrid(data: &[u8], width: u32, count: usize) -> Result<Vec<u32>, Error> {
+    if width > 32 {
+        return Err(invalid(format!("bad bit width {}", width)));
+    }
+    let mut values = Vec::with_capacity(count);
+    let mut at = 0;
+    while values.len() < count {
+        let header = varint(data, &mut at)?;
+        let remaining = count - values.len();
+        if header & 1 == 0 {
+            let bytes = take(data, &mut at, width.div_ceil(8) as usize)?;
+            let value = bytes
+                .iter()
+                .rev()
+                .fold(0u32, |value, &byte| value << 8 | byte as u32);
+            let run = usize::try_from(header >> 1).unwrap_or(usize::MAX);
+            values.extend(std::iter::repeat_n(value, run.min(remaining)));
+        } else {
+            let groups = usize::try_from(header >> 1).unwrap_or(usize::MAX);
+            let bytes = groups
+                .checked_mul(width as usize)
+                .ok_or_else(|| invalid("bit-packed run is too long"))?;
+            let packed = take(data, &mut at, bytes)?;
+            let unpacked = unpack(packed, width, (groups * 8).min(remaining));
+            values.extend(unpacked.into_iter().map(|value| value as u32));
+        }
+    }
+    Ok(values)
+}
+
+/// Decodes a DELTA_BINARY_PACKED run starting at `at`, leaving `at` after it.
+fn delta_binary_packed(data: &[u8], at: &mut usize) -> Result<Vec<i64>, Error> {
+    let block_size = varint(data, at)? as usize;
+    let miniblocks = varint(data, at)? as usize;
+    let total = varint(data, at)? as usize;
+    let mut value = zigzag(data, at)?;
+    if miniblocks == 0
+        || !block_size.is_multiple_of(miniblocks)
+        || !(block_size / miniblocks).is_multiple_of(8)
+    {
+        return Err(invalid("bad delta encoding block size"));
+    }
+    let per_miniblock = block_size / miniblocks;
+    let mut values = Vec::with_capacity(total.min(data.len() * 8 + 1));
+    if total > 0 {
+        values.push(value);
+    }
+    while values.len() < total {
+        let min_delta = zigzag(data, at)?;
+        let widths = take(data, at, miniblocks)?;
+        for &width in widths {
+            if values.len() >= total {
+                break;
+            }
+            if width > 64 {
+                return Err(invalid(format!("bad bit width {}", width)));
+            }
+            let packed = take(data, at, per_miniblock * width as usize / 8)?;
+            for delta in unpack(packed, width as u32, per_miniblock) {
+                if values.len() < total {
+                    value = value.wrapping_add(min_delta).wrapping_add(delta as i64);
+                    values.push(value);
+                }
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Decompresses Snappy's raw block format.
+fn snappy(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut at = 0;
+    let length = varint(data, &mut at)? as usize;
+    let mut output = Vec::with_capacity(length.min(data.len().saturating_mul(32)));
+    while at < data.len() {
+        let tag = data[at];
+        at += 1;
+        let little_endian = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .rev()
+                .fold(0usize, |value, &byte| value << 8 | byte as usize)
+        };
+        let (length, offset) = match tag & 3 {
+            0 => {
+                let mut length = (tag >> 2) as usize;
+                if length >= 60 {
+                    length = little_endian(take(data, &mut at, length - 59)?);
+                }
+                output.extend_from_slice(take(data, &mut at, length + 1)?);
+                continue;
+            }
+            1 => (
+                (tag >> 2 & 7) as usize + 4,
+                ((tag >> 5) as usize) << 8 | little_endian(take(data, &mut at, 1)?),
+            ),
+            2 => (
+                (tag >> 2) as usize + 1,
+                little_endian(take(data, &mut at, 2)?),
+            ),
+            _ => (
+                (tag >> 2) as usize + 1,
+                little_endian(take(data, &mut at, 4)?),
+            ),
+        };
+        copy_match(&mut output, offset, length)?;
+    }
+    if output.len() != length {
+        return Err(invalid("snappy data has the wrong length"));
+    }
+    Ok(output)
+}
+
+/// Appends `length` bytes copied from `offset` bytes back, which may overlap
+/// the bytes being written.
+fn copy_match(output: &mut Vec<u8>, offset: usize, length: usize) -> Result<(), Error> {
+    if offset == 0 || offset > output.len() {
+        return Err(invalid("compressed data refers back past its start"));
+    }
+    let start = output.len() - offset;
+    for i in 0..length {
+        output.push(output[start + i]);
+    }
+    Ok(())
+}
+
+/// Decompresses one LZ4 block onto the end of `output`.
+fn lz4_block(data: &[u8], output: &mut Vec<u8>) -> Result<(), Error> {
+    let mut at = 0;
+    let extended = |at: &mut usize, mut length: usize| -> Result<usize, Error> {
+        if length == 15 {
+            loop {
+                let byte = take(data, at, 1)?[0];
+                length += byte as usize;
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(length)
+    };
+    while at < data.len() {
+        let token = data[at];
+        at += 1;
+        let literals = extended(&mut at, (token >> 4) as usize)?;
+        output.extend_from_slice(take(data, &mut at, literals)?);
+        // The last sequence is literals only
+        if at == data.len() {
+            break;
+        }
+        let offset = take(data, &mut at, 2)?;
+        let offset = u16::from_le_bytes([offset[0], offset[1]]) as usize;
+        let length = extended(&mut at, (token & 15) as usize)? + 4;
+        copy_match(output, offset, length)?;
+    }
+    Ok(())
+}
+
+/// Decompresses the deprecated LZ4 codec: LZ4 blocks framed as Hadoop
+/// writes them, or a bare block as some other writers did.
+fn hadoop_lz4(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let framed = || -> Result<Vec<u8>, Error> {
+        let mut output = Vec::new();
+        let mut at = 0;
+        while at < data.len() {
+            let header = take(data, &mut at, 8)?;
+            let expected = u32::from_be_bytes(header[..4].try_into().expect("4 bytes")) as usize;
+            let length = u32::from_be_bytes(header[4..].try_into().expect("4 bytes")) as usize;
+            let start = output.len();
+            lz4_block(take(data, &mut at, length)?, &mut output)?;
+            if output.len() - start != expected {
+                return Err(invalid("LZ4 block has the wrong length"));
+            }
+        }
+        Ok(output)
+    };
+    framed().or_else(|_| {
+        let mut output = Vec::new();
+        lz4_block(data, &mut output)?;
+        Ok(output)
+    })
+}
+
+/// A value of Thrift's compact protocol, which Parquet's metadata and page
+/// headers are written in. Structs keep their fields by id, so fields this
+/// reader does not know are kept but ignored.
+#[derive(Debug)]
+enum Thrift<'a> {
+    Bool(bool),
+    Int(i64),
+    /// Only statistics hold doubles, and they are not read
+    Double,
+    Binary(&'a [u8]),
+    List(Vec<Thrift<'a>>),
+    Struct(Vec<(i16, Thrift<'a>)>),
+}
+
+impl<'a> Thrift<'a> {
+    fn field(&self, id: i16) -> Option<&Thrift<'a>> {
+        match self {
+            Thrift::Struct(fields) => fields
+                .iter()
+                .find(|(field, _)| *field == id)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn int(&self, id: i16) -> Option<i64> {
+        match self.field(id) {
+            Some(Thrift::Int(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn required(&self, id: i16, what: &str) -> Result<i64, Error> {
+        self.int(id)
+            .ok_or_else(|| invalid(format!("missing {}", what)))
+    }
+
+    fn boolean(&self, id: i16) -> Option<bool> {
+        match self.field(id) {
+            Some(Thrift::Bool(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn binary(&self, id: i16) -> Option<&'a [u8]> {
+        match self.field(id) {
+            Some(Thrift::Binary(bytes)) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    fn list(&self, id: i16) -> &[Thrift<'a>] {
+        match self.field(id) {
+            Some(Thrift::List(items)) => items,
+            _ => &[],
+        }
+    }
+}
+
+/// Parses the compact protocol.
+struct Compact<'a> {
+    data: &'a [u8],
+    at: usize,
+    depth: usize,
+}
+
+impl<'a> Compact<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Compact {
+            data,
+            at: 0,
+            depth: 0,
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(take(self.data, &mut self.at, 1)?[0])
+    }
+
+    fn structure(&mut self) -> Result<Thrift<'a>, Error> {
+        self.depth += 1;
+        if self.depth > 64 {
+            return Err(invalid("metadata is nested too deeply"));
+        }
+        let mut fields = Vec::new();
+        let mut id = 0i16;
+        loop {
+            let header = self.byte()?;
+            if header == 0 {
+                break;
+            }
+            id = match header >> 4 {
+                0 => zigzag(self.data, &mut self.at)? as i16,
+                delta => id.wrapping_add(delta as i16),
+            };
+            let value = match header & 0x0f {
+                1 => Thrift::Bool(true),
+                2 => Thrift::Bool(false),
+                kind => self.value(kind)?,
+            };
+            fields.push((id, value));
+        }
+        self.depth -= 1;
+        Ok(Thrift::Struct(fields))
+    }
+
+    fn value(&mut self, kind: u8) -> Result<Thrift<'a>, Error> {
+        Ok(match kind {
+            // Booleans inside lists take a byte each
+            1 | 2 => Thrift::Bool(self.byte()? == 1),
+            3 => Thrift::Int(self.byte()? as i8 as i64),
+            4..=6 => Thrift::Int(zigzag(self.data, &mut self.at)?),
+            7 => {
+                take(self.data, &mut self.at, 8)?;
+                Thrift::Double
+            }
+            8 => {
+                let length = varint(self.data, &mut self.at)? as usize;
+                Thrift::Binary(take(self.data, &mut self.at, length)?)
+            }
+            9 | 10 => {
+                let header = self.byte()?;
+                let length = match header >> 4 {
+                    15 => varint(self.data, &mut self.at)? as usize,
+                    length => length as usize,
+                };
+                // Each item takes at least a byte
+                if length > self.data.len() - self.at {
+                    return Err(invalid("list is longer than the metadata"));
+                }
+                let items = (0..length)
+                    .map(|_| self.value(header & 0x0f))
+                    .collect::<Result<_, _>>()?;
+                Thrift::List(items)
+            }
+            11 => {
+                let length = varint(self.data, &mut self.at)? as usize;
+                let kinds = if length > 0 { self.byte()? } else { 0 };
+                if length > self.data.len() - self.at {
+                    return Err(invalid("map is longer than the metadata"));
+                }
+                let mut entries = Vec::with_capacity(length);
+                for _ in 0..length {
+                    let key = self.value(kinds >> 4)?;
+                    let value = self.value(kinds & 0x0f)?;
+                    entries.push(Thrift::List(vec![key, value]));
+                }
+                Thrift::List(entries)
+            }
+            12 => self.structure()?,
+            other => return Err(invalid(format!("unknown metadata type {}", other))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Writes the compact protocol, for building files to read.
+    struct Writer {
+        out: Vec<u8>,
+        ids: Vec<i16>,
+    }
+
+    impl Writer {
+        fn new() -> Self {
+            Writer {
+                out: Vec::new(),
+                ids: vec![0],
+            }
+        }
+
+        fn varint(&mut self, mut value: u64) {
+            while value >= 0x80 {
+                self.out.push(value as u8 | 0x80);
+                value >>= 7;
+            }
+            self.out.push(value as u8);
+        }
+
+        fn header(&mut self, id: i16, kind: u8) {
+            let last = *self.ids.last().expect("inside a struct");
+            match id - last {
+                delta @ 1..=15 => self.out.push((delta as u8) << 4 | kind),
+                _ => {
+                    self.out.push(kind);
+                    self.varint(((id << 1) ^ (id >> 15)) as u16 as u64);
+                }
+            }
+            *self.ids.last_mut().expect("inside a struct") = id;
+        }
+
+        fn int(&mut self, id: i16, value: i64) -> &mut Self {
+            self.header(id, 6);
+            self.varint(((value << 1) ^ (value >> 63)) as u64);
+            self
+        }
+
+        fn boolean(&mut self, id: i16, value: bool) -> &mut Self {
+            self.header(id, if value { 1 } else { 2 });
+            self
+        }
+
+        fn binary(&mut self, id: i16, value: &[u8]) -> &mut Self {
+            self.header(id, 8);
+            self.varint(value.len() as u64);
+            self.out.extend_from_slice(value);
+            self
+        }
+
+        fn begin(&mut self, id: i16) -> &mut Self {
+            self.header(id, 12);
+            self.ids.push(0);
+            self
+        }
+
+        fn end(&mut self) -> &mut Self {
+            self.out.push(0);
+            self.ids.pop();
+            self
+        }
+
+        /// Starts a list of `length` structs, each begun with `item`.
+        fn list(&mut self, id: i16, length: u8) -> &mut Self {
+            self.header(id, 9);
+            self.out.push(length << 4 | 12);
+            self
+        }
+
+        fn item(&mut self) -> &mut Self {
+            self.ids.push(0);
+            self
+        }
+
+        /// Writes a list of binary values, such as a column's path.
+        fn binaries(&mut self, id: i16, values: &[&[u8]]) -> &mut Self {
+            self.header(id, 9);
+            self.out.push((values.len() as u8) << 4 | 8);
+            for value in values {
+                self.varint(value.len() as u64);
+                self.out.extend_from_slice(value);
+            }
+            self
+        }
+
+        /// Writes a list of 32-bit integers, such as a column's encodings.
+        fn ints(&mut self, id: i16, values: &[i64]) -> &mut Self {
+            self.header(id, 9);
+            self.out.push((values.len() as u8) << 4 | 5);
+            for &value in values {
+                self.varint(((value << 1) ^ (value >> 63)) as u64);
+            }
+            self
+        }
+
+        fn finish(&mut self) -> Vec<u8> {
+            self.out.push(0);
+            std::mem::take(&mut self.out)
+        }
+    }
+
+    /// A column for a test file: its schema element, metadata and pages.
+    struct TestColumn {
+        name: &'static str,
+        physical: i64,
+        optional: bool,
+        schema: fn(&mut Writer),
+        codec: i64,
+        values: i64,
+        pages: Vec<u8>,
+    }
+
+    fn page_header(kind: i64, size: usize, fields: impl FnOnce(&mut Writer)) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.int(1, kind).int(2, size as i64).int(3, size as i64);
+        fields(&mut writer);
+        writer.finish()
+    }
+
+    fn data_page(values: i64, encoding: i64, body: &[u8]) -> Vec<u8> {
+        let mut page = page_header(DATA_PAGE, body.len(), |writer| {
+            writer
+                .begin(5)
+                .int(1, values)
+                .int(2, encoding)
+                .int(3, RLE)
+                .int(4, RLE)
+                .end();
+        });
+        page.extend_from_slice(body);
+        page
+    }
+
+    fn file(rows: i64, columns: &[TestColumn]) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        let mut offsets = Vec::new();
+        for column in columns {
+            offsets.push(data.len());
+            data.extend_from_slice(&column.pages);
+        }
+        let mut writer = Writer::new();
+        writer.int(1, 1).list(2, columns.len() as u8 + 1);
+        writer
+            .item()
+            .binary(4, b"schema")
+            .int(5, columns.len() as i64)
+            .end();
+        for column in columns {
+            writer
+                .item()
+                .int(1, column.physical)
+                .int(3, column.optional as i64)
+                .binary(4, column.name.as_bytes());
+            (column.schema)(&mut writer);
+            writer.end();
+        }
+        writer
+            .int(3, rows)
+            .list(4, 1)
+            .item()
+            .list(1, columns.len() as u8);
+        for (column, offset) in columns.iter().zip(offsets) {
+            writer
+                .item()
+                .int(2, offset as i64)
+                .begin(3)
+                .int(1, column.physical)
+                .int(4, column.codec)
+                .int(5, column.values)
+                .int(6, column.pages.len() as i64)
+                .int(7, column.pages.len() as i64)
+                .int(9, offset as i64)
+                .end()
+                .end();
+        }
+        writer.int(3, rows).end();
+        let metadata = writer.finish();
+        data.extend_from_slice(&metadata);
+        data.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+        data.extend_from_slice(MAGIC);
+        data
+    }
+
+    fn plain_column(
+        name: &'static str,
+        physical: i64,
+        schema: fn(&mut Writer),
+        body: &[u8],
+    ) -> TestColumn {
+        TestColumn {
+            name,
+            physical,
+            optional: false,
+            schema,
+            codec: 0,
+            values: 3,
+            pages: data_page(3, PLAIN, body),
+        }
+    }
+
+    #[test]
+    fn test_read() {
+        // Dictionary page of two strings, then indexes 1, null, 0 with
+        // definition levels 1, 0, 1 bit-packed
+        let mut names = page_header(DICTIONARY_PAGE, 14, |writer| {
+            writer.begin(7).int(1, 2).int(2, PLAIN).end();
+        });
+        names.extend_from_slice(b"\x03\x00\x00\x00Ann\x03\x00\x00\x00Bob");
+        names.extend(data_page(
+            3,
+            RLE_DICTIONARY,
+            &[2, 0, 0, 0, 3, 0b101, 1, 3, 0b01],
+        ));
+
+        // Snappy compressed as a single literal
+        let mut body = vec![12, 11 << 2];
+        body.extend([1i32, -2, 3].iter().flat_map(|v| v.to_le_bytes()));
+        let ids = data_page(3, PLAIN, &body);
+
+        let timestamps: Vec<u8> = [0i64, 1_700_000_000_123_000, -1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let prices: Vec<u8> = [12345i32, -5, 0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let days: Vec<u8> = [0i32, 19_814, -1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let columns = [
+            TestColumn {
+                name: "id",
+                physical: INT32,
+                optional: false,
+                schema: |_| {},
+                codec: 1,
+                values: 3,
+                pages: ids,
+            },
+            TestColumn {
+                name: "name",
+                physical: BYTE_ARRAY,
+                optional: true,
+                schema: |writer| {
+                    writer.int(6, 0);
+                },
+                codec: 0,
+                values: 3,
+                pages: names,
+            },
+            plain_column(
+                "at",
+                INT64,
+                |writer| {
+                    writer
+                        .begin(10)
+                        .begin(8)
+                        .boolean(1, true)
+                        .begin(2)
+                        .begin(2)
+                        .end()
+                        .end()
+                        .end()
+                        .end();
+                },
+                &timestamps,
+            ),
+            plain_column(
+                "price",
+                INT32,
+                |writer| {
+                    writer.int(6, 5).int(7, 2).int(8, 9);
+                },
+                &prices,
+            ),
+            plain_column(
+                "day",
+                INT32,
+                |writer| {
+                    writer.begin(10).begin(6).end().end();
+                },
+                &days,
+            ),
+            plain_column("ok", BOOLEAN, |_| {}, &[0b101]),
+        ];
+        let (keys, records) = read(&file(3, &columns)).unwrap();
+        assert_eq!(keys, ["id", "name", "at", "price", "day", "ok"]);
+        assert_eq!(
+            Value::from(records.into_iter().map(Value::Object).collect::<Vec<_>>()),
+            json!([
+                {"id": 1, "name": "Bob", "at": "1970-01-01T00:00:00Z", "price": 123.45, "day": "1970-01-01", "ok": true},
+                {"id": -2, "name": null, "at": "2023-11-14T22:13:20.123Z", "price": -0.05, "day": "2024-04-01", "ok": false},
+                {"id": 3, "name": "Ann", "at": "1969-12-31T23:59:59.999999Z", "price": 0.0, "day": "1969-12-31", "ok": true},
+            ])
+        );
+    }
+
+    /// Compresses with Snappy as a single literal.
+    fn snappy_literal(data: &[u8]) -> Vec<u8> {
+        assert!((1..=60).contains(&data.len()));
+        let mut compressed = vec![data.len() as u8, (data.len() as u8 - 1) << 2];
+        compressed.extend_from_slice(data);
+        compressed
+    }
+
+    /// Page statistics as writers add them, which reading skips.
+    fn statistics(writer: &mut Writer, id: i16, min: &[u8], max: &[u8]) {
+        writer
+            .begin(id)
+            .int(3, 0)
+            .binary(5, max)
+            .binary(6, min)
+            .boolean(7, true)
+            .boolean(8, true)
+            .end();
+    }
+
+    #[test]
+    fn test_data_page_v2() {
+        // Laid out as pyarrow writes with data_page_version="2.0" and
+        // snappy: version 2 pages whose definition levels are left
+        // uncompressed ahead of the values, a dictionary page first, and
+        // statistics, encoding stats and key-value metadata throughout
+        let ids: Vec<u8> = [1i64, 2, 3, 4]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let ids_values = snappy_literal(&ids);
+        let mut id_pages = Vec::new();
+        let mut header = Writer::new();
+        header
+            .int(1, DATA_PAGE_V2)
+            .int(2, ids.len() as i64)
+            .int(3, ids_values.len() as i64)
+            .begin(8)
+            .int(1, 4)
+            .int(2, 0)
+            .int(3, 4)
+            .int(4, PLAIN)
+            .int(5, 0)
+            .int(6, 0);
+        statistics(&mut header, 8, &1i64.to_le_bytes(), &4i64.to_le_bytes());
+        header.end();
+        id_pages.extend(header.finish());
+        id_pages.extend(&ids_values);
+
+        // The dictionary "red", "blue", then red, null, blue, red: levels
+        // 1 0 1 1 and indexes 0 1 0 bit-packed at a width of 1
+        let dictionary = snappy_literal(b"\x03\x00\x00\x00red\x04\x00\x00\x00blue");
+        let mut header = Writer::new();
+        header
+            .int(1, DICTIONARY_PAGE)
+            .int(2, 15)
+            .int(3, dictionary.len() as i64)
+            .begin(7)
+            .int(1, 2)
+            .int(2, PLAIN)
+            .boolean(3, false)
+            .end();
+        let mut tag_pages = header.finish();
+        tag_pages.extend(&dictionary);
+        let dictionary_length = tag_pages.len();
+        let levels = [3, 0b1101];
+        let indexes = snappy_literal(&[1, 3, 0b010]);
+        let mut header = Writer::new();
+        header
+            .int(1, DATA_PAGE_V2)
+            .int(2, (levels.len() + 3) as i64)
+            .int(3, (levels.len() + indexes.len()) as i64)
+            .begin(8)
+            .int(1, 4)
+            .int(2, 1)
+            .int(3, 4)
+            .int(4, RLE_DICTIONARY)
+            .int(5, levels.len() as i64)
+            .int(6, 0);
+        statistics(&mut header, 8, b"blue", b"red");
+        header.end();
+        tag_pages.extend(header.finish());
+        tag_pages.extend(levels);
+        tag_pages.extend(&indexes);
+
+        let mut data = MAGIC.to_vec();
+        let id_offset = data.len() as i64;
+        data.extend(&id_pages);
+        let tag_offset = data.len() as i64;
+        data.extend(&tag_pages);
+
+        let mut writer = Writer::new();
+        writer.int(1, 2).list(2, 3);
+        writer.item().binary(4, b"schema").int(5, 2).end();
+        writer.item().int(1, INT64).int(3, 0).binary(4, b"id").end();
+        writer
+            .item()
+            .int(1, BYTE_ARRAY)
+            .int(3, 1)
+            .binary(4, b"tag")
+            .int(6, 0)
+            .begin(10)
+            .begin(1)
+            .end()
+            .end()
+            .end();
+        writer.int(3, 4).list(4, 1).item().list(1, 2);
+        for (name, physical, offset, pages, dictionary_offset, encodings) in [
+            ("id", INT64, id_offset, &id_pages, None, &[PLAIN, RLE][..]),
+            (
+                "tag",
+                BYTE_ARRAY,
+                tag_offset,
+                &tag_pages,
+                Some(tag_offset),
+                &[PLAIN, RLE, RLE_DICTIONARY][..],
+            ),
+        ] {
+            let data_offset = offset + dictionary_offset.map_or(0, |_| dictionary_length as i64);
+            writer
+                .item()
+                .int(2, offset)
+                .begin(3)
+                .int(1, physical)
+                .ints(2, encodings)
+                .binaries(3, &[name.as_bytes()])
+                .int(4, 1)
+                .int(5, 4)
+                .int(6, pages.len() as i64)
+                .int(7, pages.len() as i64)
+                .int(9, data_offset);
+            if let Some(dictionary_offset) = dictionary_offset {
+                writer.int(11, dictionary_offset);
+            }
+            statistics(&mut writer, 12, b"", b"");
+            writer
+                .list(13, 1)
+                .item()
+                .int(1, DATA_PAGE_V2)
+                .int(2, encodings[encodings.len() - 1])
+                .int(3, 1)
+                .end()
+                .end()
+                .end();
+        }
+        writer
+            .int(2, data.len() as i64)
+            .int(3, 4)
+            .int(4, 4)
+            .int(5, 0)
+            .end();
+        writer
+            .list(5, 1)
+            .item()
+            .binary(1, b"ARROW:schema")
+            .binary(2, b"/////7gAAAAQAAAAAAAKAA4ABgAFAAgACgAAAAABBAAQAAAA")
+            .end()
+            .binary(6, b"parquet-cpp-arrow version 15.0.2")
+            .list(7, 2);
+        writer.item().begin(1).end().end();
+        writer.item().begin(1).end().end();
+        let metadata = writer.finish();
+        data.extend(&metadata);
+        data.extend((metadata.len() as u32).to_le_bytes());
+        data.extend(MAGIC);
+
+        let (keys, records) = read(&data).unwrap();
+        assert_eq!(keys, ["id", "tag"]);
+        assert_eq!(
+            Value::from(records.into_iter().map(Value::Object).collect::<Vec<_>>()),
+            json!([
+                {"id": 1, "tag": "red"},
+                {"id": 2, "tag": null},
+                {"id": 3, "tag": "blue"},
+                {"id": 4, "tag": "red"},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_nested_schema() {
+        let mut writer = Writer::new();
+        writer.list(2, 3);
+        writer.item().binary(4, b"schema").int(5, 1).end();
+        writer.item().binary(4, b"address").int(5, 1).end();
+        writer.item().int(1, BYTE_ARRAY).binary(4, b"city").end();
+        let metadata = writer.finish();
+        let error = schema(&Compact::new(&metadata).structure().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("address is nested"));
+        assert!(read(b"PAR1 not parquet").is_err());
+    }
+
+    #[test]
+    fn test_rle_hybrid() {
+        // A run of four 5s, then a bit-packed group of 1..=8 at 4 bits
+        let data = [8, 5, 3, 0x21, 0x43, 0x65, 0x87];
+        assert_eq!(
+            rle_hybrid(&data, 4, 10).unwrap(),
+            [5, 5, 5, 5, 1, 2, 3, 4, 5, 6]
+        );
+        assert!(rle_hybrid(&data, 4, 13).is_err());
+    }
+
+    #[test]
+    fn test_delta_binary_packed() {
+        // The specification's example: 7, 5, 3, 1, 2, 3, 4, 5 is a first
+        // value of 7, a minimum delta of -2 and deltas 0 0 0 3 3 3 3 at 2 bits
+        // in the first of four miniblocks of 32
+        let data = [
+            128, 1, 4, 8, 14, 3, 2, 0, 0, 0, 0xc0, 0x3f, 0, 0, 0, 0, 0, 0,
+        ];
+        let mut at = 0;
+        assert_eq!(
+            delta_binary_packed(&data, &mut at).unwrap(),
+            [7, 5, 3, 1, 2, 3, 4, 5]
+        );
+        assert_eq!(at, data.len());
+    }
+
+    #[test]
+    fn test_snappy() {
+        // "abcd" then a copy of 8 bytes from 4 back, then "e"
+        assert_eq!(
+            snappy(&[13, 3 << 2, b'a', b'b', b'c', b'd', 4 << 2 | 1, 4, 0, b'e']).unwrap(),
+            b"abcdabcdabcde"
+        );
+        assert!(snappy(&[13, 3 << 2, b'a', b'b', b'c', b'd']).is_err());
+        assert!(snappy(&[4, 1, 9]).is_err());
+    }
+
+    #[test]
+    fn test_lz4() {
+        // "abcd" then a match of 8 from 4 back, then the literal "e"
+        let block = [0x44, b'a', b'b', b'c', b'd', 4, 0, 0x10, b'e'];
+        let mut output = Vec::new();
+        lz4_block(&block, &mut output).unwrap();
+        assert_eq!(output, b"abcdabcdabcde");
+
+        let mut framed = vec![0, 0, 0, 13, 0, 0, 0, block.len() as u8];
+        framed.extend_from_slice(&block);
+        assert_eq!(hadoop_lz4(&framed).unwrap(), b"abcdabcdabcde");
+        assert_eq!(hadoop_lz4(&block).unwrap(), b"abcdabcdabcde");
+    }
+
+    #[test]
+    fn test_convert() {
+        let column = |physical, logical| Column {
+            name: "c".to_string(),
+            physical,
+            type_length: 0,
+            optional: false,
+            logical,
+        };
+        let convert = |physical, logical, raw| column(physical, logical).convert(raw);
+        assert_eq!(
+            convert(INT64, Logical::Time(Unit::Micros), Raw::Int(45_296_500_000)),
+            json!("12:34:56.5")
+        );
+        assert_eq!(
+            convert(INT64, Logical::Timestamp(Unit::Nanos, false), Raw::Int(1)),
+            json!("1970-01-01T00:00:00.000000001")
+        );
+        assert_eq!(
+            convert(INT32, Logical::Unsigned, Raw::Int(-1)),
+            json!(4294967295u32)
+        );
+        // 2024-04-01T12:00:00 as nanoseconds into the day and a Julian day
+        let mut int96 = 43_200_000_000_000i64.to_le_bytes().to_vec();
+        int96.extend_from_slice(&2_460_402i32.to_le_bytes());
+        assert_eq!(
+            convert(INT96, Logical::None, Raw::Bytes(&int96)),
+            json!("2024-04-01T12:00:00Z")
+        );
+        assert_eq!(
+            convert(
+                FIXED_LEN_BYTE_ARRAY,
+                Logical::Decimal(3),
+                Raw::Bytes(&[0xff, 0x85])
+            ),
+            json!(-0.123)
+        );
+        assert_eq!(
+            convert(
+                FIXED_LEN_BYTE_ARRAY,
+                Logical::Float16,
+                Raw::Bytes(&[0x00, 0x3e])
+            ),
+            json!(1.5)
+        );
+        assert_eq!(
+            convert(FIXED_LEN_BYTE_ARRAY, Logical::Uuid, Raw::Bytes(&[0xab; 16])),
+            json!("abababab-abab-abab-abab-abababababab")
+        );
+        assert_eq!(
+            convert(BYTE_ARRAY, Logical::None, Raw::Bytes(b"\xff\x00ab")),
+            json!("/wBhYg==")
+        );
+        assert_eq!(
+            convert(FLOAT, Logical::None, raw(FLOAT, &[0xcd, 0xcc, 0xcc, 0x3d])),
+            json!(0.1)
+        );
+        assert_eq!(
+            convert(DOUBLE, Logical::None, Raw::Float(f64::NAN)),
+            json!(null)
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! `--partition-by`: writes one file per distinct value of a column, named
+//! Hive style, e.g. `country=JP.json`. The files are written on a pool of
+//! threads, so writing and compressing many files is not done one at a time.
+
+use crate::compression::Sink;
+use crate::error::Error;
+use crate::stats::{self, Stage};
+use crate::writer::RecordWriter;
+use crate::Record;
+use serde_json::Value;
+use std::collections::hash_map::{Entry, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, JoinHandle};
+
+/// Records queued for each writer thread before handing it more waits.
+const QUEUE_RECORDS: usize = 1024;
+
+/// The partition of records whose value is null, empty or missing, as Hive
+/// names it.
+pub const DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// The file name, without extension, of the partition holding `value`.
+pub fn file_stem(column: &str, value: Option<&Value>) -> String {
+    let value = match value {
+        None | Some(Value::Null) => DEFAULT_PARTITION.to_string(),
+        Some(Value::String(text)) if text.is_empty() => DEFAULT_PARTITION.to_string(),
+        Some(Value::String(text)) => escape(text),
+        Some(other) => escape(&other.to_string()),
+    };
+    format!("{}={}", escape(column), value)
+}
+
+/// Percent-encodes the characters Hive escapes in partition names, which
+/// covers path separators and characters that file systems reject.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii_control() || "\"#%'*/:=?\\{[]^".contains(c) {
+            escaped.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// How many partition files may be open at once: a quarter of the files
+/// the process may open, leaving the rest for the input, compressors and
+/// the spill.
+pub fn max_open() -> usize {
+    static MAX_OPEN: OnceLock<usize> = OnceLock::new();
+    *MAX_OPEN.get_or_init(|| {
+        #[cfg(unix)]
+        {
+            let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+            if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } == 0 {
+                return (limit.rlim_cur / 4).clamp(4, 1024) as usize;
+            }
+        }
+        256
+    })
+}
+
+/// Records of partitions that cannot be opened while `max_open` others are,
+/// kept in a temporary file as JSON Lines with their partition, to be
+/// written once those are finished.
+pub struct Spill {
+    file: BufWriter<File>,
+}
+
+impl Spill {
+    /// Creates the spill in `dir`, or the system temporary directory.
+    pub fn new(dir: Option<&str>) -> io::Result<Spill> {
+        let file = match dir {
+            Some(dir) => tempfile::tempfile_in(dir)?,
+            None => tempfile::tempfile()?,
+        };
+        Ok(Spill {
+            file: BufWriter::new(file),
+        })
+    }
+
+    pub fn push(&mut self, stem: &str, record: &Record) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.file, &(stem, record))?;
+        self.file.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Reads the records back in the order they were spilled.
+    pub fn records(self) -> Result<impl Iterator<Item = Result<(String, Record), Error>>, Error> {
+        let mut file = self.file.into_inner().map_err(|e| e.into_error())?;
+        file.rewind()?;
+        Ok(BufReader::new(file)
+            .lines()
+            .map(|line| Ok(serde_json::from_str(&line?)?)))
+    }
+}
+
+/// Opens the file of a partition, given its stem.
+pub type Open = Arc<dyn Fn(&str) -> Result<RecordWriter<Box<dyn Sink>>, Error> + Send + Sync>;
+
+/// Writes partition files on a pool of threads, each with its own files
+/// and buffers. A partition is always written by the thread it was first
+/// given to, so its records stay in order.
+pub struct Writers {
+    threads: Vec<WriterThread>,
+    /// The thread writing each partition opened so far
+    assigned: HashMap<String, usize>,
+    /// Set once every record is handed over, so the threads finish their
+    /// files rather than dropping them when the conversion failed
+    complete: Arc<AtomicBool>,
+}
+
+struct WriterThread {
+    sender: Option<SyncSender<(String, Record)>>,
+    handle: Option<JoinHandle<Result<usize, Error>>>,
+}
+
+impl Writers {
+    pub fn new(threads: usize, open: &Open) -> Writers {
+        let complete = Arc::new(AtomicBool::new(false));
+        let threads = (0..threads.max(1))
+            .map(|_| {
+                let (sender, receiver) = mpsc::sync_channel::<(String, Record)>(QUEUE_RECORDS);
+                let open = Arc::clone(open);
+                let complete = Arc::clone(&complete);
+                let handle = thread::spawn(move || {
+                    let mut files = HashMap::new();
+                    for (stem, record) in receiver {
+                        let writer = match files.entry(stem) {
+                            Entry::Occupied(entry) => entry.into_mut(),
+                            Entry::Vacant(entry) => {
+                                let writer = open(entry.key())?;
+                                entry.insert(writer)
+                            }
+                        };
+                        stats::timed(Stage::Serialize, || writer.write_record(&record))?;
+                    }
+                    if !complete.load(Ordering::Acquire) {
+                        return Ok(0);
+                    }
+                    let count = files.len();
+                    for (_, writer) in files {
+                        writer.finish()?.finish()?;
+                    }
+                    Ok(count)
+                });
+                WriterThread {
+                    sender: Some(sender),
+                    handle: Some(handle),
+                }
+            })
+            .collect();
+        Writers {
+            threads,
+            assigned: HashMap::new(),
+            complete,
+        }
+    }
+
+    /// The number of partitions opened so far.
+    pub fn len(&self) -> usize {
+        self.assigned.len()
+    }
+
+    pub fn contains(&self, stem: &str) -> bool {
+        self.assigned.contains_key(stem)
+    }
+
+    /// Hands a record to the thread writing its partition, giving a new
+    /// partition to the threads in turn.
+    pub fn push(&mut self, stem: String, record: Record) -> Result<(), Error> {
+        let next = self.assigned.len() % self.threads.len();
+        let index = *self.assigned.entry(stem.clone()).or_insert(next);
+        let thread = &mut self.threads[index];
+        let sender = thread
+            .sender
+            .as_ref()
+            .expect("senders are kept until finish");
+        if sender.send((stem, record)).is_ok() {
+            return Ok(());
+        }
+        // The thread only stops taking records when it fails
+        thread.sender = None;
+        match join(thread.handle.take().expect("a thread is joined once")) {
+            Err(e) => Err(e),
+            Ok(_) => unreachable!("a writer thread only returns once its records end"),
+        }
+    }
+
+    /// Finishes every partition file, returning the number written.
+    pub fn finish(mut self) -> Result<usize, Error> {
+        self.complete.store(true, Ordering::Release);
+        for thread in &mut self.threads {
+            thread.sender = None;
+        }
+        let mut files = 0;
+        let mut failure = None;
+        for thread in &mut self.threads {
+            match thread.handle.take().map(join) {
+                Some(Ok(count)) => files += count,
+                Some(Err(e)) => {
+                    failure.get_or_insert(e);
+                }
+                None => {}
+            }
+        }
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(files),
+        }
+    }
+}
+
+impl Drop for Writers {
+    /// Waits for the threads to drop their unfinished files.
+    fn drop(&mut self) {
+        for thread in &mut self.threads {
+            thread.sender = None;
+        }
+        for thread in &mut self.threads {
+            if let Some(handle) = thread.handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+/// Waits for a writer thread, passing on a panic in it.
+fn join(handle: JoinHandle<Result<usize, Error>>) -> Result<usize, Error> {
+    handle
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_stem() {
+        assert_eq!(file_stem("country", Some(&Value::from("JP"))), "country=JP");
+        assert_eq!(file_stem("year", Some(&Value::from(2024))), "year=2024");
+        assert_eq!(
+            file_stem("path", Some(&Value::from("a/b:c%"))),
+            "path=a%2Fb%3Ac%25"
+        );
+        assert_eq!(file_stem("a=b", Some(&Value::from("日本"))), "a%3Db=日本");
+        assert_eq!(
+            file_stem("country", Some(&Value::from(""))),
+            "country=__HIVE_DEFAULT_PARTITION__"
+        );
+        assert_eq!(
+            file_stem("country", None),
+            "country=__HIVE_DEFAULT_PARTITION__"
+        );
+    }
+
+    #[test]
+    fn test_spill() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Spill::new(Some(&dir.path().join("missing").to_string_lossy())).is_err());
+        let mut spill = Spill::new(Some(&dir.path().to_string_lossy())).unwrap();
+        let record: Record = serde_json::from_str(r#"{"b":"two\nlines","a":1.5}"#).unwrap();
+        spill.push("k=1", &record).unwrap();
+        spill.push("k=2", &Record::new()).unwrap();
+        let records: Vec<(String, Record)> = spill.records().unwrap().map(Result::unwrap).collect();
+        assert_eq!(
+            records,
+            [
+                ("k=1".to_string(), record),
+                ("k=2".to_string(), Record::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_writers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_path_buf();
+        let open: Open = Arc::new(move |stem: &str| {
+            let file = File::create(path.join(format!("{}.jsonl", stem)))?;
+            let sink: Box<dyn Sink> = Box::new(BufWriter::new(file));
+            Ok(RecordWriter::new(sink, crate::OutputFormat::Jsonl, false))
+        });
+        let mut writers = Writers::new(3, &open);
+        for i in 0..100 {
+            let record: Record = serde_json::from_value(serde_json::json!({"i": i})).unwrap();
+            writers.push(format!("k={}", i % 5), record).unwrap();
+        }
+        assert_eq!(writers.len(), 5);
+        assert!(writers.contains("k=4"));
+        assert_eq!(writers.finish().unwrap(), 5);
+        let lines = std::fs::read_to_string(dir.path().join("k=2.jsonl")).unwrap();
+        let expected: Vec<String> = (0..100)
+            .filter(|i| i % 5 == 2)
+            .map(|i| format!(r#"{{"i":{}}}"#, i))
+            .collect();
+        assert_eq!(lines.lines().collect::<Vec<_>>(), expected);
+
+        let failing: Open = Arc::new(|_: &str| Err(Error::Io(io::Error::other("disk full"))));
+        let mut writers = Writers::new(1, &failing);
+        let error = (0..10_000)
+            .find_map(|_| writers.push("k=1".to_string(), Record::new()).err())
+            .unwrap_or_else(|| writers.finish().unwrap_err());
+        assert!(matches!(error, Error::Io(_)));
+    }
+}
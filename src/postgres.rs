@@ -0,0 +1,157 @@
+//! `--format postgres`: a script for `psql` that creates a table typed from
+//! the records and loads them with `COPY ... FROM stdin`.
+
+use crate::columns;
+use crate::error::Error;
+use crate::Record;
+use serde_json::Value;
+use std::io::Write;
+
+/// The column type for each type `--with-columns-meta` reports. Columns with
+/// only nulls, strings or mixed values are text.
+fn column_type(kind: &str) -> &'static str {
+    match kind {
+        "integer" => "bigint",
+        "number" => "double precision",
+        "boolean" => "boolean",
+        "array" | "object" => "jsonb",
+        _ => "text",
+    }
+}
+
+/// Quotes an SQL identifier.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Writes the `CREATE TABLE` statement for `table`, with a column per key in
+/// the order first seen, then a `COPY` of every record.
+pub fn write_script<W: Write>(records: &[Record], table: &str, out: &mut W) -> Result<(), Error> {
+    let columns = columns::describe(records);
+    let types: Vec<&str> = columns.iter().map(|c| column_type(c.kind)).collect();
+    let table = quote(table);
+
+    writeln!(out, "CREATE TABLE {} (", table)?;
+    for (i, (column, kind)) in columns.iter().zip(&types).enumerate() {
+        let separator = if i + 1 < columns.len() { "," } else { "" };
+        writeln!(out, "    {} {}{}", quote(&column.name), kind, separator)?;
+    }
+    writeln!(out, ");")?;
+
+    let names: Vec<String> = columns.iter().map(|c| quote(&c.name)).collect();
+    if names.is_empty() {
+        writeln!(out, "COPY {} FROM stdin;", table)?;
+    } else {
+        writeln!(out, "COPY {} ({}) FROM stdin;", table, names.join(", "))?;
+    }
+    let mut line = String::new();
+    for record in records {
+        line.clear();
+        for (i, (column, kind)) in columns.iter().zip(&types).enumerate() {
+            if i > 0 {
+                line.push('\t');
+            }
+            copy_value(
+                record.get(&column.name).unwrap_or(&Value::Null),
+                kind,
+                &mut line,
+            );
+        }
+        line.push('\n');
+        out.write_all(line.as_bytes())?;
+    }
+    writeln!(out, "\\.")?;
+    Ok(())
+}
+
+/// Appends a value in COPY's text format: `\N` for NULL, which blank values
+/// are outside text columns, and the JSON text of values that are not strings.
+fn copy_value(value: &Value, kind: &str, line: &mut String) {
+    match value {
+        Value::Null => line.push_str("\\N"),
+        Value::String(text) if text.is_empty() && kind != "text" => line.push_str("\\N"),
+        Value::String(text) => escape(text, line),
+        other => escape(&other.to_string(), line),
+    }
+}
+
+/// Escapes the characters COPY's text format gives meaning to.
+fn escape(text: &str, line: &mut String) {
+    for c in text.chars() {
+        match c {
+            '\\' => line.push_str("\\\\"),
+            '\t' => line.push_str("\\t"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            c => line.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn record(value: Value) -> Record {
+        let Value::Object(fields) = value else {
+            panic!("records are objects");
+        };
+        fields
+            .into_iter()
+            .map(|(key, value)| (Arc::from(key), value))
+            .collect()
+    }
+
+    #[test]
+    fn test_copy_value() {
+        let copied = |value: Value, kind: &str| {
+            let mut line = String::new();
+            copy_value(&value, kind, &mut line);
+            line
+        };
+        assert_eq!(copied(json!(null), "text"), "\\N");
+        assert_eq!(copied(json!(""), "text"), "");
+        assert_eq!(copied(json!(""), "bigint"), "\\N");
+        assert_eq!(copied(json!("a\tb\nc\\d\r"), "text"), "a\\tb\\nc\\\\d\\r");
+        assert_eq!(copied(json!(1.5), "double precision"), "1.5");
+        assert_eq!(copied(json!(true), "boolean"), "true");
+        assert_eq!(copied(json!({"k": "a\tb"}), "jsonb"), "{\"k\":\"a\\\\tb\"}");
+    }
+
+    #[test]
+    fn test_write_script() {
+        let records = vec![
+            record(json!({"id": 1, "name": "alice", "score": 1.5, "ok": true, "tags": ["x"]})),
+            record(json!({"id": 2, "name": "", "score": 2, "ok": false, "note": null})),
+        ];
+        let mut out = Vec::new();
+        write_script(&records, "my \"table\"", &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "CREATE TABLE \"my \"\"table\"\"\" (\n\
+             \x20   \"id\" bigint,\n\
+             \x20   \"name\" text,\n\
+             \x20   \"score\" double precision,\n\
+             \x20   \"ok\" boolean,\n\
+             \x20   \"tags\" jsonb,\n\
+             \x20   \"note\" text\n\
+             );\n\
+             COPY \"my \"\"table\"\"\" (\"id\", \"name\", \"score\", \"ok\", \"tags\", \"note\") FROM stdin;\n\
+             1\talice\t1.5\ttrue\t[\"x\"]\t\\N\n\
+             2\t\t2\tfalse\t\\N\t\\N\n\
+             \\.\n"
+        );
+    }
+
+    #[test]
+    fn test_no_records() {
+        let mut out = Vec::new();
+        write_script(&[], "records", &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "CREATE TABLE \"records\" (\n);\nCOPY \"records\" FROM stdin;\n\\.\n"
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! `--preflight-space`: estimates the size of the output from a sample of
+//! records and checks that its filesystem has room before anything is written.
+
+use crate::error::Error;
+use crate::progress::human_bytes;
+use std::io;
+use std::path::Path;
+
+/// Number of records held back to estimate the output size.
+pub const SAMPLE_RECORDS: usize = 1000;
+
+/// Headroom over the estimate, since it is extrapolated from the sample.
+const MARGIN: f64 = 1.2;
+
+/// Scales the output size of a sample by the share of the input it took, as
+/// counted by `stats::CountingReader`. Read-ahead buffering makes `input_read`
+/// run slightly ahead of the sample, which `MARGIN` allows for.
+pub fn estimate(sample_bytes: u64, input_read: u64, input_size: u64) -> u64 {
+    if input_read == 0 || input_read >= input_size {
+        return sample_bytes;
+    }
+    (sample_bytes as f64 * input_size as f64 / input_read as f64) as u64
+}
+
+/// Fails if the filesystem holding `output_file` has less free space than the
+/// estimated output size plus a margin.
+pub fn check(output_file: &str, estimated: u64) -> Result<(), Error> {
+    let required = (estimated as f64 * MARGIN) as u64;
+    let available = available_space(Path::new(output_file))?;
+    if required > available {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!(
+                "Not enough free space for {}: about {} needed, {} available",
+                output_file,
+                human_bytes(required),
+                human_bytes(available)
+            ),
+        )));
+    }
+    Ok(())
+}
+
+/// Free space for unprivileged users on the output's filesystem, counting the
+/// existing output file, which is truncated when it is overwritten.
+#[cfg(unix)]
+fn available_space(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let directory = CString::new(directory.as_os_str().as_bytes())?;
+    // SAFETY: statvfs only writes to the zeroed struct we pass it
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(directory.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let free = stat.f_bavail as u64 * stat.f_frsize as u64;
+    let existing = std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+    Ok(free + existing)
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--preflight-space is only supported on Unix",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        assert_eq!(estimate(300, 100, 1000), 3000);
+        assert_eq!(estimate(300, 1000, 1000), 300);
+        assert_eq!(estimate(300, 0, 1000), 300);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.json");
+        let output = output.to_string_lossy();
+        check(&output, 1).unwrap();
+        let error = check(&output, u64::MAX / 2).unwrap_err();
+        assert_eq!(error.exit_code(), 3);
+        assert!(error.to_string().contains("Not enough free space"));
+    }
+}
@@ -0,0 +1,134 @@
+//! A progress bar on stderr for `--progress`, driven by bytes read from the input file.
+
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+const BAR_WIDTH: usize = 30;
+
+/// Wraps the input file, counting bytes and lines as they are read and
+/// redrawing the bar at most every `REDRAW_INTERVAL`. Lines stand in for
+/// rows, so rows with quoted line breaks are counted more than once.
+pub struct ProgressReader<R> {
+    inner: R,
+    total: u64,
+    read: u64,
+    lines: u64,
+    started: Instant,
+    last_draw: Option<Instant>,
+    done: bool,
+}
+
+impl<R: Read> ProgressReader<R> {
+    pub fn new(inner: R, total: u64) -> Self {
+        ProgressReader {
+            inner,
+            total,
+            read: 0,
+            lines: 0,
+            started: Instant::now(),
+            last_draw: None,
+            done: false,
+        }
+    }
+
+    fn draw(&mut self) {
+        let line = render(self.read, self.total, self.lines, self.started.elapsed());
+        // Progress is best effort; a failed write must not fail the conversion
+        let _ = write!(io::stderr(), "\r{}", line);
+        self.last_draw = Some(Instant::now());
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        self.lines += buf[..n].iter().filter(|&&b| b == b'\n').count() as u64;
+        if n == 0 && !self.done {
+            self.draw();
+            eprintln!();
+            self.done = true;
+        } else if self
+            .last_draw
+            .is_none_or(|last| last.elapsed() >= REDRAW_INTERVAL)
+        {
+            self.draw();
+        }
+        Ok(n)
+    }
+}
+
+impl<R> Drop for ProgressReader<R> {
+    /// Ends the bar's line if the conversion stopped before the end of the input.
+    fn drop(&mut self) {
+        if self.last_draw.is_some() && !self.done {
+            eprintln!();
+        }
+    }
+}
+
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Formats the bar, e.g. `[#######-----------------------]  25% 1.0 MiB/4.0 MiB 5000 rows/s`.
+fn render(read: u64, total: u64, lines: u64, elapsed: Duration) -> String {
+    let fraction = if total == 0 {
+        1.0
+    } else {
+        (read as f64 / total as f64).min(1.0)
+    };
+    let filled = (fraction * BAR_WIDTH as f64) as usize;
+    let seconds = elapsed.as_secs_f64();
+    let rate = if seconds > 0.0 {
+        (lines as f64 / seconds) as u64
+    } else {
+        0
+    };
+    format!(
+        "[{}{}] {:>3}% {}/{} {} rows/s",
+        "#".repeat(filled),
+        "-".repeat(BAR_WIDTH - filled),
+        (fraction * 100.0) as u32,
+        human_bytes(read),
+        human_bytes(total),
+        rate
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render(1024 * 1024, 4 * 1024 * 1024, 10_000, Duration::from_secs(2)),
+            "[#######-----------------------]  25% 1.0 MiB/4.0 MiB 5000 rows/s"
+        );
+        assert_eq!(
+            render(0, 0, 0, Duration::ZERO),
+            "[##############################] 100% 0 B/0 B 0 rows/s"
+        );
+    }
+
+    #[test]
+    fn test_counts_bytes_and_lines() {
+        let mut reader = ProgressReader::new("a,b\n1,2\n".as_bytes(), 8);
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+        assert_eq!((reader.read, reader.lines, reader.done), (8, 2, true));
+    }
+}
@@ -0,0 +1,336 @@
+//! `--repair-quotes`: rewrites records with common quoting damage so they
+//! parse, reporting every repair with its line number.
+
+use crate::log;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read};
+
+/// Lines a quoted field may span before its opening quote is taken to be
+/// unbalanced.
+const MAX_QUOTED_LINES: usize = 100;
+
+/// A kind of quoting damage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Repair {
+    /// `a,5" tall` becomes `a,"5"" tall"`
+    QuoteInUnquotedField,
+    /// `"say "hi" now"` becomes `"say ""hi"" now"`
+    QuoteInQuotedField,
+    /// A quoted field still open at the end of input, after
+    /// `MAX_QUOTED_LINES` lines, or closed only past a stray quote on a
+    /// later line, is closed at the end of its first line
+    UnclosedQuote,
+    /// Or at the next comma, when that gives the record as many fields as
+    /// the header: `"open,2` becomes `open,2`
+    QuoteClosedAtComma,
+}
+
+impl Repair {
+    fn describe(&self) -> &'static str {
+        match self {
+            Repair::QuoteInUnquotedField => "quoted a field containing a stray quote",
+            Repair::QuoteInQuotedField => "doubled a stray quote inside a quoted field",
+            Repair::UnclosedQuote => "closed a quote left open at the end of the line",
+            Repair::QuoteClosedAtComma => "closed a quote left open at the next comma",
+        }
+    }
+}
+
+/// A record parsed leniently, or `None` if a quoted field is still open.
+struct Parsed {
+    fields: Vec<Vec<u8>>,
+    terminator: Vec<u8>,
+    repairs: Vec<Repair>,
+}
+
+/// Where a quoted field that is still open gets closed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Close {
+    /// Nowhere: it goes on over line breaks
+    Never,
+    /// At the line break
+    AtLineEnd,
+    /// At the next comma, or else at the line break
+    AtComma,
+}
+
+/// Parses one record from `text`, closing quoted fields still open as
+/// `close` says.
+fn parse(text: &[u8], close: Close) -> Option<Parsed> {
+    let mut parsed = Parsed {
+        fields: Vec::new(),
+        terminator: Vec::new(),
+        repairs: Vec::new(),
+    };
+    let mut field = Vec::new();
+    let (mut field_start, mut in_quotes) = (true, false);
+    let mut i = 0;
+    while i < text.len() {
+        let byte = text[i];
+        if in_quotes {
+            match (byte, text.get(i + 1)) {
+                (b'"', Some(b'"')) => {
+                    field.push(b'"');
+                    i += 1;
+                }
+                (b'"', None | Some(b',' | b'\n' | b'\r')) => in_quotes = false,
+                (b'"', _) => {
+                    parsed.repairs.push(Repair::QuoteInQuotedField);
+                    field.push(b'"');
+                }
+                (b',', _) if close == Close::AtComma => {
+                    parsed.repairs.push(Repair::QuoteClosedAtComma);
+                    in_quotes = false;
+                    continue;
+                }
+                (b'\n' | b'\r', _) if close != Close::Never => {
+                    parsed.repairs.push(Repair::UnclosedQuote);
+                    in_quotes = false;
+                    continue;
+                }
+                _ => field.push(byte),
+            }
+            i += 1;
+            continue;
+        }
+        match byte {
+            b',' => {
+                parsed.fields.push(std::mem::take(&mut field));
+                field_start = true;
+                i += 1;
+                continue;
+            }
+            b'\n' | b'\r' => {
+                parsed.terminator = text[i..].to_vec();
+                break;
+            }
+            b'"' if field_start => in_quotes = true,
+            b'"' => {
+                parsed.repairs.push(Repair::QuoteInUnquotedField);
+                field.push(byte);
+            }
+            _ => field.push(byte),
+        }
+        field_start = false;
+        i += 1;
+    }
+    if in_quotes {
+        if close == Close::Never {
+            return None;
+        }
+        parsed.repairs.push(Repair::UnclosedQuote);
+    }
+    parsed.fields.push(field);
+    Some(parsed)
+}
+
+/// Writes a record as well-formed CSV, quoting only the fields that need it.
+fn write_record(parsed: &Parsed, output: &mut Vec<u8>) {
+    for (i, field) in parsed.fields.iter().enumerate() {
+        if i > 0 {
+            output.push(b',');
+        }
+        if field
+            .iter()
+            .any(|b| matches!(b, b',' | b'"' | b'\n' | b'\r'))
+        {
+            output.push(b'"');
+            for &byte in field {
+                if byte == b'"' {
+                    output.push(b'"');
+                }
+                output.push(byte);
+            }
+            output.push(b'"');
+        } else {
+            output.extend_from_slice(field);
+        }
+    }
+    output.extend_from_slice(&parsed.terminator);
+}
+
+/// Repairs quoting record by record as the input is read. Records without
+/// damage pass through byte for byte.
+pub struct QuoteRepairer<R> {
+    inner: R,
+    comment: Option<u8>,
+    quiet: bool,
+    /// Lines read ahead while looking for the end of a quoted field
+    lookahead: VecDeque<Vec<u8>>,
+    /// Line number of the next line to read
+    line: u64,
+    /// Fields in the header, the first record
+    width: Option<usize>,
+    output: Vec<u8>,
+    position: usize,
+    repaired: usize,
+}
+
+impl<R: BufRead> QuoteRepairer<R> {
+    pub fn new(inner: R, comment: Option<u8>, quiet: bool) -> Self {
+        QuoteRepairer {
+            inner,
+            comment,
+            quiet,
+            lookahead: VecDeque::new(),
+            line: 1,
+            width: None,
+            output: Vec::new(),
+            position: 0,
+            repaired: 0,
+        }
+    }
+
+    fn next_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if let Some(line) = self.lookahead.pop_front() {
+            return Ok(Some(line));
+        }
+        let mut line = Vec::new();
+        if self.inner.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line))
+    }
+
+    /// Gives the lines after the first back to be read again.
+    fn give_back(&mut self, lines: &mut Vec<Vec<u8>>) {
+        for later in lines.drain(1..).rev() {
+            self.lookahead.push_front(later);
+        }
+    }
+
+    /// Reads the next record into `output`, repairing it if needed.
+    fn next_record(&mut self) -> io::Result<bool> {
+        self.output.clear();
+        self.position = 0;
+        let Some(first) = self.next_line()? else {
+            log::info!("Made {} quoting repairs", self.repaired);
+            return Ok(false);
+        };
+        let line = self.line;
+        if self.comment.is_some() && first.first() == self.comment.as_ref() {
+            self.line += 1;
+            self.output = first;
+            return Ok(true);
+        }
+
+        let mut lines = vec![first];
+        let mut text = lines[0].clone();
+        let mut parsed = loop {
+            match parse(&text, Close::Never) {
+                // A quote that only closes by way of a stray quote on a later
+                // line was more likely never closed
+                Some(parsed)
+                    if lines.len() == 1
+                        || !parsed.repairs.contains(&Repair::QuoteInQuotedField) =>
+                {
+                    break parsed
+                }
+                Some(_) => {}
+                None if lines.len() < MAX_QUOTED_LINES => {
+                    if let Some(next) = self.next_line()? {
+                        text.extend_from_slice(&next);
+                        lines.push(next);
+                        continue;
+                    }
+                }
+                None => {}
+            }
+            self.give_back(&mut lines);
+            text = lines[0].clone();
+            break parse(&text, Close::AtLineEnd).expect("quotes are closed at the line end");
+        };
+        // A repair that leaves the record short of the header's fields is
+        // likely wrong, where closing the quote at the next comma may not be
+        if let Some(width) = self.width {
+            if !parsed.repairs.is_empty() && parsed.fields.len() != width {
+                let closed = parse(&lines[0], Close::AtComma).expect("quotes are closed");
+                if closed.fields.len() == width {
+                    self.give_back(&mut lines);
+                    text = lines[0].clone();
+                    parsed = closed;
+                }
+            }
+        }
+        self.width.get_or_insert(parsed.fields.len());
+        self.line += lines.len() as u64;
+
+        if parsed.repairs.is_empty() {
+            self.output = text;
+        } else {
+            self.repaired += parsed.repairs.len();
+            if !self.quiet {
+                for repair in &parsed.repairs {
+                    eprintln!("Repaired line {}: {}", line, repair.describe());
+                }
+            }
+            write_record(&parsed, &mut self.output);
+        }
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Read for QuoteRepairer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.output.len() && !self.next_record()? {
+            return Ok(0);
+        }
+        let available = &self.output[self.position..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repair(input: &str) -> (String, usize) {
+        let mut repairer = QuoteRepairer::new(input.as_bytes(), Some(b'#'), true);
+        let mut output = String::new();
+        repairer.read_to_string(&mut output).unwrap();
+        (output, repairer.repaired)
+    }
+
+    #[test]
+    fn test_intact_input_passes_through() {
+        let input = "a,b\r\n\"x, \"\"y\"\"\",\"two\nlines\"\n# a \"comment\nplain,row";
+        assert_eq!(repair(input), (input.to_string(), 0));
+    }
+
+    #[test]
+    fn test_repairs() {
+        assert_eq!(
+            repair("a,b\n1,5\" tall\n"),
+            ("a,b\n1,\"5\"\" tall\"\n".to_string(), 1)
+        );
+        assert_eq!(
+            repair("a,b\n\"say \"hi\" now\",2\n"),
+            ("a,b\n\"say \"\"hi\"\" now\",2\n".to_string(), 2)
+        );
+        // The unclosed quote would otherwise swallow the rest of the file
+        assert_eq!(
+            repair("a,b\n\"open,1\n2,3\n4,5\n"),
+            ("a,b\nopen,1\n2,3\n4,5\n".to_string(), 1)
+        );
+        assert_eq!(
+            repair("a,b\n\"open,1\n2,\"x\"\n"),
+            ("a,b\nopen,1\n2,\"x\"\n".to_string(), 1)
+        );
+        assert_eq!(
+            repair("a,b\n\"x\"y,2\n\"he said \"hi\"\",3\n4,5\n"),
+            (
+                "a,b\n\"x\"\"y\",2\n\"he said \"\"hi\"\"\",3\n4,5\n".to_string(),
+                4
+            )
+        );
+        // Closing at the comma would not give the header's width
+        assert_eq!(
+            repair("a\n\"open,1\n2\n"),
+            ("a\n\"open,1\"\n2\n".to_string(), 1)
+        );
+        assert_eq!(repair("a\n\"open"), ("a\nopen".to_string(), 1));
+    }
+}
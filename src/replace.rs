@@ -0,0 +1,240 @@
+//! Output files are written under a temporary name next to the output and
+//! renamed over it once complete, so a conversion that fails midway leaves
+//! an existing output as it was rather than truncated. A file appended to
+//! is put back as it was instead.
+
+use crate::compression::Sink;
+use crate::error::Error;
+use crate::log;
+use std::fs::{self, File};
+use std::io::{self, Seek, Write};
+use std::path::{Path, PathBuf};
+use tempfile::TempPath;
+
+/// A temporary file that takes the place of `path` when committed, and is
+/// deleted if it is dropped first.
+pub struct Replacement {
+    temp: TempPath,
+    path: PathBuf,
+    backup: bool,
+}
+
+/// Creates the file to write `path` through. Devices and pipes such as
+/// `/dev/null` cannot be replaced, so they are opened as they are, as are
+/// read-only files, which are not ours to replace.
+pub fn create(path: &str, backup: bool) -> Result<(File, Option<Replacement>), Error> {
+    let existing = match fs::metadata(path) {
+        Ok(metadata) if !metadata.is_file() || metadata.permissions().readonly() => {
+            if backup {
+                back_up(path)?;
+            }
+            return Ok((File::create(path)?, None));
+        }
+        Ok(metadata) => Some(metadata),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    // A symbolic link keeps pointing at the file it names
+    let path = match &existing {
+        Some(_) => fs::canonicalize(path)?,
+        None => PathBuf::from(path),
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(".ctj-").suffix(".tmp");
+    // Made like a new file, or with the permissions of the one it replaces
+    let permissions = match existing {
+        Some(metadata) => Some(metadata.permissions()),
+        None => default_permissions(),
+    };
+    if let Some(permissions) = permissions {
+        builder.permissions(permissions);
+    }
+    let (file, temp) = builder.tempfile_in(dir)?.into_parts();
+    Ok((file, Some(Replacement { temp, path, backup })))
+}
+
+#[cfg(unix)]
+fn default_permissions() -> Option<fs::Permissions> {
+    use std::os::unix::fs::PermissionsExt;
+    // Narrowed by the umask, as for any file created
+    Some(fs::Permissions::from_mode(0o666))
+}
+
+#[cfg(not(unix))]
+fn default_permissions() -> Option<fs::Permissions> {
+    None
+}
+
+impl Replacement {
+    /// Where the file is written until it is committed.
+    pub fn temp_path(&self) -> &Path {
+        &self.temp
+    }
+
+    /// Moves the written file into place, first moving the existing one
+    /// aside for `--backup`.
+    pub fn commit(self) -> io::Result<()> {
+        let path = self.path.to_string_lossy();
+        if self.backup {
+            back_up(&path)?;
+        }
+        self.temp.persist(&self.path).map_err(|e| e.error)
+    }
+}
+
+/// Renames an existing file to `<path>.bak` for `--backup`, replacing any
+/// earlier backup.
+pub fn back_up(path: &str) -> io::Result<()> {
+    let backup = format!("{}.bak", path);
+    match fs::rename(path, &backup) {
+        Ok(()) => {
+            log::info!("Moved the existing {} to {}", path, backup);
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// An output sink whose file replaces the output when it is finished.
+pub struct Replacing {
+    inner: Box<dyn Sink>,
+    replacement: Replacement,
+}
+
+impl Replacing {
+    pub fn new(inner: Box<dyn Sink>, replacement: Replacement) -> Self {
+        Replacing { inner, replacement }
+    }
+}
+
+impl Write for Replacing {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Sink for Replacing {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let Replacing { inner, replacement } = *self;
+        inner.finish()?;
+        replacement.commit()
+    }
+}
+
+/// An output sink appending to an existing file, which is put back as it
+/// was unless the sink is finished: cut back to its original content, with
+/// the end `--append` removed, such as a closing bracket, written again.
+pub struct Restoring {
+    inner: Option<Box<dyn Sink>>,
+    file: File,
+    path: PathBuf,
+    /// Where appending started, and what was after it
+    offset: u64,
+    tail: Vec<u8>,
+    /// Whether the file was created for appending, so it is removed
+    created: bool,
+}
+
+impl Restoring {
+    /// Wraps `inner`, which writes to `file` from `offset`. `file` is
+    /// another handle to the same file.
+    pub fn new(
+        inner: Box<dyn Sink>,
+        file: File,
+        path: &str,
+        offset: u64,
+        tail: Vec<u8>,
+        created: bool,
+    ) -> Self {
+        Restoring {
+            inner: Some(inner),
+            file,
+            path: PathBuf::from(path),
+            offset,
+            tail,
+            created,
+        }
+    }
+
+    fn restore(&mut self) {
+        let restored = if self.created {
+            fs::remove_file(&self.path)
+        } else {
+            self.file
+                .set_len(self.offset)
+                .and_then(|()| self.file.seek(io::SeekFrom::Start(self.offset)))
+                .and_then(|_| self.file.write_all(&self.tail))
+        };
+        match restored {
+            Ok(()) => log::info!("Restored {} as it was", self.path.display()),
+            Err(e) => eprintln!("Warning: could not restore {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+impl Write for Restoring {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.as_mut().expect("not finished").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.as_mut().expect("not finished").flush()
+    }
+}
+
+impl Sink for Restoring {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        let inner = self.inner.take().expect("finished once");
+        let finished = inner.finish();
+        if finished.is_err() {
+            self.restore();
+        }
+        finished
+    }
+}
+
+impl Drop for Restoring {
+    fn drop(&mut self) {
+        // Dropped unfinished: the buffer is flushed first, so nothing it
+        // held lands after the restored end
+        if let Some(inner) = self.inner.take() {
+            drop(inner);
+            self.restore();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replaced_only_when_finished() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.json");
+        let path = path.to_str().unwrap();
+        fs::write(path, "[1]").unwrap();
+
+        let (mut file, replacement) = create(path, false).unwrap();
+        file.write_all(b"[2").unwrap();
+        drop((file, replacement));
+        assert_eq!(fs::read_to_string(path).unwrap(), "[1]");
+
+        let (mut file, replacement) = create(path, true).unwrap();
+        file.write_all(b"[3]").unwrap();
+        replacement.unwrap().commit().unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "[3]");
+        assert_eq!(fs::read_to_string(format!("{}.bak", path)).unwrap(), "[1]");
+        // Only the output and its backup are left
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
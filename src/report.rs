@@ -0,0 +1,76 @@
+//! `--report`: a JSON summary of the checks a conversion ran and whether it
+//! passed, for a CI data-quality gate to read.
+
+use crate::error::Error;
+use crate::warnings::Warnings;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Mutex;
+
+/// Malformed rows quoted in the report; the rest are only counted.
+pub const MAX_EXAMPLES: usize = 5;
+
+/// The checks run so far, in the order they finished.
+static CHECKS: Mutex<Vec<Check>> = Mutex::new(Vec::new());
+
+/// The outcome of one check, with details such as the expected and actual
+/// values.
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    #[serde(flatten)]
+    pub details: Map<String, Value>,
+}
+
+/// Notes the outcome of a check. `details` is a JSON object.
+pub fn record(name: &'static str, passed: bool, details: Value) {
+    let Value::Object(details) = details else {
+        panic!("check details are an object");
+    };
+    CHECKS.lock().expect("check list poisoned").push(Check {
+        name,
+        passed,
+        details,
+    });
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    passed: bool,
+    exit_code: i32,
+    error: Option<String>,
+    records: Option<usize>,
+    checks: &'a [Check],
+}
+
+/// Writes the report for a finished conversion: the records written, or the
+/// error that stopped it, and every check recorded, with the warning counts
+/// last.
+pub fn write(path: &str, result: Result<usize, &Error>, warnings: &Warnings) -> Result<(), Error> {
+    let mut checks = std::mem::take(&mut *CHECKS.lock().expect("check list poisoned"));
+    let counts: Map<String, Value> = warnings
+        .counts()
+        .map(|(class, count)| (class.name().to_string(), Value::from(count)))
+        .collect();
+    checks.push(Check {
+        name: "warnings",
+        passed: !(warnings.is_fatal() && counts.values().any(|count| count != 0)),
+        details: Map::from_iter([("counts".to_string(), Value::Object(counts))]),
+    });
+
+    let report = Report {
+        passed: result.is_ok() && checks.iter().all(|check| check.passed),
+        exit_code: result.as_ref().map_or_else(|e| e.exit_code(), |_| 0),
+        error: result.as_ref().err().map(|e| e.to_string()),
+        records: result.ok(),
+        checks: &checks,
+    };
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, &report)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,181 @@
+//! `--strict-rfc4180`: rejects CSV that the lenient parser would accept but
+//! that breaks RFC 4180, naming the line, column and field of the problem.
+
+use crate::error::Malformed;
+use std::io::{self, Read};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// At the start of a record, where a comment line may begin
+    RecordStart,
+    FieldStart,
+    Unquoted,
+    Quoted,
+    /// After a quote inside a quoted field: either the closing quote or the
+    /// first half of an escaped `""`
+    QuoteInQuoted,
+    /// After a carriage return, which must begin a CRLF line break
+    CarriageReturn,
+    Comment,
+}
+
+/// Checks the input against RFC 4180 as it is read. Line feeds alone are
+/// accepted as line breaks, as nearly every producer writes them.
+pub struct Validator<R> {
+    inner: R,
+    comment: Option<u8>,
+    state: State,
+    line: u64,
+    column: u64,
+    field: u64,
+    /// Where the quoted field being read opened
+    quote_at: (u64, u64),
+}
+
+impl<R: Read> Validator<R> {
+    pub fn new(inner: R, comment: Option<u8>) -> Self {
+        Validator {
+            inner,
+            comment,
+            state: State::RecordStart,
+            line: 1,
+            column: 0,
+            field: 1,
+            quote_at: (1, 0),
+        }
+    }
+
+    fn violation(&self, problem: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            Malformed(format!(
+                "RFC 4180 violation at line {}, column {} (field {}): {}",
+                self.line, self.column, self.field, problem
+            )),
+        )
+    }
+
+    fn check(&mut self, byte: u8) -> io::Result<()> {
+        self.column += 1;
+        self.state = match (self.state, byte) {
+            (State::RecordStart, byte) if Some(byte) == self.comment => State::Comment,
+            (State::Comment, b'\n') => State::RecordStart,
+            (State::Comment, _) => State::Comment,
+            (State::Quoted, b'"') => State::QuoteInQuoted,
+            (State::Quoted, _) => State::Quoted,
+            (State::QuoteInQuoted, b'"') => State::Quoted,
+            (State::CarriageReturn, b'\n') => State::RecordStart,
+            (State::CarriageReturn, _) => {
+                self.column -= 1;
+                return Err(self.violation("carriage return without a line feed"));
+            }
+            (State::RecordStart | State::FieldStart, b'"') => {
+                self.quote_at = (self.line, self.column);
+                State::Quoted
+            }
+            (State::Unquoted, b'"') => {
+                return Err(self.violation(
+                    "quote inside an unquoted field; quote the field and double the quote",
+                ))
+            }
+            (_, b',') => {
+                self.field += 1;
+                State::FieldStart
+            }
+            (_, b'\r') => State::CarriageReturn,
+            (_, b'\n') => State::RecordStart,
+            (State::QuoteInQuoted, byte) => {
+                return Err(self.violation(&format!(
+                    "{} after the closing quote of a field",
+                    describe_byte(byte)
+                )))
+            }
+            _ => State::Unquoted,
+        };
+        if byte == b'\n' {
+            self.line += 1;
+            self.column = 0;
+            if self.state == State::RecordStart {
+                self.field = 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.state {
+            State::Quoted => {
+                (self.line, self.column) = self.quote_at;
+                Err(self.violation("quoted field is not closed before end of input"))
+            }
+            State::CarriageReturn => Err(self.violation("carriage return without a line feed")),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn describe_byte(byte: u8) -> String {
+    if byte.is_ascii_graphic() {
+        format!("{:?}", byte as char)
+    } else {
+        format!("byte 0x{:02x}", byte)
+    }
+}
+
+impl<R: Read> Read for Validator<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.finish()?;
+        }
+        for &byte in &buf[..n] {
+            self.check(byte)?;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(input: &str) -> Result<(), String> {
+        let mut output = Vec::new();
+        Validator::new(input.as_bytes(), Some(b'#'))
+            .read_to_end(&mut output)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_conforming_input() {
+        assert!(validate("a,b\r\n1,\"x, \"\"y\"\"\"\r\n").is_ok());
+        assert!(validate("a,b\n\"two\r\nlines\",\n# it's \"fine\"\n").is_ok());
+        assert!(validate("").is_ok());
+    }
+
+    #[test]
+    fn test_violations() {
+        assert_eq!(
+            validate("a,b\n1,x\"y\n").unwrap_err(),
+            "RFC 4180 violation at line 2, column 4 (field 2): \
+             quote inside an unquoted field; quote the field and double the quote"
+        );
+        assert_eq!(
+            validate("a,b\n\"1\"x,2\n").unwrap_err(),
+            "RFC 4180 violation at line 2, column 4 (field 1): \
+             'x' after the closing quote of a field"
+        );
+        assert_eq!(
+            validate("a,b\r1,2\n").unwrap_err(),
+            "RFC 4180 violation at line 1, column 4 (field 2): \
+             carriage return without a line feed"
+        );
+        // Reported where the quote opens rather than at the end of input
+        assert_eq!(
+            validate("a,b\n1,\"open\n2,3\n").unwrap_err(),
+            "RFC 4180 violation at line 2, column 3 (field 2): \
+             quoted field is not closed before end of input"
+        );
+    }
+}
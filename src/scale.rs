@@ -0,0 +1,120 @@
+//! Linear unit conversions of numeric columns, e.g. `temp_f:(-32)*5/9`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// `value * factor + offset` applied to one column.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scale {
+    pub column: String,
+    pub factor: f64,
+    pub offset: f64,
+}
+
+impl Scale {
+    /// Scales a numeric value; anything else is returned unchanged. Integers
+    /// stay integers when the result is whole.
+    pub fn apply(&self, value: &Value) -> Value {
+        let Some(number) = value.as_f64() else {
+            return value.clone();
+        };
+        let scaled = number * self.factor + self.offset;
+        if value.is_i64() && scaled.fract() == 0.0 && scaled.abs() < i64::MAX as f64 {
+            Value::from(scaled as i64)
+        } else {
+            serde_json::Number::from_f64(scaled).map_or(Value::Null, Value::Number)
+        }
+    }
+}
+
+/// Parses `COLUMN:OPS[,COLUMN:OPS...]`, where OPS is a sequence of `*n`, `/n`,
+/// `+n`, `-n` or a parenthesized offset like `(-32)`, applied left to right.
+pub fn parse_scales(s: &str) -> Result<Vec<Scale>, String> {
+    s.split(',').map(parse_scale).collect()
+}
+
+fn parse_scale(s: &str) -> Result<Scale, String> {
+    let error = |detail: &str| format!("invalid scale '{}': {}", s, detail);
+    let (column, mut ops) = s
+        .rsplit_once(':')
+        .ok_or_else(|| error("expected COLUMN:OPS"))?;
+    if column.is_empty() || ops.is_empty() {
+        return Err(error("expected COLUMN:OPS"));
+    }
+
+    let mut scale = Scale {
+        column: column.to_string(),
+        factor: 1.0,
+        offset: 0.0,
+    };
+    while !ops.is_empty() {
+        let (op, operand, rest) = if let Some(inner) = ops.strip_prefix('(') {
+            let end = inner.find(')').ok_or_else(|| error("unclosed '('"))?;
+            ('+', &inner[..end], &inner[end + 1..])
+        } else {
+            let op = ops.chars().next().unwrap_or_default();
+            let rest = &ops[op.len_utf8()..];
+            // The operand is an optionally signed number like 1024, -2 or 0.5
+            let sign_len = usize::from(rest.starts_with(['+', '-']));
+            let end = rest[sign_len..]
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .map_or(rest.len(), |i| i + sign_len);
+            (op, &rest[..end], &rest[end..])
+        };
+        let operand: f64 = operand
+            .trim()
+            .parse()
+            .map_err(|_| error(&format!("'{}' is not a number", operand)))?;
+        match op {
+            '*' => {
+                scale.factor *= operand;
+                scale.offset *= operand;
+            }
+            '/' if operand == 0.0 => return Err(error("division by zero")),
+            '/' => {
+                scale.factor /= operand;
+                scale.offset /= operand;
+            }
+            '+' => scale.offset += operand,
+            '-' => scale.offset -= operand,
+            other => return Err(error(&format!("unknown operator '{}'", other))),
+        }
+        ops = rest;
+    }
+    Ok(scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scales() {
+        let scales = parse_scales("temp_f:(-32)*5/9,size_kb:*1024").unwrap();
+        assert_eq!(scales[0].column, "temp_f");
+        assert!((scales[0].factor - 5.0 / 9.0).abs() < 1e-12);
+        assert!((scales[0].offset - (-32.0 * 5.0 / 9.0)).abs() < 1e-12);
+        assert_eq!(scales[1].factor, 1024.0);
+        assert_eq!(scales[1].offset, 0.0);
+
+        let scale = parse_scales("x:*-2+1").unwrap().remove(0);
+        assert_eq!((scale.factor, scale.offset), (-2.0, 1.0));
+
+        assert!(parse_scales("x:*").is_err());
+        assert!(parse_scales("x:/0").is_err());
+        assert!(parse_scales("x:%2").is_err());
+        assert!(parse_scales("*2").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let scale = parse_scales("temp_f:(-32)*5/9").unwrap().remove(0);
+        assert_eq!(scale.apply(&Value::from(212)), 100);
+        assert_eq!(scale.apply(&Value::from(50.0)), 10.0);
+        assert_eq!(scale.apply(&Value::from("n/a")), "n/a");
+
+        let scale = parse_scales("size_kb:*1024").unwrap().remove(0);
+        assert!(scale.apply(&Value::from(3)).is_i64());
+        assert_eq!(scale.apply(&Value::from(1.5)), 1536.0);
+    }
+}
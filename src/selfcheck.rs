@@ -0,0 +1,184 @@
+//! Re-reads written output for `--self-check`, to catch truncated or corrupt
+//! files before a job reports success.
+
+use crate::compression;
+use crate::error::Error;
+use serde::de::{Deserializer, SeqAccess, Visitor};
+use serde_json::Value;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// Records to compare against the output, by position. Keeping the first
+/// record and those at powers of two bounds memory while still covering the
+/// whole file.
+#[derive(Debug, Default)]
+pub struct Samples {
+    count: usize,
+    records: Vec<(usize, Value)>,
+}
+
+impl Samples {
+    /// Notes one written record, keeping a copy if its position is sampled.
+    pub fn add(&mut self, record: &impl serde::Serialize) -> Result<(), Error> {
+        if self.count == 0 || self.count.is_power_of_two() {
+            self.records
+                .push((self.count, serde_json::to_value(record)?));
+        }
+        self.count += 1;
+        Ok(())
+    }
+}
+
+fn failed(reason: impl fmt::Display) -> Error {
+    Error::Validation(format!("Self-check failed: {}", reason))
+}
+
+/// Compares records as they are read back with the samples.
+struct Checker<'a> {
+    samples: &'a Samples,
+    next_sample: usize,
+    count: usize,
+}
+
+impl Checker<'_> {
+    fn check(&mut self, record: Value) -> Result<(), String> {
+        if let Some((index, expected)) = self.samples.records.get(self.next_sample) {
+            if *index == self.count {
+                if record != *expected {
+                    return Err(format!(
+                        "record {} differs from what was written",
+                        index + 1
+                    ));
+                }
+                self.next_sample += 1;
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        if self.count != self.samples.count {
+            return Err(failed(format!(
+                "expected {} records, but the output contains {}",
+                self.samples.count, self.count
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl<'de> Visitor<'de> for &mut Checker<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON array of records")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(record) = seq.next_element::<Value>()? {
+            self.check(record).map_err(serde::de::Error::custom)?;
+        }
+        Ok(())
+    }
+}
+
+/// Re-reads the output file and checks it holds the sampled records. A JSON
+/// array is read one element at a time; `records_key` names the member
+/// holding the array when the records are wrapped in an object.
+pub fn verify(
+    path: &str,
+    json_lines: bool,
+    records_key: Option<&str>,
+    samples: &Samples,
+) -> Result<(), Error> {
+    let mut checker = Checker {
+        samples,
+        next_sample: 0,
+        count: 0,
+    };
+    // Compressed output is checked as it decompresses
+    let file = BufReader::new(File::open(path)?);
+    let reader = BufReader::new(compression::decompress(file, Some(path))?);
+
+    if json_lines {
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(&line).map_err(failed)?;
+            checker.check(record).map_err(failed)?;
+        }
+    } else if let Some(key) = records_key {
+        let mut envelope: Value = serde_json::from_reader(reader).map_err(failed)?;
+        let Some(Value::Array(records)) = envelope.get_mut(key).map(Value::take) else {
+            return Err(failed(format!("the output has no {} array", key)));
+        };
+        for record in records {
+            checker.check(record).map_err(failed)?;
+        }
+    } else {
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        deserializer
+            .deserialize_seq(&mut checker)
+            .and_then(|()| deserializer.end())
+            .map_err(failed)?;
+    }
+    checker.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn samples(records: &[Value]) -> Samples {
+        let mut samples = Samples::default();
+        for record in records {
+            samples.add(record).unwrap();
+        }
+        samples
+    }
+
+    #[test]
+    fn test_verify_array() {
+        let records: Vec<Value> = (0..5).map(|i| serde_json::json!({ "id": i })).collect();
+        let file = NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+
+        std::fs::write(path, serde_json::to_string(&records).unwrap()).unwrap();
+        verify(path, false, None, &samples(&records)).unwrap();
+
+        // Truncated output no longer parses
+        let full = serde_json::to_string(&records).unwrap();
+        std::fs::write(path, &full[..full.len() - 10]).unwrap();
+        assert!(verify(path, false, None, &samples(&records)).is_err());
+
+        // A changed sample or a missing record is caught
+        std::fs::write(path, r#"[{"id":0},{"id":1},{"id":9},{"id":3},{"id":4}]"#).unwrap();
+        assert!(verify(path, false, None, &samples(&records)).is_err());
+        std::fs::write(path, r#"[{"id":0},{"id":1},{"id":2},{"id":3}]"#).unwrap();
+        assert!(verify(path, false, None, &samples(&records)).is_err());
+    }
+
+    #[test]
+    fn test_verify_lines_and_envelope() {
+        let records: Vec<Value> = (0..3).map(|i| serde_json::json!({ "id": i })).collect();
+        let file = NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+
+        std::fs::write(path, "{\"id\":0}\n{\"id\":1}\n{\"id\":2}\n").unwrap();
+        verify(path, true, None, &samples(&records)).unwrap();
+        std::fs::write(path, "{\"id\":0}\n{\"id\":1}\n").unwrap();
+        assert!(verify(path, true, None, &samples(&records)).is_err());
+
+        std::fs::write(
+            path,
+            r#"{"columns":[],"records":[{"id":0},{"id":1},{"id":2}]}"#,
+        )
+        .unwrap();
+        verify(path, false, Some("records"), &samples(&records)).unwrap();
+    }
+}
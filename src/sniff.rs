@@ -0,0 +1,71 @@
+//! `--auto-format`: guesses the input format from the start of the data, so
+//! `some-command | ctj` works whatever the command writes.
+
+use crate::parquet;
+use crate::sqlite;
+use crate::InputFormat;
+
+/// Guesses whether `start` begins CSV, TSV, JSON Lines, a JSON document, a
+/// Parquet file or a SQLite database. Text that starts with `{` is JSON
+/// Lines when its first line is a whole object; otherwise, tab-separated
+/// values have more tabs than commas in their first line.
+pub fn detect(start: &[u8]) -> InputFormat {
+    if start.starts_with(parquet::MAGIC) {
+        return InputFormat::Parquet;
+    }
+    if start.starts_with(sqlite::MAGIC) {
+        return InputFormat::Sqlite;
+    }
+    let start = start.strip_prefix(b"\xef\xbb\xbf").unwrap_or(start);
+    let content = start.trim_ascii_start();
+    match content.first() {
+        Some(b'[') => InputFormat::Json,
+        Some(b'{') => {
+            let line = first_line(content);
+            if serde_json::from_slice::<serde_json::Value>(line).is_ok() {
+                InputFormat::Jsonl
+            } else {
+                InputFormat::Json
+            }
+        }
+        _ => {
+            let line = first_line(start);
+            let count = |byte: u8| line.iter().filter(|&&b| b == byte).count();
+            if count(b'\t') > count(b',') {
+                InputFormat::Tsv
+            } else {
+                InputFormat::Csv
+            }
+        }
+    }
+}
+
+fn first_line(text: &[u8]) -> &[u8] {
+    match text.iter().position(|&b| b == b'\n') {
+        Some(end) => &text[..end],
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(b"name,age\nJohn,30\n"), InputFormat::Csv);
+        assert_eq!(
+            detect(b"\xef\xbb\xbfname\tage\nJohn\t30\n"),
+            InputFormat::Tsv
+        );
+        assert_eq!(detect(b"note\tcount,total\n"), InputFormat::Csv);
+        assert_eq!(detect(b"name\n"), InputFormat::Csv);
+        assert_eq!(detect(b""), InputFormat::Csv);
+        assert_eq!(detect(b"{\"a\":1}\n{\"a\":2}\n"), InputFormat::Jsonl);
+        assert_eq!(detect(b"{\"a\":1}"), InputFormat::Jsonl);
+        assert_eq!(detect(b"  [{\"a\":1}]"), InputFormat::Json);
+        assert_eq!(detect(b"{\n  \"a\": 1\n}\n"), InputFormat::Json);
+        assert_eq!(detect(b"PAR1\x15\x04"), InputFormat::Parquet);
+        assert_eq!(detect(b"SQLite format 3\x00\x10\x00"), InputFormat::Sqlite);
+    }
+}
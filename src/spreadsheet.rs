@@ -0,0 +1,128 @@
+//! Spreadsheet input: one worksheet of an Excel (.xlsx) or OpenDocument
+//! (.ods) file, both ZIP archives of XML parts. The sheet is turned into CSV
+//! text, which is then converted like any other CSV input.
+
+use crate::error::Error;
+use crate::ods;
+use crate::xlsx;
+use crate::zip::{self, Entry};
+use std::fs::File;
+use std::io;
+
+/// The kinds of spreadsheet file read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Xlsx,
+    Ods,
+}
+
+/// The kind of spreadsheet a ZIP archive is, judged by its members, or
+/// `None` for any other archive.
+pub fn detect(file: &File, entries: &[Entry]) -> Result<Option<Kind>, Error> {
+    if entries.iter().any(|entry| entry.name == xlsx::WORKBOOK) {
+        return Ok(Some(Kind::Xlsx));
+    }
+    // OpenDocument files start with a member naming their type
+    match entries.iter().find(|entry| entry.name == "mimetype") {
+        Some(entry) if zip::read_member(file, entry)?.trim_ascii() == ods::MIMETYPE.as_bytes() => {
+            Ok(Some(Kind::Ods))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Reads the worksheet `sheet` names, by name or 1-based position, or else
+/// the first one, as CSV text.
+pub fn sheet_csv(
+    file: &File,
+    entries: &[Entry],
+    kind: Kind,
+    sheet: Option<&str>,
+) -> Result<Vec<u8>, Error> {
+    let read = |name: &str| match entries.iter().find(|entry| entry.name == name) {
+        Some(entry) => String::from_utf8(zip::read_member(file, entry)?)
+            .map(Some)
+            .map_err(|_| {
+                Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is not UTF-8", name),
+                ))
+            }),
+        None => Ok(None),
+    };
+    let rows = match kind {
+        Kind::Xlsx => xlsx::read_sheet(read, sheet)?,
+        Kind::Ods => ods::read_sheet(read, sheet)?,
+    };
+    to_csv(rows)
+}
+
+/// The index of the sheet `sheet` names, by name or 1-based position, among
+/// `names`, or of the first sheet.
+pub fn pick(names: &[&str], sheet: Option<&str>) -> Result<usize, Error> {
+    let found = match sheet {
+        None => (!names.is_empty()).then_some(0),
+        Some(sheet) => names.iter().position(|name| *name == sheet).or_else(|| {
+            sheet
+                .parse::<usize>()
+                .ok()
+                .and_then(|position| position.checked_sub(1))
+                .filter(|&index| index < names.len())
+        }),
+    };
+    found.ok_or_else(|| {
+        Error::Config(format!(
+            "No worksheet {} in the file (worksheets: {})",
+            sheet.unwrap_or("at all"),
+            if names.is_empty() {
+                "none".to_string()
+            } else {
+                names.join(", ")
+            }
+        ))
+    })
+}
+
+/// Writes rows as CSV. Every row gets the same number of fields, so the
+/// sheet reads as a rectangular CSV whatever cells were left empty.
+fn to_csv(rows: Vec<Vec<String>>) -> Result<Vec<u8>, Error> {
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for mut row in rows {
+        row.resize(width, String::new());
+        writer.write_record(&row)?;
+    }
+    writer.into_inner().map_err(|e| Error::Io(e.into_error()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick() {
+        let names = ["Summary", "Data", "3"];
+        assert_eq!(pick(&names, None).unwrap(), 0);
+        assert_eq!(pick(&names, Some("Data")).unwrap(), 1);
+        assert_eq!(pick(&names, Some("2")).unwrap(), 1);
+        // A name wins over a position
+        assert_eq!(pick(&names, Some("3")).unwrap(), 2);
+        let error = pick(&names, Some("4")).unwrap_err();
+        assert!(
+            matches!(error, Error::Config(ref message) if message.contains("Summary, Data, 3"))
+        );
+        assert!(pick(&[], None).is_err());
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let rows = vec![
+            vec!["id".to_string(), "name".to_string(), "note".to_string()],
+            vec!["1".to_string(), "Jane, \"J\"".to_string()],
+        ];
+        assert_eq!(
+            String::from_utf8(to_csv(rows).unwrap()).unwrap(),
+            "id,name,note\n1,\"Jane, \"\"J\"\"\",\n"
+        );
+    }
+}
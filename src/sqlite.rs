@@ -0,0 +1,1133 @@
+//! SQLite database files. `--format sqlite` writes records as a database
+//! holding one table, built page by page in the SQLite file format; a
+//! database given as input has a table read back the same way, or the rows
+//! of a `--query` selected with the `sqlite3` command.
+
+use crate::base64;
+use crate::columns;
+use crate::error::Error;
+use crate::log;
+use crate::Record;
+use indexmap::IndexSet;
+use serde_json::{Map, Number, Value};
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The most columns SQLite allows in a table by default.
+const MAX_COLUMNS: usize = 2000;
+
+/// Written as the SQLite version that last wrote the file.
+const SQLITE_VERSION: u32 = 3_045_000;
+
+/// The first bytes of every SQLite database file.
+pub const MAGIC: &[u8] = b"SQLite format 3\0";
+
+/// B-tree page types
+const INTERIOR_TABLE: u8 = 0x05;
+const LEAF_TABLE: u8 = 0x0d;
+
+/// The declared type of each column, from the types `--with-columns-meta`
+/// reports. Columns with only nulls have none.
+fn declared_type(kind: &str) -> Option<&'static str> {
+    match kind {
+        "null" => None,
+        "integer" | "boolean" => Some("INTEGER"),
+        "number" => Some("REAL"),
+        _ => Some("TEXT"),
+    }
+}
+
+/// Quotes an SQL identifier.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Writes a database with a table named `table` holding the records, with a
+/// column per key in the order first seen.
+pub fn write_database(records: &[Record], table: &str, out: &mut Vec<u8>) -> Result<(), Error> {
+    let columns = columns::describe(records);
+    if columns.len() > MAX_COLUMNS {
+        return Err(Error::Config(format!(
+            "SQLite tables hold at most {} columns, but the records have {}",
+            MAX_COLUMNS,
+            columns.len()
+        )));
+    }
+    let definitions: Vec<String> = columns
+        .iter()
+        .map(|column| match declared_type(column.kind) {
+            Some(kind) => format!("{} {}", quote(&column.name), kind),
+            None => quote(&column.name),
+        })
+        .collect();
+    let sql = format!("CREATE TABLE {} ({})", quote(table), definitions.join(", "));
+
+    // The schema table lives on page 1 after the file header; larger pages
+    // make room for the statements of very wide tables
+    let mut page_size = 4096;
+    loop {
+        let mut probe = Pages::new(page_size);
+        let cell = probe.leaf_cell(1, &master_record(table, 0, &sql));
+        if probe.fits(100, 8, &[cell]) {
+            break;
+        }
+        if page_size == 65536 {
+            return Err(Error::Config(format!(
+                "The column names of table {} are too long for SQLite",
+                table
+            )));
+        }
+        page_size *= 2;
+    }
+
+    let mut pages = Pages::new(page_size);
+    let kinds: Vec<Option<&str>> = columns.iter().map(|c| declared_type(c.kind)).collect();
+    let mut cells = Vec::with_capacity(records.len());
+    for (i, record) in records.iter().enumerate() {
+        let values = columns.iter().zip(&kinds).map(|(column, kind)| {
+            let value = record.get(&column.name).unwrap_or(&Value::Null);
+            to_sql(value, *kind)
+        });
+        let rowid = i as u64 + 1;
+        cells.push((rowid, pages.leaf_cell(rowid, &encode_record(values))));
+    }
+    let root = pages.write_tree(cells);
+
+    let master = pages.leaf_cell(1, &master_record(table, root, &sql));
+    let page_count = pages.pages.len() as u32;
+    let first = &mut pages.pages[0];
+    *first = btree_page(page_size, LEAF_TABLE, 100, &[master], None);
+    write_header(&mut first[..100], page_size, page_count);
+
+    for page in &pages.pages {
+        out.extend_from_slice(page);
+    }
+    Ok(())
+}
+
+/// A value as SQLite stores it in a column of a declared type: blank values
+/// are NULL except in text columns, booleans are 0 or 1, and text columns
+/// hold the JSON text of values that are not strings.
+enum SqlValue<'a> {
+    Null,
+    Integer(i64),
+    /// An integer stored in 4 bytes whatever its value
+    Int32(u32),
+    Real(f64),
+    Text(std::borrow::Cow<'a, str>),
+}
+
+fn to_sql<'a>(value: &'a Value, kind: Option<&str>) -> SqlValue<'a> {
+    match (value, kind) {
+        (Value::Null, _) => SqlValue::Null,
+        (Value::String(text), Some("TEXT")) => SqlValue::Text(text.into()),
+        (Value::String(text), _) if text.is_empty() => SqlValue::Null,
+        (Value::Bool(b), Some("INTEGER")) => SqlValue::Integer(i64::from(*b)),
+        (Value::Number(n), Some("INTEGER" | "REAL")) => match (n.as_i64(), kind) {
+            (Some(n), Some("INTEGER")) => SqlValue::Integer(n),
+            _ => SqlValue::Real(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        (Value::String(text), _) => SqlValue::Text(text.into()),
+        (other, _) => SqlValue::Text(other.to_string().into()),
+    }
+}
+
+/// The schema table's row for the table. The root page number always takes
+/// 4 bytes, so the row's size is known before the page number is.
+fn master_record(table: &str, root: u32, sql: &str) -> Vec<u8> {
+    encode_record(
+        [
+            SqlValue::Text("table".into()),
+            SqlValue::Text(table.into()),
+            SqlValue::Text(table.into()),
+            SqlValue::Int32(root),
+            SqlValue::Text(sql.into()),
+        ]
+        .into_iter(),
+    )
+}
+
+/// Encodes a row in the record format: a header of serial types, then the
+/// values.
+fn encode_record<'a>(values: impl Iterator<Item = SqlValue<'a>>) -> Vec<u8> {
+    let mut types = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        let serial_type = match value {
+            SqlValue::Null => 0,
+            SqlValue::Integer(0) => 8,
+            SqlValue::Integer(1) => 9,
+            SqlValue::Integer(n) => {
+                let (serial_type, width) = match n {
+                    -0x80..=0x7f => (1, 1),
+                    -0x8000..=0x7fff => (2, 2),
+                    -0x80_0000..=0x7f_ffff => (3, 3),
+                    -0x8000_0000..=0x7fff_ffff => (4, 4),
+                    -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                    _ => (6, 8),
+                };
+                body.extend_from_slice(&n.to_be_bytes()[8 - width..]);
+                serial_type
+            }
+            SqlValue::Int32(n) => {
+                body.extend_from_slice(&n.to_be_bytes());
+                4
+            }
+            SqlValue::Real(n) => {
+                body.extend_from_slice(&n.to_be_bytes());
+                7
+            }
+            SqlValue::Text(text) => {
+                body.extend_from_slice(text.as_bytes());
+                13 + 2 * text.len() as u64
+            }
+        };
+        write_varint(serial_type, &mut types);
+    }
+
+    // The header size counts its own varint
+    let mut header_size = types.len() + 1;
+    while types.len() + varint_len(header_size as u64) != header_size {
+        header_size = types.len() + varint_len(header_size as u64);
+    }
+    let mut record = Vec::with_capacity(header_size + body.len());
+    write_varint(header_size as u64, &mut record);
+    record.extend_from_slice(&types);
+    record.extend_from_slice(&body);
+    record
+}
+
+/// The pages of the file in order; page 1 is filled in last.
+struct Pages {
+    size: usize,
+    pages: Vec<Vec<u8>>,
+}
+
+impl Pages {
+    fn new(size: usize) -> Self {
+        Pages {
+            size,
+            pages: vec![Vec::new()],
+        }
+    }
+
+    fn add(&mut self, page: Vec<u8>) -> u32 {
+        self.pages.push(page);
+        self.pages.len() as u32
+    }
+
+    /// A table leaf cell holding a record, with the part of it that does not
+    /// fit on the page moved to a chain of overflow pages.
+    fn leaf_cell(&mut self, rowid: u64, payload: &[u8]) -> Vec<u8> {
+        let usable = self.size;
+        let max_local = usable - 35;
+        let min_local = (usable - 12) * 32 / 255 - 23;
+        let local = if payload.len() <= max_local {
+            payload.len()
+        } else {
+            let local = min_local + (payload.len() - min_local) % (usable - 4);
+            if local <= max_local {
+                local
+            } else {
+                min_local
+            }
+        };
+
+        let mut cell = Vec::with_capacity(local + 22);
+        write_varint(payload.len() as u64, &mut cell);
+        write_varint(rowid, &mut cell);
+        cell.extend_from_slice(&payload[..local]);
+        if local < payload.len() {
+            let chunks: Vec<&[u8]> = payload[local..].chunks(usable - 4).collect();
+            let first = self.pages.len() as u32 + 1;
+            cell.extend_from_slice(&first.to_be_bytes());
+            for (i, chunk) in chunks.iter().enumerate() {
+                let next = if i + 1 < chunks.len() {
+                    first + i as u32 + 1
+                } else {
+                    0
+                };
+                let mut page = Vec::with_capacity(usable);
+                page.extend_from_slice(&next.to_be_bytes());
+                page.extend_from_slice(chunk);
+                page.resize(usable, 0);
+                self.add(page);
+            }
+        }
+        cell
+    }
+
+    /// Whether cells fit on one b-tree page with a header of `header` bytes
+    /// at `offset`.
+    fn fits(&self, offset: usize, header: usize, cells: &[Vec<u8>]) -> bool {
+        let used: usize = cells.iter().map(|cell| cell.len() + 2).sum();
+        offset + header + used <= self.size
+    }
+
+    /// Packs leaf cells into pages, then adds levels of interior pages until
+    /// one page is the root, and returns the root's page number.
+    fn write_tree(&mut self, cells: Vec<(u64, Vec<u8>)>) -> u32 {
+        let mut level: Vec<(u32, u64)> = Vec::new();
+        let mut leaf: Vec<Vec<u8>> = Vec::new();
+        let mut last_rowid = 0;
+        for (rowid, cell) in cells {
+            leaf.push(cell);
+            if leaf.len() > 1 && !self.fits(0, 8, &leaf) {
+                let cell = leaf.pop().expect("pushed above");
+                let page = btree_page(self.size, LEAF_TABLE, 0, &leaf, None);
+                level.push((self.add(page), last_rowid));
+                leaf = vec![cell];
+            }
+            last_rowid = rowid;
+        }
+        if !leaf.is_empty() || level.is_empty() {
+            let page = btree_page(self.size, LEAF_TABLE, 0, &leaf, None);
+            level.push((self.add(page), last_rowid));
+        }
+
+        // Each interior page points to up to `fanout` children: a cell with
+        // the largest rowid of each but the last, which is the right child
+        let fanout = (self.size - 12) / (4 + 9 + 2) + 1;
+        while level.len() > 1 {
+            // Children are spread evenly, so no page has a single child and
+            // so no cells
+            let count = level.len().div_ceil(fanout);
+            let mut rest = level.as_slice();
+            let groups: Vec<_> = (0..count)
+                .map(|i| {
+                    let take = rest.len() / (count - i);
+                    let (group, tail) = rest.split_at(take);
+                    rest = tail;
+                    group
+                })
+                .collect();
+            level = groups
+                .into_iter()
+                .map(|group| {
+                    let (children, right) = group.split_at(group.len() - 1);
+                    let cells: Vec<Vec<u8>> = children
+                        .iter()
+                        .map(|(page, rowid)| {
+                            let mut cell = page.to_be_bytes().to_vec();
+                            write_varint(*rowid, &mut cell);
+                            cell
+                        })
+                        .collect();
+                    let page = btree_page(self.size, INTERIOR_TABLE, 0, &cells, Some(right[0].0));
+                    (self.add(page), right[0].1)
+                })
+                .collect();
+        }
+        level[0].0
+    }
+}
+
+/// Lays out a b-tree page: the header at `offset`, the cell pointers after
+/// it, and the cells packed at the end of the page in order.
+fn btree_page(
+    size: usize,
+    kind: u8,
+    offset: usize,
+    cells: &[Vec<u8>],
+    right: Option<u32>,
+) -> Vec<u8> {
+    let mut page = vec![0; size];
+    let header_len = if right.is_some() { 12 } else { 8 };
+    let mut content = size;
+    for (i, cell) in cells.iter().enumerate() {
+        content -= cell.len();
+        page[content..content + cell.len()].copy_from_slice(cell);
+        let pointer = offset + header_len + 2 * i;
+        page[pointer..pointer + 2].copy_from_slice(&(content as u16).to_be_bytes());
+    }
+    page[offset] = kind;
+    page[offset + 3..offset + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    // A content area starting at 65536 is stored as 0
+    page[offset + 5..offset + 7].copy_from_slice(&(content as u16).to_be_bytes());
+    if let Some(right) = right {
+        page[offset + 8..offset + 12].copy_from_slice(&right.to_be_bytes());
+    }
+    page
+}
+
+/// Fills in the 100-byte file header.
+fn write_header(header: &mut [u8], page_size: usize, page_count: u32) {
+    header[..16].copy_from_slice(MAGIC);
+    // A page size of 65536 is stored as 1
+    header[16..18]
+        .copy_from_slice(&(page_size as u16 | u16::from(page_size == 65536)).to_be_bytes());
+    // File format versions (legacy journal), no reserved bytes, and the
+    // fixed payload fractions
+    header[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]);
+    // File change counter
+    header[24..28].copy_from_slice(&1u32.to_be_bytes());
+    header[28..32].copy_from_slice(&page_count.to_be_bytes());
+    // Schema cookie and schema format 4
+    header[40..44].copy_from_slice(&1u32.to_be_bytes());
+    header[44..48].copy_from_slice(&4u32.to_be_bytes());
+    // UTF-8 text
+    header[56..60].copy_from_slice(&1u32.to_be_bytes());
+    // The change counter the version number below was written at
+    header[92..96].copy_from_slice(&1u32.to_be_bytes());
+    header[96..100].copy_from_slice(&SQLITE_VERSION.to_be_bytes());
+}
+
+/// A big-endian variable-length integer: 7 bits per byte, with the high bit
+/// set on all but the last, and a full 8 bits in a ninth byte.
+fn write_varint(n: u64, out: &mut Vec<u8>) {
+    if n >> 56 != 0 {
+        for i in (1..9).rev() {
+            out.push((n >> (i * 7 + 1)) as u8 | 0x80);
+        }
+        out.push(n as u8);
+        return;
+    }
+    let len = varint_len(n);
+    for i in (0..len).rev() {
+        let byte = (n >> (7 * i)) as u8 & 0x7f;
+        out.push(if i > 0 { byte | 0x80 } else { byte });
+    }
+}
+
+fn varint_len(n: u64) -> usize {
+    if n >> 56 != 0 {
+        9
+    } else {
+        (64 - n.leading_zeros() as usize).div_ceil(7).max(1)
+    }
+}
+
+/// Checks whether a file is a SQLite database, leaving it at its start.
+pub fn is_database(file: &mut File) -> io::Result<bool> {
+    let mut magic = [0; 16];
+    let is_database = match file.read_exact(&mut magic) {
+        Ok(()) => magic == MAGIC,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e),
+    };
+    file.rewind()?;
+    Ok(is_database)
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("sqlite: {}", message.into()),
+    ))
+}
+
+/// Rows read from a database, with their column names in order.
+pub struct Table {
+    pub columns: Vec<String>,
+    pub rows: Vec<Map<String, Value>>,
+}
+
+/// Reads the table `table` names, or else the database's only table, in
+/// rowid order.
+pub fn read_table(data: &[u8], table: Option<&str>) -> Result<Table, Error> {
+    let database = Database::open(data)?;
+    // The schema table: type, name, table name, root page and SQL of each
+    // table, index, view and trigger
+    let mut schema = Vec::new();
+    database.rows(1, &mut |_, payload| {
+        schema.push(database.record(&payload)?);
+        Ok(())
+    })?;
+    fn text(entry: &[Value], i: usize) -> &str {
+        entry.get(i).and_then(Value::as_str).unwrap_or("")
+    }
+    let tables: Vec<&[Value]> = schema
+        .iter()
+        .map(Vec::as_slice)
+        .filter(|entry| text(entry, 0) == "table" && !text(entry, 1).starts_with("sqlite_"))
+        .collect();
+    let names = || {
+        let names: Vec<&str> = tables.iter().map(|entry| text(entry, 1)).collect();
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        }
+    };
+    let entry = match table {
+        Some(table) => {
+            let entry = schema.iter().find(|entry| {
+                matches!(text(entry, 0), "table" | "view")
+                    && text(entry, 1).eq_ignore_ascii_case(table)
+            });
+            match entry {
+                Some(entry) if text(entry, 0) == "view" => {
+                    return Err(Error::Config(format!(
+                        "{} is a view; read it with --query 'SELECT * FROM {}'",
+                        table,
+                        quote(table)
+                    )))
+                }
+                Some(entry) => entry.as_slice(),
+                None => {
+                    return Err(Error::Config(format!(
+                        "No table {} in the database (tables: {})",
+                        table,
+                        names()
+                    )))
+                }
+            }
+        }
+        None => match tables[..] {
+            [entry] => entry,
+            _ => {
+                return Err(Error::Config(format!(
+                    "Choose a table of the database with --table (tables: {})",
+                    names()
+                )))
+            }
+        },
+    };
+    let name = text(entry, 1);
+    let root = entry
+        .get(3)
+        .and_then(Value::as_u64)
+        .and_then(|root| u32::try_from(root).ok())
+        .ok_or_else(|| invalid(format!("table {} has no root page", name)))?;
+    let definition = TableDefinition::parse(name, text(entry, 4))?;
+    log::info!("Reading table {} from the database", name);
+
+    let mut rows = Vec::new();
+    database.rows(root, &mut |rowid, payload| {
+        let mut values = database.record(&payload)?.into_iter();
+        // Columns added by ALTER TABLE are missing from older rows
+        let row = definition
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                let value = match values.next().unwrap_or(Value::Null) {
+                    _ if definition.rowid == Some(i) => Value::from(rowid),
+                    // Whole numbers in REAL columns are stored as integers
+                    Value::Number(n) if definition.reals[i] => n
+                        .as_i64()
+                        .and_then(|n| Number::from_f64(n as f64))
+                        .map_or(Value::Number(n), Value::Number),
+                    value => value,
+                };
+                (column.clone(), value)
+            })
+            .collect();
+        rows.push(row);
+        Ok(())
+    })?;
+    Ok(Table {
+        columns: definition.columns,
+        rows,
+    })
+}
+
+/// Runs `sql` on the database at `path` with the `sqlite3` command, for
+/// joins, filters and anything else beyond reading a whole table.
+pub fn query(path: &str, sql: &str) -> Result<Table, Error> {
+    // A file name starting with - would be taken for an option
+    let path = if path.starts_with('-') {
+        Path::new(".").join(path)
+    } else {
+        Path::new(path).to_path_buf()
+    };
+    let output = Command::new("sqlite3")
+        .args(["-readonly", "-bail", "-json"])
+        .arg(&path)
+        .arg(sql)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::Config(
+                "--query requires the sqlite3 command, which was not found".to_string(),
+            ),
+            _ => Error::Io(e),
+        })?;
+    if !output.status.success() {
+        return Err(Error::Config(format!(
+            "sqlite3 failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    // Each statement that returns rows prints an array of them, and one
+    // that returns none prints nothing
+    let mut columns = IndexSet::new();
+    let mut rows = Vec::new();
+    for batch in
+        serde_json::Deserializer::from_slice(&output.stdout).into_iter::<Vec<Map<String, Value>>>()
+    {
+        let batch = batch.map_err(|e| invalid(format!("unexpected sqlite3 output: {}", e)))?;
+        for row in batch {
+            columns.extend(row.keys().cloned());
+            rows.push(row);
+        }
+    }
+    Ok(Table {
+        columns: columns.into_iter().collect(),
+        rows,
+    })
+}
+
+/// The column names a `CREATE TABLE` statement gives, which of them have
+/// REAL affinity, and which column, if any, is an alias for the rowid and so
+/// stored as NULL.
+#[derive(Debug, PartialEq)]
+struct TableDefinition {
+    columns: Vec<String>,
+    reals: Vec<bool>,
+    rowid: Option<usize>,
+}
+
+/// Words that end a column's declared type.
+const CONSTRAINTS: [&str; 11] = [
+    "CONSTRAINT",
+    "PRIMARY",
+    "NOT",
+    "NULL",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+    "AS",
+];
+
+impl TableDefinition {
+    fn parse(table: &str, sql: &str) -> Result<TableDefinition, Error> {
+        let unsupported = |what: &str| {
+            Error::Config(format!(
+                "Table {} {}, which only --query can read",
+                table, what
+            ))
+        };
+        let (definitions, options) = split_definitions(sql)
+            .ok_or_else(|| invalid(format!("cannot read the columns of table {}", table)))?;
+        if options.to_ascii_uppercase().contains("WITHOUT ROWID") {
+            return Err(unsupported("is a WITHOUT ROWID table"));
+        }
+
+        let mut columns = Vec::new();
+        let mut integers = Vec::new();
+        let mut reals = Vec::new();
+        let mut rowid = None;
+        let mut primary_key = None;
+        for definition in definitions {
+            let (name, rest, quoted) = identifier(definition.trim());
+            let words: Vec<String> = rest
+                .split(|c: char| c.is_whitespace() || c == '(')
+                .filter(|word| !word.is_empty())
+                .map(str::to_ascii_uppercase)
+                .collect();
+            let first = name.to_ascii_uppercase();
+            if !quoted
+                && ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"].contains(&first.as_str())
+            {
+                if first == "PRIMARY" {
+                    primary_key = primary_key_column(rest);
+                }
+                continue;
+            }
+            let declared: Vec<&str> = words
+                .iter()
+                .map(String::as_str)
+                .take_while(|word| !CONSTRAINTS.contains(word))
+                .collect();
+            let is_integer = declared == ["INTEGER"];
+            if words.iter().any(|word| word == "AS") && !words.iter().any(|word| word == "STORED") {
+                return Err(unsupported(&format!("has the virtual column {}", name)));
+            }
+            let upper = rest.to_ascii_uppercase();
+            if is_integer && upper.contains("PRIMARY KEY") && !upper.contains("PRIMARY KEY DESC") {
+                rowid = Some(columns.len());
+            }
+            let declared = declared.join(" ");
+            let has = |part: &str| declared.contains(part);
+            reals.push(
+                !has("INT")
+                    && !has("CHAR")
+                    && !has("CLOB")
+                    && !has("TEXT")
+                    && (has("REAL") || has("FLOA") || has("DOUB")),
+            );
+            columns.push(name);
+            integers.push(is_integer);
+        }
+        if let Some(key) = primary_key {
+            if let Some(index) = columns
+                .iter()
+                .position(|column| column.eq_ignore_ascii_case(&key))
+            {
+                if integers[index] {
+                    rowid = Some(index);
+                }
+            }
+        }
+        Ok(TableDefinition {
+            columns,
+            reals,
+            rowid,
+        })
+    }
+}
+
+/// The column and constraint definitions between the parentheses of a
+/// `CREATE TABLE` statement, and the text after them.
+fn split_definitions(sql: &str) -> Option<(Vec<&str>, &str)> {
+    let mut definitions = Vec::new();
+    let (mut depth, mut start, mut quote) = (0, 0, None);
+    for (i, c) in sql.char_indices() {
+        match (quote, c) {
+            (Some(end), c) if c == end => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'' | '`') => quote = Some(c),
+            (None, '[') => quote = Some(']'),
+            (None, '(') => {
+                depth += 1;
+                if depth == 1 {
+                    start = i + 1;
+                }
+            }
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    definitions.push(&sql[start..i]);
+                    return Some((definitions, &sql[i + 1..]));
+                }
+            }
+            (None, ',') if depth == 1 => {
+                definitions.push(&sql[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The name at the start of a definition, unquoted, with the text after it
+/// and whether it was quoted.
+fn identifier(definition: &str) -> (String, &str, bool) {
+    let close = match definition.chars().next() {
+        Some('"') => '"',
+        Some('`') => '`',
+        Some('\'') => '\'',
+        Some('[') => ']',
+        _ => {
+            let end = definition
+                .find(|c: char| c.is_whitespace() || c == '(')
+                .unwrap_or(definition.len());
+            return (definition[..end].to_string(), &definition[end..], false);
+        }
+    };
+    let mut name = String::new();
+    let mut chars = definition.char_indices().skip(1).peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == close {
+            // A doubled quote stands for itself, except in brackets
+            if close != ']' && chars.peek().is_some_and(|&(_, next)| next == close) {
+                chars.next();
+            } else {
+                return (name, &definition[i + 1..], true);
+            }
+        }
+        name.push(c);
+    }
+    (name, "", true)
+}
+
+/// The column of a table's `PRIMARY KEY (column)` constraint, if it has one
+/// column.
+fn primary_key_column(constraint: &str) -> Option<String> {
+    let (_, columns) = constraint.split_once('(')?;
+    let (columns, _) = columns.rsplit_once(')')?;
+    if columns.contains(',') {
+        return None;
+    }
+    let (name, _, _) = identifier(columns.trim());
+    Some(name)
+}
+
+/// The text encodings a database can store text in.
+#[derive(Debug, Clone, Copy)]
+enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// A database file in memory.
+struct Database<'a> {
+    data: &'a [u8],
+    page_size: usize,
+    usable: usize,
+    encoding: TextEncoding,
+}
+
+impl<'a> Database<'a> {
+    fn open(data: &'a [u8]) -> Result<Self, Error> {
+        if data.len() < 100 || !data.starts_with(MAGIC) {
+            return Err(invalid("not a SQLite database"));
+        }
+        let page_size = match u16::from_be_bytes([data[16], data[17]]) {
+            1 => 65536,
+            size => size as usize,
+        };
+        if page_size < 512 || !page_size.is_power_of_two() {
+            return Err(invalid(format!("bad page size {}", page_size)));
+        }
+        let usable = page_size - data[20] as usize;
+        if usable < 480 {
+            return Err(invalid("too many reserved bytes per page"));
+        }
+        let encoding = match u32::from_be_bytes(data[56..60].try_into().expect("4 bytes")) {
+            0 | 1 => TextEncoding::Utf8,
+            2 => TextEncoding::Utf16Le,
+            3 => TextEncoding::Utf16Be,
+            other => return Err(invalid(format!("unknown text encoding {}", other))),
+        };
+        Ok(Database {
+            data,
+            page_size,
+            usable,
+            encoding,
+        })
+    }
+
+    fn page(&self, number: u32) -> Result<&'a [u8], Error> {
+        (number as usize)
+            .checked_sub(1)
+            .map(|index| index * self.page_size)
+            .and_then(|start| self.data.get(start..start + self.page_size))
+            .ok_or_else(|| invalid(format!("page {} is past the end of the file", number)))
+    }
+
+    /// Calls `visit` with the rowid and payload of each row of the table
+    /// b-tree rooted at page `root`, in rowid order.
+    fn rows(
+        &self,
+        root: u32,
+        visit: &mut dyn FnMut(i64, Cow<'a, [u8]>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut pending = vec![root];
+        // A b-tree can hold each page only once, so more visits mean a loop
+        let mut visits = self.data.len() / self.page_size;
+        while let Some(number) = pending.pop() {
+            visits = visits
+                .checked_sub(1)
+                .ok_or_else(|| invalid("b-tree pages form a loop"))?;
+            let page = self.page(number)?;
+            let header = if number == 1 { 100 } else { 0 };
+            let cells = u16::from_be_bytes([page[header + 3], page[header + 4]]) as usize;
+            let pointer = |i: usize, first: usize| -> Result<usize, Error> {
+                let at = header + first + 2 * i;
+                page.get(at..at + 2)
+                    .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+                    .filter(|&cell| cell < page.len())
+                    .ok_or_else(|| invalid(format!("page {} has a bad cell pointer", number)))
+            };
+            match page[header] {
+                LEAF_TABLE => {
+                    for i in 0..cells {
+                        let mut at = pointer(i, 8)?;
+                        let size = read_varint(page, &mut at)?;
+                        let rowid = read_varint(page, &mut at)? as i64;
+                        visit(rowid, self.payload(page, at, size)?)?;
+                    }
+                }
+                INTERIOR_TABLE => {
+                    // Children are taken from the end, so push the rightmost first
+                    pending.push(be_u32(page, header + 8)?);
+                    for i in (0..cells).rev() {
+                        pending.push(be_u32(page, pointer(i, 12)?)?);
+                    }
+                }
+                other => {
+                    return Err(invalid(format!(
+                        "page {} is not a table page (type {})",
+                        number, other
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// A row's payload, gathered from overflow pages when it does not fit
+    /// in its cell.
+    fn payload(&self, page: &'a [u8], at: usize, size: u64) -> Result<Cow<'a, [u8]>, Error> {
+        let truncated = || invalid("row is truncated");
+        let size = usize::try_from(size).map_err(|_| truncated())?;
+        let max_local = self.usable - 35;
+        if size <= max_local {
+            return page
+                .get(at..at + size)
+                .map(Cow::Borrowed)
+                .ok_or_else(truncated);
+        }
+        let min_local = (self.usable - 12) * 32 / 255 - 23;
+        let local = match min_local + (size - min_local) % (self.usable - 4) {
+            local if local <= max_local => local,
+            _ => min_local,
+        };
+        let mut payload = Vec::with_capacity(size.min(self.data.len()));
+        payload.extend_from_slice(page.get(at..at + local).ok_or_else(truncated)?);
+        let mut next = be_u32(page, at + local)?;
+        while payload.len() < size {
+            if next == 0 || payload.len() > self.data.len() {
+                return Err(truncated());
+            }
+            let overflow = self.page(next)?;
+            next = be_u32(overflow, 0)?;
+            let length = (size - payload.len()).min(self.usable - 4);
+            payload.extend_from_slice(&overflow[4..4 + length]);
+        }
+        Ok(Cow::Owned(payload))
+    }
+
+    /// Decodes a record: a header of serial types, then the values.
+    fn record(&self, payload: &[u8]) -> Result<Vec<Value>, Error> {
+        let truncated = || invalid("record is truncated");
+        let mut at = 0;
+        let header_size = read_varint(payload, &mut at)? as usize;
+        let mut types = Vec::new();
+        while at < header_size {
+            types.push(read_varint(payload, &mut at)?);
+        }
+        let mut at = header_size;
+        let mut take = |length: usize| {
+            let bytes = payload.get(at..at + length).ok_or_else(truncated)?;
+            at += length;
+            Ok::<_, Error>(bytes)
+        };
+        types
+            .into_iter()
+            .map(|serial| {
+                Ok(match serial {
+                    0 => Value::Null,
+                    1..=6 => {
+                        let length = [1, 2, 3, 4, 6, 8][serial as usize - 1];
+                        let bytes = take(length)?;
+                        // Sign-extended from the first byte
+                        let first = if bytes[0] >= 0x80 { -1i64 } else { 0 };
+                        Value::from(
+                            bytes
+                                .iter()
+                                .fold(first, |value, &byte| value << 8 | byte as i64),
+                        )
+                    }
+                    7 => {
+                        let value = f64::from_be_bytes(take(8)?.try_into().expect("8 bytes"));
+                        Number::from_f64(value).map_or(Value::Null, Value::Number)
+                    }
+                    8 => Value::from(0),
+                    9 => Value::from(1),
+                    10 | 11 => return Err(invalid(format!("reserved serial type {}", serial))),
+                    _ if serial % 2 == 0 => {
+                        Value::from(base64::encode(take((serial as usize - 12) / 2)?))
+                    }
+                    _ => Value::from(self.text(take((serial as usize - 13) / 2)?)),
+                })
+            })
+            .collect()
+    }
+
+    fn text(&self, bytes: &[u8]) -> String {
+        let units = |unit: fn([u8; 2]) -> u16| -> Vec<u16> {
+            bytes
+                .chunks_exact(2)
+                .map(|pair| unit([pair[0], pair[1]]))
+                .collect()
+        };
+        match self.encoding {
+            TextEncoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            TextEncoding::Utf16Le => String::from_utf16_lossy(&units(u16::from_le_bytes)),
+            TextEncoding::Utf16Be => String::from_utf16_lossy(&units(u16::from_be_bytes)),
+        }
+    }
+}
+
+fn be_u32(data: &[u8], at: usize) -> Result<u32, Error> {
+    data.get(at..at + 4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().expect("4 bytes")))
+        .ok_or_else(|| invalid("page is truncated"))
+}
+
+/// Reads a varint as `write_varint` writes it.
+fn read_varint(data: &[u8], at: &mut usize) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for i in 0..9 {
+        let byte = *data
+            .get(*at)
+            .ok_or_else(|| invalid("varint is truncated"))?;
+        *at += 1;
+        if i == 8 {
+            return Ok(value << 8 | byte as u64);
+        }
+        value = value << 7 | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_write_varint() {
+        for (n, expected) in [
+            (0, vec![0x00]),
+            (127, vec![0x7f]),
+            (128, vec![0x81, 0x00]),
+            (16383, vec![0xff, 0x7f]),
+            (u64::MAX, vec![0xff; 9]),
+        ] {
+            let mut out = Vec::new();
+            write_varint(n, &mut out);
+            assert_eq!(out, expected, "{}", n);
+        }
+    }
+
+    #[test]
+    fn test_encode_record() {
+        let record = encode_record(
+            [
+                SqlValue::Null,
+                SqlValue::Integer(1),
+                SqlValue::Integer(-200),
+                SqlValue::Real(1.5),
+                SqlValue::Text("hi".into()),
+            ]
+            .into_iter(),
+        );
+        assert_eq!(
+            record,
+            [6, 0, 9, 2, 7, 17, 0xff, 0x38, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0, b'h', b'i']
+        );
+    }
+
+    #[test]
+    fn test_to_sql() {
+        assert!(matches!(
+            to_sql(&Value::from(""), Some("INTEGER")),
+            SqlValue::Null
+        ));
+        assert!(
+            matches!(to_sql(&Value::from(""), Some("TEXT")), SqlValue::Text(text) if text.is_empty())
+        );
+        assert!(matches!(
+            to_sql(&Value::from(true), Some("INTEGER")),
+            SqlValue::Integer(1)
+        ));
+        assert!(matches!(to_sql(&Value::from(2), Some("REAL")), SqlValue::Real(n) if n == 2.0));
+        assert!(matches!(
+            to_sql(&serde_json::json!(["a"]), Some("TEXT")),
+            SqlValue::Text(text) if text == "[\"a\"]"
+        ));
+    }
+
+    #[test]
+    fn test_write_database() {
+        let records: Vec<Record> = (0..3000)
+            .map(|i| {
+                Record::from([
+                    (Arc::from("id"), Value::from(i)),
+                    (Arc::from("note"), Value::from("x".repeat(i % 7 * 1000))),
+                ])
+            })
+            .collect();
+        let mut file = Vec::new();
+        write_database(&records, "t", &mut file).unwrap();
+        assert!(file.starts_with(b"SQLite format 3\0"));
+        assert_eq!(file.len() % 4096, 0);
+        let page_count = u32::from_be_bytes(file[28..32].try_into().unwrap());
+        assert_eq!(file.len(), page_count as usize * 4096);
+        // The schema table's one row, at the end of page 1
+        let sql = b"CREATE TABLE \"t\" (\"id\" INTEGER, \"note\" TEXT)";
+        assert!(file[..4096].ends_with(sql));
+    }
+
+    #[test]
+    fn test_read_varint() {
+        for n in [0, 127, 128, 16383, 1 << 40, u64::MAX] {
+            let mut out = Vec::new();
+            write_varint(n, &mut out);
+            let mut at = 0;
+            assert_eq!(read_varint(&out, &mut at).unwrap(), n);
+            assert_eq!(at, out.len());
+        }
+    }
+
+    #[test]
+    fn test_read_table() {
+        // Enough rows for interior pages, and notes long enough to overflow
+        let records: Vec<Record> = (0..3000)
+            .map(|i| {
+                Record::from([
+                    (Arc::from("id"), Value::from(i)),
+                    (Arc::from("note"), Value::from("x".repeat(i % 7 * 1000))),
+                    (Arc::from("ratio"), Value::from(i as f64 / 4.0)),
+                ])
+            })
+            .collect();
+        let mut file = Vec::new();
+        write_database(&records, "t", &mut file).unwrap();
+
+        let table = read_table(&file, None).unwrap();
+        assert_eq!(table.columns, ["id", "note", "ratio"]);
+        assert_eq!(table.rows.len(), 3000);
+        for (i, row) in table.rows.iter().enumerate() {
+            assert_eq!(row["id"], Value::from(i));
+            assert_eq!(row["note"], Value::from("x".repeat(i % 7 * 1000)));
+            assert_eq!(row["ratio"], Value::from(i as f64 / 4.0));
+        }
+        assert_eq!(read_table(&file, Some("T")).unwrap().rows.len(), 3000);
+        let error = read_table(&file, Some("other")).err().unwrap();
+        assert!(matches!(error, Error::Config(ref message) if message.contains("(tables: t)")));
+        assert!(read_table(&file[..2048], None).is_err());
+    }
+
+    #[test]
+    fn test_table_definition() {
+        let parse = |sql: &str| TableDefinition::parse("t", sql);
+        assert_eq!(
+            parse(
+                "CREATE TABLE t (\"user \"\"id\"\"\" INTEGER PRIMARY KEY, [name] TEXT DEFAULT 'a, b', \
+                 score double precision CHECK (score > 0), `n`, CONSTRAINT c UNIQUE (name))"
+            )
+            .unwrap(),
+            TableDefinition {
+                columns: vec![
+                    "user \"id\"".to_string(),
+                    "name".to_string(),
+                    "score".to_string(),
+                    "n".to_string()
+                ],
+                reals: vec![false, false, true, false],
+                rowid: Some(0),
+            }
+        );
+        let definition = parse("CREATE TABLE t(a text, id integer, PRIMARY KEY (\"id\"))").unwrap();
+        assert_eq!(definition.rowid, Some(1));
+        // Only INTEGER itself makes an alias
+        let definition = parse("CREATE TABLE t(id int primary key)").unwrap();
+        assert_eq!(definition.rowid, None);
+        assert!(matches!(
+            parse("CREATE TABLE t(k TEXT PRIMARY KEY) WITHOUT ROWID"),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            parse("CREATE TABLE t(a INT, b INT AS (a * 2))"),
+            Err(Error::Config(_))
+        ));
+        assert!(parse("CREATE TABLE t(a INT, b INT AS (a * 2) STORED)").is_ok());
+    }
+}
@@ -0,0 +1,294 @@
+//! Counters for a conversion and the `--timing` report built from them.
+
+use crate::compression::Sink;
+use crate::progress::human_bytes;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    /// The counter of the `CountingReader` created last on this thread. The
+    /// reader may be moved to another thread, such as the one feeding an
+    /// external decompressor, so the count itself is shared.
+    static INPUT_READ: RefCell<Arc<AtomicU64>> = RefCell::default();
+    /// The stage this thread is timing and when its current stretch began
+    static CURRENT_STAGE: Cell<Option<(Stage, Instant)>> = const { Cell::new(None) };
+}
+
+/// Whether `timed` measures anything; set for `--timing`.
+static STAGES_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Nanoseconds spent in each stage, summed over every thread.
+static STAGE_NANOS: [AtomicU64; STAGES.len()] = [const { AtomicU64::new(0) }; STAGES.len()];
+
+/// A part of the conversion whose time `--timing` reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading, decompressing and parsing the input, and anything else done
+    /// on the reading thread outside the other stages
+    Read,
+    /// Filtering rows and building records, on the `--threads` workers
+    Transform,
+    /// Turning records into JSON text
+    Serialize,
+    /// Writing, and compressing, the output
+    Write,
+    /// The reading thread waiting for the workers to convert rows
+    Wait,
+}
+
+const STAGES: [Stage; 5] = [
+    Stage::Read,
+    Stage::Transform,
+    Stage::Serialize,
+    Stage::Write,
+    Stage::Wait,
+];
+
+impl Stage {
+    fn label(&self) -> &'static str {
+        match self {
+            Stage::Read => "read/decode",
+            Stage::Transform => "transform",
+            Stage::Serialize => "serialize",
+            Stage::Write => "write",
+            Stage::Wait => "waiting",
+        }
+    }
+}
+
+pub fn enable_stage_timing() {
+    STAGES_ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Runs `f`, adding the time it takes to `stage`. Time spent in a stage
+/// timed inside `f` counts toward that stage only, so stages never overlap.
+pub fn timed<T>(stage: Stage, f: impl FnOnce() -> T) -> T {
+    if !STAGES_ENABLED.load(Ordering::Relaxed) {
+        return f();
+    }
+    let start = Instant::now();
+    let outer = CURRENT_STAGE.replace(Some((stage, start)));
+    if let Some((outer_stage, since)) = outer {
+        add(outer_stage, start - since);
+    }
+    let result = f();
+    let end = Instant::now();
+    if let Some((_, since)) = CURRENT_STAGE.get() {
+        add(stage, end - since);
+    }
+    CURRENT_STAGE.set(outer.map(|(outer_stage, _)| (outer_stage, end)));
+    result
+}
+
+fn add(stage: Stage, elapsed: Duration) {
+    STAGE_NANOS[stage as usize].fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+}
+
+/// The time spent in each stage, summed over every thread.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StageTimes([Duration; STAGES.len()]);
+
+impl StageTimes {
+    /// The stage times of this process so far.
+    pub fn now() -> Self {
+        StageTimes(std::array::from_fn(|index| {
+            Duration::from_nanos(STAGE_NANOS[index].load(Ordering::Relaxed))
+        }))
+    }
+
+    /// The time spent in each stage since `earlier`.
+    pub fn since(&self, earlier: &StageTimes) -> Self {
+        StageTimes(std::array::from_fn(|index| {
+            self.0[index].saturating_sub(earlier.0[index])
+        }))
+    }
+}
+
+impl fmt::Display for StageTimes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Stage times (summed over threads):")?;
+        for stage in STAGES {
+            let elapsed = self.0[stage as usize];
+            // Only conversions on several threads wait for them
+            if stage == Stage::Wait && elapsed.is_zero() {
+                continue;
+            }
+            writeln!(f, "  {:<12}{:.3} s", stage.label(), elapsed.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+/// Wraps the output, timing writes as `Stage::Write`.
+pub struct TimedWriter<W> {
+    inner: W,
+}
+
+impl<W> TimedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        TimedWriter { inner }
+    }
+}
+
+impl<W: Write> Write for TimedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        timed(Stage::Write, || self.inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        timed(Stage::Write, || self.inner.flush())
+    }
+}
+
+impl Sink for TimedWriter<Box<dyn Sink>> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        timed(Stage::Write, || self.inner.finish())
+    }
+}
+
+/// Wraps the input, counting the bytes read so far.
+pub struct CountingReader<R> {
+    inner: R,
+    read: Arc<AtomicU64>,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub fn new(inner: R) -> Self {
+        let read = Arc::new(AtomicU64::new(0));
+        INPUT_READ.set(Arc::clone(&read));
+        CountingReader { inner, read }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Bytes of input read through the `CountingReader` created last on this thread.
+pub fn input_read() -> u64 {
+    INPUT_READ.with_borrow(|read| read.load(Ordering::Relaxed))
+}
+
+/// The `--timing` summary of a finished conversion.
+#[derive(Debug)]
+pub struct Timing {
+    pub records: usize,
+    pub bytes_read: u64,
+    pub elapsed: Duration,
+    pub peak_memory: Option<u64>,
+    pub stages: StageTimes,
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.elapsed.as_secs_f64();
+        let rate = if seconds > 0.0 {
+            (self.records as f64 / seconds) as u64
+        } else {
+            0
+        };
+        writeln!(f, "Records:     {}", self.records)?;
+        writeln!(f, "Bytes read:  {}", human_bytes(self.bytes_read))?;
+        writeln!(f, "Wall time:   {:.3} s", seconds)?;
+        writeln!(f, "Throughput:  {} records/s", rate)?;
+        match self.peak_memory {
+            Some(bytes) => writeln!(f, "Peak memory: {}", human_bytes(bytes))?,
+            None => writeln!(f, "Peak memory: unknown")?,
+        }
+        write!(f, "{}", self.stages)
+    }
+}
+
+/// The peak resident set size of this process.
+#[cfg(unix)]
+pub fn peak_memory() -> Option<u64> {
+    // SAFETY: getrusage only writes to the zeroed struct we pass it
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+    let max_rss = u64::try_from(usage.ru_maxrss).ok()?;
+    // macOS reports bytes, other systems kilobytes
+    if cfg!(target_os = "macos") {
+        Some(max_rss)
+    } else {
+        Some(max_rss * 1024)
+    }
+}
+
+#[cfg(not(unix))]
+pub fn peak_memory() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counting_reader() {
+        let mut reader = CountingReader::new("a,b\n1,2\n".as_bytes());
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+        assert_eq!(input_read(), 8);
+    }
+
+    #[test]
+    fn test_timed_stages_do_not_overlap() {
+        enable_stage_timing();
+        let before = StageTimes::now();
+        timed(Stage::Read, || {
+            std::thread::sleep(Duration::from_millis(10));
+            timed(Stage::Write, || {
+                std::thread::sleep(Duration::from_millis(40))
+            });
+        });
+        let stages = StageTimes::now().since(&before);
+        let read = stages.0[Stage::Read as usize];
+        let write = stages.0[Stage::Write as usize];
+        assert!(read >= Duration::from_millis(10));
+        assert!(write >= Duration::from_millis(40));
+        // Had the write counted toward reading too, reading would take longer
+        assert!(read < write);
+    }
+
+    #[test]
+    fn test_timing_report() {
+        let timing = Timing {
+            records: 5000,
+            bytes_read: 3 * 1024 * 1024,
+            elapsed: Duration::from_millis(2500),
+            peak_memory: Some(10 * 1024 * 1024),
+            stages: StageTimes([
+                Duration::from_millis(1200),
+                Duration::from_millis(800),
+                Duration::from_millis(300),
+                Duration::from_millis(200),
+                Duration::ZERO,
+            ]),
+        };
+        assert_eq!(
+            timing.to_string(),
+            "Records:     5000\n\
+             Bytes read:  3.0 MiB\n\
+             Wall time:   2.500 s\n\
+             Throughput:  2000 records/s\n\
+             Peak memory: 10.0 MiB\n\
+             Stage times (summed over threads):\n\
+             \x20 read/decode 1.200 s\n\
+             \x20 transform   0.800 s\n\
+             \x20 serialize   0.300 s\n\
+             \x20 write       0.200 s\n"
+        );
+        #[cfg(unix)]
+        assert!(peak_memory().is_some_and(|bytes| bytes > 0));
+    }
+}
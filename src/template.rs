@@ -0,0 +1,101 @@
+//! `--output-template`: names the output files of a directory conversion,
+//! e.g. `{dir}/{stem}.json` or `out/{stem}.{ext}.gz`.
+
+use serde::{Deserialize, Serialize};
+
+/// The placeholders a template may use.
+const PLACEHOLDERS: [&str; 4] = ["dir", "stem", "name", "ext"];
+
+/// An output path with `{placeholder}`s, checked when it is parsed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputTemplate(String);
+
+/// The values of the placeholders for one input file.
+pub struct Fields<'a> {
+    /// Where the output goes by default: next to the input, or the matching
+    /// directory under `--output`
+    pub dir: &'a str,
+    /// The input file name without its extension
+    pub stem: &'a str,
+    /// The input file name
+    pub name: &'a str,
+    /// `json` or `jsonl`, following `--format`
+    pub ext: &'a str,
+}
+
+impl OutputTemplate {
+    pub fn render(&self, fields: &Fields) -> String {
+        expand(&self.0, |placeholder| match placeholder {
+            "dir" => Some(fields.dir),
+            "stem" => Some(fields.stem),
+            "name" => Some(fields.name),
+            "ext" => Some(fields.ext),
+            _ => None,
+        })
+        .expect("the template was checked when parsed")
+    }
+}
+
+/// Parses a template, rejecting unknown placeholders and unmatched braces.
+pub fn parse_template(s: &str) -> Result<OutputTemplate, String> {
+    expand(s, |placeholder| {
+        PLACEHOLDERS.contains(&placeholder).then_some("")
+    })?;
+    Ok(OutputTemplate(s.to_string()))
+}
+
+fn expand<'a>(template: &str, lookup: impl Fn(&str) -> Option<&'a str>) -> Result<String, String> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        expanded.push_str(&rest[..start]);
+        if rest[start..].starts_with('}') {
+            return Err(format!("unmatched '}}' in '{}'", template));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unmatched '{{' in '{}'", template))?;
+        let placeholder = &rest[start + 1..start + end];
+        let value = lookup(placeholder).ok_or_else(|| {
+            format!(
+                "unknown placeholder '{{{}}}', expected one of {{{}}}",
+                placeholder,
+                PLACEHOLDERS.join("}, {")
+            )
+        })?;
+        expanded.push_str(value);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let fields = Fields {
+            dir: "data/2024",
+            stem: "sales",
+            name: "sales.csv",
+            ext: "jsonl",
+        };
+        let template = parse_template("{dir}/{stem}.json").unwrap();
+        assert_eq!(template.render(&fields), "data/2024/sales.json");
+        let template = parse_template("out/{name}.{ext}.gz").unwrap();
+        assert_eq!(template.render(&fields), "out/sales.csv.jsonl.gz");
+        let template = parse_template("all.json").unwrap();
+        assert_eq!(template.render(&fields), "all.json");
+    }
+
+    #[test]
+    fn test_parse_template_errors() {
+        assert!(parse_template("{dir}/{base}.json")
+            .unwrap_err()
+            .contains("unknown placeholder '{base}', expected one of {dir}, {stem}"));
+        assert!(parse_template("{dir/x.json").is_err());
+        assert!(parse_template("dir}/x.json").is_err());
+    }
+}
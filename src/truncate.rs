@@ -0,0 +1,97 @@
+//! Length limits on text columns, e.g. `description:500`, for stores that
+//! reject documents with oversized fields.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Ends a shortened value, and counts toward the limit.
+const ELLIPSIS: char = '…';
+
+/// Limits one column's strings to `max_chars` characters.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Truncation {
+    pub column: String,
+    pub max_chars: usize,
+}
+
+impl Truncation {
+    /// Shortens a string longer than the limit so that, with an ellipsis
+    /// added, it is exactly the limit. Returns whether the value changed;
+    /// anything but a string is left alone.
+    pub fn apply(&self, value: &mut Value) -> bool {
+        let Value::String(text) = value else {
+            return false;
+        };
+        if text.char_indices().nth(self.max_chars).is_none() {
+            return false;
+        }
+        // The limit is at least 1, so there is room for the ellipsis
+        let (keep, _) = text
+            .char_indices()
+            .nth(self.max_chars - 1)
+            .expect("the text is longer than the limit");
+        text.truncate(keep);
+        text.push(ELLIPSIS);
+        true
+    }
+
+    /// The name of the `--truncate-flag` field saying whether a value was cut.
+    pub fn flag_name(&self) -> String {
+        format!("{}_truncated", self.column)
+    }
+}
+
+/// Parses `COLUMN:MAX[,COLUMN:MAX...]`, where MAX is a length in characters.
+pub fn parse_truncations(s: &str) -> Result<Vec<Truncation>, String> {
+    s.split(',').map(parse_truncation).collect()
+}
+
+fn parse_truncation(s: &str) -> Result<Truncation, String> {
+    let error = || format!("expected COLUMN:MAX with MAX at least 1, got '{}'", s);
+    let (column, max_chars) = s.rsplit_once(':').ok_or_else(error)?;
+    match max_chars.trim().parse() {
+        Ok(max_chars) if max_chars > 0 && !column.is_empty() => Ok(Truncation {
+            column: column.to_string(),
+            max_chars,
+        }),
+        _ => Err(error()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_truncations() {
+        let truncations = parse_truncations("description:500,a:b:3").unwrap();
+        assert_eq!(truncations[0].column, "description");
+        assert_eq!(truncations[0].max_chars, 500);
+        assert_eq!(truncations[1].column, "a:b");
+        assert_eq!(truncations[1].flag_name(), "a:b_truncated");
+
+        assert!(parse_truncations("description").is_err());
+        assert!(parse_truncations("description:0").is_err());
+        assert!(parse_truncations(":5").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let truncation = parse_truncations("note:5").unwrap().remove(0);
+        let mut value = Value::from("short");
+        assert!(!truncation.apply(&mut value));
+        assert_eq!(value, "short");
+
+        let mut value = Value::from("longer text");
+        assert!(truncation.apply(&mut value));
+        assert_eq!(value, "long…");
+
+        // Characters, not bytes, are counted
+        let mut value = Value::from("日本語のテキスト");
+        assert!(truncation.apply(&mut value));
+        assert_eq!(value, "日本語の…");
+
+        let mut value = Value::from(1234567);
+        assert!(!truncation.apply(&mut value));
+    }
+}
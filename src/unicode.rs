@@ -0,0 +1,224 @@
+//! `--normalize-unicode`: Unicode normalization of headers and values, so
+//! text that looks the same is the same string, such as a `ガ` typed on a Mac
+//! (`カ` and a combining mark) and on Windows (one character).
+
+use crate::unicode_data::{CANONICAL, COMBINING_CLASSES, COMPATIBILITY, COMPOSITIONS};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+
+/// A Unicode normalization form.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum Form {
+    /// Canonical composition: only combines characters with their marks
+    Nfc,
+    /// Compatibility composition: also folds variants such as full-width
+    /// letters, half-width katakana and ligatures into their plain forms
+    Nfkc,
+}
+
+// Hangul syllables are composed of leading consonants, vowels and trailing
+// consonants by arithmetic
+const S_BASE: u32 = 0xAC00;
+const L_BASE: u32 = 0x1100;
+const V_BASE: u32 = 0x1161;
+const T_BASE: u32 = 0x11A7;
+const L_COUNT: u32 = 19;
+const V_COUNT: u32 = 21;
+const T_COUNT: u32 = 28;
+const S_COUNT: u32 = L_COUNT * V_COUNT * T_COUNT;
+
+/// Normalizes `text` to `form`, borrowing it when it is ASCII, which every
+/// form leaves as it is.
+pub fn normalize(text: &str, form: Form) -> Cow<'_, str> {
+    if text.is_ascii() {
+        return Cow::Borrowed(text);
+    }
+    let mut chars = decompose(text, form == Form::Nfkc);
+    compose(&mut chars);
+    let normalized: String = chars.into_iter().collect();
+    if normalized == text {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(normalized)
+    }
+}
+
+/// Normalizes the strings of a JSON value, and the keys of its objects.
+pub fn normalize_value(value: &mut Value, form: Form) {
+    match value {
+        Value::String(text) => {
+            if let Cow::Owned(normalized) = normalize(text, form) {
+                *text = normalized;
+            }
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| normalize_value(item, form)),
+        Value::Object(object) => {
+            *object = std::mem::take(object)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    normalize_value(&mut value, form);
+                    (normalize(&key, form).into_owned(), value)
+                })
+                .collect();
+        }
+        _ => {}
+    }
+}
+
+fn combining_class(c: char) -> u8 {
+    let c = c as u32;
+    match COMBINING_CLASSES.binary_search_by(|&(start, end, _)| {
+        if end < c {
+            std::cmp::Ordering::Less
+        } else if start > c {
+            std::cmp::Ordering::Greater
+        } else {
+            std::cmp::Ordering::Equal
+        }
+    }) {
+        Ok(index) => COMBINING_CLASSES[index].2,
+        Err(_) => 0,
+    }
+}
+
+fn decomposition(table: &'static [(u32, &'static [u32])], c: char) -> Option<&'static [u32]> {
+    let index = table.binary_search_by_key(&(c as u32), |&(c, _)| c).ok()?;
+    Some(table[index].1)
+}
+
+/// The full decomposition of `text`, with combining marks in canonical order.
+fn decompose(text: &str, compatibility: bool) -> Vec<char> {
+    let mut chars = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        let code = c as u32;
+        if (S_BASE..S_BASE + S_COUNT).contains(&code) {
+            let index = code - S_BASE;
+            let (l, v, t) = (
+                index / (V_COUNT * T_COUNT),
+                index / T_COUNT % V_COUNT,
+                index % T_COUNT,
+            );
+            chars.extend([L_BASE + l, V_BASE + v].map(char_of));
+            if t > 0 {
+                chars.push(char_of(T_BASE + t));
+            }
+            continue;
+        }
+        let decomposed = compatibility
+            .then(|| decomposition(&COMPATIBILITY, c))
+            .flatten()
+            .or_else(|| decomposition(&CANONICAL, c));
+        match decomposed {
+            Some(decomposed) => chars.extend(decomposed.iter().map(|&c| char_of(c))),
+            None => chars.push(c),
+        }
+    }
+    // A stable sort of each run of combining marks by class
+    let mut start = 0;
+    while start < chars.len() {
+        if combining_class(chars[start]) == 0 {
+            start += 1;
+            continue;
+        }
+        let end = chars[start..]
+            .iter()
+            .position(|&c| combining_class(c) == 0)
+            .map_or(chars.len(), |length| start + length);
+        chars[start..end].sort_by_key(|&c| combining_class(c));
+        start = end;
+    }
+    chars
+}
+
+fn char_of(code: u32) -> char {
+    char::from_u32(code).expect("tables hold characters")
+}
+
+fn composite(first: char, second: char) -> Option<char> {
+    let (first, second) = (first as u32, second as u32);
+    if (L_BASE..L_BASE + L_COUNT).contains(&first) && (V_BASE..V_BASE + V_COUNT).contains(&second) {
+        let syllable = S_BASE + ((first - L_BASE) * V_COUNT + second - V_BASE) * T_COUNT;
+        return Some(char_of(syllable));
+    }
+    if (S_BASE..S_BASE + S_COUNT).contains(&first)
+        && (first - S_BASE).is_multiple_of(T_COUNT)
+        && (T_BASE + 1..T_BASE + T_COUNT).contains(&second)
+    {
+        return Some(char_of(first + second - T_BASE));
+    }
+    let index = COMPOSITIONS
+        .binary_search_by_key(&(first, second), |&(a, b, _)| (a, b))
+        .ok()?;
+    Some(char_of(COMPOSITIONS[index].2))
+}
+
+/// Composes decomposed characters in place: each mark joins the starter
+/// before it unless another mark of the same or a lower class comes
+/// between them.
+fn compose(chars: &mut Vec<char>) {
+    let mut starter = None;
+    // The class of the last character kept since the starter, if any
+    let mut last_class = None;
+    let mut kept = 0;
+    for index in 0..chars.len() {
+        let c = chars[index];
+        let class = combining_class(c);
+        if let Some(position) = starter {
+            let blocked = match last_class {
+                None => false,
+                Some(last) => last == 0 || last >= class,
+            };
+            if !blocked {
+                if let Some(composed) = composite(chars[position], c) {
+                    chars[position] = composed;
+                    continue;
+                }
+            }
+        }
+        if class == 0 {
+            starter = Some(kept);
+            last_class = None;
+        } else {
+            last_class = Some(class);
+        }
+        chars[kept] = c;
+        kept += 1;
+    }
+    chars.truncate(kept);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nfc() {
+        // "ガ" from the Mac, as カ and a combining voiced sound mark
+        assert_eq!(normalize("\u{30ab}\u{3099}", Form::Nfc), "\u{30ac}");
+        assert_eq!(normalize("e\u{301}\u{327}", Form::Nfc), "\u{229}\u{301}");
+        assert_eq!(normalize("\u{1100}\u{1161}\u{11a8}", Form::Nfc), "\u{ac01}");
+        // Full-width letters stay as they are
+        assert_eq!(normalize("ＡＢＣ", Form::Nfc), "ＡＢＣ");
+        assert!(matches!(normalize("café", Form::Nfc), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_nfkc() {
+        assert_eq!(normalize("ＡＢＣ１２３", Form::Nfkc), "ABC123");
+        assert_eq!(normalize("ｶﾞｷﾞ", Form::Nfkc), "ガギ");
+        assert_eq!(normalize("①㈱ﬁ", Form::Nfkc), "1(株)fi");
+    }
+
+    #[test]
+    fn test_normalize_value() {
+        let mut value = serde_json::json!({"ｶﾅ": ["ﾃｽﾄ", 1, {"Ａ": null}]});
+        normalize_value(&mut value, Form::Nfkc);
+        assert_eq!(
+            value,
+            serde_json::json!({"カナ": ["テスト", 1, {"A": null}]})
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Warnings about data that converted with a caveat, such as a row padded
+//! with nulls. Each class of warning is counted, only the first few of each
+//! class are printed, and the counts are summarized when conversion ends.
+
+use crate::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A class of warning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Warning {
+    /// Two columns have the same name, so one value replaces the other
+    DuplicateHeader,
+    /// A row has a different number of fields than the header
+    RaggedRow,
+    /// A record has no value for a column, so it is set to null
+    MissingField,
+    /// A number has more digits than a JSON number can hold exactly
+    PrecisionLoss,
+    /// A record has a key that is not a column, so it is left out
+    ExtraField,
+}
+
+const CLASSES: [Warning; 5] = [
+    Warning::DuplicateHeader,
+    Warning::RaggedRow,
+    Warning::MissingField,
+    Warning::PrecisionLoss,
+    Warning::ExtraField,
+];
+
+impl Warning {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Warning::DuplicateHeader => "duplicate header",
+            Warning::RaggedRow => "ragged row",
+            Warning::MissingField => "missing field",
+            Warning::PrecisionLoss => "precision loss",
+            Warning::ExtraField => "extra field",
+        }
+    }
+}
+
+/// Counts warnings across the threads of a conversion. By default each class
+/// is printed once; `--max-warnings` changes how many are printed per class,
+/// and `--fatal-warnings` turns the first warning into an error.
+#[derive(Debug)]
+pub struct Warnings {
+    counts: [AtomicUsize; CLASSES.len()],
+    max_shown: usize,
+    fatal: bool,
+    quiet: bool,
+}
+
+impl Warnings {
+    pub fn new(max_warnings: Option<usize>, fatal: bool, quiet: bool) -> Self {
+        Warnings {
+            counts: Default::default(),
+            max_shown: max_warnings.unwrap_or(1),
+            fatal,
+            quiet,
+        }
+    }
+
+    /// Records a warning. The message is only built if it is shown.
+    pub fn warn(&self, class: Warning, message: impl FnOnce() -> String) -> Result<(), Error> {
+        let count = self.counts[class as usize].fetch_add(1, Ordering::Relaxed) + 1;
+        if self.fatal {
+            return Err(Error::Validation(format!(
+                "{} (warnings are fatal)",
+                message()
+            )));
+        }
+        if !self.quiet {
+            if count <= self.max_shown {
+                eprintln!("Warning: {}", message());
+            } else if count == self.max_shown + 1 && self.max_shown > 0 {
+                eprintln!(
+                    "Warning: further {} warnings are counted but not shown",
+                    class.name()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn count(&self, class: Warning) -> usize {
+        self.counts[class as usize].load(Ordering::Relaxed)
+    }
+
+    /// Every class of warning with its count, including those never raised.
+    pub fn counts(&self) -> impl Iterator<Item = (Warning, usize)> + '_ {
+        CLASSES.into_iter().map(|class| (class, self.count(class)))
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        self.fatal
+    }
+
+    /// Summarizes the warnings, e.g. `ragged row (3), precision loss (1)`,
+    /// or returns `None` if there were none.
+    pub fn summary(&self) -> Option<String> {
+        let counts: Vec<String> = self
+            .counts()
+            .filter(|(_, count)| *count > 0)
+            .map(|(class, count)| format!("{} ({})", class.name(), count))
+            .collect();
+        (!counts.is_empty()).then(|| counts.join(", "))
+    }
+
+    /// Prints the summary on stderr unless `--quiet` is set.
+    pub fn finish(&self) {
+        if let Some(summary) = self.summary() {
+            if !self.quiet {
+                eprintln!("Warnings: {}", summary);
+            }
+        }
+    }
+}
+
+/// Whether a number field has significant digits that its JSON value, an
+/// `f64`, drops, as in `12345678901234567890` or `0.12345678901234567891`.
+pub fn loses_precision(field: &str, value: f64) -> bool {
+    // Up to 15 significant digits always survive the round trip
+    let digits = field.bytes().filter(u8::is_ascii_digit).count();
+    if digits <= 15 || field.contains(['e', 'E']) {
+        return false;
+    }
+    significant_digits(field) != significant_digits(&value.to_string())
+}
+
+/// The digits of a plain decimal number without leading or trailing zeros.
+fn significant_digits(number: &str) -> String {
+    let digits: String = number.chars().filter(char::is_ascii_digit).collect();
+    digits
+        .trim_start_matches('0')
+        .trim_end_matches('0')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_and_summary() {
+        let warnings = Warnings::new(Some(0), false, true);
+        assert_eq!(warnings.summary(), None);
+        for _ in 0..3 {
+            warnings
+                .warn(Warning::RaggedRow, || "short row".to_string())
+                .unwrap();
+        }
+        warnings
+            .warn(Warning::PrecisionLoss, || "long number".to_string())
+            .unwrap();
+        assert_eq!(warnings.count(Warning::RaggedRow), 3);
+        assert_eq!(
+            warnings.summary().unwrap(),
+            "ragged row (3), precision loss (1)"
+        );
+
+        let fatal = Warnings::new(None, true, true);
+        let error = fatal
+            .warn(Warning::DuplicateHeader, || "duplicate 'id'".to_string())
+            .unwrap_err();
+        assert_eq!(error.exit_code(), 6);
+    }
+
+    #[test]
+    fn test_loses_precision() {
+        let check = |field: &str| loses_precision(field, field.parse().unwrap());
+        assert!(check("12345678901234567890"));
+        assert!(check("0.12345678901234567891"));
+        assert!(!check("123456789012345.5"));
+        assert!(!check("10000000000000000000"));
+        assert!(!check("2.50"));
+        assert!(!check("1.5e300"));
+    }
+}
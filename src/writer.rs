@@ -0,0 +1,813 @@
+use crate::arrow;
+use crate::avro;
+use crate::bson;
+use crate::cbor;
+use crate::compression::Sink;
+use crate::error::Error;
+use crate::msgpack;
+use crate::postgres;
+use crate::sqlite;
+use crate::{OutputFormat, Record};
+use serde_json::Value;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Writes records as a JSON array one at a time, so output never has to be
+/// assembled in memory. Pretty output matches `serde_json::to_writer_pretty`.
+pub struct JsonArrayWriter<W: Write> {
+    writer: W,
+    pretty: bool,
+    count: usize,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> JsonArrayWriter<W> {
+    pub fn new(writer: W, pretty: bool) -> Self {
+        JsonArrayWriter {
+            writer,
+            pretty,
+            count: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Continues an array of earlier records whose closing bracket has been
+    /// cut from the end of `writer`, for `--append`.
+    pub fn appending(writer: W, pretty: bool) -> Self {
+        JsonArrayWriter {
+            count: 1,
+            ..JsonArrayWriter::new(writer, pretty)
+        }
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
+        self.writer
+            .write_all(if self.count == 0 { b"[" } else { b"," })?;
+
+        if self.pretty {
+            // Pretty-print the record on its own, then indent it one level
+            // to nest it inside the array. Newlines inside strings are
+            // escaped, so every raw newline is structural.
+            self.buffer.clear();
+            serde_json::to_writer_pretty(&mut self.buffer, record)?;
+            self.writer.write_all(b"\n  ")?;
+            for (i, line) in self.buffer.split(|&b| b == b'\n').enumerate() {
+                if i > 0 {
+                    self.writer.write_all(b"\n  ")?;
+                }
+                self.writer.write_all(line)?;
+            }
+        } else {
+            serde_json::to_writer(&mut self.writer, record)?;
+        }
+
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Closes the array and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        let closing: &[u8] = match (self.count, self.pretty) {
+            (0, _) => b"[]",
+            (_, true) => b"\n]",
+            (_, false) => b"]",
+        };
+        self.writer.write_all(closing)?;
+        Ok(self.writer)
+    }
+}
+
+/// Writes records as JSON Lines (NDJSON): one compact object per line.
+pub struct JsonLinesWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    pub fn new(writer: W) -> Self {
+        JsonLinesWriter { writer }
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<W, Error> {
+        Ok(self.writer)
+    }
+}
+
+/// Writes records as a stream of MessagePack maps, one after another with no
+/// enclosing array, like JSON Lines.
+pub struct MessagePackWriter<W: Write> {
+    writer: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> MessagePackWriter<W> {
+    pub fn new(writer: W) -> Self {
+        MessagePackWriter {
+            writer,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
+        self.buffer.clear();
+        msgpack::encode_record(record, &mut self.buffer);
+        self.writer.write_all(&self.buffer)?;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<W, Error> {
+        Ok(self.writer)
+    }
+}
+
+/// Writes records as BSON documents, one after another with no enclosing
+/// array, as `mongorestore` reads them.
+pub struct BsonWriter<W: Write> {
+    writer: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> BsonWriter<W> {
+    pub fn new(writer: W) -> Self {
+        BsonWriter {
+            writer,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
+        self.buffer.clear();
+        bson::encode_record(record, &mut self.buffer)?;
+        self.writer.write_all(&self.buffer)?;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<W, Error> {
+        Ok(self.writer)
+    }
+}
+
+/// Writes records as an Avro object container file. Without a schema, the
+/// records are held until `finish` to infer one from all of them; with one,
+/// they are written in blocks as they come.
+pub struct AvroWriter<W: Write> {
+    writer: W,
+    schema: Option<Arc<avro::Schema>>,
+    held: Vec<Record>,
+    block: Vec<u8>,
+    block_count: usize,
+    count: usize,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> AvroWriter<W> {
+    pub fn new(writer: W, schema: Option<Arc<avro::Schema>>) -> Self {
+        AvroWriter {
+            writer,
+            schema,
+            held: Vec::new(),
+            block: Vec::new(),
+            block_count: 0,
+            count: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
+        match &self.schema {
+            Some(_) => self.encode(record),
+            None => {
+                self.held.push(record.clone());
+                Ok(())
+            }
+        }
+    }
+
+    fn encode(&mut self, record: &Record) -> Result<(), Error> {
+        let schema = self.schema.as_deref().expect("the schema is known");
+        if self.count == 0 {
+            self.buffer.clear();
+            schema.write_header(&mut self.buffer);
+            self.writer.write_all(&self.buffer)?;
+        }
+        self.count += 1;
+        schema.encode(record, &mut self.block).map_err(|e| {
+            Error::Validation(format!(
+                "Record {} does not match the Avro schema: {}",
+                self.count, e
+            ))
+        })?;
+        self.block_count += 1;
+        if self.block.len() >= avro::BLOCK_SIZE {
+            self.flush_block()?;
+        }
+        Ok(())
+    }
+
+    fn flush_block(&mut self) -> Result<(), Error> {
+        let schema = self.schema.as_deref().expect("the schema is known");
+        self.buffer.clear();
+        avro::write_block(
+            self.block_count,
+            &self.block,
+            &schema.sync_marker(),
+            &mut self.buffer,
+        );
+        self.writer.write_all(&self.buffer)?;
+        self.block.clear();
+        self.block_count = 0;
+        Ok(())
+    }
+
+    /// Writes the held records or the last block and returns the underlying
+    /// writer. A file with no records still has a header.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.schema.is_none() {
+            self.schema = Some(Arc::new(avro::infer_schema(&self.held)));
+            for record in std::mem::take(&mut self.held) {
+                self.encode(&record)?;
+            }
+        }
+        if self.count == 0 {
+            let schema = self.schema.as_deref().expect("the schema is known");
+            self.buffer.clear();
+            schema.write_header(&mut self.buffer);
+            self.writer.write_all(&self.buffer)?;
+        } else if self.block_count > 0 {
+            self.flush_block()?;
+        }
+        Ok(self.writer)
+    }
+}
+
+/// Writes records as an Arrow IPC file. Columns are typed from every value,
+/// so the records are held until `finish`.
+pub struct ArrowWriter<W: Write> {
+    writer: W,
+    held: Vec<Record>,
+}
+
+impl<W: Write> ArrowWriter<W> {
+    pub fn new(writer: W) -> Self {
+        ArrowWriter {
+            writer,
+            held: Vec::new(),
+        }
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
+        self.held.push(record.clone());
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, Error> {
+        let mut file = Vec::new();
+        arrow::write_file(&self.held, &mut file)?;
+        self.writer.write_all(&file)?;
+        Ok(self.writer)
+    }
+}
+
+/// The table SQLite and PostgreSQL output create when `--table` is not given.
+pub const DEFAULT_TABLE: &str = "records";
+
+/// Writes records as a SQLite database with one table. Column types come
+/// from every value, so the records are held until `finish`.
+pub struct SqliteWriter<W: Write> {
+    writer: W,
+    held: Vec<Record>,
+    table: String,
+}
+
+impl<W: Write> SqliteWriter<W> {
+    pub fn new(writer: W, table: &str) -> Self {
+        SqliteWriter {
+            writer,
+            held: Vec::new(),
+            table: table.to_string(),
+        }
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
+        self.held.push(record.clone());
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, Error> {
+        let mut file = Vec::new();
+        sqlite::write_database(&self.held, &self.table, &mut file)?;
+        self.writer.write_all(&file)?;
+        Ok(self.writer)
+    }
+}
+
+/// Writes records as a PostgreSQL script that creates a table and loads it
+/// with `COPY`. Column types come from every value, so the records are held
+/// until `finish`.
+pub struct PostgresWriter<W: Write> {
+    writer: W,
+    held: Vec<Record>,
+    table: String,
+}
+
+impl<W: Write> PostgresWriter<W> {
+    pub fn new(writer: W, table: &str) -> Self {
+        PostgresWriter {
+            writer,
+            held: Vec::new(),
+            table: table.to_string(),
+        }
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
+        self.held.push(record.clone());
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, Error> {
+        postgres::write_script(&self.held, &self.table, &mut self.writer)?;
+        Ok(self.writer)
+    }
+}
+
+/// Writes records as a CBOR array of maps. The array is encoded with an
+/// indefinite length, so records can be written before their count is known.
+pub struct CborWriter<W: Write> {
+    writer: W,
+    started: bool,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> CborWriter<W> {
+    pub fn new(writer: W) -> Self {
+        CborWriter {
+            writer,
+            started: false,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
+        self.buffer.clear();
+        if !self.started {
+            self.buffer.push(cbor::ARRAY_START);
+            self.started = true;
+        }
+        cbor::encode_record(record, &mut self.buffer);
+        self.writer.write_all(&self.buffer)?;
+        Ok(())
+    }
+
+    /// Closes the array and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if !self.started {
+            self.writer.write_all(&[cbor::ARRAY_START])?;
+        }
+        self.writer.write_all(&[cbor::BREAK])?;
+        Ok(self.writer)
+    }
+}
+
+/// The page around a `--pretty` HTML table, with a minimal style sheet.
+const HTML_PAGE_START: &str = "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<style>
+table { border-collapse: collapse; font-family: sans-serif; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }
+th { background: #f4f4f4; }
+tbody tr:nth-child(even) { background: #fafafa; }
+</style>
+</head>
+<body>
+";
+const HTML_PAGE_END: &str = "</body>
+</html>";
+
+/// Writes records as the rows of an HTML table. The columns are those of the
+/// first record; a later record's other fields are left out. With `pretty`
+/// the table is a complete page with a minimal style, one row per line.
+pub struct HtmlTableWriter<W: Write> {
+    writer: W,
+    pretty: bool,
+    columns: Option<Vec<Arc<str>>>,
+}
+
+impl<W: Write> HtmlTableWriter<W> {
+    pub fn new(writer: W, pretty: bool) -> Self {
+        HtmlTableWriter {
+            writer,
+            pretty,
+            columns: None,
+        }
+    }
+
+    fn newline(&self) -> &'static str {
+        if self.pretty {
+            "\n"
+        } else {
+            ""
+        }
+    }
+
+    fn start(&mut self) -> Result<(), Error> {
+        if self.pretty {
+            self.writer.write_all(HTML_PAGE_START.as_bytes())?;
+        }
+        write!(self.writer, "<table>{}", self.newline())?;
+        Ok(())
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
+        let newline = self.newline();
+        let columns = match &self.columns {
+            Some(columns) => columns,
+            None => {
+                self.start()?;
+                write!(self.writer, "<thead>{}<tr>", newline)?;
+                for column in record.keys() {
+                    write!(self.writer, "<th>{}</th>", escape_html(column))?;
+                }
+                write!(self.writer, "</tr>{0}</thead>{0}<tbody>{0}", newline)?;
+                self.columns.insert(record.keys().cloned().collect())
+            }
+        };
+        self.writer.write_all(b"<tr>")?;
+        for column in columns {
+            let cell = match record.get(column) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(text)) => escape_html(text),
+                Some(other) => escape_html(&other.to_string()),
+            };
+            write!(self.writer, "<td>{}</td>", cell)?;
+        }
+        write!(self.writer, "</tr>{}", newline)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, Error> {
+        let newline = self.newline();
+        if self.columns.is_none() {
+            self.start()?;
+        } else {
+            write!(self.writer, "</tbody>{}", newline)?;
+        }
+        write!(self.writer, "</table>{}", newline)?;
+        if self.pretty {
+            self.writer.write_all(HTML_PAGE_END.as_bytes())?;
+        }
+        Ok(self.writer)
+    }
+}
+
+/// Escapes text for an HTML element or attribute.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes every non-ASCII character of JSON text as `\uXXXX`, with
+/// surrogate pairs beyond the Basic Multilingual Plane, for `--ascii`. JSON
+/// only has such characters inside strings, where the escape means the same.
+pub struct AsciiEscaper<W: Write> {
+    inner: W,
+    /// The start of a character whose remaining bytes come in the next write.
+    partial: Vec<u8>,
+}
+
+impl<W: Write> AsciiEscaper<W> {
+    pub fn new(inner: W) -> Self {
+        AsciiEscaper {
+            inner,
+            partial: Vec::new(),
+        }
+    }
+
+    fn escape(&mut self, text: &str) -> io::Result<()> {
+        let mut rest = text;
+        while let Some(start) = rest.find(|c: char| !c.is_ascii()) {
+            self.inner.write_all(&rest.as_bytes()[..start])?;
+            let c = rest[start..].chars().next().expect("found above");
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                write!(self.inner, "\\u{:04x}", unit)?;
+            }
+            rest = &rest[start + c.len_utf8()..];
+        }
+        self.inner.write_all(rest.as_bytes())
+    }
+}
+
+impl<W: Write> Write for AsciiEscaper<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bytes = std::mem::take(&mut self.partial);
+        bytes.extend_from_slice(buf);
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            match std::str::from_utf8(rest) {
+                Ok(text) => {
+                    self.escape(text)?;
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    self.escape(std::str::from_utf8(valid).expect("checked valid"))?;
+                    match e.error_len() {
+                        // Not JSON text, so passed on as it is
+                        Some(len) => {
+                            self.inner.write_all(&after[..len])?;
+                            rest = &after[len..];
+                        }
+                        None => {
+                            self.partial = after.to_vec();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Sink for AsciiEscaper<Box<dyn Sink>> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        let partial = std::mem::take(&mut self.partial);
+        self.inner.write_all(&partial)?;
+        self.inner.finish()
+    }
+}
+
+/// A writer for the selected output format.
+pub enum RecordWriter<W: Write> {
+    Array(JsonArrayWriter<W>),
+    Lines(JsonLinesWriter<W>),
+    Html(HtmlTableWriter<W>),
+    MessagePack(MessagePackWriter<W>),
+    Cbor(CborWriter<W>),
+    Bson(BsonWriter<W>),
+    Avro(AvroWriter<W>),
+    Arrow(ArrowWriter<W>),
+    Sqlite(SqliteWriter<W>),
+    Postgres(PostgresWriter<W>),
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(writer: W, format: OutputFormat, pretty: bool) -> Self {
+        match format {
+            OutputFormat::Json => RecordWriter::Array(JsonArrayWriter::new(writer, pretty)),
+            OutputFormat::Jsonl => RecordWriter::Lines(JsonLinesWriter::new(writer)),
+            OutputFormat::Html => RecordWriter::Html(HtmlTableWriter::new(writer, pretty)),
+            OutputFormat::Msgpack => RecordWriter::MessagePack(MessagePackWriter::new(writer)),
+            OutputFormat::Cbor => RecordWriter::Cbor(CborWriter::new(writer)),
+            OutputFormat::Bson => RecordWriter::Bson(BsonWriter::new(writer)),
+            OutputFormat::Avro => RecordWriter::Avro(AvroWriter::new(writer, None)),
+            OutputFormat::Arrow => RecordWriter::Arrow(ArrowWriter::new(writer)),
+            OutputFormat::Sqlite => RecordWriter::Sqlite(SqliteWriter::new(writer, DEFAULT_TABLE)),
+            OutputFormat::Postgres => {
+                RecordWriter::Postgres(PostgresWriter::new(writer, DEFAULT_TABLE))
+            }
+        }
+    }
+
+    /// Writes after records already in `writer`; see `JsonArrayWriter::appending`.
+    pub fn appending(writer: W, format: OutputFormat, pretty: bool) -> Self {
+        match format {
+            OutputFormat::Json => RecordWriter::Array(JsonArrayWriter::appending(writer, pretty)),
+            _ => RecordWriter::new(writer, format, pretty),
+        }
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
+        match self {
+            RecordWriter::Array(writer) => writer.write_record(record),
+            RecordWriter::Lines(writer) => writer.write_record(record),
+            RecordWriter::Html(writer) => writer.write_record(record),
+            RecordWriter::MessagePack(writer) => writer.write_record(record),
+            RecordWriter::Cbor(writer) => writer.write_record(record),
+            RecordWriter::Bson(writer) => writer.write_record(record),
+            RecordWriter::Avro(writer) => writer.write_record(record),
+            RecordWriter::Arrow(writer) => writer.write_record(record),
+            RecordWriter::Sqlite(writer) => writer.write_record(record),
+            RecordWriter::Postgres(writer) => writer.write_record(record),
+        }
+    }
+
+    pub fn finish(self) -> Result<W, Error> {
+        match self {
+            RecordWriter::Array(writer) => writer.finish(),
+            RecordWriter::Lines(writer) => writer.finish(),
+            RecordWriter::Html(writer) => writer.finish(),
+            RecordWriter::MessagePack(writer) => writer.finish(),
+            RecordWriter::Cbor(writer) => writer.finish(),
+            RecordWriter::Bson(writer) => writer.finish(),
+            RecordWriter::Avro(writer) => writer.finish(),
+            RecordWriter::Arrow(writer) => writer.finish(),
+            RecordWriter::Sqlite(writer) => writer.finish(),
+            RecordWriter::Postgres(writer) => writer.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    fn sample_records() -> Vec<Record> {
+        let name: Arc<str> = Arc::from("name");
+        let note: Arc<str> = Arc::from("note");
+        vec![
+            Record::from([
+                (Arc::clone(&name), Value::from("John")),
+                (Arc::clone(&note), Value::from("line\nbreak")),
+            ]),
+            Record::from([(name, Value::from("Jane")), (note, Value::from(30))]),
+        ]
+    }
+
+    fn write_all(records: &[Record], pretty: bool) -> String {
+        let mut writer = JsonArrayWriter::new(Vec::new(), pretty);
+        for record in records {
+            writer.write_record(record).unwrap();
+        }
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_matches_serde_json_output() {
+        let records = sample_records();
+        assert_eq!(
+            write_all(&records, false),
+            serde_json::to_string(&records).unwrap()
+        );
+        assert_eq!(
+            write_all(&records, true),
+            serde_json::to_string_pretty(&records).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let mut writer = JsonLinesWriter::new(Vec::new());
+        for record in &sample_records() {
+            writer.write_record(record).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(writer.finish().unwrap()).unwrap(),
+            "{\"name\":\"John\",\"note\":\"line\\nbreak\"}\n{\"name\":\"Jane\",\"note\":30}\n"
+        );
+    }
+
+    /// A writer whose output can be read while the record writer still holds it.
+    #[derive(Clone, Default)]
+    struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_records_written_as_they_come() {
+        let records = sample_records();
+        let shared = Shared::default();
+        let mut writer = JsonArrayWriter::new(shared.clone(), false);
+        writer.write_record(&records[0]).unwrap();
+        // Each record reaches the writer before the next one is given
+        let first = shared.0.borrow().len();
+        assert!(String::from_utf8(shared.0.borrow().clone())
+            .unwrap()
+            .contains("John"));
+        writer.write_record(&records[1]).unwrap();
+        assert!(shared.0.borrow().len() > first);
+        writer.finish().unwrap();
+
+        let shared = Shared::default();
+        let mut writer = JsonLinesWriter::new(shared.clone());
+        writer.write_record(&records[0]).unwrap();
+        assert_eq!(
+            shared.0.borrow().as_slice(),
+            b"{\"name\":\"John\",\"note\":\"line\\nbreak\"}\n"
+        );
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_appending() {
+        let records = sample_records();
+        for pretty in [false, true] {
+            let first = write_all(&records[..1], pretty);
+            let mut writer = JsonArrayWriter::appending(Vec::new(), pretty);
+            writer.write_record(&records[1]).unwrap();
+            let rest = String::from_utf8(writer.finish().unwrap()).unwrap();
+            // The closing bracket and the line break before it are cut
+            let kept = first.trim_end_matches(']').trim_end();
+            assert_eq!(format!("{}{}", kept, rest), write_all(&records, pretty));
+        }
+    }
+
+    #[test]
+    fn test_html_table() {
+        let mut records = sample_records();
+        records[0].insert(Arc::from("<b>"), Value::from("a & \"b\""));
+        let mut writer = HtmlTableWriter::new(Vec::new(), false);
+        for record in &records {
+            writer.write_record(record).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(writer.finish().unwrap()).unwrap(),
+            "<table><thead><tr><th>name</th><th>note</th><th>&lt;b&gt;</th></tr></thead>\
+             <tbody><tr><td>John</td><td>line\nbreak</td><td>a &amp; &quot;b&quot;</td></tr>\
+             <tr><td>Jane</td><td>30</td><td></td></tr></tbody></table>"
+        );
+
+        let writer = HtmlTableWriter::new(Vec::new(), false);
+        assert_eq!(writer.finish().unwrap(), b"<table></table>");
+
+        let mut writer = HtmlTableWriter::new(Vec::new(), true);
+        writer.write_record(&records[1]).unwrap();
+        let page = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert!(page.starts_with("<!DOCTYPE html>\n"));
+        assert!(page.contains("<tr><td>Jane</td><td>30</td></tr>\n</tbody>\n</table>\n</body>"));
+    }
+
+    #[test]
+    fn test_empty_array() {
+        assert_eq!(write_all(&[], false), "[]");
+        assert_eq!(write_all(&[], true), "[]");
+    }
+
+    #[test]
+    fn test_cbor_array() {
+        let writer = CborWriter::new(Vec::new());
+        assert_eq!(writer.finish().unwrap(), [0x9f, 0xff]);
+
+        let mut writer = CborWriter::new(Vec::new());
+        for value in [1, 2] {
+            let record = Record::from([(Arc::from("a"), Value::from(value))]);
+            writer.write_record(&record).unwrap();
+        }
+        assert_eq!(
+            writer.finish().unwrap(),
+            [0x9f, 0xa1, 0x61, b'a', 0x01, 0xa1, 0x61, b'a', 0x02, 0xff]
+        );
+    }
+
+    #[test]
+    fn test_avro_container() {
+        let record = Record::from([(Arc::from("a"), Value::from(1))]);
+        let mut writer = AvroWriter::new(Vec::new(), None);
+        writer.write_record(&record).unwrap();
+        writer.write_record(&record).unwrap();
+        let file = writer.finish().unwrap();
+
+        let schema = avro::infer_schema(&[record]);
+        let mut expected = Vec::new();
+        schema.write_header(&mut expected);
+        avro::write_block(2, &[0x02, 0x02], &schema.sync_marker(), &mut expected);
+        assert_eq!(file, expected);
+    }
+
+    #[test]
+    fn test_ascii_escaper() {
+        let json = serde_json::to_vec(&serde_json::json!({"名前": "café 😀\n"})).unwrap();
+        // Split mid-character, as buffered writes may be
+        let mut escaper = AsciiEscaper::new(Vec::new());
+        for chunk in json.chunks(2) {
+            escaper.write_all(chunk).unwrap();
+        }
+        let escaped = String::from_utf8(escaper.inner).unwrap();
+        assert_eq!(escaped, r#"{"\u540d\u524d":"caf\u00e9 \ud83d\ude00\n"}"#);
+        assert_eq!(
+            serde_json::from_str::<Value>(&escaped).unwrap(),
+            serde_json::from_slice::<Value>(&json).unwrap()
+        );
+    }
+}
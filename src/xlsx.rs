@@ -0,0 +1,541 @@
+//! Reading a worksheet out of an Excel (.xlsx) workbook, a ZIP archive of
+//! XML parts.
+
+use crate::datetime::{civil_from_days, days_from_civil};
+use crate::error::Error;
+use crate::log;
+use crate::spreadsheet;
+use crate::xml::{attribute, Event, Events};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+
+pub const WORKBOOK: &str = "xl/workbook.xml";
+const WORKBOOK_RELATIONSHIPS: &str = "xl/_rels/workbook.xml.rels";
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("xlsx: {}", message.into()),
+    ))
+}
+
+/// Reads the rows of the worksheet `sheet` picks, with `read` giving the
+/// text of each part, or `None` for a part the workbook lacks.
+pub fn read_sheet(
+    mut read: impl FnMut(&str) -> Result<Option<String>, Error>,
+    sheet: Option<&str>,
+) -> Result<Vec<Vec<String>>, Error> {
+    let workbook = read(WORKBOOK)?.ok_or_else(|| invalid("missing workbook"))?;
+    let workbook = parse_workbook(&workbook)?;
+    let relationships = match read(WORKBOOK_RELATIONSHIPS)? {
+        Some(xml) => parse_relationships(&xml)?,
+        None => Vec::new(),
+    };
+
+    let names: Vec<&str> = workbook
+        .sheets
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect();
+    let (name, id) = &workbook.sheets[spreadsheet::pick(&names, sheet)?];
+    let target = relationships
+        .iter()
+        .find(|relationship| relationship.id == *id)
+        .map(|relationship| part_path(&relationship.target))
+        .ok_or_else(|| invalid(format!("no part found for worksheet {}", name)))?;
+    log::info!("Reading worksheet {} ({}) from the workbook", name, target);
+
+    // Shared strings and styles live in parts of their own, named by the
+    // workbook's relationships and usually at these paths
+    let related = |kind: &str, default: &str| {
+        relationships
+            .iter()
+            .find(|relationship| relationship.kind.ends_with(kind))
+            .map(|relationship| part_path(&relationship.target))
+            .unwrap_or_else(|| default.to_string())
+    };
+    let strings = match read(&related("/sharedStrings", "xl/sharedStrings.xml"))? {
+        Some(xml) => parse_shared_strings(&xml)?,
+        None => Vec::new(),
+    };
+    let date_styles = match read(&related("/styles", "xl/styles.xml"))? {
+        Some(xml) => parse_date_styles(&xml)?,
+        None => Vec::new(),
+    };
+
+    let xml = read(&target)?.ok_or_else(|| invalid(format!("missing part {}", target)))?;
+    let cells = Cells {
+        strings: &strings,
+        date_styles: &date_styles,
+        date1904: workbook.date1904,
+    };
+    parse_sheet(&xml, &cells)
+}
+
+/// A path in the archive for a relationship target, which is relative to
+/// the `xl/` folder unless it starts with a slash.
+fn part_path(target: &str) -> String {
+    match target.strip_prefix('/') {
+        Some(absolute) => absolute.to_string(),
+        None => format!("xl/{}", target),
+    }
+}
+
+struct Workbook {
+    /// Each sheet's name and relationship ID, in workbook order
+    sheets: Vec<(String, String)>,
+    /// Whether serial dates count from 1904 instead of 1900
+    date1904: bool,
+}
+
+fn parse_workbook(xml: &str) -> Result<Workbook, Error> {
+    let mut workbook = Workbook {
+        sheets: Vec::new(),
+        date1904: false,
+    };
+    let mut events = Events::new(xml);
+    while let Some(event) = events.next()? {
+        match event {
+            Event::Start("sheet", attributes) => {
+                let name = attribute(attributes, "name")?;
+                let id = attribute(attributes, "id")?;
+                if let (Some(name), Some(id)) = (name, id) {
+                    workbook.sheets.push((name.into_owned(), id.into_owned()));
+                }
+            }
+            Event::Start("workbookPr", attributes) => {
+                workbook.date1904 = matches!(
+                    attribute(attributes, "date1904")?.as_deref(),
+                    Some("1" | "true")
+                );
+            }
+            _ => {}
+        }
+    }
+    Ok(workbook)
+}
+
+struct Relationship {
+    id: String,
+    kind: String,
+    target: String,
+}
+
+fn parse_relationships(xml: &str) -> Result<Vec<Relationship>, Error> {
+    let mut relationships = Vec::new();
+    let mut events = Events::new(xml);
+    while let Some(event) = events.next()? {
+        if let Event::Start("Relationship", attributes) = event {
+            relationships.push(Relationship {
+                id: attribute(attributes, "Id")?
+                    .unwrap_or_default()
+                    .into_owned(),
+                kind: attribute(attributes, "Type")?
+                    .unwrap_or_default()
+                    .into_owned(),
+                target: attribute(attributes, "Target")?
+                    .unwrap_or_default()
+                    .into_owned(),
+            });
+        }
+    }
+    Ok(relationships)
+}
+
+/// The shared string table. A string may be split into runs of rich text,
+/// and phonetic guides (`rPh`) are not part of its value.
+fn parse_shared_strings(xml: &str) -> Result<Vec<String>, Error> {
+    let mut strings = Vec::new();
+    let mut current = String::new();
+    let (mut in_text, mut in_phonetic) = (false, false);
+    let mut events = Events::new(xml);
+    while let Some(event) = events.next()? {
+        match event {
+            Event::Start("si", _) => current.clear(),
+            Event::End("si") => strings.push(decode_escapes(&current)),
+            Event::Start("t", _) => in_text = !in_phonetic,
+            Event::End("t") => in_text = false,
+            Event::Start("rPh", _) => in_phonetic = true,
+            Event::End("rPh") => in_phonetic = false,
+            Event::Text(text) if in_text => current.push_str(&text),
+            _ => {}
+        }
+    }
+    Ok(strings)
+}
+
+/// How a number shown with a date or time format is written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DateKind {
+    Date,
+    Time,
+    DateTime,
+}
+
+/// The kind of date each cell style shows, indexed by the style number
+/// cells refer to; `None` for styles that show plain numbers.
+fn parse_date_styles(xml: &str) -> Result<Vec<Option<DateKind>>, Error> {
+    let mut custom = HashMap::new();
+    let mut styles = Vec::new();
+    let mut in_cell_formats = false;
+    let mut events = Events::new(xml);
+    while let Some(event) = events.next()? {
+        match event {
+            Event::Start("numFmt", attributes) => {
+                let id = attribute(attributes, "numFmtId")?;
+                let code = attribute(attributes, "formatCode")?;
+                if let (Some(id), Some(code)) = (id, code) {
+                    custom.insert(id.into_owned(), format_kind(&code));
+                }
+            }
+            Event::Start("cellXfs", _) => in_cell_formats = true,
+            Event::End("cellXfs") => in_cell_formats = false,
+            Event::Start("xf", attributes) if in_cell_formats => {
+                let id = attribute(attributes, "numFmtId")?.unwrap_or(Cow::Borrowed("0"));
+                styles.push(match custom.get(&*id) {
+                    Some(kind) => *kind,
+                    None => builtin_kind(id.parse().unwrap_or(0)),
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(styles)
+}
+
+/// The kind of date the built-in number formats show, including the ones
+/// for East Asian locales.
+fn builtin_kind(id: u32) -> Option<DateKind> {
+    match id {
+        14..=17 | 27..=31 | 34..=36 | 50..=58 => Some(DateKind::Date),
+        18..=21 | 32 | 33 | 45..=47 => Some(DateKind::Time),
+        22 => Some(DateKind::DateTime),
+        _ => None,
+    }
+}
+
+/// The kind of date a custom format code shows, from the date and time
+/// letters outside its quoted text, escaped characters and `[...]` parts.
+fn format_kind(code: &str) -> Option<DateKind> {
+    let mut letters = String::new();
+    let mut chars = code.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                }
+            }
+            '[' => {
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                }
+            }
+            // The next character is shown as is
+            '\\' | '_' | '*' => {
+                chars.next();
+            }
+            // Formats for negative numbers, zero and text follow the first
+            ';' => break,
+            c => letters.push(c.to_ascii_lowercase()),
+        }
+    }
+    let date = letters.contains(['y', 'd']);
+    let time = letters.contains(['h', 's']);
+    match (date, time) {
+        (true, true) => Some(DateKind::DateTime),
+        (false, true) => Some(DateKind::Time),
+        // Months may also stand alone, as in "mmm"
+        _ if date || letters.contains('m') => Some(DateKind::Date),
+        _ => None,
+    }
+}
+
+/// Writes a serial date the way the cell's format shows it, in ISO 8601.
+/// Serial dates count days from 1899-12-30, which takes in Excel's phantom
+/// 1900-02-29, or from 1904-01-01 in 1904-based workbooks.
+fn format_date(serial: f64, kind: DateKind, date1904: bool) -> Option<String> {
+    // Excel's dates end with 9999-12-31
+    if !(0.0..2_958_466.0).contains(&serial) {
+        return None;
+    }
+    let epoch = if date1904 {
+        days_from_civil(1904, 1, 1)
+    } else {
+        days_from_civil(1899, 12, 30)
+    };
+    let total_seconds = (serial * 86_400.0).round() as i64;
+    let (days, seconds) = (total_seconds / 86_400, total_seconds % 86_400);
+    let (year, month, day) = civil_from_days(epoch + days);
+    let clock = |hours: i64| format!("{:02}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60);
+    Some(match kind {
+        DateKind::Date => format!("{:04}-{:02}-{:02}", year, month, day),
+        DateKind::DateTime => format!(
+            "{:04}-{:02}-{:02}T{}",
+            year,
+            month,
+            day,
+            clock(seconds / 3600)
+        ),
+        // Elapsed times like [h]:mm can pass 24 hours
+        DateKind::Time => clock(total_seconds / 3600),
+    })
+}
+
+/// What turning cells into text needs from the rest of the workbook.
+struct Cells<'a> {
+    strings: &'a [String],
+    date_styles: &'a [Option<DateKind>],
+    date1904: bool,
+}
+
+impl Cells<'_> {
+    /// The text of a cell from its type (`t`), style (`s`) and value.
+    fn text(&self, kind: &str, style: usize, value: String) -> Result<String, Error> {
+        Ok(match kind {
+            "s" => value
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| self.strings.get(index))
+                .ok_or_else(|| invalid(format!("no shared string {}", value)))?
+                .clone(),
+            "inlineStr" => decode_escapes(&value),
+            "b" => match value.trim() {
+                "1" => "true".to_string(),
+                _ => "false".to_string(),
+            },
+            // Strings from formulas, errors like #N/A and ISO 8601 dates
+            "str" | "e" | "d" => value,
+            _ => {
+                let date = self.date_styles.get(style).copied().flatten();
+                match (date, value.trim().parse::<f64>()) {
+                    (Some(kind), Ok(serial)) => {
+                        format_date(serial, kind, self.date1904).unwrap_or(value)
+                    }
+                    _ => value,
+                }
+            }
+        })
+    }
+}
+
+/// The rows of a worksheet, with each cell at the column its reference
+/// names. Rows without any values are left out, and so are the empty cells
+/// at the end of a row.
+fn parse_sheet(xml: &str, cells: &Cells) -> Result<Vec<Vec<String>>, Error> {
+    let mut rows = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let (mut kind, mut style, mut column) = (String::new(), 0, 0);
+    let mut value = String::new();
+    let (mut in_value, mut in_inline, mut in_phonetic) = (false, false, false);
+    let mut events = Events::new(xml);
+    while let Some(event) = events.next()? {
+        match event {
+            Event::Start("row", _) => row.clear(),
+            Event::End("row") => {
+                while row.last().is_some_and(String::is_empty) {
+                    row.pop();
+                }
+                if !row.is_empty() {
+                    rows.push(std::mem::take(&mut row));
+                }
+            }
+            Event::Start("c", attributes) => {
+                column = match attribute(attributes, "r")? {
+                    Some(reference) => column_index(&reference)
+                        .ok_or_else(|| invalid(format!("bad cell reference {}", reference)))?,
+                    None => row.len(),
+                };
+                kind = attribute(attributes, "t")?.unwrap_or_default().into_owned();
+                style = match attribute(attributes, "s")? {
+                    Some(s) => s.parse().unwrap_or(0),
+                    None => 0,
+                };
+                value.clear();
+            }
+            Event::End("c") => {
+                let text = cells.text(&kind, style, std::mem::take(&mut value))?;
+                if row.len() <= column {
+                    row.resize(column + 1, String::new());
+                }
+                row[column] = text;
+            }
+            Event::Start("v", _) => in_value = true,
+            Event::End("v") => in_value = false,
+            Event::Start("is", _) => in_inline = true,
+            Event::End("is") => in_inline = false,
+            Event::Start("rPh", _) => in_phonetic = true,
+            Event::End("rPh") => in_phonetic = false,
+            Event::Text(text) if in_value || (in_inline && !in_phonetic) => value.push_str(&text),
+            _ => {}
+        }
+    }
+    Ok(rows)
+}
+
+/// The 0-based column of a cell reference like `AB12`.
+fn column_index(reference: &str) -> Option<usize> {
+    let letters = reference
+        .bytes()
+        .take_while(u8::is_ascii_alphabetic)
+        .map(|b| (b.to_ascii_uppercase() - b'A') as usize + 1);
+    let index = letters.fold(0, |index, letter| index * 26 + letter);
+    index.checked_sub(1)
+}
+
+/// Decodes the `_xHHHH_` escapes Excel writes for characters XML cannot
+/// hold, such as `_x000D_` for a carriage return.
+fn decode_escapes(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find("_x") {
+        decoded.push_str(&rest[..at]);
+        let escape = rest[at..].get(..7).filter(|escape| escape.ends_with('_'));
+        let c = escape
+            .and_then(|escape| u32::from_str_radix(&escape[2..6], 16).ok())
+            .and_then(char::from_u32);
+        match c {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[at + 7..];
+            }
+            None => {
+                decoded.push_str("_x");
+                rest = &rest[at + 2..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_strings() {
+        let xml = "<sst><si><t>plain</t></si>\
+                   <si><r><t>rich </t></r><r><rPr><b/></rPr><t xml:space=\"preserve\">text</t></r></si>\
+                   <si><t>漢字</t><rPh sb=\"0\" eb=\"2\"><t>かんじ</t></rPh></si>\
+                   <si><t>line_x000D_\nbreak</t></si><si><t/></si></sst>";
+        assert_eq!(
+            parse_shared_strings(xml).unwrap(),
+            vec!["plain", "rich text", "漢字", "line\r\nbreak", ""]
+        );
+    }
+
+    #[test]
+    fn test_format_kind() {
+        assert_eq!(format_kind("yyyy-mm-dd"), Some(DateKind::Date));
+        assert_eq!(format_kind("mmm"), Some(DateKind::Date));
+        assert_eq!(format_kind("d/m/yyyy h:mm AM/PM"), Some(DateKind::DateTime));
+        assert_eq!(format_kind("[h]:mm:ss"), Some(DateKind::Time));
+        assert_eq!(format_kind("General"), None);
+        assert_eq!(format_kind("\"days\" 0.00"), None);
+        assert_eq!(format_kind("#,##0;[Red]-#,##0"), None);
+        assert_eq!(format_kind("0.00E+00"), None);
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(
+            format_date(45292.0, DateKind::Date, false).as_deref(),
+            Some("2024-01-01")
+        );
+        assert_eq!(
+            format_date(45292.75, DateKind::DateTime, false).as_deref(),
+            Some("2024-01-01T18:00:00")
+        );
+        assert_eq!(
+            format_date(43830.0, DateKind::Date, true).as_deref(),
+            Some("2024-01-01")
+        );
+        assert_eq!(
+            format_date(1.5, DateKind::Time, false).as_deref(),
+            Some("36:00:00")
+        );
+        assert_eq!(format_date(-1.0, DateKind::Date, false), None);
+    }
+
+    #[test]
+    fn test_column_index() {
+        assert_eq!(column_index("A1"), Some(0));
+        assert_eq!(column_index("Z9"), Some(25));
+        assert_eq!(column_index("AA10"), Some(26));
+        assert_eq!(column_index("XFD1"), Some(16383));
+        assert_eq!(column_index("12"), None);
+    }
+
+    fn workbook(sheet: Option<&str>) -> Result<Vec<Vec<String>>, Error> {
+        let parts = HashMap::from([
+            (
+                WORKBOOK,
+                "<workbook xmlns:r=\"r\"><sheets>\
+                 <sheet name=\"Summary\" sheetId=\"1\" r:id=\"rId1\"/>\
+                 <sheet name=\"Data\" sheetId=\"2\" r:id=\"rId2\"/>\
+                 </sheets></workbook>",
+            ),
+            (
+                WORKBOOK_RELATIONSHIPS,
+                "<Relationships>\
+                 <Relationship Id=\"rId1\" Type=\"x/worksheet\" Target=\"worksheets/sheet1.xml\"/>\
+                 <Relationship Id=\"rId2\" Type=\"x/worksheet\" Target=\"/xl/worksheets/sheet2.xml\"/>\
+                 <Relationship Id=\"rId3\" Type=\"x/sharedStrings\" Target=\"sharedStrings.xml\"/>\
+                 <Relationship Id=\"rId4\" Type=\"x/styles\" Target=\"styles.xml\"/>\
+                 </Relationships>",
+            ),
+            (
+                "xl/sharedStrings.xml",
+                "<sst><si><t>id</t></si><si><t>name</t></si><si><t>Jane, \"J\"</t></si></sst>",
+            ),
+            (
+                "xl/styles.xml",
+                "<styleSheet><numFmts><numFmt numFmtId=\"164\" formatCode=\"yyyy-mm-dd\"/></numFmts>\
+                 <cellXfs><xf numFmtId=\"0\"/><xf numFmtId=\"164\"/><xf numFmtId=\"22\"/></cellXfs>\
+                 </styleSheet>",
+            ),
+            (
+                "xl/worksheets/sheet1.xml",
+                "<worksheet><sheetData><row r=\"1\"><c r=\"A1\" t=\"inlineStr\"><is><t>total</t></is></c>\
+                 <c r=\"B1\"><f>SUM(Data!A:A)</f><v>3</v></c></row></sheetData></worksheet>",
+            ),
+            (
+                "xl/worksheets/sheet2.xml",
+                "<worksheet><sheetData>\
+                 <row r=\"1\"><c r=\"A1\" t=\"s\"><v>0</v></c><c r=\"B1\" t=\"s\"><v>1</v></c>\
+                 <c r=\"C1\" t=\"inlineStr\"><is><t>joined</t></is></c><c r=\"D1\" t=\"inlineStr\"><is><t>ok</t></is></c></row>\
+                 <row r=\"2\"><c r=\"A2\"><v>1</v></c><c r=\"B2\" t=\"s\"><v>2</v></c>\
+                 <c r=\"C2\" s=\"1\"><v>45292</v></c><c r=\"D2\" t=\"b\"><v>1</v></c></row>\
+                 <row r=\"3\" spans=\"1:4\"><c r=\"A3\" s=\"1\"/></row>\
+                 <row r=\"5\"><c r=\"A5\"><v>2.5</v></c><c r=\"C5\" s=\"2\"><v>45292.5</v></c></row>\
+                 </sheetData></worksheet>",
+            ),
+        ]);
+        read_sheet(|name| Ok(parts.get(name).map(|xml| xml.to_string())), sheet)
+    }
+
+    #[test]
+    fn test_read_sheet() {
+        assert_eq!(workbook(None).unwrap(), vec![vec!["total", "3"]]);
+        let data = vec![
+            vec!["id", "name", "joined", "ok"],
+            vec!["1", "Jane, \"J\"", "2024-01-01", "true"],
+            vec!["2.5", "", "2024-01-01T12:00:00"],
+        ];
+        assert_eq!(workbook(Some("Data")).unwrap(), data);
+        assert_eq!(workbook(Some("2")).unwrap(), data);
+        let error = workbook(Some("Missing")).unwrap_err();
+        assert!(
+            matches!(error, Error::Config(ref message) if message.contains("Summary, Data")),
+            "{:?}",
+            error
+        );
+    }
+}
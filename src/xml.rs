@@ -0,0 +1,227 @@
+//! A small reader for the XML parts of spreadsheet files: elements,
+//! attributes and text, without namespaces, validation or a DOM.
+
+use crate::error::Error;
+use std::borrow::Cow;
+use std::io;
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("xml: {}", message.into()),
+    ))
+}
+
+/// A piece of an XML document. Element names have their namespace prefix
+/// removed, and an empty element gives a start and an end.
+#[derive(Debug, PartialEq)]
+pub enum Event<'a> {
+    /// An element's name and the text of its attributes
+    Start(&'a str, &'a str),
+    End(&'a str),
+    Text(Cow<'a, str>),
+}
+
+/// Reads the elements and text of an XML document in order.
+pub struct Events<'a> {
+    xml: &'a str,
+    at: usize,
+    /// The end of an empty element, given after its start
+    pending_end: Option<&'a str>,
+}
+
+impl<'a> Events<'a> {
+    pub fn new(xml: &'a str) -> Self {
+        Events {
+            xml,
+            at: 0,
+            pending_end: None,
+        }
+    }
+
+    pub fn next(&mut self) -> Result<Option<Event<'a>>, Error> {
+        if let Some(name) = self.pending_end.take() {
+            return Ok(Some(Event::End(name)));
+        }
+        let rest = &self.xml[self.at..];
+        if rest.is_empty() {
+            return Ok(None);
+        }
+        if !rest.starts_with('<') {
+            let end = rest.find('<').unwrap_or(rest.len());
+            self.at += end;
+            return Ok(Some(Event::Text(unescape(&rest[..end])?)));
+        }
+        for (open, close) in [("<?", "?>"), ("<!--", "-->")] {
+            if rest.starts_with(open) {
+                self.at += self.skip_past(close)?;
+                return self.next();
+            }
+        }
+        if let Some(data) = rest.strip_prefix("<![CDATA[") {
+            let end = data
+                .find("]]>")
+                .ok_or_else(|| invalid("unterminated CDATA section"))?;
+            self.at += "<![CDATA[".len() + end + "]]>".len();
+            return Ok(Some(Event::Text(Cow::Borrowed(&data[..end]))));
+        }
+        if rest.starts_with("<!") {
+            self.at += self.skip_past(">")?;
+            return self.next();
+        }
+
+        // Attribute values may hold '>', so the tag ends at the first one
+        // outside quotes
+        let mut quote = None;
+        let end = rest
+            .bytes()
+            .position(|b| match quote {
+                Some(q) => {
+                    if b == q {
+                        quote = None;
+                    }
+                    false
+                }
+                None if b == b'"' || b == b'\'' => {
+                    quote = Some(b);
+                    false
+                }
+                None => b == b'>',
+            })
+            .ok_or_else(|| invalid("unterminated tag"))?;
+        self.at += end + 1;
+        let tag = &rest[1..end];
+        if let Some(name) = tag.strip_prefix('/') {
+            return Ok(Some(Event::End(local_name(name.trim()))));
+        }
+        let (tag, empty) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let (name, attributes) = tag
+            .split_once(|c: char| c.is_ascii_whitespace())
+            .unwrap_or((tag, ""));
+        let name = local_name(name);
+        if empty {
+            self.pending_end = Some(name);
+        }
+        Ok(Some(Event::Start(name, attributes)))
+    }
+
+    /// The length from here to just past the next `close`.
+    fn skip_past(&self, close: &str) -> Result<usize, Error> {
+        self.xml[self.at..]
+            .find(close)
+            .map(|end| end + close.len())
+            .ok_or_else(|| invalid("unterminated markup"))
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// The unescaped value of the attribute with local name `name`.
+pub fn attribute<'a>(attributes: &'a str, name: &str) -> Result<Option<Cow<'a, str>>, Error> {
+    let mut rest = attributes;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return Ok(None);
+        }
+        let (key, after) = rest
+            .split_once('=')
+            .ok_or_else(|| invalid("attribute without a value"))?;
+        let after = after.trim_start();
+        let quote = after
+            .chars()
+            .next()
+            .filter(|&c| c == '"' || c == '\'')
+            .ok_or_else(|| invalid("unquoted attribute value"))?;
+        let value_end = after[1..]
+            .find(quote)
+            .ok_or_else(|| invalid("unterminated attribute value"))?;
+        if local_name(key.trim()) == name {
+            return unescape(&after[1..1 + value_end]).map(Some);
+        }
+        rest = &after[value_end + 2..];
+    }
+}
+
+/// Replaces character and entity references.
+fn unescape(text: &str) -> Result<Cow<'_, str>, Error> {
+    if !text.contains('&') {
+        return Ok(Cow::Borrowed(text));
+    }
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('&') {
+        unescaped.push_str(&rest[..at]);
+        let end = rest[at..]
+            .find(';')
+            .ok_or_else(|| invalid("unterminated entity reference"))?;
+        let entity = &rest[at + 1..at + end];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+            {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                None => entity
+                    .strip_prefix('#')
+                    .and_then(|decimal| decimal.parse().ok())
+                    .and_then(char::from_u32),
+            },
+        };
+        unescaped.push(c.ok_or_else(|| invalid(format!("unknown entity &{};", entity)))?);
+        rest = &rest[at + end + 1..];
+    }
+    unescaped.push_str(rest);
+    Ok(Cow::Owned(unescaped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(xml: &str) -> Vec<Event<'_>> {
+        let mut events = Events::new(xml);
+        let mut all = Vec::new();
+        while let Some(event) = events.next().unwrap() {
+            all.push(event);
+        }
+        all
+    }
+
+    #[test]
+    fn test_events() {
+        assert_eq!(
+            events(
+                "<?xml version=\"1.0\"?><!-- c --><x:a k=\"1 > 0\"><b/>t &amp; &#x41;<![CDATA[<&>]]></x:a>"
+            ),
+            vec![
+                Event::Start("a", "k=\"1 > 0\""),
+                Event::Start("b", ""),
+                Event::End("b"),
+                Event::Text(Cow::Borrowed("t & A")),
+                Event::Text(Cow::Borrowed("<&>")),
+                Event::End("a"),
+            ]
+        );
+        assert_eq!(
+            attribute(" name='A &lt;B&gt;' r:id=\"rId2\"", "id").unwrap(),
+            Some(Cow::Borrowed("rId2"))
+        );
+        assert_eq!(
+            attribute(" name='A &lt;B&gt;' r:id=\"rId2\"", "name").unwrap(),
+            Some(Cow::Borrowed("A <B>"))
+        );
+        assert_eq!(attribute("name='A'", "id").unwrap(), None);
+        assert!(unescape("&nope;").is_err());
+    }
+}
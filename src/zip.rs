@@ -0,0 +1,437 @@
+//! Reading CSV files out of a ZIP archive, as many open-data portals
+//! publish them. Members may be stored or deflated.
+
+use crate::error::{Error, Malformed};
+use crate::gzip::{crc32_update, GzDecoder};
+use crate::log;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+
+/// The end of central directory record is 22 bytes plus a comment of up to
+/// 64 KiB.
+const MAX_END_RECORD: u64 = 22 + 0xffff;
+
+/// Whether a file is a ZIP archive, judged by its first bytes.
+pub fn is_zip(file: &mut File) -> io::Result<bool> {
+    let mut magic = [0; 4];
+    let is_zip = match file.read_exact(&mut magic) {
+        Ok(()) => u32::from_le_bytes(magic) == LOCAL_HEADER,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e),
+    };
+    file.rewind()?;
+    Ok(is_zip)
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("zip: {}", message.into()),
+    ))
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// A file in the archive, from its central directory entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    method: u16,
+    encrypted: bool,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    offset: u64,
+}
+
+impl Entry {
+    fn is_csv(&self) -> bool {
+        // macOS adds resource forks under __MACOSX/ that are not CSV data
+        self.name.to_ascii_lowercase().ends_with(".csv") && !self.name.starts_with("__MACOSX/")
+    }
+
+    fn file_name(&self) -> &str {
+        self.name.rsplit('/').next().unwrap_or(&self.name)
+    }
+}
+
+/// Lists the files in an archive from its central directory.
+pub fn entries(file: &mut File) -> Result<Vec<Entry>, Error> {
+    let length = file.seek(SeekFrom::End(0))?;
+    let tail_start = length.saturating_sub(MAX_END_RECORD);
+    file.seek(SeekFrom::Start(tail_start))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&at| u32_at(&tail, at) == END_OF_CENTRAL_DIRECTORY)
+        .ok_or_else(|| invalid("no central directory found; the archive may be truncated"))?;
+    let count = u16_at(&tail, end + 10);
+    let directory_size = u32_at(&tail, end + 12);
+    let directory_offset = u32_at(&tail, end + 16);
+    if count == 0xffff || directory_offset == 0xffff_ffff {
+        return Err(invalid("ZIP64 archives are not supported"));
+    }
+
+    file.seek(SeekFrom::Start(directory_offset as u64))?;
+    let mut directory = vec![0; directory_size as usize];
+    file.read_exact(&mut directory)?;
+    let mut entries = Vec::with_capacity(count as usize);
+    let mut at = 0;
+    for _ in 0..count {
+        if directory.len() < at + 46 || u32_at(&directory, at) != CENTRAL_HEADER {
+            return Err(invalid("corrupt central directory"));
+        }
+        let name_length = u16_at(&directory, at + 28) as usize;
+        let extra_length = u16_at(&directory, at + 30) as usize;
+        let comment_length = u16_at(&directory, at + 32) as usize;
+        let name = directory
+            .get(at + 46..at + 46 + name_length)
+            .ok_or_else(|| invalid("corrupt central directory"))?;
+        entries.push(Entry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: u16_at(&directory, at + 10),
+            encrypted: u16_at(&directory, at + 8) & 1 != 0,
+            crc: u32_at(&directory, at + 16),
+            compressed_size: u32_at(&directory, at + 20) as u64,
+            size: u32_at(&directory, at + 24) as u64,
+            offset: u32_at(&directory, at + 42) as u64,
+        });
+        at += 46 + name_length + extra_length + comment_length;
+    }
+    Ok(entries)
+}
+
+/// Picks the members to convert: the one named by `--zip-member`, matched
+/// against its path in the archive or its file name, or else every CSV file.
+pub fn select(entries: Vec<Entry>, member: Option<&str>) -> Result<Vec<Entry>, Error> {
+    let csv_names = || {
+        let names: Vec<&str> = entries
+            .iter()
+            .filter(|entry| entry.is_csv())
+            .map(|entry| entry.name.as_str())
+            .collect();
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        }
+    };
+    let selected: Vec<Entry> = match member {
+        Some(member) => {
+            let found = entries
+                .iter()
+                .find(|entry| entry.name == member)
+                .or_else(|| entries.iter().find(|entry| entry.file_name() == member));
+            match found {
+                Some(entry) => vec![entry.clone()],
+                None => {
+                    return Err(Error::Config(format!(
+                        "No member {} in the archive (CSV members: {})",
+                        member,
+                        csv_names()
+                    )))
+                }
+            }
+        }
+        None => entries
+            .iter()
+            .filter(|entry| entry.is_csv())
+            .cloned()
+            .collect(),
+    };
+    if selected.is_empty() {
+        return Err(Error::Config(
+            "The archive contains no CSV files; choose a member with --zip-member".to_string(),
+        ));
+    }
+    for entry in &selected {
+        check(entry)?;
+    }
+    Ok(selected)
+}
+
+/// Fails for a member this reader cannot decompress.
+fn check(entry: &Entry) -> Result<(), Error> {
+    if entry.encrypted {
+        return Err(invalid(format!("{} is encrypted", entry.name)));
+    }
+    if !matches!(entry.method, 0 | 8) {
+        return Err(invalid(format!(
+            "{} uses unsupported compression method {}",
+            entry.name, entry.method
+        )));
+    }
+    Ok(())
+}
+
+/// Reads the whole of one member.
+pub fn read_member(file: &File, entry: &Entry) -> Result<Vec<u8>, Error> {
+    check(entry)?;
+    let mut data = Vec::with_capacity(entry.size as usize);
+    open(file, entry)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Opens a member for reading, decompressing it and checking its CRC-32.
+fn open(file: &File, entry: &Entry) -> io::Result<Box<dyn Read + Send>> {
+    let mut file = file.try_clone()?;
+    file.seek(SeekFrom::Start(entry.offset))?;
+    let mut header = [0; 30];
+    file.read_exact(&mut header)?;
+    if u32_at(&header, 0) != LOCAL_HEADER {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("zip: corrupt local header for {}", entry.name),
+        ));
+    }
+    let skip = u16_at(&header, 26) as i64 + u16_at(&header, 28) as i64;
+    file.seek(SeekFrom::Current(skip))?;
+    let data = file.take(entry.compressed_size);
+    Ok(match entry.method {
+        8 => Box::new(GzDecoder::deflate(
+            BufReader::new(data),
+            entry.crc,
+            entry.size as u32,
+        )),
+        _ => Box::new(Stored {
+            inner: data,
+            crc: 0,
+            size: 0,
+            expected: (entry.crc, entry.size),
+        }),
+    })
+}
+
+/// Checks a stored member against its CRC-32 and length.
+struct Stored<R> {
+    inner: R,
+    crc: u32,
+    size: u64,
+    expected: (u32, u64),
+}
+
+impl<R: Read> Read for Stored<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc = crc32_update(self.crc, &buf[..n]);
+        self.size += n as u64;
+        if n == 0 && !buf.is_empty() && (self.crc, self.size) != self.expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "zip: checksum mismatch, the data is corrupt",
+            ));
+        }
+        Ok(n)
+    }
+}
+
+/// Reads the selected members one after another as a single CSV input. With
+/// a header row, each later member's header is dropped, and must match the
+/// first member's.
+pub struct Members {
+    file: File,
+    entries: VecDeque<Entry>,
+    has_header: bool,
+    current: Option<BufReader<Box<dyn Read + Send>>>,
+    header: Option<Vec<u8>>,
+    /// Bytes to return before reading on, such as a member's header row
+    pending: Vec<u8>,
+    ends_with_newline: bool,
+}
+
+impl Members {
+    pub fn new(file: File, entries: Vec<Entry>, has_header: bool) -> Self {
+        Members {
+            file,
+            entries: entries.into(),
+            has_header,
+            current: None,
+            header: None,
+            pending: Vec::new(),
+            ends_with_newline: true,
+        }
+    }
+
+    /// Opens the next member, queueing what it starts with. Returns false
+    /// when there are no more.
+    fn next_member(&mut self) -> io::Result<bool> {
+        let Some(entry) = self.entries.pop_front() else {
+            return Ok(false);
+        };
+        log::info!("Reading {} from the archive", entry.name);
+        let mut reader = BufReader::new(open(&self.file, &entry)?);
+        // Keeps a member's first row from joining the last row of the one before
+        if !self.ends_with_newline {
+            self.pending.push(b'\n');
+            self.ends_with_newline = true;
+        }
+        if self.has_header {
+            let mut line = Vec::new();
+            reader.read_until(b'\n', &mut line)?;
+            let trimmed = line.strip_suffix(b"\n").unwrap_or(&line);
+            let trimmed = trimmed.strip_suffix(b"\r").unwrap_or(trimmed).to_vec();
+            match &self.header {
+                None => {
+                    self.header = Some(trimmed);
+                    self.pending.extend_from_slice(&line);
+                }
+                Some(first) if *first == trimmed || line.is_empty() => {}
+                Some(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        Malformed(format!(
+                            "{} has a different header row from the members before it; \
+                             choose one with --zip-member",
+                            entry.name
+                        )),
+                    ))
+                }
+            }
+        }
+        self.current = Some(reader);
+        Ok(true)
+    }
+}
+
+impl Read for Members {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if !self.pending.is_empty() {
+                let n = self.pending.len().min(buf.len());
+                buf[..n].copy_from_slice(&self.pending[..n]);
+                self.pending.drain(..n);
+                self.ends_with_newline = buf[n - 1] == b'\n';
+                return Ok(n);
+            }
+            if let Some(reader) = &mut self.current {
+                let n = reader.read(buf)?;
+                if n > 0 {
+                    self.ends_with_newline = buf[n - 1] == b'\n';
+                    return Ok(n);
+                }
+                self.current = None;
+            }
+            if !self.next_member()? {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gzip::GzEncoder;
+    use std::io::Write;
+
+    /// Builds an archive of `(name, data, deflated)` members.
+    fn archive(members: &[(&str, &str, bool)]) -> tempfile::NamedTempFile {
+        let (mut data, mut directory) = (Vec::new(), Vec::new());
+        for &(name, content, deflated) in members {
+            let stored = if deflated {
+                let mut encoder = GzEncoder::new(Vec::new());
+                encoder.write_all(content.as_bytes()).unwrap();
+                let gzip = encoder.finish().unwrap();
+                // The bare DEFLATE stream between the gzip header and trailer
+                gzip[10..gzip.len() - 8].to_vec()
+            } else {
+                content.as_bytes().to_vec()
+            };
+            let mut fields = Vec::new();
+            fields.extend_from_slice(&(if deflated { 8u16 } else { 0 }).to_le_bytes());
+            fields.extend_from_slice(&[0; 4]);
+            fields.extend_from_slice(&crc32_update(0, content.as_bytes()).to_le_bytes());
+            fields.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(content.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            fields.extend_from_slice(&[0; 2]);
+
+            let offset = data.len() as u32;
+            data.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+            data.extend_from_slice(&[20, 0, 0, 0]);
+            data.extend_from_slice(&fields);
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(&stored);
+
+            directory.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+            directory.extend_from_slice(&[20, 0, 20, 0, 0, 0]);
+            directory.extend_from_slice(&fields);
+            directory.extend_from_slice(&[0; 10]);
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+        let directory_offset = data.len() as u32;
+        data.extend_from_slice(&directory);
+        data.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(members.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(members.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        data.extend_from_slice(&directory_offset.to_le_bytes());
+        data.extend_from_slice(&[0; 2]);
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&data).unwrap();
+        file
+    }
+
+    fn read(members: &[(&str, &str, bool)], member: Option<&str>) -> Result<String, Error> {
+        let mut file = archive(members).reopen().unwrap();
+        assert!(is_zip(&mut file).unwrap());
+        let entries = select(entries(&mut file)?, member)?;
+        let mut output = String::new();
+        Members::new(file, entries, true).read_to_string(&mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_members() {
+        let members = [
+            ("data/a.csv", "id,name\n1,John", true),
+            ("data/b.csv", "id,name\r\n2,Jane\n", false),
+            ("readme.txt", "not data", false),
+        ];
+        assert_eq!(read(&members, None).unwrap(), "id,name\n1,John\n2,Jane\n");
+        assert_eq!(
+            read(&members, Some("b.csv")).unwrap(),
+            "id,name\r\n2,Jane\n"
+        );
+        assert_eq!(read(&members, Some("readme.txt")).unwrap(), "not data");
+        assert!(matches!(
+            read(&members, Some("c.csv")),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_member() {
+        let mut file = archive(&[("a.txt", "first", false), ("b.txt", "second", true)])
+            .reopen()
+            .unwrap();
+        let entries = entries(&mut file).unwrap();
+        assert_eq!(read_member(&file, &entries[1]).unwrap(), b"second");
+        assert_eq!(read_member(&file, &entries[0]).unwrap(), b"first");
+    }
+
+    #[test]
+    fn test_mismatched_headers() {
+        let members = [("a.csv", "id\n1\n", false), ("b.csv", "name\nx\n", true)];
+        let error = read(&members, None).unwrap_err();
+        assert!(matches!(error, Error::Csv(ref message) if message.contains("b.csv")));
+    }
+}
@@ -1,3 +1,6 @@
+// Arguments are passed as `&[..]` slices throughout
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use std::fs;
 use std::process::Command;
 use tempfile::NamedTempFile;
@@ -16,6 +19,7 @@ fn test_cli_malformed_csv() {
         .expect("Failed to execute command");
 
     assert!(!output.status.success());
+    assert_eq!(output.status.code(), Some(4));
 }
 
 #[test]
@@ -57,6 +61,7 @@ fn test_cli_invalid_output_path() {
         .expect("Failed to execute command");
 
     assert!(!output.status.success());
+    assert_eq!(output.status.code(), Some(3));
 }
 
 #[test]
@@ -263,3 +268,226 @@ fn test_cli_case_insensitive_booleans() {
     assert_eq!(parsed[2]["value"], true);
     assert_eq!(parsed[3]["value"], false);
 }
+
+#[test]
+fn test_cli_fail_on_empty() {
+    let temp_input = NamedTempFile::new().unwrap();
+
+    fs::write(temp_input.path(), "").unwrap();
+
+    let output = Command::new("cargo")
+        .args(&["run", "--", "-i"])
+        .arg(temp_input.path())
+        .arg("--fail-on-empty")
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(!output.status.success());
+    assert_eq!(output.status.code(), Some(6));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_cli_exit_codes() {
+    let temp_input = NamedTempFile::new().unwrap();
+    fs::write(temp_input.path(), "name,age\nJohn,30").unwrap();
+
+    let output = Command::new("cargo")
+        .args(&["run", "--", "-i", "/nonexistent/input.csv"])
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(output.status.code(), Some(3));
+
+    let output = Command::new("cargo")
+        .args(&["run", "--", "-i"])
+        .arg(temp_input.path())
+        .args(&["--select", "email"])
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Error: Selected column not found: email"));
+}
+
+#[test]
+fn test_cli_skip_errors_reports_rows() {
+    let temp_input = NamedTempFile::new().unwrap();
+
+    let csv_content = "name,age\nJohn,30\nbroken\nJane,25";
+    fs::write(temp_input.path(), csv_content).unwrap();
+
+    let output = Command::new("cargo")
+        .args(&["run", "--", "-i"])
+        .arg(temp_input.path())
+        .arg("--skip-errors")
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("line: 3"));
+    assert!(stderr.contains("Skipped 1 malformed row(s)"));
+
+    let parsed: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(parsed.len(), 2);
+}
+
+#[test]
+fn test_cli_status_messages_go_to_stderr() {
+    let temp_input = NamedTempFile::new().unwrap();
+    let temp_output = NamedTempFile::new().unwrap();
+
+    let csv_content = "name,age\nJohn,30\nbroken\nJane,25";
+    fs::write(temp_input.path(), csv_content).unwrap();
+
+    let output = Command::new("cargo")
+        .args(&["run", "--", "-i"])
+        .arg(temp_input.path())
+        .arg("-o")
+        .arg(temp_output.path())
+        .arg("--skip-errors")
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Output written to:"));
+
+    let output = Command::new("cargo")
+        .args(&["run", "-q", "--", "-i"])
+        .arg(temp_input.path())
+        .arg("-o")
+        .arg(temp_output.path())
+        .args(&["--skip-errors", "--quiet"])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_cli_verbose_logging() {
+    let temp_input = NamedTempFile::new().unwrap();
+    fs::write(temp_input.path(), "name,age\nJohn,30\nJane,25").unwrap();
+
+    let output = Command::new("cargo")
+        .args(&["run", "-q", "--", "-i"])
+        .arg(temp_input.path())
+        .arg("-v")
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("[info] Header row has 2 columns"));
+    assert!(stderr.contains("[info] Read 2 data rows"));
+    assert!(stderr.contains("[info] Finished in"));
+    assert!(!stderr.contains("[debug]"));
+
+    let parsed: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(parsed.len(), 2);
+}
+
+#[test]
+fn test_cli_warnings() {
+    let temp_input = NamedTempFile::new().unwrap();
+    fs::write(temp_input.path(), "name,age\nJohn\nJane\nJim,40").unwrap();
+
+    let output = Command::new("cargo")
+        .args(&["run", "-q", "--", "-i"])
+        .arg(temp_input.path())
+        .args(&["--on-ragged", "pad"])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr.matches("expected 2; padded with null").count(), 1);
+    assert!(stderr.contains("further ragged row warnings are counted but not shown"));
+    assert!(stderr.contains("Warnings: ragged row (2)"));
+
+    let output = Command::new("cargo")
+        .args(&["run", "-q", "--", "-i"])
+        .arg(temp_input.path())
+        .args(&["--on-ragged", "pad", "--fatal-warnings"])
+        .output()
+        .expect("Failed to execute command");
+
+    assert_eq!(output.status.code(), Some(6));
+}
+
+#[test]
+fn test_cli_timing_report() {
+    let temp_input = NamedTempFile::new().unwrap();
+    fs::write(temp_input.path(), "name,age\nJohn,30\nJane,25").unwrap();
+
+    let output = Command::new("cargo")
+        .args(&["run", "-q", "--", "-i"])
+        .arg(temp_input.path())
+        .args(&["--timing", "--quiet"])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Records:     2\n"));
+    assert!(stderr.contains("Bytes read:  24 B\n"));
+    assert!(stderr.contains("Peak memory:"));
+}
+
+#[test]
+fn test_cli_report() {
+    let temp_input = NamedTempFile::new().unwrap();
+    let temp_report = NamedTempFile::new().unwrap();
+    fs::write(temp_input.path(), "name,age\nJohn,30\nbroken\nJane,25").unwrap();
+
+    let output = Command::new("cargo")
+        .args(&["run", "--", "-q", "-i"])
+        .arg(temp_input.path())
+        .args(&["--skip-errors", "--expect-rows", "5.."])
+        .arg("--report")
+        .arg(temp_report.path())
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(output.status.code(), Some(6));
+
+    let report: serde_json::Value =
+        serde_json::from_slice(&fs::read(temp_report.path()).unwrap()).unwrap();
+    assert_eq!(report["passed"], false);
+    assert_eq!(report["exit_code"], 6);
+    assert_eq!(report["records"], serde_json::Value::Null);
+    let checks = report["checks"].as_array().unwrap();
+    let names: Vec<&str> = checks
+        .iter()
+        .map(|check| check["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["malformed_rows", "expect_rows", "warnings"]);
+    assert_eq!(checks[0]["passed"], true);
+    assert_eq!(checks[0]["count"], 1);
+    assert!(checks[0]["examples"][0]
+        .as_str()
+        .unwrap()
+        .contains("line: 3"));
+    assert_eq!(checks[1]["passed"], false);
+    assert_eq!(checks[1]["expected"], "5..");
+    assert_eq!(checks[1]["actual"], 2);
+
+    let output = Command::new("cargo")
+        .args(&["run", "--", "-q", "-i"])
+        .arg(temp_input.path())
+        .args(&["--skip-errors", "--expect-rows", "2..2"])
+        .arg("--report")
+        .arg(temp_report.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let report: serde_json::Value =
+        serde_json::from_slice(&fs::read(temp_report.path()).unwrap()).unwrap();
+    assert_eq!(report["passed"], true);
+    assert_eq!(report["records"], 2);
+    assert_eq!(report["error"], serde_json::Value::Null);
+}
@@ -1,3 +1,6 @@
+// Arguments are passed as `&[..]` slices throughout
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use std::fs;
 use std::io::Write;
 use std::process::{Command, Stdio};
@@ -148,6 +151,8 @@ fn test_cli_help() {
     assert!(stdout.contains("--input"));
     assert!(stdout.contains("--output"));
     assert!(stdout.contains("--pretty"));
+    // Not broken up by clap, which reads {n} as a line break
+    assert!(stdout.contains("Prefix for generated column names, e.g. col for col_0"));
 }
 
 #[test]
@@ -364,3 +369,156 @@ fn test_cli_stdin_mixed_data_types() {
     assert_eq!(parsed[1]["score"], 100);
     assert_eq!(parsed[1]["active"], false);
 }
+
+#[test]
+fn test_cli_select_columns() {
+    let temp_input = NamedTempFile::new().unwrap();
+
+    let csv_content = "name,age,city\nJohn,30,Tokyo";
+    fs::write(temp_input.path(), csv_content).unwrap();
+
+    let output = Command::new("cargo")
+        .args(&["run", "--"])
+        .arg(temp_input.path())
+        .args(&["--select", "city,age"])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.trim(), r#"[{"city":"Tokyo","age":30}]"#);
+}
+
+#[test]
+fn test_cli_print_header_hash() {
+    let temp_input = NamedTempFile::new().unwrap();
+
+    let csv_content = "name,age\nJohn,30";
+    fs::write(temp_input.path(), csv_content).unwrap();
+
+    let output = Command::new("cargo")
+        .args(&["run", "--"])
+        .arg(temp_input.path())
+        .arg("--print-header-hash")
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+
+    let hash = String::from_utf8(output.stdout).unwrap();
+    let hash = hash.trim();
+    assert_eq!(hash.len(), 64);
+
+    let output = Command::new("cargo")
+        .args(&["run", "--"])
+        .arg(temp_input.path())
+        .args(&["--expect-header-hash", hash])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_cli_multiple_inputs() {
+    let first = NamedTempFile::new().unwrap();
+    let second = NamedTempFile::new().unwrap();
+    fs::write(first.path(), "name,age\nJohn,30").unwrap();
+    fs::write(second.path(), "city\nTokyo").unwrap();
+
+    let output = Command::new("cargo")
+        .args(&["run", "--"])
+        .arg(first.path())
+        .arg(second.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+
+    let parsed: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let first_name = first.path().to_string_lossy();
+    let second_name = second.path().to_string_lossy();
+    assert_eq!(parsed[first_name.as_ref()][0]["age"], 30);
+    assert_eq!(parsed[second_name.as_ref()][0]["city"], "Tokyo");
+}
+
+#[test]
+fn test_cli_quarantine() {
+    let input = NamedTempFile::new().unwrap();
+    let rejects = NamedTempFile::new().unwrap();
+    fs::write(input.path(), "name,age\nJohn,30\nbroken\n").unwrap();
+
+    let output = Command::new("cargo")
+        .args(&["run", "--"])
+        .arg(input.path())
+        .arg("--quarantine")
+        .arg(rejects.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        r#"[{"name":"John","age":30}]"#
+    );
+    assert_eq!(
+        fs::read_to_string(rejects.path()).unwrap(),
+        "name,age\nbroken\n"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("line: 3"));
+
+    // Quoted line breaks, doubled quotes, CRLF and invalid UTF-8 survive
+    let malformed = b"\"a \"\"quoted\"\"\r\nnote\",1,2\r\n\xff,\xfe\r\n";
+    let mut content = b"name,age\r\nJohn,30\r\n".to_vec();
+    content.extend_from_slice(malformed);
+    fs::write(input.path(), &content).unwrap();
+
+    let output = Command::new("cargo")
+        .args(&["run", "--"])
+        .arg(input.path())
+        .arg("--quarantine")
+        .arg(rejects.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let mut expected = b"name,age\r\n".to_vec();
+    expected.extend_from_slice(malformed);
+    assert_eq!(fs::read(rejects.path()).unwrap(), expected);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cli_partition_by_more_partitions_than_open_files() {
+    let temp_input = NamedTempFile::new().unwrap();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let output_dir = temp_dir.path().join("out");
+    let mut csv_content = String::from("id,key\n");
+    for id in 0..600 {
+        csv_content.push_str(&format!("{},{}\n", id, id % 300));
+    }
+    fs::write(temp_input.path(), csv_content).unwrap();
+
+    // 300 partitions under a limit of 64 open files
+    let output = Command::new("sh")
+        .args(&["-c", "ulimit -n 64 && exec \"$0\" \"$@\""])
+        .arg(env!("CARGO_BIN_EXE_ctj"))
+        .arg(temp_input.path())
+        .arg("-o")
+        .arg(&output_dir)
+        .args(&["--partition-by", "key", "--format", "jsonl"])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read_dir(&output_dir).unwrap().count(), 300);
+    assert_eq!(
+        fs::read_to_string(output_dir.join("key=7.jsonl")).unwrap(),
+        "{\"id\":7,\"key\":7}\n{\"id\":307,\"key\":7}\n"
+    );
+}